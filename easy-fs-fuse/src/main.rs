@@ -8,6 +8,8 @@ use std::sync::Mutex;
 /// Use a block size of 512 bytes
 const BLOCK_SZ: usize = 512;
 const BLOCK_NUM: usize = 16384;
+/// Blocks behind the file system the kernel swaps pages out to
+const SWAP_BLOCK_NUM: usize = 8192;
 
 /// Wrapper for turning a File into a BlockDevice
 struct BlockFile(Mutex<File>);
@@ -60,7 +62,7 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .write(true)
             .create(true)
             .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len((BLOCK_NUM * BLOCK_SZ) as u64).unwrap();
+        f.set_len(((BLOCK_NUM + SWAP_BLOCK_NUM) * BLOCK_SZ) as u64).unwrap();
         f
     })));
     let efs = EasyFileSystem::create(block_file.clone(), BLOCK_NUM as u32, 1);
@@ -151,5 +153,67 @@ fn efs_test() -> std::io::Result<()> {
     random_str_test(1000 * BLOCK_SZ);
     random_str_test(2000 * BLOCK_SZ);
 
+    assert!(EasyFileSystem::fsck(&efs, false).is_clean());
+    Ok(())
+}
+
+#[test]
+fn efs_fsck_test() -> std::io::Result<()> {
+    use easy_fs::FsckError;
+    use std::convert::TryInto;
+    let block_file = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open("target/fsck.img")?;
+        f.set_len(BLOCK_NUM * BLOCK_SZ).unwrap();
+        f
+    })));
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    root_inode.create("filea");
+    root_inode.create("fileb");
+    let filea = root_inode.find("filea").unwrap();
+    filea.write_at(0, b"Hello, fsck!");
+    assert!(EasyFileSystem::fsck(&efs, false).is_clean());
+
+    // a dirent is 28 bytes of name and the inode number
+    const DIRENT_SZ: usize = 32;
+    let dirent = |name: &str, inode: u32| {
+        let mut bytes = [0u8; DIRENT_SZ];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        bytes[28..].copy_from_slice(&inode.to_le_bytes());
+        bytes
+    };
+    let mut entry = [0u8; DIRENT_SZ];
+    root_inode.read_at(0, &mut entry);
+    assert_eq!(&entry[..6], b"filea\0");
+    let filea_inode = u32::from_le_bytes(entry[28..].try_into().unwrap());
+    // a second entry raises the link count of filea to 2
+    root_inode.write_at(root_inode.size(), &dirent("alias", filea_inode));
+    // an entry of an inode that was never allocated
+    root_inode.write_at(root_inode.size(), &dirent("ghost", 100));
+    // a block marked in the bitmap with no owner
+    let leaked = efs.lock().alloc_data();
+
+    let report = EasyFileSystem::fsck(&efs, false);
+    assert_eq!(report.repaired, 0);
+    assert!(report.errors.contains(&FsckError::ExtraLinks { inode: filea_inode, links: 2 }));
+    assert!(report.errors.contains(&FsckError::DanglingEntry { dir: 0, inode: 100 }));
+    assert!(report.errors.contains(&FsckError::LeakedBlock { block: leaked }));
+    assert_eq!(report.errors.len(), 3);
+
+    let report = EasyFileSystem::fsck(&efs, true);
+    assert_eq!(report.errors.len(), 3);
+    assert_eq!(report.repaired, 3);
+    assert!(EasyFileSystem::fsck(&efs, false).is_clean());
+    let mut names = root_inode.ls();
+    names.sort();
+    assert_eq!(names, ["filea", "fileb"]);
+    let mut buffer = [0u8; 32];
+    let len = root_inode.find("filea").unwrap().read_at(0, &mut buffer);
+    assert_eq!(&buffer[..len], b"Hello, fsck!");
     Ok(())
 }
@@ -96,6 +96,12 @@ impl EasyFileSystem {
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
+                assert!(
+                    super_block.is_supported(),
+                    "Unsupported EFS version {} with features {:#x}!",
+                    super_block.version,
+                    super_block.features
+                );
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
                 let efs = Self {
@@ -0,0 +1,274 @@
+//! Consistency check of an easy fs: validates the super block, both bitmaps
+//! and the directory tree, optionally repairing what can be repaired safely.
+//!
+//! There are no hard links, so the link count of an inode is the number of
+//! directory entries referring to it and has to be exactly 1 for every
+//! inode but the root.
+
+use super::{
+    BlockDevice,
+    DirEntry,
+    DiskInode,
+    EasyFileSystem,
+    SuperBlock,
+    DIRENT_SZ,
+    BLOCK_SZ,
+    MAX_FILE_BLOCKS,
+    get_block_cache,
+    block_cache_sync_all,
+};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// An inconsistency found by fsck
+#[derive(Debug, PartialEq)]
+pub enum FsckError {
+    /// The magic number of the super block is wrong
+    BadSuperBlock,
+    /// The image was written by a newer version or uses unknown features
+    UnsupportedFormat { version: u32, features: u32 },
+    /// A directory entry refers to an inode which is not allocated,
+    /// repairable by dropping the entry
+    DanglingEntry { dir: u32, inode: u32 },
+    /// An inode is referred to by more than one directory entry,
+    /// repairable by dropping all but the first
+    ExtraLinks { inode: u32, links: u32 },
+    /// The size of a directory is not a multiple of the entry size
+    BadDirectorySize { inode: u32, size: u32 },
+    /// An inode refers to a block outside the data area
+    BlockOutOfRange { inode: u32, block: u32 },
+    /// An inode is larger than the data area or the largest file, so its
+    /// blocks are not walked
+    OversizedInode { inode: u32, size: u32 },
+    /// A data block is owned by two inodes
+    DuplicateBlock { block: u32, first: u32, second: u32 },
+    /// An inode is allocated but not reachable from the root, repairable
+    OrphanInode { inode: u32 },
+    /// A data block is allocated but owned by no inode, repairable
+    LeakedBlock { block: u32 },
+    /// A data block is in use but not marked in the bitmap, repairable
+    UnmarkedBlock { block: u32, inode: u32 },
+}
+
+/// Result of a fsck pass
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// all inconsistencies found, repaired or not
+    pub errors: Vec<FsckError>,
+    /// number of inconsistencies that have been repaired
+    pub repaired: usize,
+}
+
+impl FsckReport {
+    /// Whether no inconsistency has been found
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl EasyFileSystem {
+    /// Check the filesystem, and repair the bitmaps if `repair` is set
+    pub fn fsck(efs: &Arc<Mutex<Self>>, repair: bool) -> FsckReport {
+        let fs = efs.lock();
+        let block_device = Arc::clone(&fs.block_device);
+        let mut report = FsckReport::default();
+        // super block
+        let areas = get_block_cache(
+            0,
+            Arc::clone(&block_device)
+        ).lock().read(0, |super_block: &SuperBlock| {
+            if !super_block.is_valid() {
+                Err(FsckError::BadSuperBlock)
+            } else if !super_block.is_supported() {
+                Err(FsckError::UnsupportedFormat {
+                    version: super_block.version,
+                    features: super_block.features,
+                })
+            } else {
+                Ok((super_block.inode_area_blocks, super_block.data_area_blocks))
+            }
+        });
+        let (inode_area_blocks, data_area_blocks) = match areas {
+            Ok(areas) => areas,
+            Err(err) => {
+                report.errors.push(err);
+                return report;
+            }
+        };
+        let inodes_per_block = BLOCK_SZ / core::mem::size_of::<DiskInode>();
+        let inode_count = (inode_area_blocks as usize * inodes_per_block)
+            .min(fs.inode_bitmap.maximum());
+        let data_start = fs.get_data_block_id(0);
+        let data_end = data_start + data_area_blocks;
+        // owner of each data block and number of entries referring to each inode
+        let mut owner: Vec<Option<u32>> = vec![None; data_area_blocks as usize];
+        let mut links: Vec<u32> = vec![0; inode_count];
+        // (directory, index) of the entries to drop
+        let mut bad_entries: Vec<(u32, usize)> = Vec::new();
+        // no inode can hold more than this, which bounds the walk of each
+        let max_size = (data_area_blocks as usize).min(MAX_FILE_BLOCKS) * BLOCK_SZ;
+        // set once an inode is not walked, whose blocks are then unknown
+        let mut skipped = false;
+        // walk the directory tree from the root, every inode at most once
+        links[0] = 1;
+        let mut stack: Vec<u32> = vec![0];
+        while let Some(inode_id) = stack.pop() {
+            let disk_inode = read_disk_inode(&fs, &block_device, inode_id);
+            if disk_inode.size as usize > max_size {
+                report.errors.push(FsckError::OversizedInode {
+                    inode: inode_id,
+                    size: disk_inode.size,
+                });
+                skipped = true;
+                continue;
+            }
+            let mut children: Vec<u32> = Vec::new();
+            if disk_inode.is_dir() {
+                if disk_inode.size as usize % DIRENT_SZ != 0 {
+                    report.errors.push(FsckError::BadDirectorySize {
+                        inode: inode_id,
+                        size: disk_inode.size,
+                    });
+                }
+                let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+                let mut dirent = DirEntry::empty();
+                for i in 0..file_count {
+                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &block_device);
+                    children.push(dirent.inode_number());
+                }
+            }
+            let blocks = disk_inode.owned_blocks(&block_device);
+            for block in blocks {
+                if block < data_start || block >= data_end {
+                    report.errors.push(FsckError::BlockOutOfRange { inode: inode_id, block });
+                    continue;
+                }
+                let slot = &mut owner[(block - data_start) as usize];
+                match *slot {
+                    Some(first) => report.errors.push(FsckError::DuplicateBlock {
+                        block,
+                        first,
+                        second: inode_id,
+                    }),
+                    None => *slot = Some(inode_id),
+                }
+            }
+            for (index, child) in children.into_iter().enumerate() {
+                if child as usize >= inode_count
+                    || !fs.inode_bitmap.is_allocated(&block_device, child as usize)
+                {
+                    report.errors.push(FsckError::DanglingEntry { dir: inode_id, inode: child });
+                    bad_entries.push((inode_id, index));
+                    continue;
+                }
+                links[child as usize] += 1;
+                // every inode is walked only once, even if it is linked again
+                if links[child as usize] == 1 {
+                    stack.push(child);
+                } else {
+                    bad_entries.push((inode_id, index));
+                }
+            }
+        }
+        // link counts and orphans
+        for inode_id in 0..inode_count {
+            let allocated = fs.inode_bitmap.is_allocated(&block_device, inode_id);
+            if links[inode_id] > 1 {
+                report.errors.push(FsckError::ExtraLinks {
+                    inode: inode_id as u32,
+                    links: links[inode_id],
+                });
+            }
+            if allocated && links[inode_id] == 0 {
+                report.errors.push(FsckError::OrphanInode { inode: inode_id as u32 });
+                if repair {
+                    // its data blocks are owned by nobody and get freed below
+                    fs.inode_bitmap.dealloc(&block_device, inode_id);
+                    report.repaired += 1;
+                }
+            }
+        }
+        if repair && !bad_entries.is_empty() {
+            // 从后往前删，前面条目的下标不受影响
+            bad_entries.sort_unstable();
+            for &(dir, index) in bad_entries.iter().rev() {
+                remove_dirent(&fs, &block_device, dir, index);
+                report.repaired += 1;
+            }
+            // a directory that shrank may have given up a block
+            let mut dirs: Vec<u32> = bad_entries.iter().map(|&(dir, _)| dir).collect();
+            dirs.dedup();
+            for dir in dirs {
+                let blocks = read_disk_inode(&fs, &block_device, dir).owned_blocks(&block_device);
+                for slot in owner.iter_mut() {
+                    if *slot == Some(dir) {
+                        *slot = None;
+                    }
+                }
+                for block in blocks {
+                    if block >= data_start && block < data_end {
+                        owner[(block - data_start) as usize] = Some(dir);
+                    }
+                }
+            }
+        }
+        // data bitmap against the owners found above
+        for (bit, slot) in owner.iter().enumerate() {
+            let block = data_start + bit as u32;
+            match (fs.data_bitmap.is_allocated(&block_device, bit), *slot) {
+                (true, None) => {
+                    report.errors.push(FsckError::LeakedBlock { block });
+                    // it may belong to an inode that was not walked
+                    if repair && !skipped {
+                        fs.data_bitmap.dealloc(&block_device, bit);
+                        report.repaired += 1;
+                    }
+                }
+                (false, Some(inode)) => {
+                    report.errors.push(FsckError::UnmarkedBlock { block, inode });
+                    if repair {
+                        fs.data_bitmap.set(&block_device, bit);
+                        report.repaired += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if report.repaired > 0 {
+            block_cache_sync_all();
+        }
+        report
+    }
+}
+
+/// A copy of the disk inode `inode_id`, so that the blocks it refers to
+/// are read without its own block locked: a corrupted inode may point back
+/// at that block.
+fn read_disk_inode(fs: &EasyFileSystem, block_device: &Arc<dyn BlockDevice>, inode_id: u32) -> DiskInode {
+    let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+    get_block_cache(
+        block_id as usize,
+        Arc::clone(block_device)
+    ).lock().read(block_offset, |disk_inode: &DiskInode| disk_inode.clone())
+}
+
+/// Drop entry `index` of directory `dir`, moving its last entry into the
+/// place of it.
+fn remove_dirent(fs: &EasyFileSystem, block_device: &Arc<dyn BlockDevice>, dir: u32, index: usize) {
+    let mut disk_inode = read_disk_inode(fs, block_device, dir);
+    let last = disk_inode.size as usize / DIRENT_SZ - 1;
+    if index != last {
+        let mut dirent = DirEntry::empty();
+        disk_inode.read_at(last * DIRENT_SZ, dirent.as_bytes_mut(), block_device);
+        disk_inode.write_at(index * DIRENT_SZ, dirent.as_bytes(), block_device);
+    }
+    let (block_id, block_offset) = fs.get_disk_inode_pos(dir);
+    get_block_cache(
+        block_id as usize,
+        Arc::clone(block_device)
+    ).lock().modify(block_offset, |disk_inode: &mut DiskInode| {
+        disk_inode.size -= DIRENT_SZ as u32;
+    });
+}
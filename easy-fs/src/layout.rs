@@ -7,8 +7,13 @@ use super::{
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-/// Magic number for sanity check
-const EFS_MAGIC: u32 = 0x3b800001;
+/// Magic number for sanity check, bumped from 0x3b800001 when `version`
+/// and `features` moved the fields after them, so older images are rejected
+const EFS_MAGIC: u32 = 0x3b800002;
+/// On-disk format version written by this implementation
+pub const EFS_VERSION: u32 = 1;
+/// Feature bits this implementation understands, none are defined yet
+pub const EFS_SUPPORTED_FEATURES: u32 = 0;
 /// The max number of direct inodes
 const INODE_DIRECT_COUNT: usize = 28;
 /// The max length of inode name
@@ -22,13 +27,16 @@ const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 /// The upper bound of indirect1 inode index
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 /// The upper bound of indirect2 inode index
-#[allow(unused)]
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// The most data blocks a file can have
+pub const MAX_FILE_BLOCKS: usize = INDIRECT2_BOUND;
 
 /// Super block of a filesystem
 #[repr(C)]
 pub struct SuperBlock {
     magic: u32,
+    pub version: u32,
+    pub features: u32,
     pub total_blocks: u32,
     pub inode_bitmap_blocks: u32,
     pub inode_area_blocks: u32,
@@ -39,6 +47,8 @@ pub struct SuperBlock {
 impl Debug for SuperBlock {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         f.debug_struct("SuperBlock")
+            .field("version", &self.version)
+            .field("features", &self.features)
             .field("total_blocks", &self.total_blocks)
             .field("inode_bitmap_blocks", &self.inode_bitmap_blocks)
             .field("inode_area_blocks", &self.inode_area_blocks)
@@ -60,6 +70,8 @@ impl SuperBlock {
     ) {
         *self = Self {
             magic: EFS_MAGIC,
+            version: EFS_VERSION,
+            features: EFS_SUPPORTED_FEATURES,
             total_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
@@ -71,10 +83,14 @@ impl SuperBlock {
     pub fn is_valid(&self) -> bool {
         self.magic == EFS_MAGIC
     }
+    /// Check if the on-disk format can be handled by this implementation
+    pub fn is_supported(&self) -> bool {
+        self.version <= EFS_VERSION && self.features & !EFS_SUPPORTED_FEATURES == 0
+    }
 }
 
 /// Type of a disk inode
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
@@ -87,6 +103,7 @@ type DataBlock = [u8; BLOCK_SZ];
 
 /// A disk inode
 #[repr(C)]
+#[derive(Clone)]
 pub struct DiskInode {
     pub size: u32,
     pub direct: [u32; INODE_DIRECT_COUNT],
@@ -343,6 +360,29 @@ impl DiskInode {
         self.indirect2 = 0;
         v
     }
+    /// Get all blocks owned by current disk inode, including index blocks,
+    /// without modifying anything
+    pub fn owned_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        let data_blocks = self.data_blocks() as usize;
+        let mut v: Vec<u32> = Vec::new();
+        for inner_id in 0..data_blocks {
+            v.push(self.get_block_id(inner_id as u32, block_device));
+        }
+        if data_blocks > INODE_DIRECT_COUNT {
+            v.push(self.indirect1);
+        }
+        if data_blocks > INDIRECT1_BOUND {
+            v.push(self.indirect2);
+            let indirect1_count =
+                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+            get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(&indirect2[..indirect1_count]);
+                });
+        }
+        v
+    }
     /// Read data from current disk inode
     pub fn read_at(
         &self,
@@ -36,6 +36,11 @@ impl Inode {
             block_device,
         }
     }
+    /// Block id and offset of the disk inode, which tell the inodes of a
+    /// file system apart
+    pub fn disk_pos(&self) -> (usize, usize) {
+        (self.block_id, self.block_offset)
+    }
     /// Call a function over a disk inode to read it
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
         get_block_cache(
@@ -195,6 +200,11 @@ impl Inode {
         block_cache_sync_all();
         size
     }
+    /// Size of the data in current inode in bytes
+    pub fn size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
+    }
     /// Clear the data in current inode
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
@@ -0,0 +1,29 @@
+//! In-kernel tests, run instead of the apps with `--features board_test`
+//!
+//! The tests run on the boot hart right after memory management is set up.
+//! A failing one panics, and the panic handler shuts down with a failure
+//! code; once all of them pass the kernel shuts down cleanly, so QEMU's exit
+//! code tells which happened. `make board-test` builds and runs them.
+
+use crate::mm;
+use crate::syscall;
+use crate::sbi::shutdown_with;
+
+const TESTS: &[(&str, fn())] = &[
+    ("heap allocator", mm::heap_test),
+    ("frame allocator", mm::frame_allocator_test),
+    ("page table map/unmap/translate", mm::page_table_test),
+    ("memory set clone", mm::memory_set_clone_test),
+    ("translated_byte_buffer across pages", mm::translated_byte_buffer_test),
+    ("page cache write past the end of a file", mm::page_cache::page_cache_test),
+    ("read into an untouched file mapping", syscall::read_into_mmap_test),
+];
+
+pub fn run() -> ! {
+    for (i, (name, test)) in TESTS.iter().enumerate() {
+        println!("[board_test] {}/{} {}", i + 1, TESTS.len(), name);
+        test();
+    }
+    println!("[board_test] all {} tests passed", TESTS.len());
+    shutdown_with(false)
+}
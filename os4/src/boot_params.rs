@@ -0,0 +1,139 @@
+//! Options from the kernel command line
+//!
+//! The command line comes from qemu's `-append` through the device tree,
+//! see [`Board::bootargs`](crate::dtb::Board::bootargs). It is a list of
+//! `key=value` separated by spaces:
+//!
+//! - `loglevel=debug`, the default log level, any of `off`, `error`, `warn`,
+//!   `info`, `debug` and `trace`; `LOG_FILTER` still applies on top of it
+//! - `scheduler=stride`, how the next thread to run is picked, `fifo` or
+//!   `stride`, see [`Scheduler`]
+//! - `ticks_per_sec=N`, scheduling ticks per second of every hart
+//! - `mmtrace=PID`, print the mappings, page faults and fork copies of
+//!   process `PID`, see [`PID_TRACER`](crate::mm::PID_TRACER)
+//! - `schedreplay=record`, log the scheduling decisions to replay them on
+//!   the next boot with `schedreplay=replay`, see [`ReplayMode`]
+//!
+//! Options left out keep what the kernel was built with. Unknown options
+//! and bad values are warned about and ignored.
+
+use crate::dtb::board;
+use crate::mm::{set_mm_observer, PID_TRACER};
+use log::LevelFilter;
+use spin::Once;
+
+/// ticks per second unless the command line says otherwise
+const DEFAULT_TICKS_PER_SEC: usize = 100;
+/// bounds of `ticks_per_sec`, a tick needs some time to do any work in
+const TICKS_PER_SEC_RANGE: core::ops::RangeInclusive<usize> = 1..=10_000;
+
+/// How the ready queue picks the next thread
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scheduler {
+    /// round robin in the order threads become ready
+    Fifo,
+    /// the thread with the smallest pass, which grows by a stride inversely
+    /// proportional to its priority every time it is picked
+    Stride,
+}
+
+/// Whether scheduling decisions are recorded or replayed, see
+/// `src/task/replay.rs`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReplayMode {
+    Off,
+    /// log every decision and write the log out at shutdown
+    Record,
+    /// make the decisions of the log written by the last `Record` boot
+    Replay,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BootParams {
+    /// `None` keeps the level of `LOG`
+    pub log_level: Option<LevelFilter>,
+    pub scheduler: Scheduler,
+    pub ticks_per_sec: usize,
+    /// the process whose paging is traced
+    pub mm_trace: Option<usize>,
+    pub replay: ReplayMode,
+}
+
+impl Default for BootParams {
+    fn default() -> Self {
+        Self {
+            log_level: None,
+            scheduler: Scheduler::Fifo,
+            ticks_per_sec: DEFAULT_TICKS_PER_SEC,
+            mm_trace: None,
+            replay: ReplayMode::Off,
+        }
+    }
+}
+
+impl BootParams {
+    /// Take one `key=value` option, `false` if it is not understood.
+    fn set(&mut self, option: &str) -> bool {
+        let (key, value) = match option.split_once('=') {
+            Some(pair) => pair,
+            None => return false,
+        };
+        match key {
+            "loglevel" => match value.parse() {
+                Ok(level) => self.log_level = Some(level),
+                Err(_) => return false,
+            },
+            "scheduler" => match value {
+                "fifo" => self.scheduler = Scheduler::Fifo,
+                "stride" => self.scheduler = Scheduler::Stride,
+                _ => return false,
+            },
+            "ticks_per_sec" => match value.parse() {
+                Ok(ticks) if TICKS_PER_SEC_RANGE.contains(&ticks) => self.ticks_per_sec = ticks,
+                _ => return false,
+            },
+            "mmtrace" => match value.parse() {
+                Ok(pid) => self.mm_trace = Some(pid),
+                Err(_) => return false,
+            },
+            "schedreplay" => match value {
+                "off" => self.replay = ReplayMode::Off,
+                "record" => self.replay = ReplayMode::Record,
+                "replay" => self.replay = ReplayMode::Replay,
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+static BOOT_PARAMS: Once<BootParams> = Once::new();
+
+/// The options as found by [`init`], the defaults before.
+pub fn boot_params() -> &'static BootParams {
+    BOOT_PARAMS.call_once(BootParams::default)
+}
+
+/// Parse the command line of the device tree on the boot hart, after
+/// [`dtb::init`](crate::dtb::init) and before the other harts start.
+pub fn init() {
+    let cmdline = board().bootargs();
+    let mut params = BootParams::default();
+    for option in cmdline.split_whitespace() {
+        if !params.set(option) {
+            warn!("[kernel] ignoring bad boot option {}", option);
+        }
+    }
+    if !cmdline.is_empty() {
+        info!("[kernel] command line: {}", cmdline);
+    }
+    BOOT_PARAMS.call_once(|| params);
+    if let Some(level) = params.log_level {
+        crate::logging::set_level(level);
+    }
+    if let Some(pid) = params.mm_trace {
+        PID_TRACER.follow(pid);
+        set_mm_observer(Some(&PID_TRACER));
+    }
+}
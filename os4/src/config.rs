@@ -1,18 +1,115 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// virtual space reserved for the user stack, which grows into it on page fault
+pub const USER_STACK_MAX_SIZE: usize = 4096 * 64;
+/// with feature `aslr`, the user stack, heap and mmap region each move by
+/// fewer pages than this
+pub const ASLR_MAX_PAGES: usize = 256;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+/// end of RAM when there is no device tree, see `dtb::board`
 pub const MEMORY_END: usize = 0x88000000;
+/// start of physical memory, where the SBI firmware is loaded
+pub const MEMORY_START: usize = 0x80000000;
+/// warn when fewer free frames than this are left, `None` to stay quiet
+pub const FRAME_LOW_WATERMARK: Option<usize> = Some(256);
+/// how many cached file pages to evict at once when out of frames
+pub const PAGE_CACHE_SHRINK: usize = 32;
+/// how many anonymous pages to swap out at once when out of frames
+pub const SWAP_BATCH: usize = 16;
+/// first block of the swap area, right behind the easy-fs image
+pub const SWAP_START_BLOCK: usize = 16384;
+/// pages the swap area holds, easy-fs-fuse leaves the blocks for them
+pub const SWAP_PAGES: usize = 1024;
+/// time between two access scans, see `task::set_access_scan_hook`
+pub const ACCESS_SCAN_INTERVAL_US: usize = 100_000;
+/// time between two checks of the watchdog, which warns about harts that
+/// had no timer tick for this long
+pub const WATCHDOG_INTERVAL_US: usize = 1_000_000;
+/// time slices a thread may use up in a row, without yielding or blocking,
+/// before the watchdog reports it, `None` to turn the task watchdog off
+pub const TASK_WATCHDOG_SLICES: Option<usize> = if cfg!(debug_assertions) {
+    Some(200)
+} else {
+    None
+};
+/// pcs kept per thread by the `profiling` feature, its report covers them
+pub const PROFILE_SAMPLES: usize = 1024;
+/// pcs kept of ticks interrupting the kernel, on any hart
+pub const PROFILE_KERNEL_SAMPLES: usize = 16384;
+/// pcs shown in a profile report
+pub const PROFILE_TOP: usize = 10;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+
+/// levels of the page table, Sv39 by default or Sv48 with feature `sv48`
+#[cfg(not(feature = "sv48"))]
+pub const PAGE_TABLE_LEVELS: usize = 3;
+#[cfg(feature = "sv48")]
+pub const PAGE_TABLE_LEVELS: usize = 4;
+/// MODE field of `satp` for the chosen paging scheme
+#[cfg(not(feature = "sv48"))]
+pub const SATP_MODE: usize = 8;
+#[cfg(feature = "sv48")]
+pub const SATP_MODE: usize = 9;
+/// width of a virtual address
+pub const VA_WIDTH: usize = PAGE_SIZE_BITS + 9 * PAGE_TABLE_LEVELS;
+/// end of the lower half of the address space, which user mappings live in
+pub const USER_SPACE_END: usize = 1 << (VA_WIDTH - 1);
+/// size of a huge page mapped by a leaf entry in the middle-level page table
+pub const HUGE_PAGE_SIZE: usize = 0x20_0000;
 pub const MAX_SYSCALL_NUM: usize = 500;
+/// bound on the fd `sys_dup2` accepts, which grows the fd table up to it
+pub const MAX_FD: usize = 256;
+/// priority threads start with, which only the stride scheduler looks at
+pub const DEFAULT_PRIORITY: usize = 16;
+/// processes `sys_mempinfo` reports at most, matching the user lib
+pub const MEMINFO_MAX_TASKS: usize = 32;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 /// Return (bottom, top) of a kernel stack in kernel space.
-pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
-    let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
+/// The page right below `bottom` is a guard page and is never mapped.
+pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - kstack_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
 
+/// Return the kernel stack slot whose guard page contains `addr`, if any.
+pub fn kernel_stack_guard_owner(addr: usize) -> Option<usize> {
+    if addr >= TRAMPOLINE {
+        return None;
+    }
+    let offset = TRAMPOLINE - 1 - addr;
+    let stride = KERNEL_STACK_SIZE + PAGE_SIZE;
+    if offset % stride >= KERNEL_STACK_SIZE {
+        Some(offset / stride)
+    } else {
+        None
+    }
+}
+
 pub const CLOCK_FREQ: usize = 12500000;
+
+// 以下是没有设备树时使用的qemu virt机器的默认值
+/// memory-mapped device registers of the qemu `virt` machine, (start, len)
+pub const MMIO: &[(usize, usize)] = &[
+    (0x0c00_0000, 0x40_0000),
+    (0x1000_0000, 0x1000),
+    (0x1000_1000, 0x1000),
+];
+/// registers of the platform-level interrupt controller
+pub const PLIC: usize = 0x0c00_0000;
+/// registers of the ns16550a UART behind the console
+pub const UART0: usize = 0x1000_0000;
+/// interrupt source of the UART at the PLIC
+pub const UART0_IRQ: usize = 10;
+/// (registers, interrupt source or 0) of a second ns16550a UART for the gdb
+/// stub without a device tree, `None` as the `virt` machine has only one
+pub const GDB_UART: Option<(usize, usize)> = None;
+/// registers of the first virtio-mmio device, the block device
+pub const VIRTIO0: usize = 0x10001000;
+
+/// harts the kernel brings up at most, whatever the device tree lists,
+/// matching the boot stacks in `entry.asm`
+pub const MAX_HARTS: usize = 4;
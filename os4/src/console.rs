@@ -2,24 +2,61 @@
     本模块实现了 print 和 println 宏
 */
 
-use crate::sbi::console_putchar;
+use crate::config::{MEMORY_START, USER_SPACE_END};
+use crate::sbi::{console_putchar, console_write, has_dbcn};
+use crate::sync::SpinNoIrq;
 use core::fmt::{self, Write};
+use spin::Once;
+
+/// 防止多个hart的输出交错在一起
+static PRINT_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+/// Whether the firmware takes whole buffers, asked once
+static DBCN: Once<bool> = Once::new();
 
 struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
-        }
+        write_locked(s.as_bytes());
         Ok(())
     }
 }
 
+/// Write `bytes` with the print lock held, a whole buffer per SBI call if
+/// the firmware has the debug console extension, a byte per call otherwise.
+fn write_locked(bytes: &[u8]) {
+    let mut rest = bytes;
+    // 内核空间低半部分是恒等映射，可以把地址直接交给SBI，内核栈则不行
+    let start = bytes.as_ptr() as usize;
+    let identity_mapped = start >= MEMORY_START && start + bytes.len() <= USER_SPACE_END;
+    if identity_mapped && *DBCN.call_once(has_dbcn) {
+        while !rest.is_empty() {
+            match console_write(rest.as_ptr() as usize, rest.len()) {
+                Ok(written) if written > 0 => rest = &rest[written.min(rest.len())..],
+                _ => break,
+            }
+        }
+    }
+    for &byte in rest {
+        console_putchar(byte as usize);
+    }
+}
+
 pub fn print(args: fmt::Arguments) {
+    let _guard = PRINT_LOCK.lock();
     Stdout.write_fmt(args).unwrap();
 }
 
+/// Write `chunks` one after the other, without output of other harts in
+/// between.
+pub fn write_bytes(chunks: &[&[u8]]) {
+    let _guard = PRINT_LOCK.lock();
+    for chunk in chunks {
+        write_locked(chunk);
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($fmt: literal $(, $($arg: tt)+)?) => {
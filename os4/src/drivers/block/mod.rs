@@ -0,0 +1,34 @@
+//! Block devices, read and written a block at a time
+
+mod virtio_blk;
+
+use alloc::sync::Arc;
+use lazy_static::*;
+
+// the trait easy-fs is built on, so any block device can hold a file system
+pub use easy_fs::{BlockDevice, BLOCK_SZ};
+
+type BlockDeviceImpl = virtio_blk::VirtIOBlock;
+
+lazy_static! {
+    /// the block device of the machine, shared by whoever stores data on it
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+}
+
+#[allow(unused)]
+/// Write a pattern to the first blocks and read it back, destroying what
+/// was there.
+pub fn block_device_test() {
+    let block_device = BLOCK_DEVICE.clone();
+    let mut write_buffer = [0u8; BLOCK_SZ];
+    let mut read_buffer = [0u8; BLOCK_SZ];
+    for i in 0..512 {
+        for byte in write_buffer.iter_mut() {
+            *byte = i as u8;
+        }
+        block_device.write_block(i as usize, &write_buffer);
+        block_device.read_block(i as usize, &mut read_buffer);
+        assert_eq!(write_buffer, read_buffer);
+    }
+    info!("block device test passed!");
+}
@@ -0,0 +1,104 @@
+//! virtio-blk over MMIO, driven by the `virtio-drivers` crate
+//!
+//! The crate finds its DMA memory and address translation through the
+//! `virtio_*` hooks exported below.
+
+use super::BlockDevice;
+use crate::dtb::board;
+use crate::mm::{
+    frame_alloc_contiguous, FrameTracker, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE,
+};
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use lazy_static::*;
+use virtio_drivers::{VirtIOBlk, VirtIOHeader};
+
+pub struct VirtIOBlock(SpinLock<VirtIOBlk<'static>>);
+
+lazy_static! {
+    /// frames handed out to the device as virtqueues, each run kept whole
+    static ref QUEUE_FRAMES: SpinLock<Vec<Vec<FrameTracker>>> = SpinLock::new(Vec::new());
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.0
+            .lock()
+            .read_block(block_id, buf)
+            .expect("Error when reading VirtIOBlk");
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.0
+            .lock()
+            .write_block(block_id, buf)
+            .expect("Error when writing VirtIOBlk");
+    }
+}
+
+/// `MagicValue` of a virtio-mmio slot, "virt" in little endian
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+/// `DeviceID` of a block device, 0 meaning an empty slot
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+/// The first virtio-mmio slot with a block device behind it, or the first
+/// slot if none says so.
+fn find_block_device() -> Option<usize> {
+    let slots = board().virtio();
+    slots
+        .iter()
+        .copied()
+        .find(|&base| unsafe {
+            core::ptr::read_volatile(base as *const u32) == VIRTIO_MAGIC
+                && core::ptr::read_volatile((base + 8) as *const u32) == VIRTIO_DEVICE_BLOCK
+        })
+        .or_else(|| slots.first().copied())
+}
+
+impl VirtIOBlock {
+    pub fn new() -> Self {
+        let base = find_block_device().expect("no virtio block device");
+        unsafe {
+            Self(SpinLock::new(
+                VirtIOBlk::new(&mut *(base as *mut VirtIOHeader)).unwrap(),
+            ))
+        }
+    }
+}
+
+/// Give the device `pages` physically contiguous frames.
+#[no_mangle]
+pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
+    let frames = frame_alloc_contiguous(pages).expect("out of memory for virtqueues");
+    let pa: PhysAddr = frames[0].ppn.into();
+    QUEUE_FRAMES.lock().push(frames);
+    pa
+}
+
+/// Take back the frames given out by [`virtio_dma_alloc`] starting at `pa`.
+#[no_mangle]
+pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
+    let ppn: PhysPageNum = pa.into();
+    let mut queue_frames = QUEUE_FRAMES.lock();
+    match queue_frames
+        .iter()
+        .position(|frames| frames[0].ppn == ppn && frames.len() == pages)
+    {
+        Some(i) => {
+            queue_frames.swap_remove(i);
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Physical memory is mapped identically in the kernel space.
+#[no_mangle]
+pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    VirtAddr(paddr.0)
+}
+
+/// Buffers may also be on a kernel stack, which is not mapped identically.
+#[no_mangle]
+pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
+    KERNEL_SPACE.lock().translate_va(vaddr).unwrap()
+}
@@ -0,0 +1,52 @@
+//! Device drivers
+//!
+//! The virtio block device and the console UART of the qemu `virt` machine,
+//! found at the MMIO addresses the device tree lists, see [`crate::dtb`].
+//! Device interrupts come in through the PLIC.
+
+pub mod block;
+mod plic;
+pub mod uart;
+
+pub use block::{BlockDevice, BLOCK_DEVICE};
+
+use crate::dtb::board;
+
+/// the interrupt of the UART of the gdb stub, if it has one
+#[cfg(feature = "gdbstub")]
+fn gdb_irq() -> Option<usize> {
+    board().gdb_uart.map(|(_, irq)| irq).filter(|&irq| irq != 0)
+}
+
+/// Set up the devices driven by interrupts, once on the boot hart.
+pub fn init() {
+    plic::set_priority(board().uart_irq, 1);
+    uart::init();
+    #[cfg(feature = "gdbstub")]
+    if let Some(irq) = gdb_irq() {
+        plic::set_priority(irq, 1);
+    }
+}
+
+/// Route device interrupts to the current hart.
+pub fn init_hart() {
+    plic::enable(board().uart_irq);
+    #[cfg(feature = "gdbstub")]
+    if let Some(irq) = gdb_irq() {
+        plic::enable(irq);
+    }
+    plic::set_threshold(0);
+}
+
+/// Handle a supervisor external interrupt.
+pub fn handle_irq() {
+    if let Some(irq) = plic::claim() {
+        match irq {
+            irq if irq == board().uart_irq => uart::handle_irq(),
+            #[cfg(feature = "gdbstub")]
+            irq if Some(irq) == gdb_irq() => crate::gdbstub::handle_irq(),
+            _ => warn!("[kernel] unexpected external interrupt {}", irq),
+        }
+        plic::complete(irq);
+    }
+}
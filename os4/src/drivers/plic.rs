@@ -0,0 +1,58 @@
+//! The platform-level interrupt controller, at the address the device tree
+//! gives
+//!
+//! Each hart has a context for M mode and one for S mode, the S-mode one of
+//! hart `h` being context `2 * h + 1`. Every hart takes every source we
+//! enable; whoever claims an interrupt first handles it, the others claim 0.
+
+use crate::dtb::board;
+use crate::hart::hart_id;
+use core::ptr::{read_volatile, write_volatile};
+
+const PRIORITY: usize = 0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// the S-mode context of the current hart
+fn s_context() -> usize {
+    hart_id() * 2 + 1
+}
+
+/// the register at `offset` into the PLIC
+fn reg(offset: usize) -> *mut u32 {
+    (board().plic + offset) as *mut u32
+}
+
+/// Give interrupt source `irq` a nonzero priority, so that it is delivered.
+pub fn set_priority(irq: usize, priority: u32) {
+    unsafe { write_volatile(reg(PRIORITY + irq * 4), priority) }
+}
+
+/// Let source `irq` interrupt the current hart in S mode.
+pub fn enable(irq: usize) {
+    let addr = ENABLE + s_context() * ENABLE_STRIDE + irq / 32 * 4;
+    unsafe { write_volatile(reg(addr), read_volatile(reg(addr)) | 1 << (irq % 32)) }
+}
+
+/// Take interrupts of any nonzero priority on the current hart.
+pub fn set_threshold(threshold: u32) {
+    unsafe { write_volatile(reg(CONTEXT + s_context() * CONTEXT_STRIDE), threshold) }
+}
+
+/// The pending source with the highest priority, or `None` if another hart
+/// got it first.
+pub fn claim() -> Option<usize> {
+    let irq = unsafe { read_volatile(reg(CONTEXT + s_context() * CONTEXT_STRIDE + 4)) };
+    if irq == 0 {
+        None
+    } else {
+        Some(irq as usize)
+    }
+}
+
+/// Tell the PLIC `irq` is handled, so that it may come in again.
+pub fn complete(irq: usize) {
+    unsafe { write_volatile(reg(CONTEXT + s_context() * CONTEXT_STRIDE + 4), irq as u32) }
+}
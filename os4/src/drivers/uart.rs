@@ -0,0 +1,147 @@
+//! The ns16550a UART behind the console, receiving by interrupts
+//!
+//! Output still goes through the SBI console, which writes the same UART.
+//! Other ns16550a ports, like the one of the gdb stub, are polled with
+//! [`poll_read`] and [`poll_write`].
+//! Received bytes are moved from the FIFO into an input buffer by the
+//! interrupt handler, and readers of stdin block until it has something.
+//!
+//! Waking up readers takes locks an interrupted kernel path on this hart may
+//! hold, so an interrupt arriving then leaves the wake-up to the next timer
+//! tick, see [`wake_pending_readers`].
+
+use crate::dtb::board;
+use crate::sync::{locks_held, SpinNoIrq, WaitQueue};
+use crate::timer::Deadline;
+use alloc::collections::VecDeque;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+/// receive buffer, read only
+const RBR: usize = 0;
+/// transmit holding, write only
+const THR: usize = 0;
+/// interrupt enable
+const IER: usize = 1;
+/// FIFO control, write only
+const FCR: usize = 2;
+/// modem control
+const MCR: usize = 4;
+/// line status
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR: u8 = 0b11 << 1;
+/// OUT2 gates the interrupt line of the UART
+const MCR_OUT2: u8 = 1 << 3;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// bytes received but not read yet, older ones are dropped past this
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+fn read_reg(base: usize, offset: usize) -> u8 {
+    unsafe { read_volatile((base + offset) as *const u8) }
+}
+
+fn write_reg(base: usize, offset: usize, value: u8) {
+    unsafe { write_volatile((base + offset) as *mut u8, value) }
+}
+
+struct Input {
+    /// taken in the interrupt handler, so interrupts stay off while held
+    buffer: SpinNoIrq<VecDeque<u8>>,
+    readers: WaitQueue,
+    /// an interrupt had to leave waking up the readers to someone else
+    wake_pending: AtomicBool,
+}
+
+lazy_static! {
+    static ref INPUT: Input = Input {
+        buffer: SpinNoIrq::new(VecDeque::new()),
+        readers: WaitQueue::new(),
+        wake_pending: AtomicBool::new(false),
+    };
+}
+
+/// Turn on the FIFOs and the receive interrupt. The line settings are left
+/// as the firmware set them up.
+pub fn init() {
+    init_port(board().uart);
+}
+
+/// Turn on the FIFOs and the receive interrupt of the UART at `base`.
+pub fn init_port(base: usize) {
+    write_reg(base, FCR, FCR_ENABLE | FCR_CLEAR);
+    write_reg(base, MCR, read_reg(base, MCR) | MCR_OUT2);
+    write_reg(base, IER, IER_RX_AVAILABLE);
+}
+
+/// A received byte of the UART at `base`, if there is one.
+pub fn poll_read(base: usize) -> Option<u8> {
+    (read_reg(base, LSR) & LSR_DATA_READY != 0).then(|| read_reg(base, RBR))
+}
+
+/// Send `byte` over the UART at `base`, waiting for room in the FIFO.
+#[allow(unused)]
+pub fn poll_write(base: usize, byte: u8) {
+    while read_reg(base, LSR) & LSR_THR_EMPTY == 0 {
+        core::hint::spin_loop();
+    }
+    write_reg(base, THR, byte);
+}
+
+/// Move everything in the receive FIFO into the input buffer and wake up
+/// the readers, or leave that to the next tick if this hart holds a lock.
+pub fn handle_irq() {
+    let mut received = false;
+    let mut buffer = INPUT.buffer.lock();
+    while let Some(byte) = poll_read(board().uart) {
+        if buffer.len() == INPUT_BUFFER_SIZE {
+            buffer.pop_front();
+        }
+        buffer.push_back(byte);
+        received = true;
+    }
+    drop(buffer);
+    if received {
+        INPUT.wake_pending.store(true, Ordering::Release);
+    }
+    if locks_held() == 0 {
+        wake_pending_readers();
+    }
+}
+
+/// Wake up the readers an interrupt could not, called with no lock held.
+pub fn wake_pending_readers() {
+    if INPUT.wake_pending.swap(false, Ordering::AcqRel) {
+        // 先拿一下缓冲区的锁，读者要么已在队列里，要么还没检查缓冲区
+        drop(INPUT.buffer.lock());
+        INPUT.readers.wake_all();
+    }
+}
+
+/// Block until there is input, then read as much of it as fits in `buf`.
+/// `None` if `deadline` passes with nothing read.
+pub fn read(buf: &mut [u8], deadline: Deadline) -> Option<usize> {
+    if buf.is_empty() {
+        return Some(0);
+    }
+    loop {
+        let mut buffer = INPUT.buffer.lock();
+        if buffer.is_empty() {
+            if deadline.expired() {
+                return None;
+            }
+            INPUT.readers.wait_until_after(deadline, || drop(buffer));
+            continue;
+        }
+        let len = buf.len().min(buffer.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = src;
+        }
+        return Some(len);
+    }
+}
@@ -0,0 +1,310 @@
+//! The flattened device tree the SBI firmware passes in `a1`
+//!
+//! Only what the kernel needs is picked out of it: the end of RAM, the
+//! number of harts, and the registers and interrupts of the PLIC, the UARTs
+//! and the virtio-mmio slots, see [`Board`]. The first UART is the console,
+//! a second one is left to the gdb stub. The kernel command line, which
+//! qemu puts into `/chosen/bootargs` from `-append`, is copied out as well
+//! for [`boot_params`](crate::boot_params). Whatever the tree does not
+//! tell, or all of it without a valid tree, is taken from `config`, which
+//! describes the qemu `virt` machine with 128 MiB.
+
+use crate::config::{GDB_UART, MAX_HARTS, MEMORY_END, MMIO, PLIC, UART0, UART0_IRQ, VIRTIO0};
+use core::ptr::read_unaligned;
+use spin::Once;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// nodes nested deeper than this are skipped
+const MAX_DEPTH: usize = 16;
+/// virtio slots and register ranges kept at most
+const MAX_DEVICES: usize = 16;
+/// bytes of the kernel command line kept at most
+const MAX_BOOTARGS: usize = 256;
+
+/// What the kernel knows about the machine it runs on
+#[derive(Copy, Clone, Debug)]
+pub struct Board {
+    /// end of RAM, which starts at `MEMORY_START` below the kernel
+    pub memory_end: usize,
+    /// harts in the machine, at most `MAX_HARTS` of them are started
+    pub harts: usize,
+    /// registers of the PLIC
+    pub plic: usize,
+    /// registers of the console UART
+    pub uart: usize,
+    /// interrupt source of the UART at the PLIC
+    pub uart_irq: usize,
+    /// (registers, interrupt source or 0) of the UART of the gdb stub
+    pub gdb_uart: Option<(usize, usize)>,
+    /// registers of the virtio-mmio slots, a device or not behind each
+    virtio: [usize; MAX_DEVICES],
+    virtio_len: usize,
+    /// (start, len) of every register range the kernel maps
+    mmio: [(usize, usize); MAX_DEVICES],
+    mmio_len: usize,
+    /// the kernel command line, cut short at `MAX_BOOTARGS`
+    bootargs: [u8; MAX_BOOTARGS],
+    bootargs_len: usize,
+}
+
+impl Board {
+    pub fn virtio(&self) -> &[usize] {
+        &self.virtio[..self.virtio_len]
+    }
+    pub fn mmio(&self) -> &[(usize, usize)] {
+        &self.mmio[..self.mmio_len]
+    }
+    /// the kernel command line, up to where it stops being UTF-8
+    pub fn bootargs(&self) -> &str {
+        let bytes = &self.bootargs[..self.bootargs_len];
+        core::str::from_utf8(bytes).unwrap_or_else(|error| {
+            core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap()
+        })
+    }
+    fn add_virtio(&mut self, base: usize) {
+        if self.virtio_len < MAX_DEVICES {
+            self.virtio[self.virtio_len] = base;
+            self.virtio_len += 1;
+        }
+    }
+    fn add_mmio(&mut self, start: usize, len: usize) {
+        if self.mmio_len < MAX_DEVICES {
+            self.mmio[self.mmio_len] = (start, len);
+            self.mmio_len += 1;
+        }
+    }
+}
+
+/// the `virt` machine as `config` describes it
+fn default_board() -> Board {
+    let mut board = Board {
+        memory_end: MEMORY_END,
+        harts: MAX_HARTS,
+        plic: PLIC,
+        uart: UART0,
+        uart_irq: UART0_IRQ,
+        gdb_uart: GDB_UART,
+        virtio: [0; MAX_DEVICES],
+        virtio_len: 0,
+        mmio: [(0, 0); MAX_DEVICES],
+        mmio_len: 0,
+        bootargs: [0; MAX_BOOTARGS],
+        bootargs_len: 0,
+    };
+    board.add_virtio(VIRTIO0);
+    for &(start, len) in MMIO {
+        board.add_mmio(start, len);
+    }
+    if let Some((base, _)) = GDB_UART {
+        board.add_mmio(base, 0x1000);
+    }
+    board
+}
+
+static BOARD: Once<Board> = Once::new();
+
+/// The machine as found by [`init`], which comes before anyone asks.
+pub fn board() -> &'static Board {
+    BOARD.call_once(default_board)
+}
+
+/// the properties of a node the kernel looks at
+#[derive(Copy, Clone, Default)]
+struct Node {
+    name: &'static [u8],
+    compatible: &'static [u8],
+    device_type: &'static [u8],
+    reg: &'static [u8],
+    interrupts: &'static [u8],
+}
+
+impl Node {
+    fn is_compatible(&self, name: &str) -> bool {
+        self.compatible
+            .split(|&byte| byte == 0)
+            .any(|entry| entry == name.as_bytes())
+    }
+    /// the first (address, size) of `reg`, read with the cells of the parent
+    fn first_reg(&self, address_cells: usize, size_cells: usize) -> Option<(usize, usize)> {
+        if self.reg.len() < (address_cells + size_cells) * 4 {
+            return None;
+        }
+        let address = read_cells(&self.reg[..address_cells * 4]);
+        let size = read_cells(&self.reg[address_cells * 4..(address_cells + size_cells) * 4]);
+        Some((address, size))
+    }
+    fn first_interrupt(&self) -> Option<usize> {
+        (self.interrupts.len() >= 4).then(|| read_cells(&self.interrupts[..4]))
+    }
+}
+
+/// a big-endian number of one or more 32-bit cells
+fn read_cells(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, &byte| value << 8 | byte as usize)
+}
+
+/// a big-endian word of the tree
+fn be32(addr: usize) -> u32 {
+    u32::from_be(unsafe { read_unaligned(addr as *const u32) })
+}
+
+/// the nul-terminated string at `addr`, without the nul
+fn c_str(addr: usize, limit: usize) -> &'static [u8] {
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, limit) };
+    let len = bytes.iter().position(|&byte| byte == 0).unwrap_or(limit);
+    &bytes[..len]
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Fill `board` from the tree at `dtb`, `false` if it is not a valid tree.
+fn parse(dtb: usize, board: &mut Board) -> bool {
+    if dtb == 0 || dtb % 4 != 0 || be32(dtb) != FDT_MAGIC {
+        return false;
+    }
+    let total_size = be32(dtb + 4) as usize;
+    let struct_start = dtb + be32(dtb + 8) as usize;
+    let strings_start = dtb + be32(dtb + 12) as usize;
+    let end = dtb + total_size;
+    let mut nodes = [Node::default(); MAX_DEPTH];
+    // #address-cells/#size-cells of the children of the node at each depth
+    let mut cells = [(2, 1); MAX_DEPTH];
+    let mut depth = 0;
+    let (mut harts, mut virtio, mut mmio) = (0, [0; MAX_DEVICES], [(0, 0); MAX_DEVICES]);
+    let (mut virtio_len, mut mmio_len) = (0, 0);
+    // a second UART in `config` is only for machines without a tree
+    board.gdb_uart = None;
+    let mut uarts = 0;
+    let mut offset = struct_start;
+    while offset + 4 <= end {
+        let token = be32(offset);
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(offset, end - offset);
+                offset = align4(offset + name.len() + 1);
+                depth += 1;
+                if depth < MAX_DEPTH {
+                    nodes[depth] = Node {
+                        name,
+                        ..Node::default()
+                    };
+                    cells[depth] = (2, 1);
+                }
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return false;
+                }
+                if depth < MAX_DEPTH {
+                    let node = nodes[depth];
+                    let (address_cells, size_cells) = cells[depth - 1];
+                    let reg = node.first_reg(address_cells, size_cells);
+                    if node.device_type == b"cpu" {
+                        harts += 1;
+                    } else if node.device_type == b"memory" {
+                        if let Some((base, size)) = reg {
+                            board.memory_end = base + size;
+                        }
+                    } else if let Some((base, size)) = reg {
+                        let mut device = true;
+                        if node.is_compatible("riscv,plic0") || node.is_compatible("sifive,plic-1.0.0") {
+                            board.plic = base;
+                        } else if node.is_compatible("ns16550a") {
+                            let irq = node.first_interrupt();
+                            if uarts == 0 {
+                                board.uart = base;
+                                board.uart_irq = irq.unwrap_or(board.uart_irq);
+                            } else if uarts == 1 {
+                                board.gdb_uart = Some((base, irq.unwrap_or(0)));
+                            }
+                            uarts += 1;
+                        } else if node.is_compatible("virtio,mmio") {
+                            if virtio_len < MAX_DEVICES {
+                                virtio[virtio_len] = base;
+                                virtio_len += 1;
+                            }
+                        } else {
+                            device = false;
+                        }
+                        if device && mmio_len < MAX_DEVICES {
+                            mmio[mmio_len] = (base, size);
+                            mmio_len += 1;
+                        }
+                    }
+                }
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(offset) as usize;
+                let name = c_str(strings_start + be32(offset + 4) as usize, end - strings_start);
+                let value = unsafe { core::slice::from_raw_parts((offset + 8) as *const u8, len) };
+                offset = align4(offset + 8 + len);
+                if depth == 0 || depth >= MAX_DEPTH {
+                    continue;
+                }
+                let node = &mut nodes[depth];
+                match name {
+                    b"compatible" => node.compatible = value,
+                    b"device_type" => node.device_type = c_str(value.as_ptr() as usize, len),
+                    b"reg" => node.reg = value,
+                    b"interrupts" => node.interrupts = value,
+                    b"#address-cells" => cells[depth].0 = read_cells(value),
+                    b"#size-cells" => cells[depth].1 = read_cells(value),
+                    // the root node is at depth 1
+                    b"bootargs" if depth == 2 && node.name == b"chosen" => {
+                        let args = c_str(value.as_ptr() as usize, len);
+                        let len = args.len().min(MAX_BOOTARGS);
+                        board.bootargs[..len].copy_from_slice(&args[..len]);
+                        board.bootargs_len = len;
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return false,
+        }
+    }
+    if harts > 0 {
+        board.harts = harts;
+    }
+    // 树里找到了设备就只用树里的，否则保留config中的
+    if mmio_len > 0 {
+        board.mmio = mmio;
+        board.mmio_len = mmio_len;
+    }
+    if virtio_len > 0 {
+        board.virtio = virtio;
+        board.virtio_len = virtio_len;
+    }
+    true
+}
+
+/// Read the device tree at `dtb` on the boot hart, before memory management
+/// is set up, while the tree is still where the firmware put it.
+pub fn init(dtb: usize) {
+    let mut board = default_board();
+    if !parse(dtb, &mut board) {
+        board = default_board();
+        warn!("[kernel] no device tree at {:#x}, assuming the qemu virt machine", dtb);
+    }
+    info!(
+        "[kernel] RAM ends at {:#x}, {} harts, UART at {:#x} (irq {}), PLIC at {:#x}, {} virtio slots",
+        board.memory_end,
+        board.harts,
+        board.uart,
+        board.uart_irq,
+        board.plic,
+        board.virtio_len
+    );
+    BOARD.call_once(|| board);
+}
@@ -1,12 +1,30 @@
+    # every hart enters with its hart id in a0 and gets a boot stack of its
+    # own, 4096 * 16 bytes each; tp holds the hart id from then on
+    .macro SET_BOOT_STACK
+    mv tp, a0
+    addi t0, a0, 1
+    li t1, 4096 * 16
+    mul t0, t0, t1
+    la sp, boot_stack
+    add sp, sp, t0
+    .endm
+
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    SET_BOOT_STACK
     call rust_main
 
+    # secondary harts are started here through SBI HSM
+    .globl _start_secondary
+_start_secondary:
+    SET_BOOT_STACK
+    call rust_main_secondary
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    # MAX_HARTS boot stacks
+    .space 4096 * 16 * 4
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
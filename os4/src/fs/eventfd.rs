@@ -0,0 +1,80 @@
+//! eventfd: a counter behind a file descriptor
+//!
+//! Writing an 8-byte value adds it to the counter and wakes up the readers;
+//! reading takes the whole count, or 1 of it with [`EFD_SEMAPHORE`], and
+//! blocks while it is 0. A write that would overflow the counter fails with
+//! `EAGAIN` instead of waiting for a reader.
+
+use super::File;
+use crate::mm::UserBuffer;
+use crate::sync::{EventCounter, SpinLock, WaitQueue};
+use crate::timer::{Deadline, ETIMEDOUT};
+
+/// reads take 1 from the counter rather than all of it
+pub const EFD_SEMAPHORE: usize = 1;
+
+const EAGAIN: isize = -11;
+const EINVAL: isize = -22;
+
+/// An event counter readers can wait on
+pub struct EventFd {
+    counter: SpinLock<EventCounter>,
+    /// readers waiting for the counter to go above 0
+    readers: WaitQueue,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, flags: usize) -> Self {
+        Self {
+            counter: SpinLock::new(EventCounter::new(initval, flags & EFD_SEMAPHORE != 0)),
+            readers: WaitQueue::new(),
+        }
+    }
+}
+
+impl File for EventFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// Block until the counter is above 0, then take from it and store
+    /// what was taken as a `u64`.
+    fn read(&self, mut buf: UserBuffer, deadline: Deadline) -> isize {
+        if buf.len() < 8 {
+            return EINVAL;
+        }
+        loop {
+            let mut counter = self.counter.lock();
+            match counter.consume() {
+                Some(value) => {
+                    drop(counter);
+                    buf.write(&value.to_ne_bytes());
+                    return 8;
+                }
+                None if deadline.expired() => return ETIMEDOUT,
+                // a writer locks the counter before waking us, so it cannot
+                // do so before we are in the queue
+                None => self.readers.wait_until_after(deadline, || drop(counter)),
+            }
+        }
+    }
+    /// Add the `u64` in `buf` to the counter.
+    fn write(&self, buf: UserBuffer) -> isize {
+        let mut bytes = [0u8; 8];
+        if buf.read(&mut bytes) < 8 {
+            return EINVAL;
+        }
+        let value = u64::from_ne_bytes(bytes);
+        if value == u64::MAX {
+            return EINVAL;
+        }
+        if self.counter.lock().signal(value).is_err() {
+            return EAGAIN;
+        }
+        // 信号量模式下一次写可以满足多个读者
+        self.readers.wake_all();
+        8
+    }
+}
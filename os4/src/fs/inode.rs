@@ -0,0 +1,208 @@
+//! Regular files of the easy-fs image on [`BLOCK_DEVICE`]
+//!
+//! File data is read and written through the [`page_cache`], dirty pages
+//! reach the disk once the last handle of the opened file is dropped or
+//! when the page is evicted.
+//!
+//! The image is checked with [`EasyFileSystem::fsck`] when it is mounted,
+//! on first use, and what can be repaired is repaired.
+//!
+//! easy-fs guards its block cache with locks the kernel does not count, so
+//! it is only ever entered with a [`SpinLock`] held, which keeps the task
+//! from being preempted inside it.
+
+use super::File;
+use crate::drivers::BLOCK_DEVICE;
+use crate::mm::{page_cache, UserBuffer};
+use crate::sync::SpinLock;
+use crate::timer::Deadline;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::{EasyFileSystem, Inode};
+use lazy_static::*;
+
+/// A wrapper around a filesystem inode
+/// to implement File trait atop
+pub struct OSInode {
+    readable: bool,
+    writable: bool,
+    inner: SpinLock<OSInodeInner>,
+}
+
+/// Where an opened file is read and written next
+pub struct OSInodeInner {
+    offset: usize,
+    inode: Arc<Inode>,
+}
+
+impl OSInode {
+    /// Construct an OS inode from a inode
+    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+        Self {
+            readable,
+            writable,
+            inner: SpinLock::new(OSInodeInner { offset: 0, inode }),
+        }
+    }
+    /// Read all data from the current offset on into a vector
+    pub fn read_all(&self) -> Vec<u8> {
+        let mut inner = self.inner.lock();
+        let mut buffer = [0u8; 512];
+        let mut v: Vec<u8> = Vec::new();
+        loop {
+            let len = page_cache::read_at(&inner.inode, inner.offset, &mut buffer);
+            if len == 0 {
+                break;
+            }
+            inner.offset += len;
+            v.extend_from_slice(&buffer[..len]);
+        }
+        v
+    }
+    /// Write `data` from the current offset on, returning how much of it
+    /// fit on the disk
+    pub fn write_all(&self, data: &[u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let len = page_cache::write_at(&inner.inode, inner.offset, data);
+        inner.offset += len;
+        len
+    }
+}
+
+impl Drop for OSInode {
+    fn drop(&mut self) {
+        page_cache::sync(&self.inner.lock().inode);
+    }
+}
+
+lazy_static! {
+    /// The root of all inodes, or '/' in short
+    static ref ROOT_INODE: SpinLock<Arc<Inode>> = {
+        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        let report = EasyFileSystem::fsck(&efs, true);
+        for error in report.errors.iter() {
+            warn!("[kernel] fsck: {:?}", error);
+        }
+        if !report.is_clean() {
+            warn!(
+                "[kernel] fsck: {} inconsistencies, {} repaired",
+                report.errors.len(),
+                report.repaired
+            );
+        }
+        SpinLock::new(Arc::new(EasyFileSystem::root_inode(&efs)))
+    };
+}
+
+/// Names of all files in the root directory
+pub fn list_files() -> Vec<String> {
+    ROOT_INODE.lock().ls()
+}
+
+/// List all files in the filesystems
+pub fn list_apps() {
+    println!("/**** APPS ****");
+    for app in list_files() {
+        println!("{}", app);
+    }
+    println!("**************/");
+}
+
+/// Read the whole file `name`, e.g. an ELF to run
+pub fn read_file(name: &str) -> Option<Vec<u8>> {
+    open_file(name, OpenFlags::RDONLY).map(|inode| inode.read_all())
+}
+
+/// Make `data` the whole of file `name`, creating it if needed, `false` if
+/// it could not be created or did not fit
+pub fn write_file(name: &str, data: &[u8]) -> bool {
+    match open_file(name, OpenFlags::CREATE | OpenFlags::WRONLY) {
+        Some(inode) => inode.write_all(data) == data.len(),
+        None => false,
+    }
+}
+
+bitflags! {
+    /// Flags for opening files
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 9;
+        const TRUNC = 1 << 10;
+    }
+}
+
+impl OpenFlags {
+    /// Get the current read write permission on an inode
+    /// does not check validity for simplicity
+    /// returns (readable, writable)
+    pub fn read_write(&self) -> (bool, bool) {
+        if self.is_empty() {
+            (true, false)
+        } else if self.contains(Self::WRONLY) {
+            (false, true)
+        } else {
+            (true, true)
+        }
+    }
+}
+
+/// Open the file `name` in the root directory, creating it with
+/// [`OpenFlags::CREATE`]
+pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+    let (readable, writable) = flags.read_write();
+    let root_inode = ROOT_INODE.lock();
+    let inode = match root_inode.find(name) {
+        Some(inode) => {
+            if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) {
+                inode.clear();
+                page_cache::invalidate(&inode);
+            }
+            inode
+        }
+        None if flags.contains(OpenFlags::CREATE) => root_inode.create(name)?,
+        None => return None,
+    };
+    Some(Arc::new(OSInode::new(readable, writable, inode)))
+}
+
+impl File for OSInode {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, mut buf: UserBuffer, _deadline: Deadline) -> isize {
+        let mut inner = self.inner.lock();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = page_cache::read_at(&inner.inode, inner.offset, slice);
+            if read_size == 0 {
+                break;
+            }
+            inner.offset += read_size;
+            total_read_size += read_size;
+        }
+        total_read_size as isize
+    }
+    fn write(&self, buf: UserBuffer) -> isize {
+        let mut inner = self.inner.lock();
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = page_cache::write_at(&inner.inode, inner.offset, slice);
+            inner.offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                // out of data blocks
+                break;
+            }
+        }
+        total_write_size as isize
+    }
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(self.inner.lock().inode.clone())
+    }
+}
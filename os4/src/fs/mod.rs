@@ -0,0 +1,38 @@
+//! File system in the kernel
+//!
+//! Everything a process reaches through a file descriptor implements
+//! [`File`]: the console as [`Stdin`] and [`Stdout`], regular files of the
+//! easy-fs image on the block device as [`OSInode`], the two ends of a
+//! [`Pipe`], and the counter of an [`EventFd`].
+
+mod eventfd;
+mod inode;
+mod pipe;
+mod stdio;
+
+use crate::mm::UserBuffer;
+use crate::timer::Deadline;
+use alloc::sync::Arc;
+use easy_fs::Inode;
+
+/// The common abstraction of all IO resources
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    /// Read into `buf`, returning the number of bytes read or a negative
+    /// error code. A read that would block gives up with `ETIMEDOUT` once
+    /// `deadline` passes.
+    fn read(&self, buf: UserBuffer, deadline: Deadline) -> isize;
+    /// Write from `buf`, returning the number of bytes written or a
+    /// negative error code
+    fn write(&self, buf: UserBuffer) -> isize;
+    /// The inode of a regular file, e.g. to map it into memory
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
+}
+
+pub use eventfd::{EventFd, EFD_SEMAPHORE};
+pub use inode::{list_apps, list_files, open_file, read_file, write_file, OSInode, OpenFlags};
+pub use pipe::{make_pipe, Pipe};
+pub use stdio::{flush_stdout, Stdin, Stdout};
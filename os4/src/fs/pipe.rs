@@ -0,0 +1,184 @@
+//! Pipes: a ring buffer with a read end and a write end
+//!
+//! A reader blocks while the buffer is empty and a writer while it is full,
+//! each waiting on a [`WaitQueue`] of its own. Dropping one end wakes up the
+//! other side, so that readers see EOF and writers give up.
+
+use super::File;
+use crate::mm::UserBuffer;
+use crate::sync::{SpinLock, WaitQueue};
+use crate::timer::{Deadline, ETIMEDOUT};
+use alloc::sync::{Arc, Weak};
+
+/// One end of a pipe
+pub struct Pipe {
+    readable: bool,
+    writable: bool,
+    shared: Arc<PipeShared>,
+}
+
+/// What both ends of a pipe share
+struct PipeShared {
+    buffer: SpinLock<PipeRingBuffer>,
+    /// readers waiting for data
+    readers: WaitQueue,
+    /// writers waiting for space
+    writers: WaitQueue,
+}
+
+const RING_BUFFER_SIZE: usize = 32;
+
+/// The underlying ring buffer of a pipe
+struct PipeRingBuffer {
+    arr: [u8; RING_BUFFER_SIZE],
+    head: usize,
+    /// number of bytes in the buffer, from `head` on
+    len: usize,
+    read_end: Weak<Pipe>,
+    write_end: Weak<Pipe>,
+}
+
+impl PipeRingBuffer {
+    fn new() -> Self {
+        Self {
+            arr: [0; RING_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            read_end: Weak::new(),
+            write_end: Weak::new(),
+        }
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.arr[(self.head + self.len) % RING_BUFFER_SIZE] = byte;
+        self.len += 1;
+    }
+    fn read_byte(&mut self) -> u8 {
+        let byte = self.arr[self.head];
+        self.head = (self.head + 1) % RING_BUFFER_SIZE;
+        self.len -= 1;
+        byte
+    }
+    fn available_read(&self) -> usize {
+        self.len
+    }
+    fn available_write(&self) -> usize {
+        RING_BUFFER_SIZE - self.len
+    }
+    /// an end is gone once the last fd referring to it is closed
+    fn read_end_closed(&self) -> bool {
+        self.read_end.upgrade().is_none()
+    }
+    fn write_end_closed(&self) -> bool {
+        self.write_end.upgrade().is_none()
+    }
+}
+
+/// Create a pipe, returning (read_end, write_end)
+pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
+    let shared = Arc::new(PipeShared {
+        buffer: SpinLock::new(PipeRingBuffer::new()),
+        readers: WaitQueue::new(),
+        writers: WaitQueue::new(),
+    });
+    let read_end = Arc::new(Pipe {
+        readable: true,
+        writable: false,
+        shared: shared.clone(),
+    });
+    let write_end = Arc::new(Pipe {
+        readable: false,
+        writable: true,
+        shared: shared.clone(),
+    });
+    let mut buffer = shared.buffer.lock();
+    buffer.read_end = Arc::downgrade(&read_end);
+    buffer.write_end = Arc::downgrade(&write_end);
+    drop(buffer);
+    (read_end, write_end)
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    /// Block until there is something to read, then read as much as is
+    /// there. Returns 0 at EOF, when the buffer is empty and the write end
+    /// is closed, and `ETIMEDOUT` if `deadline` passes first.
+    fn read(&self, buf: UserBuffer, deadline: Deadline) -> isize {
+        assert!(self.readable);
+        let want = buf.len();
+        let mut buf_iter = buf.into_iter();
+        loop {
+            let mut ring_buffer = self.shared.buffer.lock();
+            let available = ring_buffer.available_read();
+            if available == 0 {
+                if want == 0 || ring_buffer.write_end_closed() {
+                    return 0;
+                }
+                if deadline.expired() {
+                    return ETIMEDOUT;
+                }
+                // a writer locks the buffer before waking us, so it cannot
+                // do so before we are in the queue
+                self.shared.readers.wait_until_after(deadline, || drop(ring_buffer));
+                continue;
+            }
+            let mut read_size = 0;
+            while read_size < available {
+                match buf_iter.next() {
+                    Some(byte_ref) => unsafe { *byte_ref = ring_buffer.read_byte() },
+                    None => break,
+                }
+                read_size += 1;
+            }
+            drop(ring_buffer);
+            self.shared.writers.wake_all();
+            return read_size as isize;
+        }
+    }
+    /// Block until all of `buf` is written, or return early with what was
+    /// written so far once the read end is closed.
+    fn write(&self, buf: UserBuffer) -> isize {
+        assert!(self.writable);
+        let want = buf.len();
+        let mut buf_iter = buf.into_iter();
+        let mut write_size = 0;
+        while write_size < want {
+            let mut ring_buffer = self.shared.buffer.lock();
+            if ring_buffer.read_end_closed() {
+                break;
+            }
+            let available = ring_buffer.available_write();
+            if available == 0 {
+                self.shared.writers.wait_after(|| drop(ring_buffer));
+                continue;
+            }
+            for _ in 0..available {
+                match buf_iter.next() {
+                    Some(byte_ref) => ring_buffer.write_byte(unsafe { *byte_ref }),
+                    None => break,
+                }
+                write_size += 1;
+            }
+            drop(ring_buffer);
+            self.shared.readers.wake_all();
+        }
+        write_size as isize
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // taken so that a waiter of the other side is either in its queue
+        // already, or will find this end closed
+        let _buffer = self.shared.buffer.lock();
+        if self.readable {
+            self.shared.writers.wake_all();
+        } else {
+            self.shared.readers.wake_all();
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! The console as [`File`]s
+//!
+//! Output is line buffered per thread: whole lines go straight from the
+//! user pages to the console, and what follows the last newline waits in
+//! the thread until the next one, a full buffer, a read of the console, or
+//! the thread exiting, see [`flush_stdout`].
+
+use super::File;
+use crate::console::write_bytes;
+use crate::drivers::uart;
+use crate::mm::UserBuffer;
+use crate::task::{current_task, TaskControlBlock};
+use crate::timer::{Deadline, ETIMEDOUT};
+
+/// bytes without a newline a thread holds back at most
+const STDOUT_PENDING_MAX: usize = 256;
+
+/// Write out what `task` holds back of its output.
+pub fn flush_stdout(task: &TaskControlBlock) {
+    let mut pending = task.stdout_pending.lock();
+    if !pending.is_empty() {
+        write_bytes(&[&pending[..]]);
+        pending.clear();
+    }
+}
+
+/// The standard input
+pub struct Stdin;
+/// The standard output
+pub struct Stdout;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Block until there is input, then read what there is, up to a
+    /// chunk at a time.
+    fn read(&self, mut user_buf: UserBuffer, deadline: Deadline) -> isize {
+        // a prompt without a newline has to be seen before waiting for input
+        if let Some(task) = current_task() {
+            flush_stdout(&task);
+        }
+        let mut buf = [0u8; 64];
+        let len = user_buf.len().min(buf.len());
+        match uart::read(&mut buf[..len], deadline) {
+            Some(len) => user_buf.write(&buf[..len]) as isize,
+            None => ETIMEDOUT,
+        }
+    }
+    fn write(&self, _user_buf: UserBuffer) -> isize {
+        0
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _user_buf: UserBuffer, _deadline: Deadline) -> isize {
+        0
+    }
+    fn write(&self, user_buf: UserBuffer) -> isize {
+        let task = match current_task() {
+            Some(task) => task,
+            None => {
+                for buffer in user_buf.buffers.iter() {
+                    write_bytes(&[&buffer[..]]);
+                }
+                return user_buf.len() as isize;
+            }
+        };
+        let mut pending = task.stdout_pending.lock();
+        for buffer in user_buf.buffers.iter() {
+            match buffer.iter().rposition(|&byte| byte == b'\n') {
+                Some(last_newline) => {
+                    let (lines, rest) = buffer.split_at(last_newline + 1);
+                    write_bytes(&[&pending[..], lines]);
+                    pending.clear();
+                    pending.extend_from_slice(rest);
+                }
+                None => pending.extend_from_slice(buffer),
+            }
+            if pending.len() >= STDOUT_PENDING_MAX {
+                write_bytes(&[&pending[..]]);
+                pending.clear();
+            }
+        }
+        user_buf.len() as isize
+    }
+}
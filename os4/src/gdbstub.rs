@@ -0,0 +1,690 @@
+//! A gdb remote-protocol stub for debugging the kernel
+//!
+//! Built with the `gdbstub` feature, it talks to gdb over the second UART of
+//! the machine, see [`Board::gdb_uart`](crate::dtb::Board::gdb_uart), with
+//! `target remote` on whatever that UART is connected to. The kernel stops
+//! for gdb once at boot, then on a breakpoint, after a single step and when
+//! gdb sends a Ctrl-C.
+//!
+//! The stub runs in the trap taken for an `ebreak` in the kernel, on the
+//! registers `__kernelbreak` saved. Memory is read through the page table
+//! in `satp` and only where it maps RAM. Software breakpoints put a
+//! `c.ebreak` over the instruction, written with paging turned off for a
+//! moment as the kernel text is mapped read-only, and a single step places
+//! temporary ones wherever the instruction may go next.
+//!
+//! Other harts keep running while one is stopped, unless they hit a
+//! breakpoint as well and wait for their turn; boot a single hart when that
+//! matters. sp and tp cannot be changed.
+
+use crate::dtb::board;
+use crate::drivers::uart::{init_port, poll_read, poll_write};
+use crate::hart::hart_id;
+use crate::mm::translated_phys_addr;
+use crate::sbi::remote_fence_i;
+use crate::sync::SpinNoIrq;
+use crate::trap::KernelBreakFrame;
+use core::arch::asm;
+use core::ptr::read_volatile;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::satp;
+
+/// packets longer than this are dropped, announced to gdb as `PacketSize`
+const BUFFER_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+const C_EBREAK: u16 = 0x9002;
+const EBREAK: u32 = 0x0010_0073;
+const SSTATUS_SPIE: usize = 1 << 5;
+/// what gdb sends to stop the kernel while it runs
+const CTRL_C: u8 = 0x03;
+
+/// the registers of `g` in order, x0 to x31 then pc
+const TARGET_XML: &[u8] = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+    r#"<target version="1.0"><architecture>riscv:rv64</architecture>"#,
+    r#"<feature name="org.gnu.gdb.riscv.cpu">"#,
+    r#"<reg name="zero" bitsize="64" type="int"/><reg name="ra" bitsize="64" type="code_ptr"/>"#,
+    r#"<reg name="sp" bitsize="64" type="data_ptr"/><reg name="gp" bitsize="64" type="data_ptr"/>"#,
+    r#"<reg name="tp" bitsize="64" type="data_ptr"/><reg name="t0" bitsize="64" type="int"/>"#,
+    r#"<reg name="t1" bitsize="64" type="int"/><reg name="t2" bitsize="64" type="int"/>"#,
+    r#"<reg name="fp" bitsize="64" type="data_ptr"/><reg name="s1" bitsize="64" type="int"/>"#,
+    r#"<reg name="a0" bitsize="64" type="int"/><reg name="a1" bitsize="64" type="int"/>"#,
+    r#"<reg name="a2" bitsize="64" type="int"/><reg name="a3" bitsize="64" type="int"/>"#,
+    r#"<reg name="a4" bitsize="64" type="int"/><reg name="a5" bitsize="64" type="int"/>"#,
+    r#"<reg name="a6" bitsize="64" type="int"/><reg name="a7" bitsize="64" type="int"/>"#,
+    r#"<reg name="s2" bitsize="64" type="int"/><reg name="s3" bitsize="64" type="int"/>"#,
+    r#"<reg name="s4" bitsize="64" type="int"/><reg name="s5" bitsize="64" type="int"/>"#,
+    r#"<reg name="s6" bitsize="64" type="int"/><reg name="s7" bitsize="64" type="int"/>"#,
+    r#"<reg name="s8" bitsize="64" type="int"/><reg name="s9" bitsize="64" type="int"/>"#,
+    r#"<reg name="s10" bitsize="64" type="int"/><reg name="s11" bitsize="64" type="int"/>"#,
+    r#"<reg name="t3" bitsize="64" type="int"/><reg name="t4" bitsize="64" type="int"/>"#,
+    r#"<reg name="t5" bitsize="64" type="int"/><reg name="t6" bitsize="64" type="int"/>"#,
+    r#"<reg name="pc" bitsize="64" type="code_ptr"/>"#,
+    r#"</feature></target>"#,
+)
+.as_bytes();
+
+fn uart() -> usize {
+    board().gdb_uart.unwrap().0
+}
+
+fn getc() -> u8 {
+    loop {
+        if let Some(byte) = poll_read(uart()) {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn putc(byte: u8) {
+    poll_write(uart(), byte);
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0xf) as usize]
+}
+
+fn from_hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// the hex number at the start of `bytes` and what follows it
+fn parse_hex(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    let len = bytes.iter().take_while(|&&c| from_hex_digit(c).is_some()).count();
+    if len == 0 || len > 16 {
+        return None;
+    }
+    let value = bytes[..len]
+        .iter()
+        .fold(0, |value, &c| value << 4 | from_hex_digit(c).unwrap() as usize);
+    Some((value, &bytes[len..]))
+}
+
+/// `addr,len` and what follows it
+fn parse_range(bytes: &[u8]) -> Option<(usize, usize, &[u8])> {
+    let (addr, rest) = parse_hex(bytes)?;
+    let (len, rest) = parse_hex(rest.strip_prefix(b",")?)?;
+    Some((addr, len, rest))
+}
+
+/// a byte as two hex digits
+fn parse_byte(bytes: &[u8]) -> Option<u8> {
+    Some(from_hex_digit(*bytes.first()?)? << 4 | from_hex_digit(*bytes.get(1)?)?)
+}
+
+/// a register in target byte order, 16 hex digits
+fn parse_word(bytes: &[u8]) -> Option<usize> {
+    (0..8).try_fold(0, |value, i| {
+        let byte = parse_byte(bytes.get(i * 2..)?)?;
+        Some(value | (byte as usize) << (i * 8))
+    })
+}
+
+fn sext(value: usize, bits: u32) -> usize {
+    let shift = 64 - bits;
+    (((value << shift) as isize) >> shift) as usize
+}
+
+fn bit(inst: usize, n: usize) -> usize {
+    (inst >> n) & 1
+}
+
+/// the physical address of kernel address `va`, if it maps to the RAM the
+/// kernel itself maps
+fn phys(va: usize) -> Option<usize> {
+    extern "C" {
+        fn skernel();
+    }
+    let pa = translated_phys_addr(satp::read().bits(), va)?;
+    (skernel as usize..board().memory_end).contains(&pa).then(|| pa)
+}
+
+fn read_byte(va: usize) -> Option<u8> {
+    phys(va).map(|pa| unsafe { read_volatile(pa as *const u8) })
+}
+
+/// Write `byte` at `va` with paging off, which also reaches the kernel
+/// text. Everything until paging is back on stays in registers.
+fn write_byte(va: usize, byte: u8) -> bool {
+    let pa = match phys(va) {
+        Some(pa) => pa,
+        None => return false,
+    };
+    unsafe {
+        asm!(
+            "csrw satp, zero",
+            "sfence.vma",
+            "sb {byte}, 0({pa})",
+            "csrw satp, {satp}",
+            "sfence.vma",
+            byte = in(reg) byte,
+            pa = in(reg) pa,
+            satp = in(reg) satp::read().bits(),
+            options(nostack),
+        );
+    }
+    true
+}
+
+fn read_u16(va: usize) -> Option<u16> {
+    Some(read_byte(va)? as u16 | (read_byte(va + 1)? as u16) << 8)
+}
+
+fn write_u16(va: usize, value: u16) -> bool {
+    write_byte(va, value as u8) && write_byte(va + 1, (value >> 8) as u8)
+}
+
+/// Let every hart fetch the instructions written.
+fn sync_icache() {
+    unsafe {
+        asm!("fence.i");
+    }
+    remote_fence_i();
+}
+
+/// the length of the `ebreak` at `pc`, if there is one
+fn ebreak_len(pc: usize) -> Option<usize> {
+    let low = read_u16(pc)?;
+    if low == C_EBREAK {
+        return Some(2);
+    }
+    let high = read_u16(pc + 2)?;
+    (low as u32 | (high as u32) << 16 == EBREAK).then(|| 4)
+}
+
+/// Where the instruction at `cx.sepc` may go, the fall-through first.
+fn next_pcs(cx: &KernelBreakFrame) -> Option<[Option<usize>; 2]> {
+    let pc = cx.sepc;
+    let reg = |n: usize| if n == 0 { 0 } else { cx.x[n] };
+    let inst = read_u16(pc)? as usize;
+    if inst & 0b11 != 0b11 {
+        let rs1 = (inst >> 7) & 0x1f;
+        return Some(match (inst & 0b11, inst >> 13) {
+            // c.j
+            (0b01, 0b101) => {
+                let offset = bit(inst, 12) << 11
+                    | bit(inst, 11) << 4
+                    | ((inst >> 9) & 0b11) << 8
+                    | bit(inst, 8) << 10
+                    | bit(inst, 7) << 6
+                    | bit(inst, 6) << 7
+                    | ((inst >> 3) & 0b111) << 1
+                    | bit(inst, 2) << 5;
+                [Some(pc.wrapping_add(sext(offset, 12))), None]
+            }
+            // c.beqz, c.bnez
+            (0b01, 0b110 | 0b111) => {
+                let offset = bit(inst, 12) << 8
+                    | ((inst >> 10) & 0b11) << 3
+                    | ((inst >> 5) & 0b11) << 6
+                    | ((inst >> 3) & 0b11) << 1
+                    | bit(inst, 2) << 5;
+                [Some(pc + 2), Some(pc.wrapping_add(sext(offset, 9)))]
+            }
+            // c.jr, c.jalr
+            (0b10, 0b100) if inst & 0x7c == 0 && rs1 != 0 => [Some(reg(rs1) & !1), None],
+            _ => [Some(pc + 2), None],
+        });
+    }
+    let inst = inst | (read_u16(pc + 2)? as usize) << 16;
+    Some(match inst & 0x7f {
+        // jal
+        0x6f => {
+            let offset = bit(inst, 31) << 20
+                | ((inst >> 21) & 0x3ff) << 1
+                | bit(inst, 20) << 11
+                | ((inst >> 12) & 0xff) << 12;
+            [Some(pc.wrapping_add(sext(offset, 21))), None]
+        }
+        // jalr
+        0x67 => {
+            let offset = sext((inst >> 20) & 0xfff, 12);
+            [Some(reg((inst >> 15) & 0x1f).wrapping_add(offset) & !1), None]
+        }
+        // branches
+        0x63 => {
+            let offset = bit(inst, 31) << 12
+                | ((inst >> 25) & 0x3f) << 5
+                | ((inst >> 8) & 0xf) << 1
+                | bit(inst, 7) << 11;
+            [Some(pc + 4), Some(pc.wrapping_add(sext(offset, 13)))]
+        }
+        _ => [Some(pc + 4), None],
+    })
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    /// the instruction bytes the `c.ebreak` replaced
+    saved: u16,
+}
+
+impl Breakpoint {
+    fn insert(addr: usize) -> Option<Self> {
+        if addr % 2 != 0 {
+            return None;
+        }
+        let saved = read_u16(addr)?;
+        write_u16(addr, C_EBREAK).then(|| Self { addr, saved })
+    }
+    fn remove(self) {
+        write_u16(self.addr, self.saved);
+    }
+}
+
+/// The reply being put together
+struct Reply {
+    buf: [u8; BUFFER_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len < BUFFER_SIZE {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+        }
+    }
+    fn push_byte(&mut self, byte: u8) {
+        self.push(&[hex_digit(byte >> 4), hex_digit(byte)]);
+    }
+    fn push_word(&mut self, word: usize) {
+        for i in 0..8 {
+            self.push_byte((word >> (i * 8)) as u8);
+        }
+    }
+    /// Send the reply until gdb acknowledges it.
+    fn send(&mut self) {
+        loop {
+            putc(b'$');
+            let mut sum = 0u8;
+            for &byte in &self.buf[..self.len] {
+                putc(byte);
+                sum = sum.wrapping_add(byte);
+            }
+            putc(b'#');
+            putc(hex_digit(sum >> 4));
+            putc(hex_digit(sum));
+            loop {
+                match getc() {
+                    b'+' => {
+                        self.len = 0;
+                        return;
+                    }
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// what to do after a command
+enum Action {
+    Reply,
+    Continue,
+    Step,
+    /// reply, then go on without breakpoints
+    Detach,
+}
+
+struct Session {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// temporary breakpoints of the single step going on
+    step: [Option<Breakpoint>; 2],
+    /// the hart stepping, `usize::MAX` for none
+    step_hart: usize,
+    /// `sstatus.SPIE` of the stepping code, kept off during the step
+    step_spie: bool,
+    /// gdb waits for a stop reply, after `c` or `s`
+    resumed: bool,
+    reply: Reply,
+}
+
+impl Session {
+    fn is_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.iter().flatten().any(|bp| bp.addr == addr)
+    }
+    fn is_step_breakpoint(&self, addr: usize) -> bool {
+        self.step.iter().flatten().any(|bp| bp.addr == addr)
+    }
+    fn insert_breakpoint(&mut self, addr: usize) -> bool {
+        if self.is_breakpoint(addr) {
+            return true;
+        }
+        let bp = self.breakpoints.iter().position(|slot| slot.is_none()).and_then(|slot| {
+            let bp = Breakpoint::insert(addr)?;
+            self.breakpoints[slot] = Some(bp);
+            Some(bp)
+        });
+        sync_icache();
+        bp.is_some()
+    }
+    fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        let slot = self.breakpoints.iter_mut().find(|slot| slot.map_or(false, |bp| bp.addr == addr));
+        match slot.and_then(|slot| slot.take()) {
+            Some(bp) => {
+                bp.remove();
+                sync_icache();
+                true
+            }
+            None => false,
+        }
+    }
+    /// Put temporary breakpoints where the instruction at `cx.sepc` may go.
+    fn start_step(&mut self, cx: &mut KernelBreakFrame) -> bool {
+        let targets = match next_pcs(cx) {
+            Some(targets) => targets,
+            None => return false,
+        };
+        for (slot, &target) in targets.iter().enumerate() {
+            // a branch to the next instruction has only one place to go
+            let duplicate = slot == 1 && target == targets[0];
+            let bp = target
+                .filter(|&addr| !duplicate && !self.is_breakpoint(addr))
+                .and_then(Breakpoint::insert);
+            self.step[slot] = bp;
+        }
+        sync_icache();
+        self.step_hart = hart_id();
+        // 单步期间不让中断插进来
+        self.step_spie = cx.sstatus & SSTATUS_SPIE != 0;
+        cx.sstatus &= !SSTATUS_SPIE;
+        true
+    }
+    fn finish_step(&mut self, cx: &mut KernelBreakFrame) {
+        for bp in self.step.iter_mut().filter_map(|slot| slot.take()) {
+            bp.remove();
+        }
+        sync_icache();
+        self.step_hart = usize::MAX;
+        if self.step_spie {
+            cx.sstatus |= SSTATUS_SPIE;
+        }
+    }
+    /// Act on one command, `None` for a malformed one.
+    fn command(&mut self, cx: &mut KernelBreakFrame, cmd: &[u8]) -> Option<Action> {
+        let reg = |n: usize| match n {
+            0 => Some(0),
+            1..=31 => Some(cx.x[n]),
+            32 => Some(cx.sepc),
+            _ => None,
+        };
+        let (&kind, args) = cmd.split_first()?;
+        match kind {
+            b'?' => self.reply.push(b"S05"),
+            b'g' => {
+                for n in 0..=32 {
+                    self.reply.push_word(reg(n).unwrap());
+                }
+            }
+            b'G' => {
+                for n in 0..=32 {
+                    set_reg(cx, n, parse_word(args.get(n * 16..)?)?);
+                }
+                self.reply.push(b"OK");
+            }
+            b'p' => {
+                let (n, _) = parse_hex(args)?;
+                self.reply.push_word(reg(n)?);
+            }
+            b'P' => {
+                let (n, rest) = parse_hex(args)?;
+                let value = parse_word(rest.strip_prefix(b"=")?)?;
+                if n > 32 {
+                    return None;
+                }
+                set_reg(cx, n, value);
+                self.reply.push(b"OK");
+            }
+            b'm' => {
+                let (addr, len, _) = parse_range(args)?;
+                for i in 0..len.min(BUFFER_SIZE / 2) {
+                    match read_byte(addr.wrapping_add(i)) {
+                        Some(byte) => self.reply.push_byte(byte),
+                        None if i == 0 => self.reply.push(b"E14"),
+                        None => break,
+                    }
+                }
+            }
+            b'M' => {
+                let (addr, len, rest) = parse_range(args)?;
+                let data = rest.strip_prefix(b":")?;
+                let written = (0..len).all(|i| {
+                    parse_byte(data.get(i * 2..).unwrap_or(&[]))
+                        .map_or(false, |byte| write_byte(addr.wrapping_add(i), byte))
+                });
+                sync_icache();
+                self.reply.push(if written { b"OK" } else { b"E14" });
+            }
+            b'Z' | b'z' => {
+                // only software breakpoints, `kind` is ignored as they
+                // always take two bytes
+                let (addr, _, _) = parse_range(args.strip_prefix(b"0,")?)?;
+                let done = if kind == b'Z' {
+                    self.insert_breakpoint(addr)
+                } else {
+                    self.remove_breakpoint(addr)
+                };
+                self.reply.push(if done { b"OK" } else { b"E01" });
+            }
+            b'c' | b's' => {
+                if let Some((addr, _)) = parse_hex(args) {
+                    cx.sepc = addr;
+                }
+                return Some(if kind == b'c' { Action::Continue } else { Action::Step });
+            }
+            b'D' => {
+                self.reply.push(b"OK");
+                return Some(Action::Detach);
+            }
+            // a kernel cannot be killed, keep it running
+            b'k' => return Some(Action::Detach),
+            b'H' => self.reply.push(b"OK"),
+            b'q' => {
+                if cmd.starts_with(b"qSupported") {
+                    self.reply.push(b"PacketSize=1000;qXfer:features:read+");
+                } else if cmd == b"qAttached" {
+                    self.reply.push(b"1");
+                } else if let Some(rest) = cmd.strip_prefix(b"qXfer:features:read:target.xml:") {
+                    let (offset, len, _) = parse_range(rest)?;
+                    let chunk = TARGET_XML.get(offset..).unwrap_or(&[]);
+                    let chunk = &chunk[..chunk.len().min(len).min(BUFFER_SIZE - 1)];
+                    let last = offset + chunk.len() >= TARGET_XML.len();
+                    self.reply.push(if last { b"l" } else { b"m" });
+                    self.reply.push(chunk);
+                }
+            }
+            // anything else is not supported, which is an empty reply
+            _ => {}
+        }
+        Some(Action::Reply)
+    }
+}
+
+/// Set register `n` of `x0..x31, pc`, leaving alone x0 and the sp and tp
+/// `__kernelbreak` does not restore.
+fn set_reg(cx: &mut KernelBreakFrame, n: usize, value: usize) {
+    match n {
+        0 | 2 | 4 => {}
+        1..=31 => cx.x[n] = value,
+        _ => cx.sepc = value,
+    }
+}
+
+struct Stub {
+    packet: [u8; BUFFER_SIZE],
+    session: Session,
+}
+
+/// Wait for a packet with a good checksum and acknowledge it, returning its
+/// length in `packet`.
+fn receive(packet: &mut [u8; BUFFER_SIZE]) -> usize {
+    loop {
+        while getc() != b'$' {}
+        let (mut len, mut sum) = (0, 0u8);
+        loop {
+            match getc() {
+                b'#' => break,
+                // gdb gave up on the packet and started another
+                b'$' => (len, sum) = (0, 0),
+                byte => {
+                    if len < BUFFER_SIZE {
+                        packet[len] = byte;
+                    }
+                    len += 1;
+                    sum = sum.wrapping_add(byte);
+                }
+            }
+        }
+        let checksum = [getc(), getc()];
+        if len <= BUFFER_SIZE && parse_byte(&checksum) == Some(sum) {
+            putc(b'+');
+            return len;
+        }
+        putc(b'-');
+    }
+}
+
+static STUB: SpinNoIrq<Stub> = SpinNoIrq::new(Stub {
+    packet: [0; BUFFER_SIZE],
+    session: Session {
+        breakpoints: [None; MAX_BREAKPOINTS],
+        step: [None; 2],
+        step_hart: usize::MAX,
+        step_spie: false,
+        resumed: false,
+        reply: Reply {
+            buf: [0; BUFFER_SIZE],
+            len: 0,
+        },
+    },
+});
+/// the hart in the stub, `usize::MAX` for none
+static STUB_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+impl Stub {
+    /// Report a stop to gdb and serve it until it lets the kernel go on.
+    fn stop(&mut self, cx: &mut KernelBreakFrame) {
+        let session = &mut self.session;
+        let ebreak = match ebreak_len(cx.sepc) {
+            Some(len) => len,
+            // the breakpoint is gone already, run what it replaced
+            None => return,
+        };
+        if session.step_hart != usize::MAX {
+            if session.step_hart == hart_id() {
+                session.finish_step(cx);
+            } else if session.is_step_breakpoint(cx.sepc) {
+                // not our step, come back once it is over
+                return;
+            }
+        } else if !session.is_breakpoint(cx.sepc) {
+            // an `ebreak` of the kernel itself, such as `breakpoint()`
+            cx.sepc += ebreak;
+        }
+        if session.resumed {
+            session.resumed = false;
+            session.reply.push(b"S05");
+            session.reply.send();
+        }
+        loop {
+            let len = receive(&mut self.packet);
+            let cmd = &self.packet[..len];
+            match session.command(cx, cmd) {
+                Some(Action::Reply) => session.reply.send(),
+                None => {
+                    session.reply.len = 0;
+                    session.reply.push(b"E01");
+                    session.reply.send();
+                }
+                Some(Action::Continue) => {
+                    session.resumed = true;
+                    return;
+                }
+                Some(Action::Step) => {
+                    if session.start_step(cx) {
+                        session.resumed = true;
+                        return;
+                    }
+                    // nowhere to step to, report a stop right away
+                    session.reply.push(b"S05");
+                    session.reply.send();
+                }
+                Some(Action::Detach) => {
+                    // `k` wants no reply
+                    if session.reply.len > 0 {
+                        session.reply.send();
+                    }
+                    for bp in session.breakpoints.iter_mut().filter_map(|slot| slot.take()) {
+                        bp.remove();
+                    }
+                    sync_icache();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Serve gdb for the `ebreak` `cx` stopped at, called by
+/// [`kernel_breakpoint`](crate::trap::kernel_breakpoint).
+pub fn handle_break(cx: &mut KernelBreakFrame) {
+    if board().gdb_uart.is_none() {
+        panic!("a trap Breakpoint from kernel, sepc = {:#x}!", cx.sepc);
+    }
+    // another hart stopped waits until gdb lets the first one go
+    let mut stub = loop {
+        if let Some(stub) = STUB.try_lock() {
+            break stub;
+        }
+        if STUB_HART.load(Ordering::Acquire) == hart_id() {
+            panic!("a breakpoint inside the gdb stub, sepc = {:#x}!", cx.sepc);
+        }
+        core::hint::spin_loop();
+    };
+    STUB_HART.store(hart_id(), Ordering::Release);
+    stub.stop(cx);
+    STUB_HART.store(usize::MAX, Ordering::Release);
+}
+
+/// Stop here for gdb.
+pub fn breakpoint() {
+    unsafe {
+        asm!("ebreak");
+    }
+}
+
+/// Set up the UART of the stub and wait for gdb to attach, on the boot hart
+/// after the drivers.
+pub fn init() {
+    match board().gdb_uart {
+        Some((base, _)) => {
+            init_port(base);
+            info!("[kernel] waiting for gdb on the UART at {:#x}", base);
+            breakpoint();
+        }
+        None => warn!("[kernel] no second UART for the gdb stub"),
+    }
+}
+
+/// Stop for gdb on a Ctrl-C from the UART of the stub, anything else sent
+/// while the kernel runs is dropped.
+pub fn handle_irq() {
+    let mut stop = false;
+    while let Some(byte) = poll_read(uart()) {
+        stop |= byte == CTRL_C;
+    }
+    if stop {
+        breakpoint();
+    }
+}
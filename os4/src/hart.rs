@@ -0,0 +1,64 @@
+//! Hart identification and secondary hart bring-up
+//!
+//! Every hart keeps its id in `tp` while in the kernel. User code may use
+//! `tp` as it likes, so the trampoline saves the user value in the trap
+//! context and puts the hart id back on every trap.
+
+use crate::boot_params::{boot_params, ReplayMode};
+use crate::config::MAX_HARTS;
+use crate::dtb::board;
+use crate::sbi::{hart_start, hart_status, has_hsm, HartState};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// harts running the kernel so far
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Id of the hart we are running on
+#[inline(always)]
+pub fn hart_id() -> usize {
+    let id;
+    unsafe {
+        core::arch::asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Count the current hart as online.
+pub fn mark_online() {
+    ONLINE_HARTS.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Number of harts running the kernel
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::Acquire)
+}
+
+/// Ask SBI to start every other hart the device tree lists at
+/// `_start_secondary`. Harts SBI does not know of, or that are not stopped,
+/// are skipped; without the HSM extension the kernel runs on one hart.
+pub fn start_secondary_harts() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    if boot_params().replay != ReplayMode::Off {
+        // 多个hart并行时调度顺序无法复现
+        info!("[kernel] schedreplay runs on the boot hart only");
+        return;
+    }
+    if !has_hsm() {
+        warn!("[kernel] no SBI HSM extension, running on the boot hart only");
+        return;
+    }
+    let boot_hart = hart_id();
+    let harts = board().harts.min(MAX_HARTS);
+    for hartid in (0..harts).filter(|&hartid| hartid != boot_hart) {
+        match hart_status(hartid) {
+            Ok(HartState::Stopped) => match hart_start(hartid, _start_secondary as usize, 0) {
+                Ok(()) => info!("[kernel] starting hart {}", hartid),
+                Err(error) => warn!("[kernel] hart {} failed to start: {}", hartid, error),
+            },
+            Ok(state) => warn!("[kernel] hart {} is {:?}, not starting it", hartid, state),
+            Err(_) => {}
+        }
+    }
+}
@@ -1,5 +1,23 @@
-use crate::sbi::shutdown;
+//! The panic handler
+//!
+//! Besides the message it prints a backtrace, walked along the frame
+//! pointers the kernel is built with, the trap CSRs and the current task.
+//! Return addresses are not symbolized, feed them to
+//! `rust-addr2line -e target/riscv64gc-unknown-none-elf/release/os`.
+
+use crate::config::{kernel_stack_guard_owner, TRAMPOLINE};
+use crate::dtb::board;
+use crate::sbi::shutdown_with;
+use crate::task::dump_current_task;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use riscv::register::{scause, sepc, stval};
+
+/// frames printed at most
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// set by the first panic, the dumps may panic again themselves
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
@@ -13,5 +31,70 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("[kernel] Panicked: {}", info.message().unwrap());
     }
-    shutdown()
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        // 第二次 panic 多半是打印现场时出的错，或者另一个核也 panic 了
+        exit()
+    }
+    backtrace();
+    println!(
+        "[kernel] last trap on hart {}: scause = {:?}, sepc = {:#x}, stval = {:#x}",
+        crate::hart::hart_id(),
+        scause::read().cause(),
+        sepc::read(),
+        stval::read()
+    );
+    dump_current_task();
+    exit()
+}
+
+/// Any panic fails the board tests, while the kernel otherwise ends with
+/// one when all apps are done.
+fn exit() -> ! {
+    shutdown_with(cfg!(feature = "board_test"))
+}
+
+/// Whether `fp` and the two words below it are on one of the kernel stacks
+fn on_kernel_stack(fp: usize) -> bool {
+    extern "C" {
+        fn boot_stack();
+        fn boot_stack_top();
+        fn kernel_trap_stack();
+        fn kernel_trap_stack_top();
+    }
+    let in_range = |start: usize, end: usize| fp >= start + 16 && fp <= end;
+    in_range(boot_stack as usize, boot_stack_top as usize)
+        || in_range(kernel_trap_stack as usize, kernel_trap_stack_top as usize)
+        || fp >= board().memory_end
+            && fp < TRAMPOLINE
+            && kernel_stack_guard_owner(fp - 16).is_none()
+            && kernel_stack_guard_owner(fp - 1).is_none()
+}
+
+/// Print the return addresses of the frames on the stack.
+///
+/// Each frame keeps the return address at `fp - 8` and the caller's frame
+/// pointer at `fp - 16`. The walk stops at the first frame pointer off the
+/// kernel stacks or a return address outside the kernel text.
+fn backtrace() {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    let mut fp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+    }
+    println!("[kernel] backtrace:");
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        if fp % 8 != 0 || !on_kernel_stack(fp) {
+            return;
+        }
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if ra < stext as usize || ra >= etext as usize {
+            return;
+        }
+        println!("  #{:<2} ra = {:#x}, fp = {:#x}", depth, ra, fp);
+        fp = prev_fp;
+    }
+    println!("  ...");
 }
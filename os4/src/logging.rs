@@ -1,15 +1,95 @@
+//! Kernel logging through the `log` crate
+//!
+//! The level comes from `LOG` at build time and defaults to off. `LOG_FILTER`
+//! tunes single modules on top of it, as a comma-separated list of
+//! `module=level`, e.g. `LOG=INFO LOG_FILTER=mm=debug,trap::context=trace`.
+//! A module is named by its path in the kernel, and also covers its
+//! submodules; the longest matching name wins. An entry without a module
+//! sets the default level instead.
+//!
+//! Both can be changed at run time with [`set_level`] and
+//! [`set_module_level`], the default level also by `loglevel=` on the
+//! kernel command line, see [`boot_params`](crate::boot_params).
+
+use crate::hart::hart_id;
+use crate::sync::SpinNoIrq;
 use log::{self, Level, LevelFilter, Log, Metadata, Record};
 
-struct SimpleLogger;
+/// modules with a level of their own at most
+const MAX_FILTERS: usize = 16;
+
+struct Filters {
+    default: LevelFilter,
+    modules: [(&'static str, LevelFilter); MAX_FILTERS],
+    len: usize,
+}
 
-impl Log for SimpleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
+impl Filters {
+    /// The level of the module at `path`, relative to the kernel crate
+    fn level_of(&self, path: &str) -> LevelFilter {
+        let mut level = self.default;
+        let mut matched = 0;
+        for &(module, module_level) in self.modules[..self.len].iter() {
+            let covers = path == module
+                || path.starts_with(module) && path[module.len()..].starts_with("::");
+            if covers && module.len() >= matched {
+                level = module_level;
+                matched = module.len();
+            }
+        }
+        level
+    }
+    /// Returns false if there is no room for another module.
+    fn set(&mut self, module: &'static str, level: LevelFilter) -> bool {
+        if let Some(entry) = self.modules[..self.len]
+            .iter_mut()
+            .find(|(name, _)| *name == module)
+        {
+            entry.1 = level;
+        } else if self.len < MAX_FILTERS {
+            self.modules[self.len] = (module, level);
+            self.len += 1;
+        } else {
+            return false;
+        }
         true
     }
+    /// the most verbose level of any module, which `log` lets through
+    fn max_level(&self) -> LevelFilter {
+        self.modules[..self.len]
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+static FILTERS: SpinNoIrq<Filters> = SpinNoIrq::new(Filters {
+    default: LevelFilter::Off,
+    modules: [("", LevelFilter::Off); MAX_FILTERS],
+    len: 0,
+});
+
+/// `os::mm::memory_set` is `mm::memory_set`
+fn kernel_path<'a>(record: &Record<'a>) -> &'a str {
+    let path = record.module_path().unwrap_or_else(|| record.target());
+    path.strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::"))
+        .unwrap_or(path)
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
     fn log(&self, record: &Record) {
         if !self.enabled(record.metadata()) {
             return;
         }
+        let path = kernel_path(record);
+        if record.level() > FILTERS.lock().level_of(path) {
+            return;
+        }
         let color = match record.level() {
             Level::Error => 31, // Red
             Level::Warn => 93,  // BrightYellow
@@ -18,24 +98,69 @@ impl Log for SimpleLogger {
             Level::Trace => 90, // BrightBlack
         };
         println!(
-            "\u{1B}[{}m[{:>5}] {}\u{1B}[0m",
+            "\u{1B}[{}m[{:>5}][{}] {}: {}\u{1B}[0m",
             color,
             record.level(),
+            hart_id(),
+            path,
             record.args(),
         );
     }
     fn flush(&self) {}
 }
 
+fn parse_level(level: &str) -> Option<LevelFilter> {
+    level.trim().parse().ok()
+}
+
 pub fn init() {
-    static LOGGER: SimpleLogger = SimpleLogger;
+    static LOGGER: KernelLogger = KernelLogger;
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        _ => LevelFilter::Off,
-    });
+    let mut filters = FILTERS.lock();
+    filters.default = option_env!("LOG")
+        .and_then(parse_level)
+        .unwrap_or(LevelFilter::Off);
+    // 持锁时不能打日志，出错的项留到最后再报
+    let mut bad_entry = None;
+    for entry in option_env!("LOG_FILTER")
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        match entry.split_once('=') {
+            Some((module, level)) => match parse_level(level) {
+                Some(level) if filters.set(module.trim(), level) => {}
+                _ => bad_entry = Some(entry),
+            },
+            None => match parse_level(entry) {
+                Some(level) => filters.default = level,
+                None => bad_entry = Some(entry),
+            },
+        }
+    }
+    log::set_max_level(filters.max_level());
+    drop(filters);
+    if let Some(entry) = bad_entry {
+        warn!("[kernel] bad LOG_FILTER entry {}", entry);
+    }
+}
+
+/// Set the level of modules without one of their own.
+pub fn set_level(level: LevelFilter) {
+    let mut filters = FILTERS.lock();
+    filters.default = level;
+    log::set_max_level(filters.max_level());
+}
+
+/// Set the level of `module` and its submodules, e.g. `"mm"`.
+#[allow(unused)]
+pub fn set_module_level(module: &'static str, level: LevelFilter) {
+    let mut filters = FILTERS.lock();
+    let added = filters.set(module, level);
+    log::set_max_level(filters.max_level());
+    drop(filters);
+    if !added {
+        warn!("[kernel] too many log filters, {} ignored", module);
+    }
 }
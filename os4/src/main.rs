@@ -6,14 +6,16 @@
 //! - [`trap`]: Handles all cases of switching from userspace to the kernel
 //! - [`task`]: Task management
 //! - [`syscall`]: System call handling and implementation
+//! - [`fs`]: Files, including the user apps loaded from the block device
 //!
 //! The operating system also starts in this module. Kernel code starts
 //! executing from `entry.asm`, after which [`rust_main()`] is called to
 //! initialize various pieces of functionality. (See its source code for
 //! details.)
 //!
-//! We then call [`task::run_first_task()`] and for the first time go to
-//! userspace.
+//! We then call [`task::run_tasks()`] and for the first time go to
+//! userspace. Other harts are started once the boot hart is done with the
+//! global initialization, and join it in [`rust_main_secondary()`].
 
 #![no_std]
 #![no_main]
@@ -29,9 +31,17 @@ extern crate alloc;
 
 #[macro_use]
 mod console;
+#[cfg(feature = "board_test")]
+mod board_test;
+mod boot_params;
 mod config;
+mod drivers;
+mod dtb;
+mod fs;
+#[cfg(feature = "gdbstub")]
+mod gdbstub;
+mod hart;
 mod lang_items;
-mod loader;
 mod logging;
 mod mm;
 mod sbi;
@@ -42,7 +52,6 @@ mod timer;
 mod trap;
 
 core::arch::global_asm!(include_str!("entry.asm"));
-core::arch::global_asm!(include_str!("link_app.S"));
 
 /// clear BSS segment
 fn clear_bss() {
@@ -57,18 +66,49 @@ fn clear_bss() {
 }
 
 #[no_mangle]
-/// the rust entry-point of os
-pub fn rust_main() -> ! {
+/// the rust entry-point of os, with the device tree from the firmware
+pub extern "C" fn rust_main(_hart_id: usize, dtb: usize) -> ! {
     clear_bss();
     logging::init();
-    println!("[kernel] Hello, world!");
+    info!("[kernel] Hello, world!");
+    dtb::init(dtb);
+    boot_params::init();
     mm::init();
-    println!("[kernel] back to world!");
+    info!("[kernel] back to world!");
     mm::remap_test();
+    #[cfg(feature = "board_test")]
+    board_test::run();
     trap::init();
     //trap::enable_interrupt();
+    drivers::init();
+    drivers::init_hart();
+    #[cfg(feature = "gdbstub")]
+    gdbstub::init();
     trap::enable_timer_interrupt();
+    trap::enable_external_interrupt();
+    timer::init();
+    task::init_watchdog();
+    task::init_replay();
     timer::set_next_trigger();
-    task::run_first_task();
+    fs::list_apps();
+    task::add_initproc();
+    hart::mark_online();
+    hart::start_secondary_harts();
+    task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
+
+#[no_mangle]
+/// the rust entry-point of harts started by the boot hart
+pub fn rust_main_secondary() -> ! {
+    mm::init_hart();
+    trap::init();
+    drivers::init_hart();
+    trap::enable_timer_interrupt();
+    trap::enable_external_interrupt();
+    timer::set_next_trigger();
+    hart::mark_online();
+    info!("[kernel] hart {} is online", hart::hart_id());
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
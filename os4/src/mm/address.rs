@@ -1,7 +1,7 @@
 //! Implementation of physical and virtual address and page number.
 
 use super::PageTableEntry;
-use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS, PAGE_TABLE_LEVELS};
 use core::fmt::{self, Debug, Formatter};
 
 /// physical address
@@ -149,11 +149,11 @@ impl From<PhysPageNum> for PhysAddr {
 }
 
 impl VirtPageNum {
-    // 取出虚拟页号的三级页索引，并按照高到低的顺序返回
-    pub fn indexes(&self) -> [usize; 3] {
+    // 取出虚拟页号的各级页索引，并按照高到低的顺序返回
+    pub fn indexes(&self) -> [usize; PAGE_TABLE_LEVELS] {
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
+        let mut idx = [0usize; PAGE_TABLE_LEVELS];
+        for i in (0..PAGE_TABLE_LEVELS).rev() {
             idx[i] = vpn & 511;
             vpn >>= 9;
         }
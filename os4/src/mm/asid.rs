@@ -0,0 +1,127 @@
+//! Implementation of [`AsidAllocator`] and targeted TLB flushes.
+//!
+//! Every user page table gets its own address-space identifier, so TLB
+//! entries of different address spaces can live side by side and switching
+//! `satp` does not need to flush the whole TLB. ASID 0 belongs to the kernel
+//! space; it is also handed out when the hardware has no ASID bits or all
+//! of them are taken, in which case the trampoline falls back to full
+//! flushes on every switch.
+
+use super::{VirtAddr, VirtPageNum};
+use crate::config::PAGE_SIZE;
+use crate::hart::online_harts;
+use crate::sbi::remote_sfence_vma_asid;
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use lazy_static::*;
+use riscv::register::satp;
+
+/// position of the ASID field in `satp`
+pub const ASID_SHIFT: usize = 44;
+/// width mask of the ASID field in `satp`
+pub const ASID_MASK: usize = 0xffff;
+
+/// an allocator handing out ASIDs in `[1, end)` and recycling freed ones
+pub struct AsidAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl AsidAllocator {
+    pub fn new() -> Self {
+        Self {
+            current: 1,
+            end: 1,
+            recycled: Vec::new(),
+        }
+    }
+    fn init(&mut self, max_asid: usize) {
+        self.end = max_asid + 1;
+    }
+    fn alloc(&mut self) -> usize {
+        if let Some(asid) = self.recycled.pop() {
+            asid
+        } else if self.current < self.end {
+            self.current += 1;
+            self.current - 1
+        } else {
+            0
+        }
+    }
+    fn dealloc(&mut self, asid: usize) {
+        if asid == 0 {
+            return;
+        }
+        assert!(
+            asid < self.current && !self.recycled.contains(&asid),
+            "asid {} has not been allocated!",
+            asid
+        );
+        self.recycled.push(asid);
+    }
+}
+
+lazy_static! {
+    /// ASID allocator instance through lazy_static!
+    static ref ASID_ALLOCATOR: SpinLock<AsidAllocator> = SpinLock::new(AsidAllocator::new());
+}
+
+/// Probe how many ASID bits the hardware implements. Must run with the
+/// kernel space already active.
+pub fn init_asid_allocator() {
+    let old = satp::read().bits();
+    let max_asid = unsafe {
+        satp::write(old | ASID_MASK << ASID_SHIFT);
+        let max_asid = satp::read().bits() >> ASID_SHIFT & ASID_MASK;
+        satp::write(old);
+        max_asid
+    };
+    flush_tlb_asid(0);
+    ASID_ALLOCATOR.lock().init(max_asid);
+    info!("ASIDs available: {}", max_asid);
+}
+
+/// an allocated ASID, freed when dropped
+pub struct AsidHandle(pub usize);
+
+impl AsidHandle {
+    /// Allocate a fresh ASID, flushing whatever its previous owner left.
+    pub fn alloc() -> Self {
+        let asid = ASID_ALLOCATOR.lock().alloc();
+        if asid != 0 {
+            flush_tlb_asid(asid);
+        }
+        Self(asid)
+    }
+}
+
+impl Drop for AsidHandle {
+    fn drop(&mut self) {
+        ASID_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+/// Flush the translation of `vpn` in address space `asid`, on every hart
+/// as threads of the address space may be running elsewhere.
+pub fn flush_tlb_page(vpn: VirtPageNum, asid: usize) {
+    let va: VirtAddr = vpn.into();
+    if online_harts() > 1 {
+        remote_sfence_vma_asid(va.0, PAGE_SIZE, asid);
+        return;
+    }
+    unsafe {
+        core::arch::asm!("sfence.vma {}, {}", in(reg) va.0, in(reg) asid);
+    }
+}
+
+/// Flush every translation of address space `asid`, on every hart.
+pub fn flush_tlb_asid(asid: usize) {
+    if online_harts() > 1 {
+        remote_sfence_vma_asid(0, usize::MAX, asid);
+        return;
+    }
+    unsafe {
+        core::arch::asm!("sfence.vma zero, {}", in(reg) asid);
+    }
+}
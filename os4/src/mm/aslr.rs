@@ -0,0 +1,41 @@
+//! Address space layout randomization, with feature `aslr`
+//!
+//! Every program loaded gets its stack, heap and mmap region moved by a
+//! random number of pages below [`ASLR_MAX_PAGES`], from a xorshift
+//! generator stirred with the cycle counter on every draw. The range is kept
+//! small, so that the fixed addresses the lab tests map at stay free; build
+//! without the feature to get the layout of the book back.
+
+use super::UserLayout;
+use crate::config::ASLR_MAX_PAGES;
+use crate::sync::SpinNoIrq;
+use riscv::register::cycle;
+
+/// state of the generator, 0 until the first draw
+static STATE: SpinNoIrq<u64> = SpinNoIrq::new(0);
+
+fn next_random() -> u64 {
+    let mut state = STATE.lock();
+    let mut x = *state ^ cycle::read() as u64;
+    if x == 0 {
+        x = 0x9e37_79b9_7f4a_7c15;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+/// The layout of the next program loaded, all gaps 0 without `aslr`.
+pub fn user_layout() -> UserLayout {
+    if !cfg!(feature = "aslr") {
+        return UserLayout::default();
+    }
+    let mut gap = || (next_random() % ASLR_MAX_PAGES as u64) as usize;
+    UserLayout {
+        stack_gap: gap(),
+        heap_gap: gap(),
+        mmap_gap: gap(),
+    }
+}
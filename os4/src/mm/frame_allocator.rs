@@ -2,12 +2,42 @@
 //! controls all the frames in the operating system.
 
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
-use crate::sync::UPSafeCell;
+use crate::config::{FRAME_LOW_WATERMARK, MEMORY_START};
+use crate::dtb::board;
+use crate::sync::SpinNoIrq;
+use alloc::collections::BTreeSet;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// no free frame is left to satisfy an allocation
+pub struct OutOfMemory;
+
+#[allow(unused)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// what happens to the contents of a frame when it is freed
+pub enum FrameFreePolicy {
+    /// leave the old data in place
+    Keep,
+    /// clear the frame so no stale data can leak to its next owner
+    Zero,
+    /// fill the frame with [`POISON_BYTE`] and check it is untouched when
+    /// it is handed out again, catching writes after free
+    Poison,
+}
+
+/// policy applied by `frame_dealloc`, poisoning in debug builds
+pub const FRAME_FREE_POLICY: FrameFreePolicy = if cfg!(debug_assertions) {
+    FrameFreePolicy::Poison
+} else {
+    FrameFreePolicy::Zero
+};
+
+/// pattern freed frames are filled with under [`FrameFreePolicy::Poison`]
+pub const POISON_BYTE: u8 = 0xde;
+
 /// manage a frame which has the same lifecycle as the tracker
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
@@ -15,6 +45,11 @@ pub struct FrameTracker {
 
 impl FrameTracker {
     pub fn new(ppn: PhysPageNum) -> Self {
+        let poisoned = FRAME_FREE_POLICY == FrameFreePolicy::Poison
+            && POISONED.lock().take(ppn.0);
+        if poisoned {
+            check_poison(ppn);
+        }
         // page cleaning
         let bytes_array = ppn.get_bytes_array();
         for i in bytes_array {
@@ -39,9 +74,13 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// allocate `count` physically contiguous frames, returning the first one
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
+#[allow(unused)]
+/// the simplest allocator: a bump pointer plus a stack of recycled frames
 pub struct StackFrameAllocator {
     current: usize,
     end: usize,
@@ -101,40 +140,348 @@ impl FrameAllocator for StackFrameAllocator {
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+/// number of block orders managed by the buddy allocator,
+/// so the largest block is `1 << (BUDDY_MAX_ORDER - 1)` frames (4 MiB)
+const BUDDY_MAX_ORDER: usize = 11;
+
+/// buddy system allocator, able to hand out physically contiguous frames
+///
+/// A block of order `k` consists of `1 << k` frames and its first ppn is
+/// aligned to `1 << k`, so the buddy of a block is found by flipping bit `k`.
+pub struct BuddyFrameAllocator {
+    /// free blocks of each order, keyed by their first ppn
+    free_lists: [BTreeSet<usize>; BUDDY_MAX_ORDER],
+    start: usize,
+    end: usize,
+    free: usize,
+}
+
+impl BuddyFrameAllocator {
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.end = r.0;
+        // 将[l, r)切分成尽可能大的对齐块
+        let mut current = l.0;
+        while current < r.0 {
+            let mut order = BUDDY_MAX_ORDER - 1;
+            while current & ((1 << order) - 1) != 0 || current + (1 << order) > r.0 {
+                order -= 1;
+            }
+            self.free_lists[order].insert(current);
+            self.free += 1 << order;
+            current += 1 << order;
+        }
+    }
+    pub fn remain_num(&self) -> usize {
+        self.free
+    }
+    /// smallest order whose block can hold `count` frames
+    fn order_of(count: usize) -> usize {
+        count.next_power_of_two().trailing_zeros() as usize
+    }
+    /// take a free block of exactly `order`, splitting larger blocks if needed
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let found = (order..BUDDY_MAX_ORDER).find(|&i| !self.free_lists[i].is_empty())?;
+        let block = *self.free_lists[found].iter().next().unwrap();
+        self.free_lists[found].remove(&block);
+        // 把多余的一半逐级放回低阶空闲链表
+        for i in (order..found).rev() {
+            self.free_lists[i].insert(block + (1 << i));
+        }
+        self.free -= 1 << order;
+        Some(block)
+    }
+    /// give a single frame back and merge it with its buddies
+    fn free_frame(&mut self, ppn: usize) {
+        let mut block = ppn;
+        let mut order = 0;
+        while order + 1 < BUDDY_MAX_ORDER {
+            let buddy = block ^ (1 << order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(block);
+        self.free += 1;
+    }
+    /// whether `ppn` lies inside some free block
+    fn is_free(&self, ppn: usize) -> bool {
+        (0..BUDDY_MAX_ORDER).any(|order| {
+            self.free_lists[order].contains(&(ppn & !((1 << order) - 1)))
+        })
+    }
+}
+
+impl FrameAllocator for BuddyFrameAllocator {
+    fn new() -> Self {
+        Self {
+            free_lists: Default::default(),
+            start: 0,
+            end: 0,
+            free: 0,
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        self.alloc_block(0).map(|ppn| ppn.into())
+    }
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        if count == 0 {
+            return None;
+        }
+        let order = Self::order_of(count);
+        if order >= BUDDY_MAX_ORDER {
+            return None;
+        }
+        let block = self.alloc_block(order)?;
+        // 块的尾部超出count的部分立即归还
+        for ppn in block + count..block + (1 << order) {
+            self.free_frame(ppn);
+        }
+        Some(block.into())
+    }
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        let ppn = ppn.0;
+        // validation check
+        if ppn < self.start || ppn >= self.end || self.is_free(ppn) {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        }
+        self.free_frame(ppn);
+    }
+}
+
+type FrameAllocatorImpl = BuddyFrameAllocator;
+
+#[derive(Copy, Clone, Debug, Default)]
+/// counters kept across every frame allocation and deallocation
+pub struct FrameAllocatorStats {
+    /// frames managed by the allocator
+    pub total: usize,
+    /// frames currently handed out
+    pub allocated: usize,
+    /// highest value `allocated` has reached
+    pub peak: usize,
+    /// frames allocated so far
+    pub alloc_count: usize,
+    /// frames deallocated so far
+    pub dealloc_count: usize,
+}
+
+/// [`FrameAllocatorStats`] plus the low-memory warning state
+struct FrameAccounting {
+    stats: FrameAllocatorStats,
+    /// warn once free frames drop below this, `None` disables the warning
+    low_watermark: Option<usize>,
+    /// set while below the watermark, so the warning is not repeated
+    warned: bool,
+}
+
+impl FrameAccounting {
+    fn on_alloc(&mut self, count: usize) {
+        let stats = &mut self.stats;
+        stats.allocated += count;
+        stats.alloc_count += count;
+        stats.peak = stats.peak.max(stats.allocated);
+    }
+    /// The stats to warn about if free frames are below the watermark and
+    /// no warning has been given since they dropped there
+    fn low_memory(&mut self) -> Option<FrameAllocatorStats> {
+        let free = self.stats.total - self.stats.allocated;
+        let low = self.low_watermark?;
+        if free >= low || self.warned {
+            return None;
+        }
+        self.warned = true;
+        Some(self.stats)
+    }
+    fn on_dealloc(&mut self, count: usize) {
+        self.stats.allocated -= count;
+        self.stats.dealloc_count += count;
+        let free = self.stats.total - self.stats.allocated;
+        if self.low_watermark.map_or(true, |low| free >= low) {
+            self.warned = false;
+        }
+    }
+}
+
+/// one bit per managed frame, set while the frame holds the poison pattern
+struct PoisonMap {
+    start: usize,
+    bits: Vec<u64>,
+}
+
+impl PoisonMap {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            bits: Vec::new(),
+        }
+    }
+    fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.bits = vec![0; (r.0 - l.0 + 63) / 64];
+    }
+    fn mark(&mut self, ppn: usize) {
+        let idx = ppn - self.start;
+        self.bits[idx / 64] |= 1 << (idx % 64);
+    }
+    /// clear the bit of `ppn`, returning whether it was set
+    fn take(&mut self, ppn: usize) -> bool {
+        let idx = ppn - self.start;
+        let was_set = self.bits[idx / 64] & (1 << (idx % 64)) != 0;
+        self.bits[idx / 64] &= !(1 << (idx % 64));
+        was_set
+    }
+}
+
+/// Panic if a poisoned frame has been written to since it was freed.
+fn check_poison(ppn: PhysPageNum) {
+    let bytes_array = ppn.get_bytes_array();
+    if let Some(offset) = bytes_array.iter().position(|&byte| byte != POISON_BYTE) {
+        panic!(
+            "use after free of frame ppn={:#x}: byte {:#x} at offset {:#x} overwritten",
+            ppn.0, bytes_array[offset], offset
+        );
+    }
+}
 
 lazy_static! {
     /// frame allocator instance through lazy_static!
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> = 
-        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) }; 
+    pub static ref FRAME_ALLOCATOR: SpinNoIrq<FrameAllocatorImpl> = SpinNoIrq::new(FrameAllocatorImpl::new());
+    /// frames that have been poisoned since they were freed
+    static ref POISONED: SpinNoIrq<PoisonMap> = SpinNoIrq::new(PoisonMap::new());
+    /// frame statistics, updated by the `frame_*` functions
+    static ref FRAME_ACCOUNTING: SpinNoIrq<FrameAccounting> = SpinNoIrq::new(FrameAccounting {
+        stats: FrameAllocatorStats::default(),
+        low_watermark: FRAME_LOW_WATERMARK,
+        warned: false,
+    });
 }
 
-/// initiate the frame allocator using "ekernel" and `MEMORY_END`
+/// initiate the frame allocator using "ekernel" and the end of RAM
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.init(
         PhysAddr::from(ekernel as usize).ceil(), 
-        PhysAddr::from(MEMORY_END).floor(),
+        PhysAddr::from(board().memory_end).floor(),
+    );
+    POISONED.lock().init(
+        PhysAddr::from(ekernel as usize).ceil(),
+        PhysAddr::from(board().memory_end).floor(),
     );
+    FRAME_ACCOUNTING.lock().stats.total = allocator.remain_num();
 }
 
 /// allocate a frame
-// 返回值不是PhysPageNum，而是包装成了一个FrameTracker
+// 返回值不是PhysPageNum，而是包装成了一个FrameTracker；
+// 这里不回收内存，调用者可能持有锁，回收见crate::task::reclaim_frames
+    #[cfg_attr(feature = "frame-debug", track_caller)]
     pub fn frame_alloc() -> Option<FrameTracker> {
-        FRAME_ALLOCATOR
-            .exclusive_access()
-            .alloc()
-            .map(FrameTracker::new)
+        let ppn = FRAME_ALLOCATOR.lock().alloc()?;
+        account_alloc(1);
+        #[cfg(feature = "frame-debug")]
+        super::frame_debug::on_alloc(ppn.0, core::panic::Location::caller());
+        Some(FrameTracker::new(ppn))
     }
     /// dealloc a frame
     pub fn frame_dealloc(ppn: PhysPageNum) {
-        FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+        // 先检查，才能在分配器panic之前报告这个页帧的来历
+        #[cfg(feature = "frame-debug")]
+        super::frame_debug::on_dealloc(ppn.0);
+        match FRAME_FREE_POLICY {
+            FrameFreePolicy::Keep => {}
+            FrameFreePolicy::Zero => ppn.get_bytes_array().fill(0),
+            FrameFreePolicy::Poison => {
+                ppn.get_bytes_array().fill(POISON_BYTE);
+                POISONED.lock().mark(ppn.0);
+            }
+        }
+        FRAME_ALLOCATOR.lock().dealloc(ppn);
+        FRAME_ACCOUNTING.lock().on_dealloc(1);
+    }
+
+/// Count `count` frames handed out, warning about low memory once the
+/// accounting is released, as the logger may allocate.
+fn account_alloc(count: usize) {
+    let mut accounting = FRAME_ACCOUNTING.lock();
+    accounting.on_alloc(count);
+    let low = accounting.low_memory();
+    drop(accounting);
+    if let Some(stats) = low {
+        warn!(
+            "[kernel] low memory: {} of {} frames free, peak usage {}",
+            stats.total - stats.allocated,
+            stats.total,
+            stats.peak
+        );
+    }
+}
+
+/// Take a frame for the slab caches, which keep it for good.
+///
+/// Called from inside the global allocator, so it neither reclaims memory
+/// nor waits for the allocator or the accounting lock, which this hart may
+/// already hold. A low memory warning is left to the next `frame_alloc`.
+pub(super) fn frame_alloc_for_slab() -> Option<PhysPageNum> {
+    let mut allocator = FRAME_ALLOCATOR.try_lock()?;
+    let mut accounting = FRAME_ACCOUNTING.try_lock()?;
+    let ppn = allocator.alloc()?;
+    accounting.on_alloc(1);
+    Some(ppn)
+}
+
+#[allow(unused)]
+/// allocate `count` physically contiguous frames, e.g. for device queues
+#[cfg_attr(feature = "frame-debug", track_caller)]
+pub fn frame_alloc_contiguous(count: usize) -> Option<Vec<FrameTracker>> {
+    let start = FRAME_ALLOCATOR.lock().alloc_contiguous(count)?;
+    account_alloc(count);
+    #[cfg(feature = "frame-debug")]
+    for ppn in start.0..start.0 + count {
+        super::frame_debug::on_alloc(ppn, core::panic::Location::caller());
     }
+    Some(
+        (start.0..start.0 + count)
+            .map(|ppn| FrameTracker::new(ppn.into()))
+            .collect(),
+    )
+}
 
+/// frames the allocator can hand out right away
+pub fn frame_free_num() -> usize {
+    FRAME_ALLOCATOR.lock().remain_num()
+}
+
+/// free frames, counting the cached file pages that can be evicted
 pub fn frame_remain_num() -> usize {
-    FRAME_ALLOCATOR.exclusive_access().remain_num()
+    let free = FRAME_ALLOCATOR.lock().remain_num();
+    free + super::page_cache::evictable_pages()
+}
+
+/// snapshot of the frame allocator statistics
+pub fn frame_allocator_stats() -> FrameAllocatorStats {
+    FRAME_ACCOUNTING.lock().stats
+}
+
+/// frames below those the allocator manages, taken by the SBI firmware and
+/// the kernel image
+pub fn reserved_frames() -> usize {
+    extern "C" {
+        fn ekernel();
+    }
+    PhysAddr::from(ekernel as usize).ceil().0 - PhysAddr::from(MEMORY_START).floor().0
+}
+
+#[allow(unused)]
+/// warn when free frames drop below `low_watermark`, or never with `None`
+pub fn set_frame_low_watermark(low_watermark: Option<usize>) {
+    let mut accounting = FRAME_ACCOUNTING.lock();
+    accounting.low_watermark = low_watermark;
+    accounting.warned = false;
 }
 
 #[allow(unused)]
@@ -0,0 +1,100 @@
+//! Frame ownership tracking, built with the `frame-debug` feature.
+//!
+//! Every allocated frame remembers the task that was running and the call
+//! site that allocated it, so a double free can name both, and the frames a
+//! task still holds when it exits can be listed to spot teardown leaks.
+//! The exit path also audits the teardown of each address space, see
+//! [`MemorySet::recycle_data_pages_audited`](super::MemorySet::recycle_data_pages_audited).
+
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use crate::sync::SpinNoIrq;
+use alloc::collections::BTreeMap;
+use core::panic::Location;
+use lazy_static::*;
+
+#[derive(Copy, Clone)]
+/// who allocated a frame
+struct FrameOwner {
+    /// `None` for frames allocated by the kernel outside of any task
+    task: Option<usize>,
+    site: &'static Location<'static>,
+    /// tells this allocation from a later one of the same frame
+    seq: usize,
+}
+
+struct FrameDebug {
+    /// the task running on each hart
+    current_task: [Option<usize>; MAX_HARTS],
+    allocated: BTreeMap<usize, FrameOwner>,
+    /// frames freed since their last allocation, with the task freeing them
+    freed: BTreeMap<usize, (FrameOwner, Option<usize>)>,
+    next_seq: usize,
+}
+
+lazy_static! {
+    static ref FRAME_DEBUG: SpinNoIrq<FrameDebug> = SpinNoIrq::new(FrameDebug {
+        current_task: [None; MAX_HARTS],
+        allocated: BTreeMap::new(),
+        freed: BTreeMap::new(),
+        next_seq: 0,
+    });
+}
+
+/// Attribute the following allocations on this hart to `task`.
+pub fn set_frame_owner_task(task: Option<usize>) {
+    FRAME_DEBUG.lock().current_task[hart_id()] = task;
+}
+
+pub(super) fn on_alloc(ppn: usize, site: &'static Location<'static>) {
+    let mut debug = FRAME_DEBUG.lock();
+    let owner = FrameOwner {
+        task: debug.current_task[hart_id()],
+        site,
+        seq: debug.next_seq,
+    };
+    debug.next_seq += 1;
+    debug.freed.remove(&ppn);
+    debug.allocated.insert(ppn, owner);
+}
+
+/// Record the free of `ppn`, panicking with the history of the frame if it
+/// is not allocated.
+pub(super) fn on_dealloc(ppn: usize) {
+    let mut debug = FRAME_DEBUG.lock();
+    let task = debug.current_task[hart_id()];
+    match debug.allocated.remove(&ppn) {
+        Some(owner) => {
+            debug.freed.insert(ppn, (owner, task));
+        }
+        None => match debug.freed.get(&ppn) {
+            Some((owner, freed_by)) => panic!(
+                "double free of frame ppn={:#x} by task {:?}: allocated by task {:?} at {}, already freed by task {:?}",
+                ppn, task, owner.task, owner.site, freed_by
+            ),
+            None => panic!(
+                "free of frame ppn={:#x} by task {:?}, which was never allocated",
+                ppn, task
+            ),
+        },
+    }
+}
+
+/// Identifies the current allocation of `ppn`, `None` if it is free.
+pub(super) fn allocation_id(ppn: usize) -> Option<usize> {
+    FRAME_DEBUG.lock().allocated.get(&ppn).map(|owner| owner.seq)
+}
+
+/// List the frames `task` still holds, grouped by allocation site.
+pub fn dump_task_frames(task: usize) {
+    let debug = FRAME_DEBUG.lock();
+    let mut sites: BTreeMap<(&str, u32), usize> = BTreeMap::new();
+    for owner in debug.allocated.values().filter(|owner| owner.task == Some(task)) {
+        *sites.entry((owner.site.file(), owner.site.line())).or_insert(0) += 1;
+    }
+    let total: usize = sites.values().sum();
+    info!("[frame-debug] task {} still holds {} frames", task, total);
+    for ((file, line), count) in sites {
+        info!("[frame-debug]   {} from {}:{}", count, file, line);
+    }
+}
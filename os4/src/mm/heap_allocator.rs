@@ -1,16 +1,196 @@
 //! The global allocator
+//!
+//! A buddy allocator over a static array, keeping count of what it hands
+//! out. Free blocks of `2^order` bytes sit in the list of their order,
+//! linked through their first word, and are naturally aligned, so the buddy
+//! of a block is its address with bit `order` flipped.
+//!
+//! The heap is taken in interrupt handlers too, so it is a [`SpinNoIrq`].
+//! Objects of the layouts the [slab caches](super::slab) serve come from
+//! them instead and are not counted here.
 
+use super::slab::{self, slab_stats};
 // kernel heap size
 use crate::config::KERNEL_HEAP_SIZE;
-use buddy_system_allocator::LockedHeap;
+use crate::sync::SpinNoIrq;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+/// blocks of up to `2^(ORDERS - 1)` bytes
+const ORDERS: usize = 32;
+
+#[derive(Copy, Clone, Debug, Default)]
+/// counters kept across every heap allocation and deallocation
+pub struct HeapStats {
+    /// bytes managed by the allocator
+    pub total: usize,
+    /// bytes asked for by the allocations alive now
+    pub requested: usize,
+    /// bytes of the blocks handed out, with the rounding up to a power of two
+    pub allocated: usize,
+    /// highest value `allocated` has reached
+    pub peak: usize,
+    /// allocations so far
+    pub alloc_count: usize,
+    /// deallocations so far
+    pub dealloc_count: usize,
+    /// allocations that found no block large enough
+    pub failed_count: usize,
+}
+
+struct BuddyHeap {
+    /// first free block of each order, 0 if none
+    free_lists: [usize; ORDERS],
+    stats: HeapStats,
+}
+
+/// The order of the block serving `layout`
+fn order_of(layout: &Layout) -> usize {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(size_of::<usize>())
+        .next_power_of_two();
+    size.trailing_zeros() as usize
+}
+
+impl BuddyHeap {
+    const fn empty() -> Self {
+        Self {
+            free_lists: [0; ORDERS],
+            stats: HeapStats {
+                total: 0,
+                requested: 0,
+                allocated: 0,
+                peak: 0,
+                alloc_count: 0,
+                dealloc_count: 0,
+                failed_count: 0,
+            },
+        }
+    }
+    fn push(&mut self, order: usize, block: usize) {
+        unsafe { *(block as *mut usize) = self.free_lists[order] };
+        self.free_lists[order] = block;
+    }
+    fn pop(&mut self, order: usize) -> Option<usize> {
+        let block = self.free_lists[order];
+        if block == 0 {
+            return None;
+        }
+        self.free_lists[order] = unsafe { *(block as *const usize) };
+        Some(block)
+    }
+    /// Take `block` out of the list of `order` if it is there.
+    fn remove(&mut self, order: usize, block: usize) -> bool {
+        let mut link = &mut self.free_lists[order] as *mut usize;
+        unsafe {
+            while *link != 0 {
+                if *link == block {
+                    *link = *(block as *const usize);
+                    return true;
+                }
+                link = *link as *mut usize;
+            }
+        }
+        false
+    }
+    /// Cut `[start, end)` into the largest naturally aligned blocks.
+    fn add_region(&mut self, start: usize, end: usize) {
+        let mut current = (start + size_of::<usize>() - 1) & !(size_of::<usize>() - 1);
+        let end = end & !(size_of::<usize>() - 1);
+        while current + size_of::<usize>() <= end {
+            let align = current & current.wrapping_neg();
+            let fits = 1 << (usize::BITS - 1 - (end - current).leading_zeros());
+            let order = (align.min(fits).trailing_zeros() as usize).min(ORDERS - 1);
+            self.push(order, current);
+            self.stats.total += 1 << order;
+            current += 1 << order;
+        }
+    }
+    fn alloc(&mut self, layout: Layout) -> Option<usize> {
+        let order = order_of(&layout);
+        let from = (order..ORDERS).find(|&i| self.free_lists[i] != 0)?;
+        let block = self.pop(from).unwrap();
+        // 多出来的后一半逐级放回
+        for i in (order..from).rev() {
+            self.push(i, block + (1 << i));
+        }
+        self.stats.requested += layout.size();
+        self.stats.allocated += 1 << order;
+        self.stats.peak = self.stats.peak.max(self.stats.allocated);
+        self.stats.alloc_count += 1;
+        Some(block)
+    }
+    fn dealloc(&mut self, mut block: usize, layout: Layout) {
+        let mut order = order_of(&layout);
+        self.stats.requested -= layout.size();
+        self.stats.allocated -= 1 << order;
+        self.stats.dealloc_count += 1;
+        while order < ORDERS - 1 && self.remove(order, block ^ (1 << order)) {
+            block &= !(1 << order);
+            order += 1;
+        }
+        self.push(order, block);
+    }
+    /// bytes of the largest free block, which bounds the next allocation
+    fn largest_free_block(&self) -> usize {
+        match (0..ORDERS).rev().find(|&i| self.free_lists[i] != 0) {
+            Some(order) => 1 << order,
+            None => 0,
+        }
+    }
+}
+
+/// the kernel heap, see the module docs
+struct KernelHeap(SpinNoIrq<BuddyHeap>);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(object) = slab::alloc(&layout) {
+            return object;
+        }
+        let mut heap = self.0.lock();
+        match heap.alloc(layout) {
+            Some(block) => block as *mut u8,
+            None => {
+                heap.stats.failed_count += 1;
+                null_mut()
+            }
+        }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let heap_start = HEAP_SPACE.as_ptr() as usize;
+        if (heap_start..heap_start + KERNEL_HEAP_SIZE).contains(&(ptr as usize)) {
+            self.0.lock().dealloc(ptr as usize, layout)
+        } else {
+            slab::dealloc(ptr, &layout)
+        }
+    }
+}
 
 #[global_allocator]
-/// buddy heap allocator instance, inner mutex, Mutex<T>(跨线程RefCell？)
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+/// buddy heap allocator instance
+static HEAP_ALLOCATOR: KernelHeap = KernelHeap(SpinNoIrq::new(BuddyHeap::empty()));
 
 #[alloc_error_handler]
-/// panic when heap allocation error occurs
+/// report how full the heap is before panicking on an allocation failure
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+    // 先把数据取出来再打印，打印时不能持有堆的锁
+    let heap = HEAP_ALLOCATOR.0.lock();
+    let (largest, stats) = (heap.largest_free_block(), heap.stats);
+    drop(heap);
+    println!(
+        "[kernel] heap exhausted: {} bytes aligned to {} requested, largest free block is {} bytes",
+        layout.size(),
+        layout.align(),
+        largest
+    );
+    println!("[kernel] {:?}", stats);
+    for cache in slab_stats() {
+        println!("[kernel] slab {:?}", cache);
+    }
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
@@ -20,12 +200,17 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 /// initiate heap allocator
 pub fn init_heap() {
     unsafe {
-        HEAP_ALLOCATOR
-            .lock()
-            .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+        let start = HEAP_SPACE.as_ptr() as usize;
+        HEAP_ALLOCATOR.0.lock().add_region(start, start + KERNEL_HEAP_SIZE);
     }
 }
 
+#[allow(unused)]
+/// snapshot of the heap statistics
+pub fn heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.0.lock().stats
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
@@ -35,9 +220,11 @@ pub fn heap_test() {
         fn ebss();
     }
     let bss_range = sbss as usize..ebss as usize;
+    let before = heap_stats();
     let a = Box::new(5);
     assert_eq!(*a, 5);
     assert!(bss_range.contains(&(a.as_ref() as *const _ as usize)));
+    assert_eq!(heap_stats().alloc_count, before.alloc_count + 1);
     drop(a);
     let mut v: Vec<usize> = Vec::new();
     for i in 0..500 {
@@ -47,6 +234,8 @@ pub fn heap_test() {
         assert_eq!(*vi, i);
     }
     assert!(bss_range.contains(&(v.as_ptr() as usize)));
+    assert!(heap_stats().requested >= before.requested + 500 * size_of::<usize>());
     drop(v);
+    assert_eq!(heap_stats().requested, before.requested);
     info!("heap_test passed!");
 }
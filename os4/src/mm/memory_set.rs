@@ -1,16 +1,24 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::{frame_alloc, frame_remain_num, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::{frame_alloc, frame_remain_num, page_cache, FrameTracker, OutOfMemory};
+use super::{PTEFlags, PageTable, PageTableEntry, HUGE_PAGE_PAGES};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use super::swap::{swap_alloc, swap_free, swap_read, swap_write};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use super::aslr::user_layout;
+use crate::config::{
+    PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_SPACE_END, USER_STACK_MAX_SIZE, USER_STACK_SIZE,
+};
+use crate::dtb::board;
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use easy_fs::Inode;
 use lazy_static::*;
 use riscv::register::satp;
-use spin::Mutex;
+use xmas_elf::{header, program, ElfFile};
 
 extern "C" {
     fn stext();
@@ -25,23 +33,212 @@ extern "C" {
     fn strampoline();
 }
 
+/// mmap flag: treat `start` as a hint and pick the nearest free region
+pub const MAP_HINT: usize = 1 << 8;
+/// mmap flag: map exactly at `start`, replacing existing user mappings
+pub const MAP_FIXED: usize = 1 << 9;
+/// mmap flag: back the pages by a file, read in on first access
+pub const MAP_FILE: usize = 1 << 10;
+/// mmap flag: with [`MAP_FILE`], map the pages of the page cache so that
+/// stores reach the file
+pub const MAP_SHARED: usize = 1 << 11;
+/// mremap flag: move the mapping if it cannot grow where it is
+pub const MREMAP_MAYMOVE: usize = 1;
+
 lazy_static! {
     /// a memory set instance through lazy_static! managing kernel space
-    pub static ref KERNEL_SPACE: Arc<Mutex<MemorySet>> =
-        Arc::new(Mutex::new(MemorySet::new_kernel()));
+    pub static ref KERNEL_SPACE: Arc<SpinLock<MemorySet>> =
+        Arc::new(SpinLock::new(MemorySet::new_kernel()));
 }
 
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     page_table: PageTable,
-    areas: Vec<MapArea>,
+    /// areas indexed by their start vpn, which never overlap, boxed so that
+    /// they come from the slab cache of their own
+    areas: BTreeMap<VirtPageNum, Box<MapArea>>,
+    /// start of the heap area, page aligned
+    heap_bottom: usize,
+    /// current program break, the end of the heap in bytes
+    program_brk: usize,
+    /// unmapped page right below the reserved user stack
+    stack_guard: Option<VirtPageNum>,
+    /// page fault counts and the peak of resident pages
+    stats: MemStats,
+    /// where the stack, heap and mmap region of a user space were put
+    layout: UserLayout,
+}
+
+/// How far the stack, heap and mmap region of a user space are moved from
+/// where they go without randomization, in pages. Drawn anew by
+/// [`aslr::user_layout`](super::aslr::user_layout) for every program loaded.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UserLayout {
+    /// between the end of the program and the guard page of the stack
+    pub stack_gap: usize,
+    /// between the top of the stack and the bottom of the heap
+    pub heap_gap: usize,
+    /// between the end of user space and the top of the mmap region, which
+    /// thread stacks and hintless `MAP_HINT` mappings are put below
+    pub mmap_gap: usize,
+}
+
+/// [`MapRegion::backing`] of anonymous memory
+pub const REGION_ANONYMOUS: usize = 0;
+/// [`MapRegion::backing`] of a private file mapping
+pub const REGION_FILE_PRIVATE: usize = 1;
+/// [`MapRegion::backing`] of a shared file mapping
+pub const REGION_FILE_SHARED: usize = 2;
+/// [`MapRegion::backing`] of pages mapped without an area, the trampoline
+pub const REGION_UNTRACKED: usize = 3;
+/// [`MapRegion::backing`] of a shared memory object
+pub const REGION_SHM: usize = 4;
+
+/// A contiguous range of an address space, as a `pmap` line
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MapRegion {
+    pub start: usize,
+    pub end: usize,
+    /// `R`, `W`, `X` and `U` bits of [`MapPermission`]
+    pub perm: usize,
+    /// one of the `REGION_*` constants
+    pub backing: usize,
+    /// pages of the range mapped to a frame now
+    pub resident_pages: usize,
+}
+
+/// Memory statistics of an address space kept up by the page fault handler
+/// and by whatever maps pages
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemStats {
+    /// most user pages resident at any time
+    pub peak_resident: usize,
+    /// faults resolved without I/O: a fresh page or one already cached
+    pub minor_faults: usize,
+    /// faults reading the page from the swap area or a file
+    pub major_faults: usize,
+}
+
+/// Why a user page fault could not be resolved.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// the guard page below the user stack was touched
+    StackOverflow,
+    /// the address is not inside any area, or its page is already mapped
+    Invalid,
+    /// no frame left to back the page
+    OutOfMemory,
+    /// the process has as many resident pages as its limit allows
+    OverLimit,
+}
+
+/// Why an ELF cannot be loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// the headers cannot be parsed, or the magic is wrong
+    BadHeader,
+    /// not a 64-bit RISC-V executable
+    WrongTarget,
+    /// a loadable segment lies outside the file or the user space, or
+    /// overlaps the one before it
+    BadSegment,
+    /// a loadable segment is both writable and executable, see [`WX_POLICY`]
+    WritableExecutable,
+    /// no frames are left to map the program, e.g. for a huge BSS
+    OutOfMemory,
+}
+
+impl From<OutOfMemory> for ElfError {
+    fn from(_: OutOfMemory) -> Self {
+        ElfError::OutOfMemory
+    }
+}
+
+#[allow(unused)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// what the ELF loader does with a segment both writable and executable
+pub enum WxPolicy {
+    /// map it as asked
+    Allow,
+    /// map it as asked, but log a warning
+    Warn,
+    /// refuse to load the program
+    Reject,
+}
+
+/// policy of the ELF loader, rejecting in debug builds
+pub const WX_POLICY: WxPolicy = if cfg!(debug_assertions) {
+    WxPolicy::Reject
+} else {
+    WxPolicy::Warn
+};
+
+/// `e_machine` of RISC-V
+const EM_RISCV: u16 = 243;
+
+/// Check what [`MemorySet::from_elf`] relies on before anything is mapped.
+fn check_elf(elf: &ElfFile) -> Result<(), ElfError> {
+    let header = &elf.header;
+    if header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+        return Err(ElfError::BadHeader);
+    }
+    if header.pt1.class() != header::Class::SixtyFour
+        || header.pt2.machine().0 != EM_RISCV
+        || header.pt2.type_().as_type() != header::Type::Executable
+    {
+        return Err(ElfError::WrongTarget);
+    }
+    if header.pt2.entry_point() as usize >= USER_SPACE_END {
+        return Err(ElfError::BadSegment);
+    }
+    let mut prev_end = 0;
+    for i in 0..header.pt2.ph_count() {
+        let ph = elf.program_header(i).map_err(|_| ElfError::BadHeader)?;
+        if ph.get_type().map_err(|_| ElfError::BadHeader)? != program::Type::Load {
+            continue;
+        }
+        if ph.flags().is_write() && ph.flags().is_execute() {
+            match WX_POLICY {
+                WxPolicy::Allow => {}
+                WxPolicy::Warn => warn!(
+                    "[kernel] ELF segment at {:#x} is both writable and executable",
+                    ph.virtual_addr()
+                ),
+                WxPolicy::Reject => return Err(ElfError::WritableExecutable),
+            }
+        }
+        let file_end = ph.offset().checked_add(ph.file_size());
+        let mem_end = ph.virtual_addr().checked_add(ph.mem_size());
+        match (file_end, mem_end) {
+            (Some(file_end), Some(mem_end))
+                if file_end as usize <= elf.input.len()
+                    && ph.file_size() <= ph.mem_size()
+                    && ph.virtual_addr() >= prev_end
+                    && (mem_end as usize) <= USER_SPACE_END =>
+            {
+                // segments share no page with each other
+                prev_end = (mem_end + PAGE_SIZE as u64 - 1) & !(PAGE_SIZE as u64 - 1);
+            }
+            _ => return Err(ElfError::BadSegment),
+        }
+    }
+    Ok(())
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
+    pub fn new_bare() -> Result<Self, OutOfMemory> {
+        PageTable::try_new().map(Self::with_page_table)
+    }
+    fn with_page_table(page_table: PageTable) -> Self {
         Self {
-            page_table: PageTable::new(),
-            areas: Vec::new(),
+            page_table,
+            areas: BTreeMap::new(),
+            heap_bottom: 0,
+            program_brk: 0,
+            stack_guard: None,
+            stats: MemStats::default(),
+            layout: UserLayout::default(),
         }
     }
     pub fn token(&self) -> usize {
@@ -53,32 +250,146 @@ impl MemorySet {
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
-        self.push(
+    ) -> Result<(), OutOfMemory> {
+        self.try_push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
-        );
+        )
+    }
+    /// Unmap the area starting at `start_vpn` and free its frames.
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        if let Some(mut area) = self.areas.remove(&start_vpn) {
+            area.unmap(&mut self.page_table);
+        }
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// Only for building address spaces, where running out of frames is fatal.
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        self.try_push(map_area, data).expect("out of memory while building an address space");
+    }
+    /// Map the area and insert it, or leave nothing behind when out of frames.
+    fn try_push(
+        &mut self,
+        mut map_area: MapArea,
+        data: Option<&[u8]>,
+    ) -> Result<(), OutOfMemory> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
-        self.areas.push(map_area);
+        self.areas.insert(map_area.vpn_range.get_start(), Box::new(map_area));
+        self.update_peak_resident();
+        Ok(())
+    }
+    /// user pages backed by a frame right now, not counting swapped ones
+    pub fn resident_pages(&self) -> usize {
+        self.areas.values().map(|area| area.data_frames.len()).sum()
+    }
+    /// areas created by `mmap`, an area partly unmapped may count twice
+    pub fn mmap_regions(&self) -> usize {
+        self.areas.values().filter(|area| area.mmapped).count()
+    }
+    /// pages in the areas created by `mmap`, resident or not
+    pub fn mmap_pages(&self) -> usize {
+        self.areas
+            .values()
+            .filter(|area| area.mmapped)
+            .map(|area| area.vpn_range.get_end().0 - area.vpn_range.get_start().0)
+            .sum()
+    }
+    pub fn stats(&self) -> MemStats {
+        self.stats
+    }
+    /// The areas in address order, with the pages mapped outside of any
+    /// area, like the trampoline, as regions of their own.
+    pub fn regions(&self) -> Vec<MapRegion> {
+        let perm_bits = (MapPermission::R | MapPermission::W | MapPermission::X | MapPermission::U)
+            .bits() as usize;
+        let mut regions: Vec<MapRegion> = self
+            .areas
+            .values()
+            .map(|area| MapRegion {
+                start: VirtAddr::from(area.vpn_range.get_start()).0,
+                end: VirtAddr::from(area.vpn_range.get_end()).0,
+                perm: area.map_perm.bits() as usize,
+                backing: match &area.backing {
+                    _ if area.shm.is_some() => REGION_SHM,
+                    None => REGION_ANONYMOUS,
+                    Some(backing) if backing.shared => REGION_FILE_SHARED,
+                    Some(_) => REGION_FILE_PRIVATE,
+                },
+                resident_pages: 0,
+            })
+            .collect();
+        let mut untracked: Vec<MapRegion> = Vec::new();
+        for (vpn, _, flags) in self.page_table.iter_mapped() {
+            let start = VirtAddr::from(vpn).0;
+            if self.find_area(vpn).is_some() {
+                let i = regions.partition_point(|region| region.end <= start);
+                regions[i].resident_pages += 1;
+                continue;
+            }
+            let perm = flags.bits() as usize & perm_bits;
+            match untracked.last_mut() {
+                Some(last) if last.end == start && last.perm == perm => {
+                    last.end = last.end.wrapping_add(PAGE_SIZE);
+                    last.resident_pages += 1;
+                }
+                _ => untracked.push(MapRegion {
+                    start,
+                    // 0 for the trampoline, which ends at the top
+                    end: start.wrapping_add(PAGE_SIZE),
+                    perm,
+                    backing: REGION_UNTRACKED,
+                    resident_pages: 1,
+                }),
+            }
+        }
+        regions.append(&mut untracked);
+        regions.sort_unstable_by_key(|region| region.start);
+        regions
+    }
+    fn update_peak_resident(&mut self) {
+        self.stats.peak_resident = self.stats.peak_resident.max(self.resident_pages());
+    }
+    /// Find the area containing `vpn` in O(log n).
+    pub fn find_area(&self, vpn: VirtPageNum) -> Option<&MapArea> {
+        self.areas
+            .range(..=vpn)
+            .next_back()
+            .map(|(_, area)| area.as_ref())
+            .filter(|area| vpn < area.vpn_range.get_end())
+    }
+    /// areas sharing at least one page with `vpn_range`, in address order
+    fn areas_overlapping(&self, vpn_range: VPNRange) -> impl Iterator<Item = &MapArea> {
+        // only the area right before the range may start outside of it
+        let before = self
+            .areas
+            .range(..vpn_range.get_start())
+            .next_back()
+            .map(|(_, area)| area.as_ref())
+            .filter(move |area| vpn_range.get_start() < area.vpn_range.get_end());
+        let inside = if vpn_range.get_start() < vpn_range.get_end() {
+            Some(self.areas.range(vpn_range.get_start()..vpn_range.get_end()))
+        } else {
+            None
+        };
+        before
+            .into_iter()
+            .chain(inside.into_iter().flatten().map(|(_, area)| area.as_ref()))
     }
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> Result<(), OutOfMemory> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::with_page_table(PageTable::new_kernel());
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline().unwrap();
         // map kernel sections
         info!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         info!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
@@ -131,70 +442,562 @@ impl MemorySet {
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
-                MEMORY_END.into(),
+                board().memory_end.into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
             None,
         );
+        info!("mapping memory-mapped registers");
+        for &(start, len) in board().mmio() {
+            memory_set
+                .map_mmio(start, len)
+                .expect("out of memory while building an address space");
+        }
         memory_set
     }
+    /// Map the device registers in `[pa_start, pa_start + size)` at the same
+    /// addresses, for the kernel to read and write.
+    pub fn map_mmio(&mut self, pa_start: usize, size: usize) -> Result<(), OutOfMemory> {
+        self.map_mmio_at(pa_start, pa_start, size)
+    }
+    /// Map the device registers in `[pa_start, pa_start + size)` at
+    /// `va_start`, which must have the same offset into its page.
+    ///
+    /// The entries come with A and D already set, so the MMU never writes
+    /// them back on an access. Sv39 entries carry no memory type, whether
+    /// the registers are cached is up to the physical memory attributes of
+    /// the platform, which mark device regions as I/O.
+    pub fn map_mmio_at(
+        &mut self,
+        va_start: usize,
+        pa_start: usize,
+        size: usize,
+    ) -> Result<(), OutOfMemory> {
+        assert_eq!(
+            va_start % PAGE_SIZE,
+            pa_start % PAGE_SIZE,
+            "MMIO mapping of {:#x} at {:#x} is misaligned",
+            pa_start,
+            va_start
+        );
+        let start_va = VirtAddr::from(va_start);
+        let offset = start_va.floor().0.wrapping_sub(PhysAddr::from(pa_start).floor().0);
+        self.try_push(
+            MapArea::new(
+                start_va,
+                VirtAddr::from(va_start + size),
+                MapType::Mmio(offset),
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        )
+    }
 
-    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
-        if (port & !0b0000_0111 != 0) || (port & 0b0000_0111 == 0) { return -1 }
+    /// Map `len` bytes of fresh user memory at `start`.
+    ///
+    /// The low byte of `port` holds the R/W/X permission and the bits above
+    /// it select the placement: by default the range must be free and `0` is
+    /// returned; with [`MAP_HINT`] `start` is only a hint and the nearest free
+    /// hole is used instead, a zero `start` asking for the top of the mmap
+    /// region; with [`MAP_FIXED`] whatever user mappings overlap
+    /// the range are unmapped first. Both flags return the mapped address.
+    ///
+    /// With [`MAP_FILE`] the pages come from `backing` on first access
+    /// instead of being allocated right away.
+    pub fn mmap(
+        &mut self,
+        start: usize,
+        len: usize,
+        port: usize,
+        backing: Option<FileBacking>,
+    ) -> isize {
+        let map_perm = match MapPermission::from_port(port & 0xff) {
+            Some(map_perm) => map_perm,
+            None => return -1,
+        };
+        let flags = port & !0xff;
+        let placement = flags & !(MAP_FILE | MAP_SHARED);
+        if flags & !(MAP_HINT | MAP_FIXED | MAP_FILE | MAP_SHARED) != 0
+            || placement == MAP_HINT | MAP_FIXED
+            || (flags & MAP_FILE != 0) != backing.is_some()
+            || (flags & MAP_SHARED != 0 && flags & MAP_FILE == 0)
+        {
+            return -1;
+        }
         let va_start = VirtAddr::from(start);
-        let va_end = VirtAddr::from(start + len);
-        if va_start.page_offset() != 0 { return -1 }
-        let mut map_perm = MapPermission::U;
-        if port & 0b0000_0001 == 0b0000_0001 {
-            map_perm |= MapPermission::R;
+        if va_start.page_offset() != 0 || len == 0 { return -1 }
+        let pages = match len.checked_add(PAGE_SIZE - 1) {
+            Some(len) => len / PAGE_SIZE,
+            None => return -1,
+        };
+        let mut replace = false;
+        let start_vpn = if placement & MAP_HINT != 0 {
+            let hint = if start == 0 {
+                VirtPageNum(self.mmap_top().0.saturating_sub(pages))
+            } else {
+                va_start.floor()
+            };
+            match self.find_free_region(hint, pages) {
+                Some(vpn) => vpn,
+                None => return -1,
+            }
+        } else {
+            let start_vpn = va_start.floor();
+            if start_vpn.0 + pages > Self::user_end().0 {
+                return -1;
+            }
+            let vpn_range = VPNRange::new(start_vpn, VirtPageNum(start_vpn.0 + pages));
+            if self.overlaps(vpn_range) {
+                let only_user = self
+                    .areas_overlapping(vpn_range)
+                    .all(|area| area.map_perm.contains(MapPermission::U));
+                if placement & MAP_FIXED == 0 || !only_user {
+                    return -1;
+                }
+                replace = true;
+            }
+            start_vpn
+        };
+        let vpn_range = VPNRange::new(start_vpn, VirtPageNum(start_vpn.0 + pages));
+        // 先分配好所有物理页帧和页表节点，失败时地址空间保持原样；
+        // 此后的解除映射和映射都不会再失败
+        let frames: Option<Vec<FrameTracker>> = match backing {
+            // file pages are read in by handle_page_fault
+            Some(_) => Some(Vec::new()),
+            None => (0..pages).map(|_| frame_alloc()).collect(),
+        };
+        let frames = match frames {
+            Some(frames) => frames,
+            None => return -1,
+        };
+        if self.page_table.reserve(vpn_range).is_err() {
+            return -1;
         }
-        if port & 0b0000_0010 == 0b0000_0010 {
-            map_perm |= MapPermission::W;
+        if replace {
+            self.unmap_range(vpn_range);
         }
-        if port & 0b0000_0100 == 0b0000_0100 {
-            map_perm |= MapPermission::X;
+        let mut map_area = MapArea::new(
+            start_vpn.into(),
+            vpn_range.get_end().into(),
+            MapType::Framed,
+            map_perm,
+        );
+        map_area.backing = backing;
+        map_area.mmapped = true;
+        map_area.map_frames(&mut self.page_table, frames);
+        self.areas.insert(start_vpn, Box::new(map_area));
+        self.update_peak_resident();
+        if placement == 0 {
+            0
+        } else {
+            VirtAddr::from(start_vpn).0 as isize
         }
-        let map_area = MapArea::new(va_start, va_end, MapType::Framed, map_perm);
-        if VirtAddr::from(len).ceil() > VirtPageNum(frame_remain_num()) { return -1 }
-        for vpn in map_area.vpn_range {
-            if let Some(pte) = self.page_table.find_pte(vpn) {
-                if pte.is_valid() {
-                    return -1;
-                }
+    }
+
+    /// Resize the mapping `[old_addr, old_addr + old_len)`, which must lie
+    /// in one area made by `mmap`, to `new_len` bytes, returning its address
+    /// or -1. Shrinking unmaps the pages at the end. Growing maps the pages
+    /// right above if they are free, or else, with [`MREMAP_MAYMOVE`], moves
+    /// the mapping to a free place with room, where the same frames are
+    /// mapped at new addresses. New pages of a file mapping are read in on
+    /// first access, like those `mmap` maps.
+    pub fn mremap(
+        &mut self,
+        old_addr: usize,
+        old_len: usize,
+        new_len: usize,
+        flags: usize,
+    ) -> isize {
+        let va_start = VirtAddr::from(old_addr);
+        if flags & !MREMAP_MAYMOVE != 0 || !va_start.aligned() || old_len == 0 || new_len == 0 {
+            return -1;
+        }
+        let old_end = match old_addr.checked_add(old_len) {
+            Some(old_end) => VirtAddr::from(old_end).ceil(),
+            None => return -1,
+        };
+        let new_pages = match new_len.checked_add(PAGE_SIZE - 1) {
+            Some(new_len) => new_len / PAGE_SIZE,
+            None => return -1,
+        };
+        let old_start = va_start.floor();
+        let anonymous = match self.find_area(old_start) {
+            Some(area)
+                if area.mmapped && area.shm.is_none() && old_end <= area.vpn_range.get_end() =>
+            {
+                area.backing.is_none()
+            }
+            _ => return -1,
+        };
+        let old_pages = old_end.0 - old_start.0;
+        if new_pages <= old_pages {
+            if new_pages < old_pages {
+                self.unmap_range(VPNRange::new(VirtPageNum(old_start.0 + new_pages), old_end));
             }
+            return old_addr as isize;
         }
-        self.push(map_area, None);
-        0
+        let grow = new_pages - old_pages;
+        if anonymous && grow > frame_remain_num() {
+            return -1;
+        }
+        let in_place = old_start.0 + new_pages <= Self::user_end().0
+            && !self.overlaps(VPNRange::new(old_end, VirtPageNum(old_start.0 + new_pages)));
+        let start = if in_place {
+            old_start
+        } else if flags & MREMAP_MAYMOVE != 0 {
+            match self.find_free_region(old_start, new_pages) {
+                Some(start) => start,
+                None => return -1,
+            }
+        } else {
+            return -1;
+        };
+        let new_range = VPNRange::new(start, VirtPageNum(start.0 + new_pages));
+        // 和mmap一样先备好页帧和页表节点，失败时映射保持原样
+        let frames: Vec<FrameTracker> = if anonymous {
+            match (0..grow).map(|_| frame_alloc()).collect::<Option<_>>() {
+                Some(frames) => frames,
+                None => return -1,
+            }
+        } else {
+            Vec::new()
+        };
+        if self.page_table.reserve(new_range).is_err() {
+            return -1;
+        }
+        self.split_areas_at(old_start);
+        self.split_areas_at(old_end);
+        let mut area = self.areas.remove(&old_start).unwrap();
+        if start != old_start {
+            area.move_to(&mut self.page_table, start);
+        }
+        area.grow_to(&mut self.page_table, new_range.get_end(), frames);
+        self.areas.insert(start, area);
+        self.update_peak_resident();
+        VirtAddr::from(start).0 as isize
     }
 
+    /// Map the frames of shared memory object `id` at a free place below the
+    /// mmap top, returning the start address, or `None` if there is no room.
+    pub fn attach_shm(
+        &mut self,
+        id: usize,
+        frames: &[Arc<FrameTracker>],
+        map_perm: MapPermission,
+    ) -> Option<usize> {
+        let pages = frames.len();
+        let hint = VirtPageNum(self.mmap_top().0.saturating_sub(pages));
+        let start_vpn = self.find_free_region(hint, pages)?;
+        let vpn_range = VPNRange::new(start_vpn, VirtPageNum(start_vpn.0 + pages));
+        self.page_table.reserve(vpn_range).ok()?;
+        let mut map_area = MapArea::new(
+            start_vpn.into(),
+            vpn_range.get_end().into(),
+            MapType::Framed,
+            map_perm,
+        );
+        map_area.shm = Some(id);
+        for (vpn, frame) in vpn_range.into_iter().zip(frames) {
+            map_area
+                .map_frame(&mut self.page_table, vpn, frame.clone())
+                .expect("page table nodes reserved above");
+        }
+        self.areas.insert(start_vpn, Box::new(map_area));
+        self.update_peak_resident();
+        Some(VirtAddr::from(start_vpn).0)
+    }
+
+    /// Unmap the shared memory object attached at `start`, or what is left
+    /// of it there, `false` if no object starts at `start`.
+    pub fn detach_shm(&mut self, start: usize) -> bool {
+        let va = VirtAddr::from(start);
+        if va.page_offset() != 0 {
+            return false;
+        }
+        match self.areas.get(&va.floor()) {
+            Some(area) if area.shm.is_some() => {}
+            _ => return false,
+        }
+        let mut area = self.areas.remove(&va.floor()).unwrap();
+        area.unmap(&mut self.page_table);
+        true
+    }
+
+    /// Find `pages` free pages in user space as close as possible to `hint`,
+    /// looking at the holes right above and right below it.
+    pub fn find_free_region(&self, hint: VirtPageNum, pages: usize) -> Option<VirtPageNum> {
+        // never hand out the null page
+        let lowest = VirtPageNum(1);
+        let highest = Self::user_end();
+        if pages == 0 || lowest.0 + pages > highest.0 {
+            return None;
+        }
+        let hint = VirtPageNum(hint.0.max(lowest.0).min(highest.0 - pages));
+        // upwards: skip the area holding the hint, then walk holes in order
+        let mut up = hint;
+        if let Some(area) = self.find_area(up) {
+            up = area.vpn_range.get_end();
+        }
+        let mut up_found = None;
+        for (start, area) in self.areas.range(up..) {
+            if up.0 + pages <= start.0.min(highest.0) {
+                break;
+            }
+            up = up.max(area.vpn_range.get_end());
+        }
+        if up.0 + pages <= highest.0 {
+            up_found = Some(up);
+        }
+        // downwards: the end of a candidate must stay below the next area
+        let mut down_end = VirtPageNum(hint.0 + pages);
+        let mut down_found = None;
+        for (start, area) in self.areas.range(..down_end).rev() {
+            if area.vpn_range.get_end() <= VirtPageNum(down_end.0 - pages) {
+                break;
+            }
+            down_end = down_end.min(*start);
+            if down_end.0 < lowest.0 + pages {
+                break;
+            }
+        }
+        if down_end.0 >= lowest.0 + pages {
+            down_found = Some(VirtPageNum(down_end.0 - pages));
+        }
+        match (up_found, down_found) {
+            (Some(up), Some(down)) => Some(if up.0 - hint.0 <= hint.0 - down.0 { up } else { down }),
+            (up, down) => up.or(down),
+        }
+    }
+
+    /// Map a user stack for an additional thread as high as possible, away
+    /// from the heap, with an unmapped guard page below it.
+    /// Returns the bottom and top of the stack.
+    pub fn insert_thread_stack(&mut self) -> Result<(usize, usize), OutOfMemory> {
+        let pages = USER_STACK_SIZE / PAGE_SIZE + 1;
+        let hint = VirtPageNum(self.mmap_top().0 - pages);
+        let guard = self.find_free_region(hint, pages).ok_or(OutOfMemory)?;
+        let ustack_bottom = VirtAddr::from(VirtPageNum(guard.0 + 1)).0;
+        let ustack_top = ustack_bottom + USER_STACK_SIZE;
+        self.insert_framed_area(
+            ustack_bottom.into(),
+            ustack_top.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        )?;
+        Ok((ustack_bottom, ustack_top))
+    }
+
+    /// Move the program break by `increment` bytes, mapping or unmapping
+    /// heap pages as needed, and return the old break.
+    pub fn sbrk(&mut self, increment: isize) -> Option<usize> {
+        let old_brk = self.program_brk;
+        let new_brk = (old_brk as isize).checked_add(increment)?;
+        if new_brk < self.heap_bottom as isize {
+            return None;
+        }
+        let new_brk = new_brk as usize;
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        let old_end = self.areas.get(&heap_start)?.vpn_range.get_end();
+        if new_end > old_end {
+            // the pages to grow into must be free
+            let grow = VPNRange::new(old_end, new_end);
+            // the heap itself may show up here while it is still empty
+            let blocked = self
+                .areas_overlapping(grow)
+                .any(|area| area.vpn_range.get_start() != heap_start);
+            if new_end > Self::user_end() || blocked {
+                return None;
+            }
+        }
+        let heap = self.areas.get_mut(&heap_start)?;
+        if new_end > old_end {
+            heap.append_to(&mut self.page_table, new_end).ok()?;
+            self.update_peak_resident();
+        } else if new_end < old_end {
+            heap.shrink_to(&mut self.page_table, new_end);
+        }
+        self.program_brk = new_brk;
+        Some(old_brk)
+    }
+
+    /// Back the faulting page at `va` with a fresh frame if it lies in a
+    /// lazily populated area such as the reserved user stack, or is swapped
+    /// out. A file-backed area gets the page from the page cache, shared or
+    /// copied.
+    pub fn handle_page_fault(&mut self, va: VirtAddr) -> Result<(), PageFaultError> {
+        let major = self.resolve_page_fault(va)?;
+        if major {
+            self.stats.major_faults += 1;
+        } else {
+            self.stats.minor_faults += 1;
+        }
+        self.update_peak_resident();
+        Ok(())
+    }
+    /// [`MemorySet::handle_page_fault`], returning whether the page had to
+    /// be read in from the disk
+    fn resolve_page_fault(&mut self, va: VirtAddr) -> Result<bool, PageFaultError> {
+        let vpn = va.floor();
+        if Some(vpn) == self.stack_guard {
+            return Err(PageFaultError::StackOverflow);
+        }
+        let start = match self.find_area(vpn) {
+            Some(area)
+                if area.map_type == MapType::Framed && !area.data_frames.contains_key(&vpn) =>
+            {
+                area.vpn_range.get_start()
+            }
+            _ => return Err(PageFaultError::Invalid),
+        };
+        let area = self.areas.get_mut(&start).unwrap();
+        if let Some(pte) = self.page_table.translate(vpn).filter(|pte| pte.is_swapped()) {
+            return area
+                .swap_in(&mut self.page_table, vpn, pte.swap_slot())
+                .map(|_| true)
+                .map_err(|_| PageFaultError::OutOfMemory);
+        }
+        let mut major = false;
+        let cached = match &area.backing {
+            Some(backing) => {
+                let page = area.file_page(vpn).unwrap();
+                major = !page_cache::contains(&backing.inode, page);
+                let frame = page_cache::get_page(&backing.inode, page);
+                Some(frame.ok_or(PageFaultError::OutOfMemory)?)
+            }
+            None => None,
+        };
+        match cached {
+            Some(frame) if area.backing.as_ref().unwrap().shared => {
+                area.map_frame(&mut self.page_table, vpn, frame)
+            }
+            // a private copy, later stores stay in the address space
+            Some(frame) => area.map_one(&mut self.page_table, vpn).map(|_| {
+                area.data_frames[&vpn]
+                    .ppn
+                    .get_bytes_array()
+                    .copy_from_slice(frame.ppn.get_bytes_array())
+            }),
+            None => area.map_one(&mut self.page_table, vpn),
+        }
+        .map(|_| major)
+        .map_err(|_| PageFaultError::OutOfMemory)
+    }
+
+    /// Unmap the user pages in `[start, start + len)`, splitting the areas
+    /// that are only partially covered and keeping their remaining pages.
     pub fn munmap(&mut self, start: usize, len: usize) -> isize {
-        for map_area in self.areas.iter_mut() {
-            if VirtAddr::from(map_area.vpn_range.get_start()) == VirtAddr::from(start) &&
-                VirtAddr::from(map_area.vpn_range.get_end()) == VirtAddr::from(start + len) {
-                    map_area.unmap(&mut self.page_table);
-                    return 0;
-                }
+        let vpn_range = match self.user_range(start, len) {
+            Some(vpn_range) => vpn_range,
+            None => return -1,
+        };
+        self.unmap_range(vpn_range);
+        0
+    }
+
+    /// Unmap everything inside `vpn_range`, splitting areas at its borders.
+    fn unmap_range(&mut self, vpn_range: VPNRange) {
+        self.split_areas_at(vpn_range.get_start());
+        self.split_areas_at(vpn_range.get_end());
+        let inside: Vec<VirtPageNum> = self
+            .areas_overlapping(vpn_range)
+            .map(|area| area.vpn_range.get_start())
+            .collect();
+        for start in inside {
+            let mut area = self.areas.remove(&start).unwrap();
+            area.unmap(&mut self.page_table);
+        }
+    }
+
+    /// whether any area shares a page with `vpn_range`
+    fn overlaps(&self, vpn_range: VPNRange) -> bool {
+        self.areas_overlapping(vpn_range).next().is_some()
+    }
+
+    /// first page above the range that user mappings may use
+    fn user_end() -> VirtPageNum {
+        VirtAddr::from(USER_SPACE_END).floor()
+    }
+
+    /// first page above the mmap region, see [`UserLayout::mmap_gap`]
+    fn mmap_top(&self) -> VirtPageNum {
+        VirtPageNum(Self::user_end().0 - self.layout.mmap_gap)
+    }
+
+    /// Change the permission of the user pages in `[start, start + len)`,
+    /// all of which must lie in user areas.
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> isize {
+        let map_perm = match MapPermission::from_port(port) {
+            Some(map_perm) => map_perm,
+            None => return -1,
+        };
+        let vpn_range = match self.user_range(start, len) {
+            Some(vpn_range) => vpn_range,
+            None => return -1,
+        };
+        self.split_areas_at(vpn_range.get_start());
+        self.split_areas_at(vpn_range.get_end());
+        let pte_flags = PTEFlags::from_bits(map_perm.bits).unwrap();
+        for (_, area) in self.areas.range_mut(vpn_range.get_start()..vpn_range.get_end()) {
+            area.map_perm = map_perm;
+            for vpn in area.vpn_range {
+                self.page_table.remap(vpn, pte_flags);
+            }
+        }
+        0
+    }
+
+    /// Page range of `[start, start + len)` if `start` is aligned, `len` is
+    /// not zero and every page in it lies in a user area, populated yet or
+    /// not.
+    fn user_range(&self, start: usize, len: usize) -> Option<VPNRange> {
+        let va_start = VirtAddr::from(start);
+        if !va_start.aligned() || len == 0 {
+            return None;
+        }
+        let end = start.checked_add(len)?;
+        let vpn_range = VPNRange::new(va_start.floor(), VirtAddr::from(end).ceil());
+        for vpn in vpn_range {
+            let in_user_area = self
+                .find_area(vpn)
+                .map_or(false, |area| area.map_perm.contains(MapPermission::U));
+            if !in_user_area {
+                return None;
+            }
+        }
+        Some(vpn_range)
+    }
+
+    /// Split the area strictly containing `vpn`, if any, into two areas
+    /// meeting at `vpn`.
+    fn split_areas_at(&mut self, vpn: VirtPageNum) {
+        let back = self
+            .areas
+            .range_mut(..vpn)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| vpn < area.vpn_range.get_end())
+            .map(|area| area.split_off(vpn));
+        if let Some(back) = back {
+            self.areas.insert(vpn, Box::new(back));
         }
-        -1
     }
 
     /// Include sections in elf and trampoline and TrapContext and user stack,
     /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), ElfError> {
+        let elf = ElfFile::new(elf_data).map_err(|_| ElfError::BadHeader)?;
+        check_elf(&elf)?;
+        let mut memory_set = Self::new_bare()?;
+        memory_set.layout = user_layout();
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-        let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
+        let ph_count = elf.header.pt2.ph_count();
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            if ph.get_type().unwrap() == program::Type::Load {
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
                 let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
                 let mut map_perm = MapPermission::U;
@@ -210,29 +1013,52 @@ impl MemorySet {
                 }
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
+                memory_set.try_push(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                )?;
             }
         }
-        // map user stack with U flags
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
+        // reserve USER_STACK_MAX_SIZE of user stack with U flags, only the
+        // top USER_STACK_SIZE is mapped now and the rest on page fault
+        let guard_vpn = VirtPageNum(max_end_vpn.0 + memory_set.layout.stack_gap);
+        let guard_va: VirtAddr = guard_vpn.into();
+        let mut user_stack_bottom: usize = guard_va.into();
         // guard page
+        memory_set.stack_guard = Some(guard_vpn);
         user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(
+        let user_stack_top = user_stack_bottom + USER_STACK_MAX_SIZE;
+        let mut stack_area = MapArea::new(
+            user_stack_bottom.into(),
+            user_stack_top.into(),
+            MapType::Framed,
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        let stack_mapped = VPNRange::new(
+            VirtAddr::from(user_stack_top - USER_STACK_SIZE).floor(),
+            VirtAddr::from(user_stack_top).floor(),
+        );
+        for vpn in stack_mapped {
+            stack_area.map_one(&mut memory_set.page_table, vpn)?;
+        }
+        memory_set
+            .areas
+            .insert(stack_area.vpn_range.get_start(), Box::new(stack_area));
+        // map an empty heap above the user stack, grown by sbrk
+        let heap_bottom = user_stack_top + memory_set.layout.heap_gap * PAGE_SIZE;
+        memory_set.heap_bottom = heap_bottom;
+        memory_set.program_brk = heap_bottom;
+        memory_set.try_push(
             MapArea::new(
-                user_stack_bottom.into(),
-                user_stack_top.into(),
+                heap_bottom.into(),
+                heap_bottom.into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
+        )?;
         // map TrapContext
-        memory_set.push(
+        memory_set.try_push(
             MapArea::new(
                 TRAP_CONTEXT.into(),
                 TRAMPOLINE.into(),
@@ -240,12 +1066,186 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
-        (
+        )?;
+        if cfg!(debug_assertions) {
+            memory_set.audit_user_mappings();
+        }
+        Ok((
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
+    }
+    /// Look through the page table of a user space for mappings breaking the
+    /// split between user and kernel: a page of the user half without U, or
+    /// one with U outside of it, onto the kernel image or onto a page table
+    /// node. Each is logged and the number of them returned.
+    pub fn audit_user_mappings(&self) -> usize {
+        let kernel_image = stext as usize..ekernel as usize;
+        let mut bad = 0;
+        for (vpn, ppn, flags) in self.page_table.iter_mapped() {
+            let va = VirtAddr::from(vpn).0;
+            let pa = PhysAddr::from(ppn).0;
+            let user = flags.contains(PTEFlags::U);
+            let problem = if va < USER_SPACE_END && !user {
+                Some("user page without U")
+            } else if va >= USER_SPACE_END && user {
+                Some("kernel page with U")
+            } else if user && kernel_image.contains(&pa) {
+                Some("U page onto the kernel image")
+            } else if user && self.page_table.is_node(ppn) {
+                Some("U page onto a page table node")
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                error!("[kernel] {}: va {:#x} -> pa {:#x}, {:?}", problem, va, pa, flags);
+                bad += 1;
+            }
+        }
+        bad
+    }
+    /// Copy an identical user space, only the pages already populated in
+    /// `user_space` are allocated and copied. Nothing is left behind when
+    /// out of frames.
+    pub fn from_existed_user(user_space: &MemorySet) -> Result<MemorySet, OutOfMemory> {
+        let mut memory_set = Self::new_bare()?;
+        // map trampoline
+        memory_set.map_trampoline()?;
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.program_brk = user_space.program_brk;
+        memory_set.stack_guard = user_space.stack_guard;
+        memory_set.layout = user_space.layout;
+        // copy data sections/trap_context/user_stack
+        for (start, area) in user_space.areas.iter() {
+            let mut new_area = MapArea::from_another(area);
+            let shared = area.backing.as_ref().map_or(false, |backing| backing.shared)
+                || area.shm.is_some();
+            for (&vpn, src_frame) in area.data_frames.iter() {
+                if shared {
+                    // both map the same page of the page cache or object
+                    new_area.map_frame(&mut memory_set.page_table, vpn, src_frame.clone())?;
+                    continue;
+                }
+                new_area.map_one(&mut memory_set.page_table, vpn)?;
+                // copy data from another space
+                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                dst_ppn
+                    .get_bytes_array()
+                    .copy_from_slice(src_frame.ppn.get_bytes_array());
+            }
+            // pages swapped out in the parent are read into the copy
+            for vpn in area.vpn_range {
+                let slot = match user_space.translate(vpn) {
+                    Some(pte) if pte.is_swapped() => pte.swap_slot(),
+                    _ => continue,
+                };
+                new_area.map_one(&mut memory_set.page_table, vpn)?;
+                swap_read(slot, memory_set.translate(vpn).unwrap().ppn());
+            }
+            memory_set.areas.insert(*start, Box::new(new_area));
+        }
+        memory_set.update_peak_resident();
+        if cfg!(debug_assertions) {
+            memory_set.audit_user_mappings();
+        }
+        Ok(memory_set)
+    }
+    /// Swap out up to `want` anonymous user pages from `from` on, going in
+    /// address order like the hand of a clock: a page accessed since the
+    /// hand last passed it gets its A bit cleared and a second chance.
+    /// Frames `pinned` holds on to stay where they are, it is asked once a
+    /// page is unmapped so that nobody can start pinning it afterwards.
+    /// Returns how many pages went out and where the hand stopped, `None`
+    /// once it went past the last page.
+    pub fn swap_out(
+        &mut self,
+        from: VirtPageNum,
+        want: usize,
+        pinned: &dyn Fn(PhysPageNum) -> bool,
+    ) -> (usize, Option<VirtPageNum>) {
+        let mut swapped = 0;
+        for area in self.areas.values_mut() {
+            if !area.swappable() || area.vpn_range.get_end() <= from {
+                continue;
+            }
+            let vpns: Vec<VirtPageNum> = area.data_frames.range(from..).map(|(vpn, _)| *vpn).collect();
+            for vpn in vpns {
+                if swapped == want {
+                    return (swapped, Some(vpn));
+                }
+                if self.page_table.accessed(vpn) {
+                    self.page_table.clear_accessed(vpn);
+                    continue;
+                }
+                match area.swap_out_one(&mut self.page_table, vpn, pinned) {
+                    Some(true) => swapped += 1,
+                    Some(false) => {}
+                    // the swap area is full
+                    None => return (swapped, Some(vpn)),
+                }
+            }
+        }
+        (swapped, None)
+    }
+    /// Report the A and D bits of every present user page to `f` as
+    /// `f(vpn, accessed, dirty)`, clearing the A bits so that the next scan
+    /// sees the pages used in between.
+    pub fn scan_access(&mut self, mut f: impl FnMut(VirtPageNum, bool, bool)) {
+        for area in self.areas.values() {
+            if area.map_type != MapType::Framed || !area.map_perm.contains(MapPermission::U) {
+                continue;
+            }
+            for &vpn in area.data_frames.keys() {
+                let accessed = self.page_table.accessed(vpn);
+                f(vpn, accessed, self.page_table.dirty(vpn));
+                if accessed {
+                    self.page_table.clear_accessed(vpn);
+                }
+            }
+        }
+    }
+    /// Unmap and free every area, keeping only the page table itself.
+    pub fn recycle_data_pages(&mut self) {
+        for (_, mut area) in core::mem::take(&mut self.areas) {
+            area.unmap(&mut self.page_table);
+        }
+    }
+    /// [`recycle_data_pages`](Self::recycle_data_pages), then check that
+    /// nothing was left behind: no page but the trampoline may still be
+    /// mapped, and every frame only this address space held has to be free
+    /// again. Panics after listing the leaked vpn -> ppn mappings.
+    #[cfg(feature = "frame-debug")]
+    pub fn recycle_data_pages_audited(&mut self, task: usize) {
+        use super::frame_debug::allocation_id;
+        let owned: Vec<(VirtPageNum, PhysPageNum, Option<usize>)> = self
+            .areas
+            .values()
+            .flat_map(|area| area.data_frames.iter())
+            .filter(|(_, frame)| Arc::strong_count(frame) == 1)
+            .map(|(&vpn, frame)| (vpn, frame.ppn, allocation_id(frame.ppn.0)))
+            .collect();
+        let remain = frame_remain_num();
+        self.recycle_data_pages();
+        let trampoline = VirtAddr::from(TRAMPOLINE).floor();
+        let mut leaked = 0;
+        for (vpn, ppn, flags) in self.page_table.iter_mapped() {
+            if vpn != trampoline {
+                error!("[frame-debug] task {} still maps {:?} -> {:?} {:?}", task, vpn, ppn, flags);
+                leaked += 1;
+            }
+        }
+        // other harts allocate in the meantime, so only a shortfall is
+        // looked into, frame by frame
+        if frame_remain_num() < remain + owned.len() {
+            for (vpn, ppn, id) in owned {
+                if id.is_some() && allocation_id(ppn.0) == id {
+                    error!("[frame-debug] task {} did not free {:?} -> {:?}", task, vpn, ppn);
+                    leaked += 1;
+                }
+            }
+        }
+        assert!(leaked == 0, "task {} leaked {} pages at exit", task, leaked);
     }
     pub fn activate(&self) {
         let satp = self.page_table.token();
@@ -257,14 +1257,40 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// The physical address `va` is mapped to, if it is mapped
+    pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
+        self.translate(va.floor()).map(|pte| {
+            let pa: PhysAddr = pte.ppn().into();
+            PhysAddr(pa.0 + va.page_offset())
+        })
+    }
+}
+
+/// The file the pages of an area are read from on first access
+#[derive(Clone)]
+pub struct FileBacking {
+    pub inode: Arc<Inode>,
+    /// offset in the file of the first page of the area, page aligned
+    pub offset: usize,
+    /// whether the pages of the page cache are mapped themselves, or
+    /// copies of them
+    pub shared: bool,
 }
 
 /// map area structure, controls a contiguous piece of virtual memory
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// frames of the pages mapped so far, those of a shared file-backed
+    /// area belong to the page cache as well
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// `None` for anonymous memory
+    backing: Option<FileBacking>,
+    /// created by `mmap`, rather than for the ELF, the stacks or the heap
+    mmapped: bool,
+    /// the shared memory object whose frames the area maps, see `mm::shm`
+    shm: Option<usize>,
 }
 
 impl MapArea {
@@ -281,43 +1307,315 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            backing: None,
+            mmapped: false,
+            shm: None,
+        }
+    }
+    /// An empty area with the same range, type and permission as `another`.
+    pub fn from_another(another: &MapArea) -> Self {
+        Self {
+            vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
+            data_frames: BTreeMap::new(),
+            map_type: another.map_type,
+            map_perm: another.map_perm,
+            backing: another.backing.clone(),
+            mmapped: another.mmapped,
+            shm: another.shm,
+        }
+    }
+    /// Split the area at `at`, keeping `[start, at)` in `self` and returning
+    /// a new area for `[at, end)` which takes over the frames in it.
+    pub fn split_off(&mut self, at: VirtPageNum) -> Self {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        assert!(start < at && at < end, "split {:?} outside of area", at);
+        self.vpn_range = VPNRange::new(start, at);
+        let backing = self.backing.clone().map(|mut backing| {
+            backing.offset += (at.0 - start.0) * PAGE_SIZE;
+            backing
+        });
+        Self {
+            vpn_range: VPNRange::new(at, end),
+            data_frames: self.data_frames.split_off(&at),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            backing,
+            mmapped: self.mmapped,
+            shm: self.shm,
+        }
+    }
+    /// Grow the area upwards to `new_end`, mapping the new pages, or stay
+    /// as it was when out of frames.
+    pub fn append_to(
+        &mut self,
+        page_table: &mut PageTable,
+        new_end: VirtPageNum,
+    ) -> Result<(), OutOfMemory> {
+        let old_end = self.vpn_range.get_end();
+        for vpn in VPNRange::new(old_end, new_end) {
+            if let Err(err) = self.map_one(page_table, vpn) {
+                self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+                self.shrink_to(page_table, old_end);
+                return Err(err);
+            }
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+        Ok(())
+    }
+    /// Move the area to begin at `start`, along with its mappings and its
+    /// swapped out pages. The page table nodes there must be reserved.
+    fn move_to(&mut self, page_table: &mut PageTable, start: VirtPageNum) {
+        let old_start = self.vpn_range.get_start();
+        let moved = |vpn: VirtPageNum| VirtPageNum(vpn.0 - old_start.0 + start.0);
+        for vpn in self.vpn_range {
+            page_table.move_entry(vpn, moved(vpn));
+        }
+        self.data_frames = core::mem::take(&mut self.data_frames)
+            .into_iter()
+            .map(|(vpn, frame)| (moved(vpn), frame))
+            .collect();
+        self.vpn_range = VPNRange::new(start, moved(self.vpn_range.get_end()));
+    }
+    /// Grow the area up to `new_end`, mapping the new pages onto `frames`,
+    /// or leaving them to page faults if there are none. The page table
+    /// nodes must be reserved.
+    fn grow_to(
+        &mut self,
+        page_table: &mut PageTable,
+        new_end: VirtPageNum,
+        frames: Vec<FrameTracker>,
+    ) {
+        let old_end = self.vpn_range.get_end();
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+        if frames.is_empty() {
+            return;
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table
+            .map_range(old_end, frames.iter().map(|frame| frame.ppn), pte_flags)
+            .expect("page table nodes are not reserved");
+        for (vpn, frame) in VPNRange::new(old_end, new_end).into_iter().zip(frames) {
+            self.data_frames.insert(vpn, Arc::new(frame));
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        let ppn: PhysPageNum;
+    /// Shrink the area down to `new_end`, unmapping the pages above it.
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        let old_end = self.vpn_range.get_end();
+        for vpn in VPNRange::new(new_end, old_end) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+    pub fn map_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Result<(), OutOfMemory> {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         match self.map_type {
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
+            MapType::Identical | MapType::Mmio(_) => {
+                page_table.map(vpn, self.linear_ppn(vpn), self.pte_flags())?;
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let frame = frame_alloc().ok_or(OutOfMemory)?;
+                // 页表节点分配失败时frame随之回收
+                page_table.map(vpn, frame.ppn, pte_flags)?;
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        Ok(())
     }
     #[allow(unused)]
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        #[allow(clippy::single_match)]
-        match self.map_type {
-            MapType::Framed => {
-                self.data_frames.remove(&vpn);
+        if self.map_type == MapType::Framed {
+            match self.data_frames.remove(&vpn) {
+                Some(_) => self.mark_dirty(page_table, vpn),
+                None => {
+                    if let Some(slot) = page_table.take_swapped(vpn) {
+                        swap_free(slot);
+                    }
+                    // otherwise a never touched page of a lazily populated area
+                    return;
+                }
             }
-            _ => {}
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+    /// Tell the page cache about the page at `vpn` of a shared file-backed
+    /// area if it was written to, so it is written back later.
+    fn mark_dirty(&self, page_table: &PageTable, vpn: VirtPageNum) {
+        let backing = match &self.backing {
+            Some(backing) if backing.shared => backing,
+            _ => return,
+        };
+        // the hardware sets D on the first store through the mapping
+        if page_table.dirty(vpn) {
+            page_cache::mark_dirty(&backing.inode, self.file_page(vpn).unwrap());
+        }
+    }
+    /// Whether the pages of the area may be swapped out: anonymous user
+    /// memory, file pages copied by a private mapping included, but not
+    /// shared memory objects
+    fn swappable(&self) -> bool {
+        self.map_type == MapType::Framed
+            && self.map_perm.contains(MapPermission::U)
+            && !self.backing.as_ref().map_or(false, |backing| backing.shared)
+            && self.shm.is_none()
+    }
+    /// Move the page at `vpn` out to the swap area. `Some(false)` if the
+    /// frame turned out to be `pinned` and stays mapped, `None` if the swap
+    /// area is full.
+    fn swap_out_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        pinned: &dyn Fn(PhysPageNum) -> bool,
+    ) -> Option<bool> {
+        let slot = swap_alloc()?;
+        // 先让映射失效，此后没有人能再写这一页，然后才写出去
+        page_table.swap_out(vpn, slot);
+        let ppn = self.data_frames[&vpn].ppn;
+        if pinned(ppn) {
+            // the page table node is still there, the swapped entry is replaced
+            page_table
+                .map(vpn, ppn, self.pte_flags())
+                .expect("page table node is still there");
+            swap_free(slot);
+            return Some(false);
         }
+        let frame = self.data_frames.remove(&vpn).unwrap();
+        swap_write(slot, frame.ppn);
+        Some(true)
+    }
+    /// Read the page at `vpn` back from `slot` into a fresh frame.
+    fn swap_in(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        slot: usize,
+    ) -> Result<(), OutOfMemory> {
+        let frame = frame_alloc().ok_or(OutOfMemory)?;
+        swap_read(slot, frame.ppn);
+        // the page table node is still there, the swapped entry is replaced
+        self.map_frame(page_table, vpn, Arc::new(frame))?;
+        swap_free(slot);
+        Ok(())
+    }
+    /// index of the page of the backing file mapped at `vpn`
+    fn file_page(&self, vpn: VirtPageNum) -> Option<usize> {
+        let backing = self.backing.as_ref()?;
+        Some(backing.offset / PAGE_SIZE + (vpn.0 - self.vpn_range.get_start().0))
+    }
+    /// Map `vpn` onto a frame someone else holds as well, such as a page
+    /// of the page cache.
+    pub fn map_frame(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        frame: Arc<FrameTracker>,
+    ) -> Result<(), OutOfMemory> {
+        assert_eq!(self.map_type, MapType::Framed);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.map(vpn, frame.ppn, pte_flags)?;
+        self.data_frames.insert(vpn, frame);
+        Ok(())
+    }
+    /// Identical and MMIO mappings use a huge page wherever a whole aligned
+    /// 2 MiB block lies inside the area and maps an aligned one.
+    fn huge_at(&self, vpn: VirtPageNum) -> bool {
+        self.map_type != MapType::Framed
+            && vpn.0 % HUGE_PAGE_PAGES == 0
+            && self.linear_ppn(vpn).0 % HUGE_PAGE_PAGES == 0
+            && vpn.0 + HUGE_PAGE_PAGES <= self.vpn_range.get_end().0
+    }
+    /// the frame `vpn` maps to in an area that is not framed
+    fn linear_ppn(&self, vpn: VirtPageNum) -> PhysPageNum {
+        match self.map_type {
+            MapType::Identical => PhysPageNum(vpn.0),
+            MapType::Mmio(offset) => PhysPageNum(vpn.0.wrapping_sub(offset)),
+            MapType::Framed => unreachable!("framed pages have no fixed frame"),
+        }
+    }
+    /// flags of the entries of the area, with A and D preset for devices
+    fn pte_flags(&self) -> PTEFlags {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        match self.map_type {
+            MapType::Mmio(_) => pte_flags | PTEFlags::A | PTEFlags::D,
+            _ => pte_flags,
+        }
+    }
+    /// Map the pages of a framed area onto `frames`, one for each page in
+    /// order. The page table nodes must have been reserved beforehand.
+    pub fn map_frames(&mut self, page_table: &mut PageTable, frames: Vec<FrameTracker>) {
+        assert_eq!(self.map_type, MapType::Framed);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table
+            .map_range(self.vpn_range.get_start(), frames.iter().map(|frame| frame.ppn), pte_flags)
+            .expect("page table nodes are not reserved");
+        for (vpn, frame) in self.vpn_range.into_iter().zip(frames) {
+            self.data_frames.insert(vpn, Arc::new(frame));
+        }
+    }
+    /// Map every page of the area. When out of frames, the pages mapped so
+    /// far are unmapped again.
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), OutOfMemory> {
+        let pte_flags = self.pte_flags();
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        if self.map_type == MapType::Framed {
+            // 先检查剩余页帧，巨大的区域连这个Vec都分配不出来
+            if end.0 - start.0 > frame_remain_num() {
+                return Err(OutOfMemory);
+            }
+            let mut frames = Vec::with_capacity(end.0 - start.0);
+            for _ in start.0..end.0 {
+                frames.push(frame_alloc().ok_or(OutOfMemory)?);
+            }
+            // 页表节点分配失败时frames随之回收
+            page_table.map_range(start, frames.iter().map(|frame| frame.ppn), pte_flags)?;
+            for (vpn, frame) in self.vpn_range.into_iter().zip(frames) {
+                self.data_frames.insert(vpn, Arc::new(frame));
+            }
+            return Ok(());
+        }
+        let mut vpn = start;
+        while vpn < end {
+            let mapped = if self.huge_at(vpn) {
+                page_table
+                    .map_huge(vpn, self.linear_ppn(vpn), pte_flags)
+                    .map(|_| VirtPageNum(vpn.0 + HUGE_PAGE_PAGES))
+            } else {
+                // 到下一个大页边界为止的普通页一次映射
+                let run_end = ((vpn.0 / HUGE_PAGE_PAGES + 1) * HUGE_PAGE_PAGES).min(end.0);
+                let frames = (vpn.0..run_end).map(|vpn| self.linear_ppn(VirtPageNum(vpn)));
+                page_table
+                    .map_range(vpn, frames, pte_flags)
+                    .map(|_| VirtPageNum(run_end))
+            };
+            match mapped {
+                Ok(next) => vpn = next,
+                Err(err) => {
+                    self.unmap_until(page_table, vpn);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
     }
     #[allow(unused)]
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.unmap_one(page_table, vpn);
+        self.unmap_until(page_table, self.vpn_range.get_end());
+    }
+    /// unmap the pages of the area below `end`
+    fn unmap_until(&mut self, page_table: &mut PageTable, end: VirtPageNum) {
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < end {
+            if self.huge_at(vpn) {
+                page_table.unmap_huge(vpn);
+                vpn = VirtPageNum(vpn.0 + HUGE_PAGE_PAGES);
+            } else {
+                self.unmap_one(page_table, vpn);
+                vpn.step();
+            }
         }
     }
     /// data: start-aligned but maybe with shorter length
@@ -345,10 +1643,12 @@ impl MapArea {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// map type for memory set: identical or framed
+/// map type for memory set: identical, framed or device registers
 pub enum MapType {
     Identical,
     Framed,
+    /// device registers, page `vpn` mapping frame `vpn - offset`
+    Mmio(usize),
 }
 
 bitflags! {
@@ -361,6 +1661,27 @@ bitflags! {
     }
 }
 
+impl MapPermission {
+    /// user permission from the `port` argument of mmap-like syscalls,
+    /// where bit 0/1/2 stand for R/W/X and at least one of them is set
+    pub fn from_port(port: usize) -> Option<Self> {
+        if (port & !0b0000_0111 != 0) || (port & 0b0000_0111 == 0) {
+            return None;
+        }
+        let mut map_perm = MapPermission::U;
+        if port & 0b0000_0001 == 0b0000_0001 {
+            map_perm |= MapPermission::R;
+        }
+        if port & 0b0000_0010 == 0b0000_0010 {
+            map_perm |= MapPermission::W;
+        }
+        if port & 0b0000_0100 == 0b0000_0100 {
+            map_perm |= MapPermission::X;
+        }
+        Some(map_perm)
+    }
+}
+
 #[allow(unused)]
 pub fn remap_test() {
     let mut kernel_space = KERNEL_SPACE.lock();
@@ -383,4 +1704,68 @@ pub fn remap_test() {
         .unwrap()
         .executable());
     info!("remap_test passed!");
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "board_test")]
+/// a user space with `pages` framed pages from `start`, as a fork would see it
+fn test_user_space(start: usize, pages: usize) -> MemorySet {
+    let mut memory_set = MemorySet::new_bare().unwrap();
+    memory_set
+        .insert_framed_area(
+            start.into(),
+            (start + pages * PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        )
+        .unwrap();
+    memory_set
+}
+
+#[cfg(feature = "board_test")]
+/// Copy an address space as fork does: same data, separate frames.
+pub fn memory_set_clone_test() {
+    let start = 0x1000_0000;
+    let parent = test_user_space(start, 2);
+    let parent_ppn = parent.translate(VirtAddr::from(start).floor()).unwrap().ppn();
+    parent_ppn.get_bytes_array()[..4].copy_from_slice(b"fork");
+    let child = MemorySet::from_existed_user(&parent).unwrap();
+    let child_ppn = child.translate(VirtAddr::from(start).floor()).unwrap().ppn();
+    assert!(child_ppn != parent_ppn);
+    assert_eq!(&child_ppn.get_bytes_array()[..4], b"fork");
+    parent_ppn.get_bytes_array()[0] = b'F';
+    assert_eq!(child_ppn.get_bytes_array()[0], b'f');
+    info!("memory_set_clone_test passed!");
+}
+
+#[cfg(feature = "board_test")]
+/// A user buffer crossing a page boundary is split in two at it, and what
+/// is written through it lands in both pages.
+pub fn translated_byte_buffer_test() {
+    use super::{translated_byte_buffer, TranslateError, UserBuffer};
+    let start = 0x1000_0000;
+    let memory_set = test_user_space(start, 2);
+    let token = memory_set.token();
+    let ptr = (start + PAGE_SIZE - 3) as *const u8;
+    let buffers = translated_byte_buffer(token, ptr, 6).unwrap();
+    assert_eq!(buffers.len(), 2);
+    assert_eq!(buffers[0].len(), 3);
+    assert_eq!(buffers[1].len(), 3);
+    assert_eq!(UserBuffer::new(buffers).write(b"abcdef"), 6);
+    let first = memory_set.translate(VirtAddr::from(start).floor()).unwrap().ppn();
+    let second = memory_set
+        .translate(VirtAddr::from(start + PAGE_SIZE).floor())
+        .unwrap()
+        .ppn();
+    assert_eq!(&first.get_bytes_array()[PAGE_SIZE - 3..], b"abc");
+    assert_eq!(&second.get_bytes_array()[..3], b"def");
+    // a whole page is one buffer
+    let buffers = translated_byte_buffer(token, start as *const u8, PAGE_SIZE).unwrap();
+    assert_eq!(buffers.len(), 1);
+    assert_eq!(buffers[0].len(), PAGE_SIZE);
+    // running off the end of the area
+    let past_end = (start + 2 * PAGE_SIZE - 1) as *const u8;
+    assert!(matches!(
+        translated_byte_buffer(token, past_end, 2),
+        Err(TranslateError::Unmapped(_))
+    ));
+    info!("translated_byte_buffer_test passed!");
+}
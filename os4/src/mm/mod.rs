@@ -8,22 +8,59 @@
 
 
 mod address;
+mod asid;
+mod aslr;
 mod frame_allocator;
+#[cfg(feature = "frame-debug")]
+mod frame_debug;
 mod heap_allocator;
 mod memory_set;
+mod observer;
+pub mod page_cache;
 mod page_table;
+mod shm;
+mod slab;
+mod swap;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, frame_remain_num, FrameTracker};
+pub use asid::{flush_tlb_asid, flush_tlb_page};
+use asid::{AsidHandle, ASID_MASK, ASID_SHIFT};
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, frame_free_num, frame_remain_num, FrameTracker, OutOfMemory};
+pub use frame_allocator::{frame_allocator_stats, reserved_frames, set_frame_low_watermark, FrameAllocatorStats};
+#[cfg(feature = "frame-debug")]
+pub use frame_debug::{dump_task_frames, set_frame_owner_task};
 pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, translated_assign_ptr, PageTableEntry};
-use page_table::{PTEFlags, PageTable};
+#[cfg(feature = "board_test")]
+pub use frame_allocator::frame_allocator_test;
+#[cfg(feature = "board_test")]
+pub use heap_allocator::heap_test;
+pub use heap_allocator::{heap_stats, HeapStats};
+pub use slab::{slab_stats, SlabStats};
+#[cfg(feature = "board_test")]
+pub use memory_set::{memory_set_clone_test, translated_byte_buffer_test};
+#[cfg(feature = "board_test")]
+pub use page_table::page_table_test;
+pub use memory_set::{ElfError, FileBacking, MapPermission, MemStats, MemorySet, PageFaultError, KERNEL_SPACE};
+pub use memory_set::{UserLayout, MAP_FILE, MAP_SHARED};
+pub use memory_set::{MapRegion, REGION_ANONYMOUS, REGION_FILE_PRIVATE, REGION_FILE_SHARED, REGION_SHM, REGION_UNTRACKED};
+pub use observer::{notify_mm_observer, set_mm_observer, PID_TRACER};
+pub use shm::{shm_attached, shm_create, shm_frames};
+pub use swap::swap_free_slots;
+pub use page_table::{translated_byte_buffer, translated_str, copy_from_user, copy_to_user, PageTableEntry, TranslateError};
+pub use page_table::{translated_phys_addr, user_page_writable};
+pub use page_table::UserBuffer;
+use page_table::{PTEFlags, PageTable, HUGE_PAGE_PAGES};
 
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
+    asid::init_asid_allocator();
+}
+
+/// Switch a secondary hart to the kernel space set up by the boot hart.
+pub fn init_hart() {
+    KERNEL_SPACE.lock().activate();
 }
@@ -0,0 +1,94 @@
+//! Hooks on the paging activity of processes
+//!
+//! An [`MmObserver`] set by [`set_mm_observer`] hears about every mapping
+//! a process makes or drops, every page fault it takes, and the pages a
+//! child gets copied at fork, which stands in for copy-on-write as the
+//! kernel copies the whole address space up front. The events are reported
+//! by the syscalls and the fault handler once the address space has been
+//! changed, with no lock held.
+//!
+//! [`PidTracer`] is the one built in: `mmtrace=<pid>` on the kernel command
+//! line prints the events of that process.
+
+use super::PageFaultError;
+use crate::sync::SpinNoIrq;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Callbacks on the paging events of process `pid`, all of which do
+/// nothing unless overridden
+pub trait MmObserver: Sync {
+    /// `[start, start + len)` was mapped
+    fn on_map(&self, _pid: usize, _start: usize, _len: usize) {}
+    /// `[start, start + len)` was unmapped
+    fn on_unmap(&self, _pid: usize, _start: usize, _len: usize) {}
+    /// a page fault at `va` was handled with `result`
+    fn on_fault(&self, _pid: usize, _va: usize, _result: Result<(), PageFaultError>) {}
+    /// `child` was forked from `pid` with a copy of its `pages` pages
+    fn on_copy(&self, _pid: usize, _child: usize, _pages: usize) {}
+}
+
+static OBSERVER: SpinNoIrq<Option<&'static dyn MmObserver>> = SpinNoIrq::new(None);
+
+/// Report the paging events of all processes to `observer`, or to no one
+/// with `None`.
+pub fn set_mm_observer(observer: Option<&'static dyn MmObserver>) {
+    *OBSERVER.lock() = observer;
+}
+
+/// Run `event` on the observer, if there is one.
+pub fn notify_mm_observer(event: impl FnOnce(&dyn MmObserver)) {
+    // 回调可能要打印，不持锁调用
+    let observer = *OBSERVER.lock();
+    if let Some(observer) = observer {
+        event(observer);
+    }
+}
+
+/// Prints the paging events of one process
+pub struct PidTracer {
+    pid: AtomicUsize,
+}
+
+impl PidTracer {
+    pub const fn new() -> Self {
+        Self {
+            pid: AtomicUsize::new(usize::MAX),
+        }
+    }
+    /// Follow process `pid` from now on.
+    pub fn follow(&self, pid: usize) {
+        self.pid.store(pid, Ordering::Relaxed);
+    }
+    fn follows(&self, pid: usize) -> bool {
+        self.pid.load(Ordering::Relaxed) == pid
+    }
+}
+
+impl MmObserver for PidTracer {
+    fn on_map(&self, pid: usize, start: usize, len: usize) {
+        if self.follows(pid) {
+            println!("[mm {}] map [{:#x}, {:#x})", pid, start, start + len);
+        }
+    }
+    fn on_unmap(&self, pid: usize, start: usize, len: usize) {
+        if self.follows(pid) {
+            println!("[mm {}] unmap [{:#x}, {:#x})", pid, start, start + len);
+        }
+    }
+    fn on_fault(&self, pid: usize, va: usize, result: Result<(), PageFaultError>) {
+        if self.follows(pid) {
+            match result {
+                Ok(()) => println!("[mm {}] fault at {:#x}, page mapped", pid, va),
+                Err(error) => println!("[mm {}] fault at {:#x}, {:?}", pid, va, error),
+            }
+        }
+    }
+    fn on_copy(&self, pid: usize, child: usize, pages: usize) {
+        if self.follows(pid) {
+            println!("[mm {}] fork copied {} pages to child {}", pid, pages, child);
+        }
+    }
+}
+
+/// the observer behind `mmtrace=`
+pub static PID_TRACER: PidTracer = PidTracer::new();
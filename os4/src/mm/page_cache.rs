@@ -0,0 +1,298 @@
+//! Page cache shared by file reads and writes and file-backed mappings
+//!
+//! Pages are keyed by the inode and the page index in the file. A shared
+//! mapping maps the cached frame itself, which pins the page in the cache
+//! until the mapping goes away; unpinned pages are evicted least recently
+//! used first by [`shrink`] when frames run out.
+//!
+//! Since running out of frames here shrinks the cache, no frame is ever
+//! allocated with the cache locked. Everything reaching easy-fs happens with
+//! the cache locked, which keeps the task from being preempted inside it.
+
+use super::{frame_alloc, FrameTracker};
+use crate::config::{PAGE_CACHE_SHRINK, PAGE_SIZE};
+use crate::sync::{SpinLock, SpinLockGuard};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::Inode;
+use lazy_static::*;
+
+/// (position of the disk inode, page index in the file)
+type PageKey = ((usize, usize), usize);
+
+fn page_key(inode: &Inode, page: usize) -> PageKey {
+    (inode.disk_pos(), page)
+}
+
+struct CachedPage {
+    inode: Arc<Inode>,
+    frame: Arc<FrameTracker>,
+    /// changed since it was read from or written to the disk
+    dirty: bool,
+    /// clock of the last use, the smallest one is evicted first
+    last_use: usize,
+}
+
+impl CachedPage {
+    /// Write the page back if it is dirty, without growing the file.
+    fn write_back(&mut self, page: usize) {
+        if !self.dirty {
+            return;
+        }
+        let offset = page * PAGE_SIZE;
+        let size = self.inode.size();
+        if offset < size {
+            let len = PAGE_SIZE.min(size - offset);
+            self.inode
+                .write_at(offset, &self.frame.ppn.get_bytes_array()[..len]);
+        }
+        self.dirty = false;
+    }
+    /// whether nothing but the cache holds the frame
+    fn evictable(&self) -> bool {
+        Arc::strong_count(&self.frame) == 1
+    }
+}
+
+struct PageCache {
+    pages: BTreeMap<PageKey, CachedPage>,
+    clock: usize,
+}
+
+impl PageCache {
+    /// The cached page under `key`, marked as just used
+    fn touch(&mut self, key: &PageKey) -> Option<&mut CachedPage> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.pages.get_mut(key).map(|page| {
+            page.last_use = clock;
+            page
+        })
+    }
+    /// Cache page `page` of `inode` in `frame`, reading it from the disk.
+    /// Past the end of the file the frame stays zeroed.
+    fn load(&mut self, inode: &Arc<Inode>, page: usize, frame: FrameTracker) {
+        inode.read_at(page * PAGE_SIZE, frame.ppn.get_bytes_array());
+        self.pages.insert(
+            page_key(inode, page),
+            CachedPage {
+                inode: inode.clone(),
+                frame: Arc::new(frame),
+                dirty: false,
+                last_use: self.clock,
+            },
+        );
+    }
+    /// keys of all the cached pages of `inode`
+    fn keys_of(&self, inode: &Inode) -> Vec<PageKey> {
+        let pos = inode.disk_pos();
+        self.pages
+            .range((pos, 0)..=(pos, usize::MAX))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
+
+lazy_static! {
+    static ref PAGE_CACHE: SpinLock<PageCache> = SpinLock::new(PageCache {
+        pages: BTreeMap::new(),
+        clock: 0,
+    });
+}
+
+/// Lock the cache with page `page` of `inode` in it, unless there is no
+/// frame to read it into.
+fn lock_page(inode: &Arc<Inode>, page: usize) -> SpinLockGuard<'static, PageCache> {
+    let key = page_key(inode, page);
+    let mut frame = None;
+    loop {
+        let mut cache = PAGE_CACHE.lock();
+        if cache.pages.contains_key(&key) {
+            // someone else may have read it in meanwhile, our frame is dropped
+            return cache;
+        }
+        if let Some(frame) = frame.take() {
+            cache.load(inode, page, frame);
+            return cache;
+        }
+        drop(cache);
+        let mut new_frame = frame_alloc();
+        // frame_alloc does not reclaim, so make room among our own pages
+        if new_frame.is_none() && shrink(PAGE_CACHE_SHRINK) > 0 {
+            new_frame = frame_alloc();
+        }
+        match new_frame {
+            Some(new_frame) => frame = Some(new_frame),
+            None => return PAGE_CACHE.lock(),
+        }
+    }
+}
+
+/// The frame caching page `page` of `inode`, read in if needed, or `None`
+/// when out of frames.
+pub fn get_page(inode: &Arc<Inode>, page: usize) -> Option<Arc<FrameTracker>> {
+    let mut cache = lock_page(inode, page);
+    cache
+        .touch(&page_key(inode, page))
+        .map(|cached| cached.frame.clone())
+}
+
+/// Whether page `page` of `inode` is in the cache, so getting it needs no
+/// I/O.
+pub fn contains(inode: &Inode, page: usize) -> bool {
+    PAGE_CACHE.lock().pages.contains_key(&page_key(inode, page))
+}
+
+/// Read from `inode` at `offset` into `buf` through the cache, returning
+/// how many bytes were read.
+pub fn read_at(inode: &Arc<Inode>, offset: usize, buf: &mut [u8]) -> usize {
+    let end = offset.saturating_add(buf.len()).min(inode.size());
+    let mut pos = offset;
+    while pos < end {
+        let (page, in_page) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (PAGE_SIZE - in_page).min(end - pos);
+        let dst = &mut buf[pos - offset..pos - offset + len];
+        let mut cache = lock_page(inode, page);
+        match cache.touch(&page_key(inode, page)) {
+            Some(cached) => {
+                dst.copy_from_slice(&cached.frame.ppn.get_bytes_array()[in_page..in_page + len])
+            }
+            // 没有页帧缓存这一页，它也就不在缓存里，直接读盘
+            None => {
+                inode.read_at(pos, dst);
+            }
+        }
+        pos += len;
+    }
+    pos - offset
+}
+
+/// Write `buf` to `inode` at `offset` through the cache, returning how many
+/// bytes were written.
+///
+/// The part inside the file stays in the cache until written back, while
+/// growing the file has to go to the disk to allocate blocks.
+pub fn write_at(inode: &Arc<Inode>, offset: usize, buf: &[u8]) -> usize {
+    let end = offset + buf.len();
+    let mut pos = offset;
+    while pos < end.min(inode.size()) {
+        let (page, in_page) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        // 超出文件末尾的部分留给下面扩展文件
+        let len = (PAGE_SIZE - in_page).min(end.min(inode.size()) - pos);
+        let src = &buf[pos - offset..pos - offset + len];
+        let mut cache = lock_page(inode, page);
+        match cache.touch(&page_key(inode, page)) {
+            Some(cached) => {
+                cached.frame.ppn.get_bytes_array()[in_page..in_page + len].copy_from_slice(src);
+                cached.dirty = true;
+            }
+            None => {
+                inode.write_at(pos, src);
+            }
+        }
+        pos += len;
+    }
+    if pos < end {
+        let mut cache = PAGE_CACHE.lock();
+        let src = &buf[pos - offset..];
+        let written = inode.write_at(pos, src);
+        // the last page of the old file may be cached, keep it up to date
+        let mut at = pos;
+        while at < pos + written {
+            let (page, in_page) = (at / PAGE_SIZE, at % PAGE_SIZE);
+            let len = (PAGE_SIZE - in_page).min(pos + written - at);
+            if let Some(cached) = cache.pages.get_mut(&page_key(inode, page)) {
+                cached.frame.ppn.get_bytes_array()[in_page..in_page + len]
+                    .copy_from_slice(&src[at - pos..at - pos + len]);
+            }
+            at += len;
+        }
+        pos += written;
+    }
+    pos - offset
+}
+
+/// Mark page `page` of `inode` as changed through a shared mapping.
+pub fn mark_dirty(inode: &Inode, page: usize) {
+    if let Some(cached) = PAGE_CACHE.lock().pages.get_mut(&page_key(inode, page)) {
+        cached.dirty = true;
+    }
+}
+
+/// Write all dirty cached pages of `inode` back to the disk.
+pub fn sync(inode: &Inode) {
+    let mut cache = PAGE_CACHE.lock();
+    for key in cache.keys_of(inode) {
+        cache.pages.get_mut(&key).unwrap().write_back(key.1);
+    }
+}
+
+/// Forget the cached pages of `inode` after its data is cleared. Frames
+/// still mapped somewhere are no longer part of the file.
+pub fn invalidate(inode: &Inode) {
+    let mut cache = PAGE_CACHE.lock();
+    for key in cache.keys_of(inode) {
+        cache.pages.remove(&key);
+    }
+}
+
+/// Number of cached pages, each taking a frame
+pub fn cached_pages() -> usize {
+    PAGE_CACHE.lock().pages.len()
+}
+
+/// Number of cached pages that [`shrink`] could evict now
+pub fn evictable_pages() -> usize {
+    PAGE_CACHE
+        .lock()
+        .pages
+        .values()
+        .filter(|cached| cached.evictable())
+        .count()
+}
+
+/// Evict up to `count` pages no mapping holds, least recently used first,
+/// writing back the dirty ones. Returns how many frames were freed.
+pub fn shrink(count: usize) -> usize {
+    let mut cache = PAGE_CACHE.lock();
+    let mut victims: Vec<(usize, PageKey)> = cache
+        .pages
+        .iter()
+        .filter(|(_, cached)| cached.evictable())
+        .map(|(key, cached)| (cached.last_use, *key))
+        .collect();
+    victims.sort_unstable();
+    victims.truncate(count);
+    for (_, key) in victims.iter() {
+        let mut cached = cache.pages.remove(key).unwrap();
+        cached.write_back(key.1);
+    }
+    victims.len()
+}
+
+#[cfg(feature = "board_test")]
+/// A write from inside the cached last page of a file on past its end
+/// grows the file, and what is read back is what was written.
+pub fn page_cache_test() {
+    use crate::fs::{open_file, File, OpenFlags};
+    let file = open_file("board_test.tmp", OpenFlags::CREATE | OpenFlags::RDWR).unwrap();
+    let inode = file.inode().unwrap();
+    assert_eq!(write_at(&inode, 0, &[1u8; 100]), 100);
+    // the last page is cached from here on
+    let mut buf = [0u8; 100];
+    assert_eq!(read_at(&inode, 0, &mut buf), 100);
+    assert!(contains(&inode, 0));
+    assert_eq!(write_at(&inode, 50, &[2u8; 100]), 100);
+    assert_eq!(inode.size(), 150);
+    let mut buf = [0u8; 150];
+    assert_eq!(read_at(&inode, 0, &mut buf), 150);
+    assert!(buf[..50].iter().all(|&byte| byte == 1));
+    assert!(buf[50..].iter().all(|&byte| byte == 2));
+    // and it reaches the disk as well
+    sync(&inode);
+    let mut buf = [0u8; 150];
+    assert_eq!(inode.read_at(0, &mut buf), 150);
+    assert!(buf[50..].iter().all(|&byte| byte == 2));
+    info!("page_cache_test passed!");
+}
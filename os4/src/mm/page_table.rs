@@ -1,12 +1,24 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`]
 
-use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, VirtPageNum, PhysAddr};
+use super::{frame_alloc, FrameTracker, OutOfMemory, PhysPageNum, StepByOne, VirtAddr, VirtPageNum, PhysAddr};
+use super::VPNRange;
+use super::{flush_tlb_asid, flush_tlb_page, AsidHandle, ASID_MASK, ASID_SHIFT};
+use crate::config::{HUGE_PAGE_SIZE, PAGE_SIZE, PAGE_SIZE_BITS, PAGE_TABLE_LEVELS, SATP_MODE};
+use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
-use core::fmt::Debug;
 // bitflags是比特标志位的crate，它提供了一个宏，可以将u8封装成一个标志位的集合类型，支持一些常见的集合运算。
 
+/// number of 4 KiB pages covered by a huge page
+pub const HUGE_PAGE_PAGES: usize = HUGE_PAGE_SIZE / PAGE_SIZE;
+/// level of the last-level page table, counted from the root at 0
+const LEAF_LEVEL: usize = PAGE_TABLE_LEVELS - 1;
+/// level holding the leaf entries of huge pages
+const HUGE_LEVEL: usize = PAGE_TABLE_LEVELS - 2;
+/// `map_range` flushing more pages than this flushes the whole address space
+const RANGE_FLUSH_PAGES: usize = 64;
+
 bitflags! {
     pub struct PTEFlags: u8 {
         const V = 1 << 0;
@@ -25,6 +37,8 @@ bitflags! {
 pub struct PageTable {
     root_ppn: PhysPageNum,
     frames: Vec<FrameTracker>,
+    /// None for the kernel space and for views built by `from_token`
+    asid: Option<AsidHandle>,
 }
 
 #[derive(Copy, Clone)]  //自动为PageTableEntry实现copy/clone trait
@@ -34,7 +48,8 @@ pub struct PageTableEntry {
   pub bits: usize,
 }
 
-/// assume that it won't oom when creating/mapping
+/// creating a page table with `new` assumes that it won't oom, user spaces
+/// use `try_new`; mapping may fail
 impl PageTable {
     // 每个应用的地址空间都对应不同的多级列表，即不同的页表的起始地址不一样。
     // 因此pagetable需要保存根节点的物理页号root_ppn作为页表唯一的区分标志.
@@ -42,47 +57,272 @@ impl PageTable {
     // 当pagetable生命周期结束后，向量frames里frametracker也会被回收，即
     // 意味着存放多级页表节点的物理帧被回收了.
     pub fn new() -> Self {
+        Self::try_new().unwrap()
+    }
+    /// [`PageTable::new`], or `OutOfMemory` if there is no frame for the root
+    pub fn try_new() -> Result<Self, OutOfMemory> {
+        let frame = frame_alloc().ok_or(OutOfMemory)?;
+        Ok(PageTable {
+            root_ppn: frame.ppn,
+            frames: vec![frame],
+            asid: Some(AsidHandle::alloc()),
+        })
+    }
+    /// page table of the kernel space, which always uses ASID 0
+    pub fn new_kernel() -> Self {
         let frame = frame_alloc().unwrap();
         PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
+            asid: None,
         }
     }
+    /// Whether the frame `ppn` holds one of the nodes of this page table
+    pub fn is_node(&self, ppn: PhysPageNum) -> bool {
+        self.frames.iter().any(|frame| frame.ppn == ppn)
+    }
+    pub fn asid(&self) -> usize {
+        self.asid.as_ref().map_or(0, |asid| asid.0)
+    }
 
     // 多级页表并非创建之后就不再变化,为了mmu能够通过地址转换正确找到应用地址空间
     // 中的数据实际被内核放在内存中位置,os需要动态维护一个虚拟页号到页表项的映射
     // 支持插入/删除键值对
     #[allow(unused)]
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    pub fn map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), OutOfMemory> {
+        let pte = self.find_pte_create(vpn)?.unwrap();
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        flush_tlb_page(vpn, self.asid());
+        Ok(())
+    }
+    /// Map the pages from `start_vpn` on to `frames` in order, like
+    /// [`map`](Self::map) for each, but walking down to a last-level table
+    /// only once for the up to 512 pages it covers. When out of memory for a
+    /// table node, the pages mapped so far are unmapped again.
+    pub fn map_range(
+        &mut self,
+        start_vpn: VirtPageNum,
+        frames: impl IntoIterator<Item = PhysPageNum>,
+        flags: PTEFlags,
+    ) -> Result<(), OutOfMemory> {
+        let mut vpn = start_vpn;
+        // 缓存当前的末级页表节点，vpn进入下一个节点时才重新查找
+        let mut leaf_table: Option<PhysPageNum> = None;
+        for ppn in frames {
+            let idx = vpn.indexes()[LEAF_LEVEL];
+            if leaf_table.is_none() || idx == 0 {
+                match self.leaf_table_create(vpn) {
+                    Ok(table) => leaf_table = Some(table.expect("huge page in the way")),
+                    Err(err) => {
+                        for mapped in start_vpn.0..vpn.0 {
+                            self.unmap(VirtPageNum(mapped));
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+            let pte = &mut leaf_table.unwrap().get_pte_array()[idx];
+            assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+            *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+            vpn.step();
+        }
+        if vpn.0 - start_vpn.0 > RANGE_FLUSH_PAGES {
+            flush_tlb_asid(self.asid());
+        } else {
+            for flushed in start_vpn.0..vpn.0 {
+                flush_tlb_page(VirtPageNum(flushed), self.asid());
+            }
+        }
+        Ok(())
+    }
+    /// Allocate every page table node needed to map `vpn_range`, so that
+    /// mapping it afterwards cannot run out of memory.
+    pub fn reserve(&mut self, vpn_range: VPNRange) -> Result<(), OutOfMemory> {
+        let mut vpn = vpn_range.get_start();
+        while vpn < vpn_range.get_end() {
+            self.find_pte_create(vpn)?;
+            // 一个末级页表节点覆盖512页，直接跳到下一个节点
+            vpn = VirtPageNum((vpn.0 / 512 + 1) * 512);
+        }
+        Ok(())
     }
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        let pte = self.find_pte_mut(vpn, LEAF_LEVEL).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        flush_tlb_page(vpn, self.asid());
+    }
+    // 修改一个已有映射的标志位，保留它所映射的物理页帧；
+    // 尚未填充的页没有页表项可改，缺页时会按所在区域的新权限映射
+    pub fn remap(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = match self.find_pte_mut(vpn, LEAF_LEVEL) {
+            Some(pte) => pte,
+            None => return,
+        };
+        if pte.is_swapped() {
+            // the flags take effect once the page is swapped back in
+            *pte = PageTableEntry::new_swapped(pte.swap_slot(), flags);
+            return;
+        }
+        if !pte.is_valid() {
+            return;
+        }
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+        flush_tlb_page(vpn, self.asid());
+    }
+    /// Whether `vpn` is mapped and was read, written or executed since its
+    /// A bit was last cleared
+    pub fn accessed(&self, vpn: VirtPageNum) -> bool {
+        self.translate(vpn)
+            .map_or(false, |pte| pte.is_valid() && pte.flags().contains(PTEFlags::A))
+    }
+    /// Whether `vpn` is mapped and was written since it was mapped
+    pub fn dirty(&self, vpn: VirtPageNum) -> bool {
+        self.translate(vpn)
+            .map_or(false, |pte| pte.is_valid() && pte.flags().contains(PTEFlags::D))
+    }
+    /// Clear the A bit of the mapping of `vpn`. Its TLB entry goes as well,
+    /// so that the next access sets the bit again.
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) {
+        if !self.accessed(vpn) {
+            return;
+        }
+        let pte = self.find_pte_mut(vpn, LEAF_LEVEL).unwrap();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+        flush_tlb_page(vpn, self.asid());
+    }
+    /// Replace the mapping of `vpn` by an invalid entry remembering swap
+    /// slot `slot`, see [`PageTableEntry::new_swapped`].
+    pub fn swap_out(&mut self, vpn: VirtPageNum, slot: usize) {
+        let pte = self.find_pte_mut(vpn, LEAF_LEVEL).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before swapping out", vpn);
+        *pte = PageTableEntry::new_swapped(slot, pte.flags());
+        flush_tlb_page(vpn, self.asid());
+    }
+    /// Move the entry of `from`, a mapping or a swapped out page, to `to`,
+    /// keeping its flags. The page table node of `to` must exist already.
+    pub fn move_entry(&mut self, from: VirtPageNum, to: VirtPageNum) {
+        let pte = match self.find_pte_mut(from, LEAF_LEVEL) {
+            Some(pte) if pte.is_valid() || pte.is_swapped() => pte,
+            _ => return,
+        };
+        let entry = *pte;
+        *pte = PageTableEntry::empty();
+        if entry.is_valid() {
+            flush_tlb_page(from, self.asid());
+        }
+        let target = self
+            .find_pte_mut(to, LEAF_LEVEL)
+            .expect("page table node is not reserved");
+        assert!(
+            !target.is_valid() && !target.is_swapped(),
+            "vpn {:?} is mapped before moving {:?} there",
+            to,
+            from
+        );
+        *target = entry;
+    }
+    /// Clear the entry of `vpn` if it is swapped out, returning its slot.
+    pub fn take_swapped(&mut self, vpn: VirtPageNum) -> Option<usize> {
+        let pte = self.find_pte_mut(vpn, LEAF_LEVEL)?;
+        if !pte.is_swapped() {
+            return None;
+        }
+        let slot = pte.swap_slot();
+        *pte = PageTableEntry::empty();
+        Some(slot)
+    }
+    // 大页映射：在中间一级页表直接放置叶子页表项，一次映射2MiB，
+    // 要求vpn和ppn都按大页大小对齐
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), OutOfMemory> {
+        assert!(
+            vpn.0 % HUGE_PAGE_PAGES == 0 && ppn.0 % HUGE_PAGE_PAGES == 0,
+            "huge page {:?} -> {:?} is not aligned",
+            vpn,
+            ppn
+        );
+        let pte = self.find_pte_create_at(vpn, HUGE_LEVEL, true)?.unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        flush_tlb_page(vpn, self.asid());
+        Ok(())
+    }
+    #[allow(unused)]
+    pub fn unmap_huge(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte_mut(vpn, HUGE_LEVEL).unwrap();
+        assert!(pte.is_valid() && pte.is_leaf(), "vpn {:?} is not a huge page", vpn);
+        *pte = PageTableEntry::empty();
+        flush_tlb_page(vpn, self.asid());
     }
 
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    fn find_pte_create(
+        &mut self,
+        vpn: VirtPageNum,
+    ) -> Result<Option<&mut PageTableEntry>, OutOfMemory> {
+        self.find_pte_create_at(vpn, LEAF_LEVEL, true)
+    }
+    /// The last-level table covering `vpn`, allocating the nodes on the way
+    /// down, or `None` if a huge page covers `vpn`.
+    fn leaf_table_create(&mut self, vpn: VirtPageNum) -> Result<Option<PhysPageNum>, OutOfMemory> {
+        let pte = match self.find_pte_create_at(vpn, HUGE_LEVEL, true)? {
+            Some(pte) if !pte.is_valid() => pte,
+            Some(pte) if !pte.is_leaf() => return Ok(Some(pte.ppn())),
+            _ => return Ok(None),
+        };
+        let frame = frame_alloc().ok_or(OutOfMemory)?;
+        let table = frame.ppn;
+        *pte = PageTableEntry::new(table, PTEFlags::V);
+        self.frames.push(frame);
+        Ok(Some(table))
+    }
+    // 同find_pte_create_at，但不分配页表节点，缺失时返回None
+    fn find_pte_mut(&mut self, vpn: VirtPageNum, level: usize) -> Option<&mut PageTableEntry> {
+        self.find_pte_create_at(vpn, level, false).ok().flatten()
+    }
+    // 找到vpn在第level级页表中的页表项，create时沿途缺失的页表节点会被分配，
+    // 分配失败返回OutOfMemory；如果途中遇到了大页叶子项，说明该区域已被大页映射，
+    // 或者不允许分配时遇到了缺失的节点，返回None
+    fn find_pte_create_at(
+        &mut self,
+        vpn: VirtPageNum,
+        level: usize,
+        create: bool,
+    ) -> Result<Option<&mut PageTableEntry>, OutOfMemory> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for i in 0..3 {
+        for i in 0..PAGE_TABLE_LEVELS {
             let pte = &mut ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
+            if i == level {
                 result = Some(pte);
                 break;
             }
+            if pte.is_valid() && pte.is_leaf() {
+                return Ok(None);
+            }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                if !create {
+                    return Ok(None);
+                }
+                let frame = frame_alloc().ok_or(OutOfMemory)?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
             ppn = pte.ppn();
         }
-        result
+        Ok(result)
     }
 
     /// Temporarity used to get arguments from user space.
@@ -92,17 +332,24 @@ impl PageTable {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
             frames: Vec::new(),
+            asid: None,
         }
     }
     // 和create的区别在于不会试图分配物理页帧.一旦在多级页表上遍历遇到空指针就会直接返回none
+    // 如果vpn落在大页之中，返回的是那个大页的叶子页表项
     pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+        self.find_leaf(vpn).map(|(pte, _)| pte)
+    }
+    /// find the leaf pte of `vpn` and the level it sits in, where
+    /// `HUGE_LEVEL` means a 2 MiB huge page and each level above it 512x more
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&PageTableEntry, usize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&PageTableEntry> = None;
-        for i in 0..3 {
+        let mut result: Option<(&PageTableEntry, usize)> = None;
+        for i in 0..PAGE_TABLE_LEVELS {
             let pte = &ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
-                result = Some(pte);
+            if i == LEAF_LEVEL || (pte.is_valid() && pte.is_leaf()) {
+                result = Some((pte, i));
                 break;
             }
             if !pte.is_valid() {
@@ -113,12 +360,81 @@ impl PageTable {
         result
     }
     // 调用find_pte来实现,如果能够找到页表项,将页表项copy一份并返回,否则返回一个none
+    // 对于大页中的某一页，返回一个指向该页所在物理页帧的等效页表项
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn)
-            .map(|pte| {pte.clone()})
+        self.find_leaf(vpn).map(|(pte, level)| {
+            if level == LEAF_LEVEL {
+                pte.clone()
+            } else {
+                let offset = vpn.0 & ((1usize << (9 * (LEAF_LEVEL - level))) - 1);
+                PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+            }
+        })
     }
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        SATP_MODE << 60 | (self.asid() & ASID_MASK) << ASID_SHIFT | self.root_ppn.0
+    }
+    /// Every valid leaf as (vpn, ppn, flags) in address order. A huge page
+    /// shows up once, with the first vpn and ppn it covers.
+    pub fn iter_mapped(&self) -> impl Iterator<Item = (VirtPageNum, PhysPageNum, PTEFlags)> {
+        let mut mapped = Vec::new();
+        Self::walk(self.root_ppn, 0, 0, &mut |_, vpn, pte| {
+            if pte.is_leaf() {
+                mapped.push((vpn, pte.ppn(), pte.flags()));
+            }
+        });
+        mapped.into_iter()
+    }
+    /// Print every valid entry, indented by its level.
+    #[allow(unused)]
+    pub fn dump(&self) {
+        println!("page table {:?}, token = {:#x}", self.root_ppn, self.token());
+        Self::walk(self.root_ppn, 0, 0, &mut |level, vpn, pte| {
+            for _ in 0..=level {
+                print!("  ");
+            }
+            if pte.is_leaf() {
+                let va: VirtAddr = vpn.into();
+                println!(
+                    "[{}] {:?} -> {:?} {:?}",
+                    vpn.indexes()[level],
+                    va,
+                    pte.ppn(),
+                    pte.flags()
+                );
+            } else {
+                println!("[{}] -> {:?}", vpn.indexes()[level], pte.ppn());
+            }
+        });
+    }
+    // 深度优先遍历ppn所在的第level级页表节点，对每个有效页表项调用f，
+    // 传入其所在级数、所映射区域的起始虚拟页号以及页表项本身
+    fn walk(
+        ppn: PhysPageNum,
+        level: usize,
+        vpn_prefix: usize,
+        f: &mut impl FnMut(usize, VirtPageNum, &PageTableEntry),
+    ) {
+        for (idx, pte) in ppn.get_pte_array().iter().enumerate() {
+            if !pte.is_valid() {
+                continue;
+            }
+            let prefix = vpn_prefix << 9 | idx;
+            let vpn = Self::sign_extend(prefix << (9 * (LEAF_LEVEL - level)));
+            f(level, vpn, pte);
+            if level < LEAF_LEVEL && !pte.is_leaf() {
+                Self::walk(pte.ppn(), level + 1, prefix, f);
+            }
+        }
+    }
+    // 高半部分的地址需要符号扩展，才能和VirtAddr::floor得到的虚拟页号一致
+    fn sign_extend(vpn: usize) -> VirtPageNum {
+        let width = 9 * PAGE_TABLE_LEVELS;
+        if vpn & (1 << (width - 1)) != 0 {
+            VirtPageNum(vpn | (usize::MAX >> PAGE_SIZE_BITS) & !((1 << width) - 1))
+        } else {
+            VirtPageNum(vpn)
+        }
     }
 }
 
@@ -135,6 +451,20 @@ impl PageTableEntry {
       bits: 0,
     }
   }
+  /// An invalid entry of a swapped out page, holding its swap slot where
+  /// the ppn would be. The permission bits stay, so it is never zero.
+  pub fn new_swapped(slot: usize, flags: PTEFlags) -> Self {
+    let flags = flags & (PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::U);
+    PageTableEntry {
+      bits: slot << 10 | flags.bits as usize,
+    }
+  }
+  pub fn is_swapped(&self) -> bool {
+    !self.is_valid() && self.bits != 0
+  }
+  pub fn swap_slot(&self) -> usize {
+    self.bits >> 10
+  }
   pub fn ppn(&self) -> PhysPageNum {
     (self.bits >> 10 & ((1usize << 44) - 1)).into()
   }
@@ -154,18 +484,70 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    // R/W/X任一为1说明是叶子页表项，否则指向下一级页表
+    pub fn is_leaf(&self) -> bool {
+        (self.flags() & (PTEFlags::R | PTEFlags::W | PTEFlags::X)) != PTEFlags::empty()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// reasons why a user pointer cannot be accessed by the kernel
+pub enum TranslateError {
+    /// the page holding the address is not mapped
+    Unmapped(VirtAddr),
+    /// the page is mapped but not accessible from user mode
+    NotUser(VirtAddr),
+    /// the range wraps around the end of the address space
+    Overflow,
+}
+
+/// translate a user page, refusing pages that user mode cannot access and
+/// pages that are not present. Syscalls bring those in beforehand, see
+/// [`crate::task::fault_in_user_range`].
+fn translate_user_page(page_table: &PageTable, va: VirtAddr) -> Result<PhysPageNum, TranslateError> {
+    let pte = page_table
+        .translate(va.floor())
+        .filter(|pte| pte.is_valid())
+        .ok_or(TranslateError::Unmapped(va))?;
+    if !pte.flags().contains(PTEFlags::U) {
+        return Err(TranslateError::NotUser(va));
+    }
+    Ok(pte.ppn())
+}
+
+/// the physical address `va` maps to in the address space `token`, looked
+/// up without taking a lock or bringing swapped out pages in
+pub fn translated_phys_addr(token: usize, va: usize) -> Option<usize> {
+    let va = VirtAddr::from(va);
+    let pte = PageTable::from_token(token)
+        .translate(va.floor())
+        .filter(|pte| pte.is_valid())?;
+    Some(PhysAddr::from(pte.ppn()).0 + va.page_offset())
+}
+
+/// whether user mode may write the page holding `va` in address space `token`
+pub fn user_page_writable(token: usize, va: usize) -> bool {
+    PageTable::from_token(token)
+        .translate(VirtAddr::from(va).floor())
+        .map_or(false, |pte| {
+            pte.is_valid() && pte.writable() && pte.flags().contains(PTEFlags::U)
+        })
 }
 
 /// translate a pointer to a mutable u8 Vec through page table
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
+pub fn translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Result<Vec<&'static mut [u8]>, TranslateError> {
     let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.checked_add(len).ok_or(TranslateError::Overflow)?;
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = translate_user_page(&page_table, start_va)?;
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -176,19 +558,158 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         }
         start = end_va.into();
     }
-    v
+    Ok(v)
 }
 
-// give bare pointer value 
-pub fn translated_assign_ptr<T: Debug>(token: usize, ptr: *mut T, value: T) {
+/// read a nul-terminated string from user space
+pub fn translated_str(token: usize, ptr: *const u8) -> Result<String, TranslateError> {
     let page_table = PageTable::from_token(token);
-    let va = VirtAddr::from(ptr as usize);
-    let vpn = va.floor();
-    let offset = va.page_offset();
-    let ppn = page_table.translate(vpn).unwrap().ppn();
-    let pa: PhysAddr = (usize::from(PhysAddr::from(ppn)) + offset).into();
-    unsafe {
-        let ptr_pa = (pa.0 as *mut T).as_mut().unwrap();
-        *ptr_pa = value;
-    }
-}
\ No newline at end of file
+    let mut string = String::new();
+    let mut va = ptr as usize;
+    loop {
+        let ppn = translate_user_page(&page_table, VirtAddr::from(va))?;
+        let ch = ppn.get_bytes_array()[VirtAddr::from(va).page_offset()];
+        if ch == 0 {
+            break;
+        }
+        string.push(ch as char);
+        va = va.checked_add(1).ok_or(TranslateError::Overflow)?;
+    }
+    Ok(string)
+}
+
+/// copy a `T` into user space byte by byte, so that a struct straddling
+/// a page boundary lands correctly in both physical frames
+pub fn copy_to_user<T>(token: usize, dst: *mut T, src: &T) -> Result<(), TranslateError> {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(src as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    let mut start = 0;
+    for buffer in translated_byte_buffer(token, dst as *const u8, bytes.len())? {
+        buffer.copy_from_slice(&bytes[start..start + buffer.len()]);
+        start += buffer.len();
+    }
+    Ok(())
+}
+
+#[allow(unused)]
+/// read a `T` from user space byte by byte, see [`copy_to_user`]
+pub fn copy_from_user<T: Copy>(token: usize, src: *const T) -> Result<T, TranslateError> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>())
+    };
+    let mut start = 0;
+    for buffer in translated_byte_buffer(token, src as *const u8, bytes.len())? {
+        bytes[start..start + buffer.len()].copy_from_slice(buffer);
+        start += buffer.len();
+    }
+    Ok(unsafe { value.assume_init() })
+}
+
+/// an array of u8 slices that a user buffer is split into by page boundaries
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+}
+
+impl UserBuffer {
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+    /// total length of all slices
+    pub fn len(&self) -> usize {
+        self.buffers.iter().map(|b| b.len()).sum()
+    }
+    /// copy from the user buffer into `dst`, returning the number of bytes copied
+    pub fn read(&self, dst: &mut [u8]) -> usize {
+        let mut copied = 0;
+        for buffer in self.buffers.iter() {
+            let n = buffer.len().min(dst.len() - copied);
+            dst[copied..copied + n].copy_from_slice(&buffer[..n]);
+            copied += n;
+            if copied == dst.len() {
+                break;
+            }
+        }
+        copied
+    }
+    /// copy `src` into the user buffer, returning the number of bytes copied
+    pub fn write(&mut self, src: &[u8]) -> usize {
+        let mut copied = 0;
+        for buffer in self.buffers.iter_mut() {
+            let n = buffer.len().min(src.len() - copied);
+            buffer[..n].copy_from_slice(&src[copied..copied + n]);
+            copied += n;
+            if copied == src.len() {
+                break;
+            }
+        }
+        copied
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = *mut u8;
+    type IntoIter = UserBufferIterator;
+    fn into_iter(self) -> Self::IntoIter {
+        UserBufferIterator {
+            buffers: self.buffers,
+            current_buffer: 0,
+            current_idx: 0,
+        }
+    }
+}
+
+/// iterator over every byte of a [`UserBuffer`]
+pub struct UserBufferIterator {
+    buffers: Vec<&'static mut [u8]>,
+    current_buffer: usize,
+    current_idx: usize,
+}
+
+impl Iterator for UserBufferIterator {
+    type Item = *mut u8;
+    fn next(&mut self) -> Option<Self::Item> {
+        // skip empty slices, e.g. from a zero-length buffer
+        while self.current_buffer < self.buffers.len()
+            && self.current_idx == self.buffers[self.current_buffer].len()
+        {
+            self.current_buffer += 1;
+            self.current_idx = 0;
+        }
+        if self.current_buffer >= self.buffers.len() {
+            None
+        } else {
+            let r = &mut self.buffers[self.current_buffer][self.current_idx] as *mut _;
+            self.current_idx += 1;
+            Some(r)
+        }
+    }
+}
+
+#[cfg(feature = "board_test")]
+/// Map a page, translate it, take write access away and unmap it again.
+pub fn page_table_test() {
+    let mut page_table = PageTable::new();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x12345);
+    let unmapped = |page_table: &PageTable, vpn| {
+        page_table
+            .translate(vpn)
+            .map_or(true, |pte: PageTableEntry| !pte.is_valid())
+    };
+    page_table
+        .map(vpn, frame.ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U)
+        .unwrap();
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.is_valid() && pte.readable() && pte.writable());
+    assert!(pte.ppn() == frame.ppn);
+    assert!(unmapped(&page_table, VirtPageNum(vpn.0 + 1)));
+    page_table.remap(vpn, PTEFlags::R | PTEFlags::U);
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.is_valid() && !pte.writable());
+    assert!(pte.ppn() == frame.ppn);
+    page_table.unmap(vpn);
+    assert!(unmapped(&page_table, vpn));
+    info!("page_table_test passed!");
+}
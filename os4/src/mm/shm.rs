@@ -0,0 +1,104 @@
+//! Shared memory objects
+//!
+//! An object is a list of frames, created by `sys_shm_create` and mapped
+//! into any number of address spaces by `sys_shm_attach`, see
+//! [`MemorySet::attach_shm`](super::MemorySet::attach_shm). The table and
+//! every mapping hold the frames by `Arc`, so they stay alive as long as
+//! someone uses them. An object goes away once it has been attached and no
+//! address space maps any of its pages anymore; forked children map the
+//! objects of their parent as well.
+
+use super::{frame_alloc, frame_remain_num, FrameTracker};
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+struct ShmObject {
+    frames: Vec<Arc<FrameTracker>>,
+    /// mapped into some address space at least once
+    attached: bool,
+}
+
+impl ShmObject {
+    /// Whether no one but the table holds the frames anymore, after the
+    /// object has been attached.
+    fn unused(&self) -> bool {
+        self.attached
+            && self
+                .frames
+                .iter()
+                .all(|frame| Arc::strong_count(frame) == 1)
+    }
+}
+
+struct ShmTable {
+    objects: BTreeMap<usize, ShmObject>,
+    next_id: usize,
+}
+
+lazy_static! {
+    static ref SHM_TABLE: SpinLock<ShmTable> = SpinLock::new(ShmTable {
+        objects: BTreeMap::new(),
+        next_id: 0,
+    });
+}
+
+/// Drop the objects no one uses anymore, the frames go back outside of the
+/// table lock.
+fn collect_unused(table: &mut ShmTable) -> Vec<ShmObject> {
+    let unused: Vec<usize> = table
+        .objects
+        .iter()
+        .filter(|(_, object)| object.unused())
+        .map(|(&id, _)| id)
+        .collect();
+    unused
+        .into_iter()
+        .filter_map(|id| table.objects.remove(&id))
+        .collect()
+}
+
+/// Create an object of `pages` zeroed pages, returning its id, or `None`
+/// when out of frames.
+pub fn shm_create(pages: usize) -> Option<usize> {
+    if pages == 0 || pages > frame_remain_num() {
+        return None;
+    }
+    let frames: Vec<Arc<FrameTracker>> = (0..pages)
+        .map(|_| frame_alloc().map(Arc::new))
+        .collect::<Option<_>>()?;
+    let mut table = SHM_TABLE.lock();
+    let unused = collect_unused(&mut table);
+    let id = table.next_id;
+    table.next_id += 1;
+    table.objects.insert(
+        id,
+        ShmObject {
+            frames,
+            attached: false,
+        },
+    );
+    drop(table);
+    drop(unused);
+    Some(id)
+}
+
+/// The frames of object `id`, to map them.
+pub fn shm_frames(id: usize) -> Option<Vec<Arc<FrameTracker>>> {
+    let mut table = SHM_TABLE.lock();
+    let unused = collect_unused(&mut table);
+    let frames = table.objects.get(&id).map(|object| object.frames.clone());
+    drop(table);
+    drop(unused);
+    frames
+}
+
+/// Note that object `id` has been mapped, so that it goes away once it is
+/// not anymore.
+pub fn shm_attached(id: usize) {
+    if let Some(object) = SHM_TABLE.lock().objects.get_mut(&id) {
+        object.attached = true;
+    }
+}
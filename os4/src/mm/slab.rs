@@ -0,0 +1,145 @@
+//! Slab caches for the kernel objects allocated most often
+//!
+//! Each cache hands out objects of one layout, carved out of whole frames
+//! taken from the frame allocator, so that tasks and address spaces coming
+//! and going do not fragment the heap. The global allocator tries the caches
+//! before the buddy heap: an allocation whose layout matches a cache is
+//! served by it, whatever its type. A cache keeps its frames for good.
+
+use super::frame_allocator::frame_alloc_for_slab;
+use super::memory_set::MapArea;
+use super::{FrameTracker, PhysAddr};
+use crate::config::PAGE_SIZE;
+use crate::sync::SpinNoIrq;
+use crate::task::TaskControlBlock;
+use core::alloc::Layout;
+use core::sync::atomic::AtomicUsize;
+
+/// the allocation behind an `Arc<T>`, the two counters before the data
+#[allow(dead_code)]
+#[repr(C)]
+struct ArcInner<T> {
+    strong: AtomicUsize,
+    weak: AtomicUsize,
+    data: T,
+}
+
+#[derive(Copy, Clone, Debug)]
+/// counters of one slab cache
+pub struct SlabStats {
+    /// the type the cache is meant for
+    pub name: &'static str,
+    pub object_size: usize,
+    /// frames taken from the frame allocator
+    pub frames: usize,
+    /// objects handed out now
+    pub in_use: usize,
+    /// objects carved out of the frames but not handed out
+    pub free: usize,
+    /// allocations so far
+    pub alloc_count: usize,
+    /// deallocations so far
+    pub dealloc_count: usize,
+}
+
+#[derive(Copy, Clone)]
+struct SlabCache {
+    layout: Layout,
+    /// first free object, linked through their first word, 0 if none
+    free_list: usize,
+    stats: SlabStats,
+}
+
+impl SlabCache {
+    const fn new(name: &'static str, layout: Layout) -> Self {
+        Self {
+            layout,
+            free_list: 0,
+            stats: SlabStats {
+                name,
+                object_size: layout.size(),
+                frames: 0,
+                in_use: 0,
+                free: 0,
+                alloc_count: 0,
+                dealloc_count: 0,
+            },
+        }
+    }
+    /// Whether objects of this cache fit `layout`. Objects are laid out one
+    /// after another from the start of a frame, so they are aligned as well
+    /// as their size is.
+    fn serves(&self, layout: &Layout) -> bool {
+        layout.size() == self.layout.size()
+            && layout.align() <= self.layout.align()
+            && self.layout.size() <= PAGE_SIZE
+    }
+    fn pop(&mut self) -> Option<usize> {
+        let object = self.free_list;
+        if object == 0 {
+            return None;
+        }
+        self.free_list = unsafe { *(object as *const usize) };
+        self.stats.free -= 1;
+        self.stats.in_use += 1;
+        self.stats.alloc_count += 1;
+        Some(object)
+    }
+    fn push(&mut self, object: usize) {
+        unsafe { *(object as *mut usize) = self.free_list };
+        self.free_list = object;
+        self.stats.free += 1;
+        self.stats.in_use -= 1;
+        self.stats.dealloc_count += 1;
+    }
+    /// Carve the frame at `start` into free objects.
+    fn add_frame(&mut self, start: usize) {
+        let size = self.layout.size();
+        for object in (start..start + PAGE_SIZE - size + 1).step_by(size).rev() {
+            unsafe { *(object as *mut usize) = self.free_list };
+            self.free_list = object;
+            self.stats.free += 1;
+        }
+        self.stats.frames += 1;
+    }
+}
+
+/// caches in the global allocator
+pub const SLAB_CACHES: usize = 3;
+
+static CACHES: SpinNoIrq<[SlabCache; SLAB_CACHES]> = SpinNoIrq::new([
+    SlabCache::new("TaskControlBlock", Layout::new::<ArcInner<TaskControlBlock>>()),
+    SlabCache::new("FrameTracker", Layout::new::<ArcInner<FrameTracker>>()),
+    SlabCache::new("MapArea", Layout::new::<MapArea>()),
+]);
+
+/// An object for `layout` from the cache serving it, or `None` to leave the
+/// allocation to the heap.
+pub fn alloc(layout: &Layout) -> Option<*mut u8> {
+    let mut caches = CACHES.lock();
+    let i = caches.iter().position(|cache| cache.serves(layout))?;
+    if let Some(object) = caches[i].pop() {
+        return Some(object as *mut u8);
+    }
+    // 拿页帧时不能持有缓存的锁，页帧分配器那边也可能要分配内存
+    drop(caches);
+    let frame = frame_alloc_for_slab()?;
+    let mut caches = CACHES.lock();
+    caches[i].add_frame(PhysAddr::from(frame).0);
+    caches[i].pop().map(|object| object as *mut u8)
+}
+
+/// Give an object [`alloc`] handed out back to its cache.
+pub fn dealloc(ptr: *mut u8, layout: &Layout) {
+    let mut caches = CACHES.lock();
+    if let Some(cache) = caches.iter_mut().find(|cache| cache.serves(layout)) {
+        cache.push(ptr as usize);
+    }
+}
+
+/// snapshot of the statistics of every cache
+pub fn slab_stats() -> [SlabStats; SLAB_CACHES] {
+    // 先复制出来，拿着锁时不能分配内存
+    let caches = *CACHES.lock();
+    caches.map(|cache| cache.stats)
+}
@@ -0,0 +1,73 @@
+//! Swap area for anonymous user pages, on the block device right behind
+//! the file system
+//!
+//! A swapped out page leaves an invalid page table entry holding its slot
+//! behind, see [`PageTableEntry::new_swapped`](super::PageTableEntry::new_swapped).
+
+use super::PhysPageNum;
+use crate::config::{PAGE_SIZE, SWAP_PAGES, SWAP_START_BLOCK};
+use crate::drivers::block::BLOCK_SZ;
+use crate::drivers::BLOCK_DEVICE;
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SZ;
+
+/// free slots of the swap area, the never used ones are above `current`
+struct SwapSlots {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+lazy_static! {
+    static ref SWAP_SLOTS: SpinLock<SwapSlots> = SpinLock::new(SwapSlots {
+        current: 0,
+        recycled: Vec::new(),
+    });
+}
+
+/// A free slot, `None` if the swap area is full
+pub fn swap_alloc() -> Option<usize> {
+    let mut slots = SWAP_SLOTS.lock();
+    if let Some(slot) = slots.recycled.pop() {
+        Some(slot)
+    } else if slots.current < SWAP_PAGES {
+        slots.current += 1;
+        Some(slots.current - 1)
+    } else {
+        None
+    }
+}
+
+pub fn swap_free(slot: usize) {
+    let mut slots = SWAP_SLOTS.lock();
+    assert!(
+        slot < slots.current && !slots.recycled.contains(&slot),
+        "swap slot {} has not been allocated!",
+        slot
+    );
+    slots.recycled.push(slot);
+}
+
+/// number of free slots
+pub fn swap_free_slots() -> usize {
+    let slots = SWAP_SLOTS.lock();
+    SWAP_PAGES - slots.current + slots.recycled.len()
+}
+
+/// Write the page in `ppn` to `slot`
+pub fn swap_write(slot: usize, ppn: PhysPageNum) {
+    let first = SWAP_START_BLOCK + slot * BLOCKS_PER_PAGE;
+    for (i, block) in ppn.get_bytes_array().chunks(BLOCK_SZ).enumerate() {
+        BLOCK_DEVICE.write_block(first + i, block);
+    }
+}
+
+/// Read the page in `slot` into `ppn`, the slot stays allocated
+pub fn swap_read(slot: usize, ppn: PhysPageNum) {
+    let first = SWAP_START_BLOCK + slot * BLOCKS_PER_PAGE;
+    for (i, block) in ppn.get_bytes_array().chunks_mut(BLOCK_SZ).enumerate() {
+        BLOCK_DEVICE.read_block(first + i, block);
+    }
+}
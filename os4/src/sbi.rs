@@ -5,6 +5,29 @@ const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
 const SBI_SHUTDOWN: usize = 8;
 
+/// Base extension
+const SBI_EXT_BASE: usize = 0x10;
+const SBI_BASE_PROBE_EXTENSION: usize = 3;
+/// Hart State Management extension
+const SBI_EXT_HSM: usize = 0x48534D;
+const SBI_HSM_HART_START: usize = 0;
+const SBI_HSM_HART_STOP: usize = 1;
+const SBI_HSM_HART_GET_STATUS: usize = 2;
+/// System Reset extension
+const SBI_EXT_SRST: usize = 0x53525354;
+const SBI_SRST_SYSTEM_RESET: usize = 0;
+const SBI_SRST_TYPE_SHUTDOWN: usize = 0;
+const SBI_SRST_TYPE_COLD_REBOOT: usize = 1;
+const SBI_SRST_REASON_NONE: usize = 0;
+const SBI_SRST_REASON_FAILURE: usize = 1;
+/// Debug Console extension
+const SBI_EXT_DBCN: usize = 0x4442434E;
+const SBI_DBCN_CONSOLE_WRITE: usize = 0;
+/// Remote fence extension
+const SBI_EXT_RFENCE: usize = 0x52464E43;
+const SBI_RFENCE_REMOTE_FENCE_I: usize = 0;
+const SBI_RFENCE_REMOTE_SFENCE_VMA_ASID: usize = 2;
+
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let mut ret;
@@ -20,6 +43,96 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+/// SBI v0.2 call of function `fid` in extension `eid`, returning the error
+/// code, which is 0 on success, and the value
+#[inline(always)]
+fn sbi_call_ext(eid: usize, fid: usize, args: [usize; 5]) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => error,
+            inlateout("x11") args[1] => value,
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error as isize, value)
+}
+
+/// Whether the firmware implements extension `eid`.
+pub fn probe_extension(eid: usize) -> bool {
+    match sbi_call_ext(SBI_EXT_BASE, SBI_BASE_PROBE_EXTENSION, [eid, 0, 0, 0, 0]) {
+        (0, value) => value != 0,
+        _ => false,
+    }
+}
+
+/// Whether other harts can be started, see [`hart_start`]
+pub fn has_hsm() -> bool {
+    probe_extension(SBI_EXT_HSM)
+}
+
+/// State of a hart as the HSM extension reports it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+}
+
+/// Start `hartid` at physical address `start_addr` with `opaque` in a1.
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> Result<(), isize> {
+    match sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_START, [hartid, start_addr, opaque, 0, 0]) {
+        (0, _) => Ok(()),
+        (error, _) => Err(error),
+    }
+}
+
+/// Stop the current hart, handing it back to the firmware. Only returns if
+/// the firmware refuses.
+pub fn hart_stop() -> isize {
+    sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_STOP, [0; 5]).0
+}
+
+/// The state of `hartid`, an error if there is no such hart.
+pub fn hart_status(hartid: usize) -> Result<HartState, isize> {
+    match sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_GET_STATUS, [hartid, 0, 0, 0, 0]) {
+        (0, 0) => Ok(HartState::Started),
+        (0, 1) => Ok(HartState::Stopped),
+        (0, 2) => Ok(HartState::StartPending),
+        (0, 3) => Ok(HartState::StopPending),
+        (0, 4) => Ok(HartState::Suspended),
+        (0, 5) => Ok(HartState::SuspendPending),
+        (0, _) => Ok(HartState::ResumePending),
+        (error, _) => Err(error),
+    }
+}
+
+/// Flush `[start, start + size)` of address space `asid` on every hart,
+/// `size` of `usize::MAX` standing for the whole address space.
+pub fn remote_sfence_vma_asid(start: usize, size: usize, asid: usize) {
+    // a hart mask base of -1 selects all harts
+    sbi_call_ext(
+        SBI_EXT_RFENCE,
+        SBI_RFENCE_REMOTE_SFENCE_VMA_ASID,
+        [0, usize::MAX, start, size, asid],
+    );
+}
+
+/// Make instructions written to memory visible to the fetches of every hart.
+#[allow(unused)]
+pub fn remote_fence_i() {
+    sbi_call_ext(SBI_EXT_RFENCE, SBI_RFENCE_REMOTE_FENCE_I, [0, usize::MAX, 0, 0, 0]);
+}
+
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
 }
@@ -28,6 +141,21 @@ pub fn console_putchar(c: usize) {
     sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
 }
 
+/// Whether the console can take a whole buffer per call, see
+/// [`console_write`]
+pub fn has_dbcn() -> bool {
+    probe_extension(SBI_EXT_DBCN)
+}
+
+/// Write `len` bytes at physical address `pa` to the console, returning how
+/// many of them went out, which may be fewer.
+pub fn console_write(pa: usize, len: usize) -> Result<usize, isize> {
+    match sbi_call_ext(SBI_EXT_DBCN, SBI_DBCN_CONSOLE_WRITE, [len, pa, 0, 0, 0]) {
+        (0, written) => Ok(written),
+        (error, _) => Err(error),
+    }
+}
+
 pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
@@ -36,3 +164,37 @@ pub fn shutdown() -> ! {
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
     panic!("It should shutdown!");
 }
+
+/// Ask SRST for a reset of `reset_type`, which only returns if the firmware
+/// does not have SRST or refuses.
+fn system_reset(reset_type: usize, failure: bool) {
+    let reason = if failure {
+        SBI_SRST_REASON_FAILURE
+    } else {
+        SBI_SRST_REASON_NONE
+    };
+    sbi_call_ext(
+        SBI_EXT_SRST,
+        SBI_SRST_SYSTEM_RESET,
+        [reset_type, reason, 0, 0, 0],
+    );
+}
+
+/// Shut down telling the platform whether it is because of a failure, which
+/// QEMU turns into its exit code. Without SRST it is a plain shutdown.
+pub fn shutdown_with(failure: bool) -> ! {
+    system_reset(SBI_SRST_TYPE_SHUTDOWN, failure);
+    shutdown()
+}
+
+/// Restart the machine from the firmware. Without SRST there is no way to,
+/// so it shuts down instead.
+pub fn reboot() -> ! {
+    system_reset(SBI_SRST_TYPE_COLD_REBOOT, false);
+    shutdown()
+}
+
+/// Whether [`reboot`] and the exit code of [`shutdown_with`] work here
+pub fn has_srst() -> bool {
+    probe_extension(SBI_EXT_SRST)
+}
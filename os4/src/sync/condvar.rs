@@ -0,0 +1,32 @@
+//! Condition variables handed out to user threads
+
+use super::{Mutex, WaitQueue};
+use crate::timer::Deadline;
+use alloc::sync::Arc;
+
+pub struct Condvar {
+    wait_queue: WaitQueue,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn signal(&self) {
+        self.wait_queue.wake_one();
+    }
+
+    /// Release `mutex` and sleep until signaled or `deadline` passes, then
+    /// take `mutex` again either way. Returns whether it was signaled. We
+    /// are in the queue before `mutex` is released, so a signal cannot be
+    /// missed.
+    pub fn wait_until(&self, mutex: Arc<dyn Mutex>, deadline: Deadline) -> bool {
+        self.wait_queue.wait_until_after(deadline, || mutex.unlock());
+        let signaled = !deadline.expired();
+        mutex.lock();
+        signaled
+    }
+}
@@ -0,0 +1,44 @@
+//! An eventfd-style counter for single-word notifications between tasks
+
+/// A monotonic 64-bit event counter.
+///
+/// Writers add to the counter to signal, readers take the accumulated value
+/// (or a single unit in semaphore mode). Blocking while it is zero is up to
+/// the owner, see [`EventFd`](crate::fs::EventFd).
+pub struct EventCounter {
+    count: u64,
+    semaphore: bool,
+}
+
+impl EventCounter {
+    /// the largest value the counter may hold, as for Linux eventfd
+    pub const MAX: u64 = u64::MAX - 1;
+
+    pub fn new(initval: u64, semaphore: bool) -> Self {
+        Self {
+            count: initval,
+            semaphore,
+        }
+    }
+    /// Add `value` to the counter, failing if it would overflow.
+    pub fn signal(&mut self, value: u64) -> Result<(), ()> {
+        if value > Self::MAX - self.count {
+            return Err(());
+        }
+        self.count += value;
+        Ok(())
+    }
+    /// Take the counter value, or `None` if nothing has been signaled yet.
+    pub fn consume(&mut self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else if self.semaphore {
+            self.count -= 1;
+            Some(1)
+        } else {
+            let value = self.count;
+            self.count = 0;
+            Some(value)
+        }
+    }
+}
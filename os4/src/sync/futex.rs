@@ -0,0 +1,113 @@
+//! Futexes, user threads sleeping on a word of user memory
+//!
+//! User space keeps its lock in a word of its own memory and only asks the
+//! kernel to sleep while the word holds the value it saw, or to wake up the
+//! sleepers after changing it. Sleepers are queued by the physical address
+//! of the word, so threads of different processes mapping the same page,
+//! such as a shared memory object, meet in the same queue.
+//!
+//! That address has to stay put while there are sleepers: the swapper
+//! puts back a page it has just unmapped if its frame holds a futex word,
+//! see [`is_futex_frame`], and a waiter checks the translation again with
+//! the table locked, in case the page went out between looking it up and
+//! locking. One of the two always sees what the other did.
+
+use super::{SpinLock, WaitQueue};
+use crate::config::PAGE_SIZE;
+use crate::mm::{translated_byte_buffer, translated_phys_addr, PhysAddr, PhysPageNum};
+use crate::task::fault_in_user_range;
+use crate::timer::{Deadline, ETIMEDOUT};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::*;
+
+/// `op` of `sys_futex`: sleep while the word holds `val`
+pub const FUTEX_WAIT: usize = 0;
+/// `op` of `sys_futex`: wake up at most `val` sleepers
+pub const FUTEX_WAKE: usize = 1;
+
+lazy_static! {
+    /// queues by the physical address of their word, dropped once empty
+    static ref FUTEXES: SpinLock<BTreeMap<usize, Arc<WaitQueue>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// The physical address of the aligned user word at `uaddr` of the current
+/// process, bringing the page in if it is not present. `None` if the word
+/// is not accessible.
+pub fn futex_key(token: usize, uaddr: usize) -> Option<usize> {
+    if uaddr % 4 != 0 {
+        return None;
+    }
+    fault_in_user_range(uaddr, 4);
+    let buffers = translated_byte_buffer(token, uaddr as *const u8, 4).ok()?;
+    Some(buffers[0].as_ptr() as usize)
+}
+
+/// Block until woken up or `deadline` passes if the user word at `uaddr`
+/// of address space `token` holds `val`. Returns 0 once woken up, -1 if
+/// the word did not hold `val` or is not accessible and `ETIMEDOUT` if the
+/// deadline passed.
+pub fn futex_wait(token: usize, uaddr: usize, val: u32, deadline: Deadline) -> isize {
+    let (key, mut futexes) = loop {
+        let key = match futex_key(token, uaddr) {
+            Some(key) => key,
+            None => return -1,
+        };
+        let futexes = FUTEXES.lock();
+        // a page unmapped from here on is put back by the swapper, one
+        // unmapped before is brought in again on the next round
+        if translated_phys_addr(token, uaddr) == Some(key) {
+            break (key, futexes);
+        }
+    };
+    // 持表锁读取：唤醒者改完值后要先拿到表锁，不会在检查和入队之间漏掉唤醒
+    let word = unsafe { &*(key as *const AtomicU32) };
+    if word.load(Ordering::SeqCst) != val {
+        return -1;
+    }
+    if deadline.expired() {
+        return ETIMEDOUT;
+    }
+    let queue = futexes
+        .entry(key)
+        .or_insert_with(|| Arc::new(WaitQueue::new()))
+        .clone();
+    queue.wait_until_after(deadline, || drop(futexes));
+    // woken up or killed, the queue may be left without sleepers
+    let mut futexes = FUTEXES.lock();
+    if queue.is_empty() && futexes.get(&key).map_or(false, |q| Arc::ptr_eq(q, &queue)) {
+        futexes.remove(&key);
+    }
+    if deadline.expired() {
+        ETIMEDOUT
+    } else {
+        0
+    }
+}
+
+/// Whether threads sleep on a word in the frame `ppn`. The swapper asks
+/// after unmapping a page and leaves the frame in place if so.
+pub fn is_futex_frame(ppn: PhysPageNum) -> bool {
+    let start = PhysAddr::from(ppn).0;
+    FUTEXES.lock().range(start..start + PAGE_SIZE).next().is_some()
+}
+
+/// Wake up at most `count` threads sleeping on the word at `key`, returning
+/// how many there were.
+pub fn futex_wake(key: usize, count: usize) -> usize {
+    let mut futexes = FUTEXES.lock();
+    let queue = match futexes.get(&key) {
+        Some(queue) => queue.clone(),
+        None => return 0,
+    };
+    let mut woken = 0;
+    while woken < count && queue.wake_one() {
+        woken += 1;
+    }
+    if queue.is_empty() {
+        futexes.remove(&key);
+    }
+    woken
+}
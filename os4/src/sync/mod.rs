@@ -1,5 +1,17 @@
 //! Synchronization and interior mutability primitives
 
-mod up;
+mod condvar;
+mod event;
+mod futex;
+mod mutex;
+mod semaphore;
+mod spin;
+mod wait_queue;
 
-pub use up::UPSafeCell;
\ No newline at end of file
+pub use self::spin::{locks_held, SpinLock, SpinLockGuard, SpinNoIrq};
+pub use condvar::Condvar;
+pub use event::EventCounter;
+pub use futex::{futex_key, futex_wait, futex_wake, is_futex_frame, FUTEX_WAIT, FUTEX_WAKE};
+pub use mutex::{Mutex, MutexBlocking, MutexSpin};
+pub use semaphore::Semaphore;
+pub use wait_queue::WaitQueue;
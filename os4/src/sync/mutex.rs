@@ -0,0 +1,92 @@
+//! Mutexes handed out to user threads
+
+use super::{SpinLock, WaitQueue};
+use crate::task::suspend_current_and_run_next;
+use crate::timer::Deadline;
+
+pub trait Mutex: Sync + Send {
+    /// Take the mutex, giving up once `deadline` passes. Returns whether
+    /// it was taken.
+    fn lock_until(&self, deadline: Deadline) -> bool;
+    fn unlock(&self);
+    fn lock(&self) {
+        self.lock_until(Deadline::NEVER);
+    }
+}
+
+/// A mutex whose waiters keep yielding until it is released
+pub struct MutexSpin {
+    locked: SpinLock<bool>,
+}
+
+impl MutexSpin {
+    pub fn new() -> Self {
+        Self {
+            locked: SpinLock::new(false),
+        }
+    }
+}
+
+impl Mutex for MutexSpin {
+    fn lock_until(&self, deadline: Deadline) -> bool {
+        loop {
+            let mut locked = self.locked.lock();
+            if *locked {
+                drop(locked);
+                if deadline.expired() {
+                    return false;
+                }
+                suspend_current_and_run_next();
+                continue;
+            } else {
+                *locked = true;
+                return true;
+            }
+        }
+    }
+
+    fn unlock(&self) {
+        let mut locked = self.locked.lock();
+        *locked = false;
+    }
+}
+
+/// A mutex whose waiters sleep in a [`WaitQueue`] until it is released
+pub struct MutexBlocking {
+    locked: SpinLock<bool>,
+    wait_queue: WaitQueue,
+}
+
+impl MutexBlocking {
+    pub fn new() -> Self {
+        Self {
+            locked: SpinLock::new(false),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+}
+
+impl Mutex for MutexBlocking {
+    fn lock_until(&self, deadline: Deadline) -> bool {
+        loop {
+            let mut locked = self.locked.lock();
+            if !*locked {
+                *locked = true;
+                return true;
+            }
+            if deadline.expired() {
+                return false;
+            }
+            // someone else may grab it before we run again, so check again
+            self.wait_queue.wait_until_after(deadline, || drop(locked));
+        }
+    }
+
+    fn unlock(&self) {
+        let mut locked = self.locked.lock();
+        assert!(*locked);
+        *locked = false;
+        drop(locked);
+        self.wait_queue.wake_one();
+    }
+}
@@ -0,0 +1,34 @@
+//! Counting semaphores handed out to user threads
+
+use super::{SpinLock, WaitQueue};
+
+pub struct Semaphore {
+    /// resources left, never negative as waiters are kept in the queue
+    count: SpinLock<usize>,
+    wait_queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub fn new(res_count: usize) -> Self {
+        Self {
+            count: SpinLock::new(res_count),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn up(&self) {
+        *self.count.lock() += 1;
+        self.wait_queue.wake_one();
+    }
+
+    pub fn down(&self) {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                *count -= 1;
+                return;
+            }
+            self.wait_queue.wait_after(|| drop(count));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Spinlocks shared between harts
+//!
+//! [`SpinLock`] is the plain one. [`SpinNoIrq`] also keeps interrupts off
+//! on the current hart while it is held, so an interrupt handler taking the
+//! same lock cannot spin forever on a hart that is already holding it.
+//!
+//! Each hart counts the locks it holds, see [`locks_held`], so an interrupt
+//! taken in the kernel knows whether it may switch to another task.
+
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use riscv::register::sstatus;
+
+/// A lock spinning until the data is free
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+/// The data of a [`SpinLock`], released when dropped
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+    /// Spin until no one else holds the data.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        // counted first, the task cannot be moved to another hart from here
+        push_off();
+        hart_state().held += 1;
+        pop_off();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        SpinLockGuard { lock: self }
+    }
+    /// Take the lock only if no one holds it, including this hart.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        push_off();
+        hart_state().held += 1;
+        pop_off();
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return Some(SpinLockGuard { lock: self });
+        }
+        push_off();
+        hart_state().held -= 1;
+        pop_off();
+        None
+    }
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        push_off();
+        hart_state().held -= 1;
+        pop_off();
+    }
+}
+
+/// Lock bookkeeping of a hart
+struct HartState {
+    /// how deep the hart is in [`push_off`]
+    depth: usize,
+    /// whether interrupts were on before the outermost [`push_off`]
+    enabled: bool,
+    /// spinlocks the hart holds or is spinning on
+    held: usize,
+}
+
+/// only touched by its own hart with interrupts off
+struct PerHartState([UnsafeCell<HartState>; MAX_HARTS]);
+
+unsafe impl Sync for PerHartState {}
+
+const HART_STATE_INIT: UnsafeCell<HartState> = UnsafeCell::new(HartState {
+    depth: 0,
+    enabled: false,
+    held: 0,
+});
+
+static HART_STATE: PerHartState = PerHartState([HART_STATE_INIT; MAX_HARTS]);
+
+/// The state of the current hart, interrupts have to be off.
+fn hart_state() -> &'static mut HartState {
+    unsafe { &mut *HART_STATE.0[hart_id()].get() }
+}
+
+/// Turn interrupts off on this hart, remembering if they were on.
+fn push_off() {
+    let enabled = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    let state = hart_state();
+    if state.depth == 0 {
+        state.enabled = enabled;
+    }
+    state.depth += 1;
+}
+
+/// Undo a [`push_off`], turning interrupts back on after the outermost one
+/// if they were on before.
+fn pop_off() {
+    let state = hart_state();
+    state.depth -= 1;
+    if state.depth == 0 && state.enabled {
+        unsafe {
+            sstatus::set_sie();
+        }
+    }
+}
+
+/// How many spinlocks the current hart holds.
+///
+/// Only meaningful with interrupts off, e.g. in an interrupt handler.
+pub fn locks_held() -> usize {
+    hart_state().held
+}
+
+/// A [`SpinLock`] keeping interrupts off on the current hart while held
+pub struct SpinNoIrq<T> {
+    inner: SpinLock<T>,
+}
+
+/// The data of a [`SpinNoIrq`], released when dropped
+pub struct SpinNoIrqGuard<'a, T> {
+    guard: Option<SpinLockGuard<'a, T>>,
+}
+
+impl<T> SpinNoIrq<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: SpinLock::new(data),
+        }
+    }
+    /// Turn interrupts off and spin until no one else holds the data.
+    pub fn lock(&self) -> SpinNoIrqGuard<'_, T> {
+        push_off();
+        SpinNoIrqGuard {
+            guard: Some(self.inner.lock()),
+        }
+    }
+    /// Take the lock only if no one holds it, including this hart.
+    pub fn try_lock(&self) -> Option<SpinNoIrqGuard<'_, T>> {
+        push_off();
+        match self.inner.try_lock() {
+            Some(guard) => Some(SpinNoIrqGuard { guard: Some(guard) }),
+            None => {
+                pop_off();
+                None
+            }
+        }
+    }
+}
+
+impl<T> Deref for SpinNoIrqGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for SpinNoIrqGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for SpinNoIrqGuard<'_, T> {
+    fn drop(&mut self) {
+        // release the lock before interrupts may come in again
+        drop(self.guard.take());
+        pop_off();
+    }
+}
@@ -0,0 +1,89 @@
+//! A queue of blocked tasks waiting for the same event
+
+use super::SpinLock;
+use crate::task::{
+    block_current_and_run_next, current_task, mark_current_blocked, wakeup_task, TaskControlBlock,
+};
+use crate::timer::{add_wakeup, remove_wakeup, Deadline};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Tasks blocked on an event, woken up in FIFO order.
+///
+/// A woken task may find the event already consumed, so waiters check their
+/// condition again in a loop around [`WaitQueue::wait`].
+pub struct WaitQueue {
+    queue: SpinLock<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+#[allow(unused)]
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: SpinLock::new(VecDeque::new()),
+        }
+    }
+    /// Block the current task until it is woken up.
+    pub fn wait(&self) {
+        self.wait_until(Deadline::NEVER);
+    }
+    /// Block the current task until it is woken up or `deadline` passes.
+    pub fn wait_until(&self, deadline: Deadline) {
+        self.wait_until_after(deadline, || {});
+    }
+    /// Block the current task until it is woken up, calling `release` once
+    /// it is in the queue.
+    ///
+    /// The waker has to see whatever `release` gives up, e.g. the lock
+    /// guarding the condition, before it can wake anyone, so a wake-up from
+    /// another hart cannot slip in between checking and going to sleep.
+    pub fn wait_after<F: FnOnce()>(&self, release: F) {
+        self.wait_until_after(Deadline::NEVER, release);
+    }
+    /// [`WaitQueue::wait_after`] giving up once `deadline` passes.
+    pub fn wait_until_after<F: FnOnce()>(&self, deadline: Deadline, release: F) {
+        let task = current_task().unwrap();
+        mark_current_blocked();
+        self.queue.lock().push_back(task.clone());
+        if let Some(expire_us) = deadline.as_us() {
+            add_wakeup(expire_us, task.clone());
+        }
+        release();
+        block_current_and_run_next();
+        // only one of the two woke us up, forget about the other
+        self.queue
+            .lock()
+            .retain(|waiter| !Arc::ptr_eq(waiter, &task));
+        if deadline.as_us().is_some() {
+            remove_wakeup(&task);
+        }
+    }
+    /// Whether no task is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+    /// Wake up the task waiting longest, returning whether there was one.
+    /// Tasks killed while waiting are skipped.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let task = self.queue.lock().pop_front();
+            match task {
+                Some(task) => {
+                    if wakeup_task(task) {
+                        return true;
+                    }
+                }
+                None => return false,
+            }
+        }
+    }
+    /// Wake up every waiting task, returning how many there were.
+    pub fn wake_all(&self) -> usize {
+        let tasks = core::mem::take(&mut *self.queue.lock());
+        let count = tasks.len();
+        for task in tasks {
+            wakeup_task(task);
+        }
+        count
+    }
+}
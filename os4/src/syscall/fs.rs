@@ -1,21 +1,223 @@
 //! File and filesystem-related syscalls
 
-use crate::mm::translated_byte_buffer;
-use crate::task::current_user_token;
-
-const FD_STDOUT: usize = 1;
+use crate::fs::{make_pipe, open_file, EventFd, OpenFlags, EFD_SEMAPHORE};
+use alloc::sync::Arc;
+use crate::mm::{copy_to_user, translated_byte_buffer, translated_str, UserBuffer};
+use crate::task::{current_process, current_user_token, fault_in_user, fault_in_user_range, fault_in_user_str};
+use crate::timer::Deadline;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let buffers = translated_byte_buffer(current_user_token(), buf, len);
-            for buffer in buffers {
-                print!("{}", core::str::from_utf8(buffer).unwrap());
-            }
-            len as isize
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) if file.writable() => file.clone(),
+        _ => return -1,
+    };
+    // release current PCB manually, writing may take a while
+    drop(inner);
+    fault_in_user_range(buf as usize, len);
+    match translated_byte_buffer(token, buf, len) {
+        Ok(buffers) => file.write(UserBuffer::new(buffers)),
+        Err(_) => -1,
+    }
+}
+
+/// Read up to `len` bytes of `fd` into `buf`. A read that blocks returns
+/// `ETIMEDOUT` once `deadline_us` (0 for none) passes with nothing read.
+pub fn sys_read(fd: usize, buf: *const u8, len: usize, deadline_us: usize) -> isize {
+    let deadline = Deadline::from_raw(deadline_us);
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) if file.readable() => file.clone(),
+        _ => return -1,
+    };
+    // release current PCB manually, reading may block
+    drop(inner);
+    fault_in_user_range(buf as usize, len);
+    match translated_byte_buffer(token, buf, len) {
+        Ok(buffers) => file.read(UserBuffer::new(buffers), deadline),
+        Err(_) => -1,
+    }
+}
+
+/// Open the file at `path` and return its fd, or -1 if it cannot be opened
+pub fn sys_open(path: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    fault_in_user_str(path);
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(_) => return -1,
+    };
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    match open_file(path.as_str(), flags) {
+        Some(inode) => {
+            let process = current_process();
+            let mut inner = process.inner_exclusive_access();
+            let fd = match inner.alloc_fd() {
+                Some(fd) => fd,
+                None => return -1,
+            };
+            inner.fd_table[fd] = Some(inode);
+            fd as isize
         }
-        _ => {
-            panic!("Unsupported fd in sys_write!");
+        None => -1,
+    }
+}
+
+#[cfg(feature = "board_test")]
+/// A read into a page of a file mapping nothing has touched yet brings the
+/// page in instead of failing on an unmapped buffer.
+///
+/// Like the other board tests it runs on the boot hart before `trap::init`
+/// and `drivers::init`: nothing is scheduled, so the process it makes only
+/// serves as the current one for the syscalls, and the block device is
+/// polled. Both are gone afterwards; easy-fs cannot remove files, so the
+/// two files it writes are left empty.
+pub fn read_into_mmap_test() {
+    use crate::fs::{read_file, write_file};
+    use crate::mm::{VirtAddr, MAP_FILE};
+    use crate::task::{discard_process, mmap_in_current_memory_set, set_current_task, ProcessControlBlock};
+    assert!(write_file("board_test.src", &[7u8; 64]));
+    assert!(write_file("board_test.map", &[0u8; 64]));
+    let elf = read_file("ch5b_initproc").unwrap();
+    let process = ProcessControlBlock::new(&elf, None).unwrap();
+    let mut inner = process.inner_exclusive_access();
+    set_current_task(inner.tasks[0].clone());
+    let mut open = |name| {
+        let fd = inner.alloc_fd().unwrap();
+        inner.fd_table[fd] = Some(open_file(name, OpenFlags::RDONLY).unwrap());
+        fd
+    };
+    let (src_fd, map_fd) = (open("board_test.src"), open("board_test.map"));
+    drop(inner);
+    // a private read-write mapping, its pages are copied on first access
+    let start = 0x1000_0000;
+    assert_eq!(mmap_in_current_memory_set(start, 64, MAP_FILE | 0b011, map_fd, 0), 0);
+    let token = current_user_token();
+    let pte = process.inner_exclusive_access().memory_set.translate(VirtAddr::from(start).floor());
+    assert!(pte.map_or(true, |pte| !pte.is_valid()));
+    assert_eq!(sys_read(src_fd, start as *const u8, 64, 0), 64);
+    let buffers = translated_byte_buffer(token, start as *const u8, 64).unwrap();
+    assert!(buffers[0].iter().all(|&byte| byte == 7));
+    set_current_task(None);
+    discard_process(process);
+    assert!(write_file("board_test.src", &[]));
+    assert!(write_file("board_test.map", &[]));
+    info!("read_into_mmap_test passed!");
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get_mut(fd) {
+        Some(file) => file.take(),
+        None => return -1,
+    };
+    drop(inner);
+    // the file goes away outside of the PCB
+    match file {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// Make a new fd referring to the same file as `fd`, the lowest closed one
+pub fn sys_dup(fd: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    let new_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[new_fd] = Some(file);
+    new_fd as isize
+}
+
+/// Make `new_fd` refer to the same file as `old_fd`, closing the file it
+/// referred to before. Returns `new_fd`, or -1 if `old_fd` is not open or
+/// `new_fd` is over the limit of open fds.
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(old_fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    if new_fd >= inner.rlimits.max_fds {
+        return -1;
+    }
+    if inner.fd_table.len() <= new_fd {
+        inner.fd_table.resize(new_fd + 1, None);
+    }
+    let old_file = inner.fd_table[new_fd].replace(file);
+    drop(inner);
+    // the file goes away outside of the PCB
+    drop(old_file);
+    new_fd as isize
+}
+
+/// Create a pipe and store the fds of its read end and write end at `pipe`
+pub fn sys_pipe(pipe: *mut usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let (pipe_read, pipe_write) = make_pipe();
+    let read_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            // the pipe goes away outside of the PCB
+            drop(inner);
+            return -1;
+        }
+    };
+    inner.fd_table[read_fd] = Some(pipe_read);
+    let write_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            let pipe_read = inner.fd_table[read_fd].take();
+            drop(inner);
+            drop(pipe_read);
+            return -1;
         }
+    };
+    inner.fd_table[write_fd] = Some(pipe_write);
+    // bringing user memory in locks the PCB
+    drop(inner);
+    fault_in_user(pipe as *const [usize; 2]);
+    if copy_to_user(token, pipe as *mut [usize; 2], &[read_fd, write_fd]).is_err() {
+        let mut inner = process.inner_exclusive_access();
+        let ends = (inner.fd_table[read_fd].take(), inner.fd_table[write_fd].take());
+        drop(inner);
+        // the pipe goes away outside of the PCB
+        drop(ends);
+        return -1;
+    }
+    0
+}
+
+/// Create an eventfd whose counter starts at `initval` and return its fd.
+/// `flags` may only hold `EFD_SEMAPHORE`.
+pub fn sys_eventfd(initval: usize, flags: usize) -> isize {
+    if flags & !EFD_SEMAPHORE != 0 {
+        return -1;
     }
+    let eventfd = Arc::new(EventFd::new(initval as u64, flags));
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[fd] = Some(eventfd);
+    fd as isize
 }
@@ -9,37 +9,170 @@
 //! For clarity, each single syscall is implemented as its own function, named
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
+//!
+//! The syscalls of a process turned on with `sys_trace` are logged here
+//! too, see [`trace`].
 
+const SYSCALL_EVENTFD: usize = 19;
+const SYSCALL_DUP: usize = 24;
+/// not Linux' number, which is dup3 there; 24 is dup in the user lib
+const SYSCALL_DUP2: usize = 25;
+const SYSCALL_OPEN: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE: usize = 59;
+const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_CLOCK_SETTIME: usize = 112;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGRETURN: usize = 139;
 const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETRUSAGE: usize = 165;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETTID: usize = 178;
+const SYSCALL_SYSINFO: usize = 179;
+const SYSCALL_SBRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_MREMAP: usize = 216;
+const SYSCALL_FORK: usize = 220;
+const SYSCALL_EXEC: usize = 221;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_SPAWN: usize = 400;
 const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_TRACE: usize = 411;
+const SYSCALL_TASK_INFO2: usize = 412;
+const SYSCALL_MEMPINFO: usize = 413;
+const SYSCALL_TASK_LIST: usize = 414;
+const SYSCALL_TASK_MAPS: usize = 415;
+const SYSCALL_SHUTDOWN: usize = 416;
+const SYSCALL_REBOOT: usize = 417;
+const SYSCALL_SETRLIMIT: usize = 418;
+const SYSCALL_SHM_CREATE: usize = 419;
+const SYSCALL_SHM_ATTACH: usize = 420;
+const SYSCALL_SHM_DETACH: usize = 421;
+const SYSCALL_THREAD_CREATE: usize = 460;
+const SYSCALL_WAITTID: usize = 462;
+const SYSCALL_MUTEX_CREATE: usize = 463;
+const SYSCALL_MUTEX_LOCK: usize = 464;
+const SYSCALL_MUTEX_UNLOCK: usize = 466;
+const SYSCALL_SEMAPHORE_CREATE: usize = 467;
+const SYSCALL_SEMAPHORE_UP: usize = 468;
+const SYSCALL_SEMAPHORE_DOWN: usize = 470;
+const SYSCALL_CONDVAR_CREATE: usize = 471;
+const SYSCALL_CONDVAR_SIGNAL: usize = 472;
+const SYSCALL_CONDVAR_WAIT: usize = 473;
 
 mod fs;
 pub mod process;
+mod sync;
+mod thread;
+mod trace;
+
+pub use trace::name as syscall_name;
+#[cfg(feature = "board_test")]
+pub use fs::read_into_mmap_test;
 
 use fs::*;
 use process::*;
+use sync::*;
+use thread::*;
 
-use crate::task::update_syscall_times;
+use crate::mm::MapRegion;
+use crate::task::{current_process, update_syscall_times, SignalAction};
+use core::sync::atomic::Ordering;
 
 /// handle syscall exception with `syscall_id` and other arguments
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     // LAB1: You may need to update syscall info here.
     update_syscall_times(syscall_id);
+    let process = current_process();
+    if !process.traced.load(Ordering::Relaxed) {
+        drop(process);
+        return dispatch(syscall_id, args);
+    }
+    let pid = process.getpid();
+    drop(process);
+    let call = trace::describe(syscall_id, &args);
+    if matches!(syscall_id, SYSCALL_EXIT | SYSCALL_SHUTDOWN | SYSCALL_REBOOT) {
+        // never returns
+        println!("[trace {}] {} = ?", pid, call);
+    }
+    let result = dispatch(syscall_id, args);
+    println!("[trace {}] {} = {}", pid, call, result);
+    result
+}
+
+fn dispatch(syscall_id: usize, args: [usize; 6]) -> isize {
     match syscall_id {
+        SYSCALL_EVENTFD => sys_eventfd(args[0], args[1]),
+        SYSCALL_DUP => sys_dup(args[0]),
+        SYSCALL_DUP2 => sys_dup2(args[0], args[1]),
+        SYSCALL_OPEN => sys_open(args[1] as *const u8, args[2] as u32),
+        SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
+        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2], args[3]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_FUTEX => sys_futex(args[0], args[1], args[2], args[3]),
+        SYSCALL_SLEEP => sys_sleep(args[0]),
+        SYSCALL_CLOCK_SETTIME => sys_clock_settime(args[0], args[1] as *const TimeSpec),
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_KILL => sys_kill(args[0], args[1] as i32),
+        SYSCALL_SIGACTION => sys_sigaction(
+            args[0] as i32,
+            args[1] as *const SignalAction,
+            args[2] as *mut SignalAction,
+        ),
+        SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
+        SYSCALL_SIGRETURN => sys_sigreturn(),
         SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
-        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_GETRUSAGE => sys_getrusage(args[0] as isize, args[1] as *mut RUsage),
+        SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_GETTID => sys_gettid(),
+        SYSCALL_SYSINFO => sys_sysinfo(args[0] as *mut SysInfo),
+        SYSCALL_FORK => sys_fork(),
+        SYSCALL_EXEC => sys_exec(args[0] as *const u8),
+        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32, args[2]),
+        SYSCALL_SBRK => sys_sbrk(args[0] as isize),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MREMAP => sys_mremap(args[0], args[1], args[2], args[3]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_TRACE => sys_trace(args[0] as isize, args[1] != 0),
+        SYSCALL_TASK_INFO2 => sys_task_info2(args[0] as *mut TaskInfo2),
+        SYSCALL_MEMPINFO => sys_mempinfo(args[0] as *mut MemInfo),
+        SYSCALL_TASK_LIST => sys_task_list(args[0] as *mut TaskListEntry, args[1]),
+        SYSCALL_TASK_MAPS => sys_task_maps(args[0], args[1] as *mut MapRegion, args[2]),
+        SYSCALL_SHUTDOWN => sys_shutdown(args[0] != 0),
+        SYSCALL_REBOOT => sys_reboot(),
+        SYSCALL_SETRLIMIT => sys_setrlimit(args[0], args[1]),
+        SYSCALL_SHM_CREATE => sys_shm_create(args[0]),
+        SYSCALL_SHM_ATTACH => sys_shm_attach(args[0], args[1]),
+        SYSCALL_SHM_DETACH => sys_shm_detach(args[0]),
+        SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
+        SYSCALL_WAITTID => sys_waittid(args[0]) as isize,
+        SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] == 1),
+        SYSCALL_MUTEX_LOCK => sys_mutex_lock(args[0], args[1]),
+        SYSCALL_MUTEX_UNLOCK => sys_mutex_unlock(args[0]),
+        SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(args[0]),
+        SYSCALL_SEMAPHORE_UP => sys_semaphore_up(args[0]),
+        SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]),
+        SYSCALL_CONDVAR_CREATE => sys_condvar_create(args[0]),
+        SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
+        SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1], args[2]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
 }
@@ -1,9 +1,18 @@
 //! Process management syscalls
 
-use crate::config::MAX_SYSCALL_NUM;
-use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, TaskStatus, current_user_token, mmap_in_current_memory_set, munmap_in_current_memory_set, get_task_info};
-use crate::timer::get_time_us;
-use crate::mm::translated_assign_ptr;
+use crate::config::{MAX_HARTS, MAX_SYSCALL_NUM, MEMINFO_MAX_TASKS};
+use crate::hart::online_harts;
+use crate::fs::read_file;
+use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, TaskStatus, current_user_token, mmap_in_current_memory_set, munmap_in_current_memory_set, mprotect_in_current_memory_set, get_task_info, change_program_brk};
+use crate::task::{block_current_and_run_next, current_cpu_times, current_process, current_task, mark_current_blocked};
+use crate::task::{get_task_info2, pid2process, process_regions, process_resident_pages, task_list, sigreturn_current, SignalAction, SignalFlags};
+use crate::task::{attach_shm_in_current_memory_set, detach_shm_in_current_memory_set, idle_time_us, mremap_in_current_memory_set, set_current_rlimit};
+use crate::task::{fault_in_user, fault_in_user_range, fault_in_user_str, retry_after_reclaim};
+use crate::timer::{add_wakeup, clock_gettime_ns, clock_settime_ns, get_time_us, Deadline, CLOCK_MONOTONIC, ETIMEDOUT, NANO_PER_SEC};
+use crate::mm::{copy_from_user, copy_to_user, translated_str};
+use crate::mm::{frame_allocator_stats, page_cache, reserved_frames, shm_create, swap_free_slots, ElfError, MapRegion};
+use crate::sbi::{reboot, shutdown_with};
+use core::sync::atomic::Ordering;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -12,6 +21,22 @@ pub struct TimeVal {
     pub usec: usize,
 }
 
+/// Time of a clock read by `sys_clock_gettime`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+/// CPU time spent in user mode and in the kernel
+#[repr(C)]
+#[derive(Debug)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct TaskInfo {
     pub status: TaskStatus,
@@ -19,47 +44,444 @@ pub struct TaskInfo {
     pub time: usize,
 }
 
+/// One process in the list `sys_task_list` returns
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TaskListEntry {
+    pub pid: usize,
+    /// [`TaskStatus`] of the main thread as a number, zombie once it exited
+    pub status: usize,
+    pub priority: usize,
+    /// threads not reaped yet
+    pub threads: usize,
+    /// time spent in user mode by all threads
+    pub user_time_us: usize,
+    /// time spent in the kernel on behalf of all threads
+    pub kernel_time_us: usize,
+}
+
+/// Resident user pages of one process
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskMemInfo {
+    pub pid: usize,
+    pub resident_pages: usize,
+}
+
+/// System-wide memory usage, in frames
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MemInfo {
+    /// all of physical memory
+    pub total_frames: usize,
+    /// frames no one holds
+    pub free_frames: usize,
+    /// taken by the SBI firmware and the kernel image for good
+    pub reserved_frames: usize,
+    /// holding pages of files, some of them evictable
+    pub page_cache_frames: usize,
+    /// free pages in the swap area
+    pub swap_free_pages: usize,
+    /// processes alive, `tasks` holds the first of them in pid order
+    pub task_count: usize,
+    pub tasks: [TaskMemInfo; MEMINFO_MAX_TASKS],
+}
+
+/// Uptime and how much of it each hart spent idle
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SysInfo {
+    /// microseconds since boot
+    pub uptime_us: usize,
+    /// harts running the kernel, `idle_us` holds as many
+    pub harts: usize,
+    /// microseconds each hart has waited in `wfi` with nothing to run
+    pub idle_us: [usize; MAX_HARTS],
+}
+
+/// [`TaskInfo`] followed by memory statistics of the process and scheduling
+/// statistics of the calling thread
+#[derive(Clone, Copy, Debug)]
+pub struct TaskInfo2 {
+    pub status: TaskStatus,
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    pub time: usize,
+    /// user pages backed by a frame now
+    pub resident_pages: usize,
+    /// most user pages ever backed by a frame at once
+    pub peak_resident_pages: usize,
+    /// page faults resolved without I/O
+    pub minor_faults: usize,
+    /// page faults reading from the swap area or a file
+    pub major_faults: usize,
+    /// areas mapped by `mmap`
+    pub mmap_regions: usize,
+    /// times the thread was switched out by the timer
+    pub preemptions: usize,
+    /// times the thread gave up the CPU by `sys_yield`
+    pub voluntary_yields: usize,
+    /// times the thread was picked to run
+    pub dispatches: usize,
+    /// time spent ready in the queue before running, in us
+    pub sched_latency_us: usize,
+    /// longest such wait, in us
+    pub max_sched_latency_us: usize,
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     info!("[kernel] Application exited with code {}", exit_code);
-    exit_current_and_run_next();
+    exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
+/// Power the machine off, QEMU exiting with a failure code if `failure`.
+pub fn sys_shutdown(failure: bool) -> ! {
+    let pid = current_process().getpid();
+    println!("[kernel] shutdown requested by process {}, failure: {}", pid, failure);
+    crate::task::report_kernel_profile();
+    shutdown_with(failure)
+}
+
+/// Restart the machine, or power it off if the firmware cannot.
+pub fn sys_reboot() -> ! {
+    let pid = current_process().getpid();
+    println!("[kernel] reboot requested by process {}", pid);
+    reboot()
+}
+
 /// current task gives up resources for other tasks
 pub fn sys_yield() -> isize {
     suspend_current_and_run_next();
     0
 }
 
-// YOUR JOB: 引入虚地址后重写 sys_get_time
+pub fn sys_getpid() -> isize {
+    current_process().getpid() as isize
+}
+
+/// Syscall Fork which returns 0 for child process and child_pid for parent process.
+/// Only a process with a single thread can fork.
+pub fn sys_fork() -> isize {
+    let current_process = current_process();
+    if current_process.inner_exclusive_access().thread_count() != 1 {
+        return -1;
+    }
+    let new_process = match retry_after_reclaim(|| current_process.fork(), Option::is_none) {
+        Some(new_process) => new_process,
+        None => return -1,
+    };
+    let new_pid = new_process.getpid();
+    // modify trap context of new_task, because it returns immediately after switching
+    let new_process_inner = new_process.inner_exclusive_access();
+    let task = new_process_inner.get_task(0);
+    let trap_cx = task.inner_exclusive_access().get_trap_cx();
+    // we do not have to move to next instruction since we have done it before
+    // for child process, fork returns 0
+    trap_cx.x[10] = 0;
+    new_pid as isize
+}
+
+/// Syscall Exec which accepts the elf path
+pub fn sys_exec(path: *const u8) -> isize {
+    let token = current_user_token();
+    fault_in_user_str(path);
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(_) => return -1,
+    };
+    let process = current_process();
+    if process.inner_exclusive_access().thread_count() != 1 {
+        return -1;
+    }
+    let data = match read_file(path.as_str()) {
+        Some(data) => data,
+        None => return -1,
+    };
+    let result = retry_after_reclaim(
+        || process.exec(&data),
+        |result| matches!(result, Err(ElfError::OutOfMemory)),
+    );
+    match result {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Syscall Spawn which creates a child process running the elf at `path`,
+/// returns the pid of the child or -1 if there is no such valid elf
+pub fn sys_spawn(path: *const u8) -> isize {
+    let token = current_user_token();
+    fault_in_user_str(path);
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(_) => return -1,
+    };
+    let data = match read_file(path.as_str()) {
+        Some(data) => data,
+        None => return -1,
+    };
+    let process = current_process();
+    let spawned = retry_after_reclaim(
+        || process.spawn(&data),
+        |result| matches!(result, Err(ElfError::OutOfMemory)),
+    );
+    match spawned {
+        Ok(child) => child.getpid() as isize,
+        Err(_) => -1,
+    }
+}
+
+/// If there is not a child process whose pid is same as given, return -1.
+/// Else block until such a child exits, or return ETIMEDOUT once
+/// `deadline_us` (0 for none) passes.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, deadline_us: usize) -> isize {
+    let deadline = Deadline::from_raw(deadline_us);
+    let process = current_process();
+    loop {
+        // find a child process
+
+        // ---- access current PCB exclusively
+        let mut inner = process.inner_exclusive_access();
+        if !inner
+            .children
+            .iter()
+            .any(|p| pid == -1 || pid as usize == p.getpid())
+        {
+            return -1;
+            // ---- release current PCB
+        }
+        let pair = inner.children.iter().enumerate().find(|(_, p)| {
+            // ++++ temporarily access child PCB exclusively
+            p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
+            // ++++ release child PCB
+        });
+        if let Some((idx, _)) = pair {
+            // the child is deallocated once the hart it exited on is done
+            // with it, which may still take a moment
+            let child = inner.children.remove(idx);
+            let found_pid = child.getpid();
+            // ++++ temporarily access child PCB exclusively
+            let exit_code = child.inner_exclusive_access().exit_code;
+            // ++++ release child PCB
+            // bringing user memory in locks the PCB
+            let token = inner.get_user_token();
+            drop(inner);
+            // ---- release current PCB
+            fault_in_user(exit_code_ptr);
+            if copy_to_user(token, exit_code_ptr, &exit_code).is_err() {
+                return -1;
+            }
+            return found_pid as isize;
+        }
+        if deadline.expired() {
+            return ETIMEDOUT;
+        }
+        // a child wakes us with our PCB held, so it cannot do so before we
+        // are in the queue
+        process
+            .child_exited
+            .wait_until_after(deadline, || drop(inner));
+        // ---- release current PCB
+    }
+}
+
+/// Kept for the old `TimeVal` interface, it reads `CLOCK_MONOTONIC` like
+/// [`sys_clock_gettime`] does.
 pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
-    let us = get_time_us();
-    // unsafe {
-    //     *ts = TimeVal {
-    //         sec: us / 1_000_000,
-    //         usec: us % 1_000_000,
-    //     };
-    // }
-    translated_assign_ptr(
-        current_user_token(),
-        ts,
+    let us = clock_gettime_ns(CLOCK_MONOTONIC).unwrap() / 1000;
+    fault_in_user(ts);
+    match copy_to_user(current_user_token(), ts, &TimeVal::from_us(us)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Read `clock_id`, `CLOCK_REALTIME` or `CLOCK_MONOTONIC`, into `ts`.
+pub fn sys_clock_gettime(clock_id: usize, ts: *mut TimeSpec) -> isize {
+    let ns = match clock_gettime_ns(clock_id) {
+        Some(ns) => ns,
+        None => return -1,
+    };
+    let time = TimeSpec {
+        sec: ns / NANO_PER_SEC,
+        nsec: ns % NANO_PER_SEC,
+    };
+    fault_in_user(ts);
+    match copy_to_user(current_user_token(), ts, &time) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Set `clock_id` to `ts`, only `CLOCK_REALTIME` can be set.
+pub fn sys_clock_settime(clock_id: usize, ts: *const TimeSpec) -> isize {
+    fault_in_user(ts);
+    let time = match copy_from_user(current_user_token(), ts) {
+        Ok(time) => time,
+        Err(_) => return -1,
+    };
+    if time.nsec >= NANO_PER_SEC {
+        return -1;
+    }
+    let ns = match time
+        .sec
+        .checked_mul(NANO_PER_SEC)
+        .and_then(|ns| ns.checked_add(time.nsec))
+    {
+        Some(ns) => ns,
+        None => return -1,
+    };
+    if clock_settime_ns(clock_id, ns) {
+        0
+    } else {
+        -1
+    }
+}
+
+impl TimeVal {
+    fn from_us(us: usize) -> Self {
         TimeVal {
             sec: us / 1_000_000,
             usec: us % 1_000_000,
         }
-    );
+    }
+}
+
+/// Only `who == 0`, the calling process itself, is supported.
+pub fn sys_getrusage(who: isize, ru: *mut RUsage) -> isize {
+    if who != 0 {
+        return -1;
+    }
+    let (user_time, kernel_time) = current_cpu_times();
+    let usage = RUsage {
+        utime: TimeVal::from_us(user_time),
+        stime: TimeVal::from_us(kernel_time),
+    };
+    fault_in_user(ru);
+    match copy_to_user(current_user_token(), ru, &usage) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// block the current task for `ms` milliseconds
+pub fn sys_sleep(ms: usize) -> isize {
+    let expire_us = get_time_us() + ms * 1000;
+    mark_current_blocked();
+    add_wakeup(expire_us, current_task().unwrap());
+    block_current_and_run_next();
     0
 }
 
+/// send signal `signum` to the process `pid`
+pub fn sys_kill(pid: usize, signum: i32) -> isize {
+    let signal = match SignalFlags::from_signum(signum as usize) {
+        Some(signal) => signal,
+        None => return -1,
+    };
+    match pid2process(pid) {
+        Some(process) => {
+            // signals to a process are taken care of by its main thread,
+            // which sees them the next time it returns to user mode
+            let task = process.inner_exclusive_access().get_task(0);
+            task.inner_exclusive_access().signals |= signal;
+            0
+        }
+        None => -1,
+    }
+}
+
+/// set the action of signal `signum`, storing the old one to `old_action`
+/// unless it is null
+pub fn sys_sigaction(
+    signum: i32,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    let signal = match SignalFlags::from_signum(signum as usize) {
+        Some(signal) => signal,
+        None => return -1,
+    };
+    if SignalFlags::uncatchable().contains(signal) {
+        return -1;
+    }
+    let token = current_user_token();
+    let new_action = if action.is_null() {
+        None
+    } else {
+        fault_in_user(action);
+        match copy_from_user(token, action) {
+            Ok(new_action) => Some(new_action),
+            Err(_) => return -1,
+        }
+    };
+    let process = current_process();
+    // not copied out with the PCB held, bringing user memory in locks it
+    let old = process.inner_exclusive_access().signal_actions.table[signum as usize];
+    if !old_action.is_null() {
+        fault_in_user(old_action);
+        if copy_to_user(token, old_action, &old).is_err() {
+            return -1;
+        }
+    }
+    if let Some(new_action) = new_action {
+        process.inner_exclusive_access().signal_actions.table[signum as usize] = new_action;
+    }
+    0
+}
+
+/// set the blocked signals of the current thread, returning the old mask
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let old_mask = inner.signal_mask;
+    inner.signal_mask = SignalFlags::from_bits_truncate(mask) - SignalFlags::uncatchable();
+    old_mask.bits() as isize
+}
+
+/// return from a signal handler to where the thread was interrupted
+pub fn sys_sigreturn() -> isize {
+    match sigreturn_current() {
+        // the syscall return value goes to a0, which has to be restored too
+        Some(a0) => a0 as isize,
+        None => -1,
+    }
+}
+
 // CLUE: 从 ch4 开始不再对调度算法进行测试~
-pub fn sys_set_priority(_prio: isize) -> isize {
-    -1
+/// Turn syscall tracing of process `pid` on or off, -1 for the caller.
+/// Children forked or spawned later inherit it.
+pub fn sys_trace(pid: isize, enable: bool) -> isize {
+    let process = if pid == -1 {
+        current_process()
+    } else {
+        match pid2process(pid as usize) {
+            Some(process) => process,
+            None => return -1,
+        }
+    };
+    process.traced.store(enable, Ordering::Relaxed);
+    0
+}
+
+/// Set the priority of the current thread, at least 2, which gives it a
+/// share of the CPU in proportion under `scheduler=stride`.
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < 2 {
+        return -1;
+    }
+    current_task()
+        .unwrap()
+        .priority
+        .store(prio as usize, Ordering::Relaxed);
+    prio
 }
 
 // YOUR JOB: 扩展内核以实现 sys_mmap 和 sys_munmap
-pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
+/// `fd` and `offset` only matter for a file-backed mapping
+pub fn sys_mmap(start: usize, len: usize, port: usize, fd: usize, offset: usize) -> isize {
     // -1
-    mmap_in_current_memory_set(start, len, port)
+    mmap_in_current_memory_set(start, len, port, fd, offset)
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> isize {
@@ -67,13 +489,144 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
     munmap_in_current_memory_set(start, len)
 }
 
+/// Resize the mapping at `old_addr`, moving it with `MREMAP_MAYMOVE` if it
+/// cannot grow in place, and return where it is now.
+pub fn sys_mremap(old_addr: usize, old_len: usize, new_len: usize, flags: usize) -> isize {
+    mremap_in_current_memory_set(old_addr, old_len, new_len, flags)
+}
+
+/// grow or shrink the heap, returning the old program break
+pub fn sys_sbrk(increment: isize) -> isize {
+    match change_program_brk(increment) {
+        Some(old_brk) => old_brk as isize,
+        None => -1,
+    }
+}
+
+/// Limit `resource` of the current process to `limit`, `usize::MAX` for no
+/// limit. Returns -1 if there is no such resource.
+pub fn sys_setrlimit(resource: usize, limit: usize) -> isize {
+    if set_current_rlimit(resource, limit) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Create a shared memory object of `pages` zeroed pages, returning its id,
+/// or -1 when out of frames.
+pub fn sys_shm_create(pages: usize) -> isize {
+    match shm_create(pages) {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+/// Map shared memory object `id` with the permission `prot`, returning the
+/// address it is mapped at, or -1.
+pub fn sys_shm_attach(id: usize, prot: usize) -> isize {
+    attach_shm_in_current_memory_set(id, prot)
+}
+
+/// Unmap the shared memory object attached at `addr`. The object goes away
+/// once no process maps it anymore.
+pub fn sys_shm_detach(addr: usize) -> isize {
+    detach_shm_in_current_memory_set(addr)
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    mprotect_in_current_memory_set(start, len, prot)
+}
+
 // YOUR JOB: 引入虚地址后重写 sys_task_info
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
     // -1
-    translated_assign_ptr(
+    fault_in_user(ti);
+    match copy_to_user(
         current_user_token(),
         ti,
-        get_task_info()
-    );
-    0
+        &get_task_info()
+    ) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+pub fn sys_task_info2(ti: *mut TaskInfo2) -> isize {
+    fault_in_user(ti);
+    match copy_to_user(current_user_token(), ti, &get_task_info2()) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Fill `info` with the memory usage of the whole system.
+pub fn sys_mempinfo(info: *mut MemInfo) -> isize {
+    let stats = frame_allocator_stats();
+    let reserved = reserved_frames();
+    let residents = process_resident_pages();
+    let mut mem_info = MemInfo {
+        total_frames: stats.total + reserved,
+        free_frames: stats.total - stats.allocated,
+        reserved_frames: reserved,
+        page_cache_frames: page_cache::cached_pages(),
+        swap_free_pages: swap_free_slots(),
+        task_count: residents.len(),
+        tasks: [TaskMemInfo::default(); MEMINFO_MAX_TASKS],
+    };
+    for (task, &(pid, resident_pages)) in mem_info.tasks.iter_mut().zip(residents.iter()) {
+        *task = TaskMemInfo { pid, resident_pages };
+    }
+    // the struct spans pages in user space, copied through each of them
+    fault_in_user(info);
+    match copy_to_user(current_user_token(), info, &mem_info) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Fill `info` with the uptime and the idle time of every hart.
+pub fn sys_sysinfo(info: *mut SysInfo) -> isize {
+    let mut sys_info = SysInfo {
+        uptime_us: get_time_us(),
+        harts: online_harts(),
+        idle_us: [0; MAX_HARTS],
+    };
+    for (hartid, idle_us) in sys_info.idle_us.iter_mut().enumerate() {
+        *idle_us = idle_time_us(hartid);
+    }
+    fault_in_user(info);
+    match copy_to_user(current_user_token(), info, &sys_info) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Copy up to `max` of `items` to the array at `buf`, returning how many
+/// items there are in all, or -1 if `buf` is bad.
+fn copy_array_to_user<T>(buf: *mut T, max: usize, items: &[T]) -> isize {
+    let token = current_user_token();
+    let len = max.min(items.len()) * core::mem::size_of::<T>();
+    fault_in_user_range(buf as usize, len);
+    for (i, item) in items.iter().take(max).enumerate() {
+        if copy_to_user(token, buf.wrapping_add(i), item).is_err() {
+            return -1;
+        }
+    }
+    items.len() as isize
+}
+
+/// List up to `max` processes into `buf` in pid order, returning how many
+/// processes there are.
+pub fn sys_task_list(buf: *mut TaskListEntry, max: usize) -> isize {
+    copy_array_to_user(buf, max, &task_list())
+}
+
+/// Copy up to `max` regions of the memory map of process `pid` into `buf`,
+/// returning how many regions there are, or -1 without such a process.
+pub fn sys_task_maps(pid: usize, buf: *mut MapRegion, max: usize) -> isize {
+    match process_regions(pid) {
+        Some(regions) => copy_array_to_user(buf, max, &regions),
+        None => -1,
+    }
 }
\ No newline at end of file
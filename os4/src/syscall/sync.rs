@@ -0,0 +1,152 @@
+//! Synchronization syscalls
+//!
+//! Mutexes, semaphores and condition variables live in tables of the current
+//! process and are referred to by their index there. Futexes need no table,
+//! they are words of user memory, see [`crate::sync::futex_wait`].
+
+use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore};
+use crate::sync::{futex_key, futex_wait, futex_wake, FUTEX_WAIT, FUTEX_WAKE};
+use crate::task::{current_process, current_user_token};
+use crate::timer::{Deadline, ETIMEDOUT};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Put `item` into the first free slot of `list`, returning its id
+fn insert_into_free_slot<T>(list: &mut Vec<Option<T>>, item: T) -> usize {
+    if let Some(id) = list.iter().position(|slot| slot.is_none()) {
+        list[id] = Some(item);
+        id
+    } else {
+        list.push(Some(item));
+        list.len() - 1
+    }
+}
+
+fn get_mutex(mutex_id: usize) -> Option<Arc<dyn Mutex>> {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner.mutex_list.get(mutex_id)?.clone()
+}
+
+fn get_semaphore(sem_id: usize) -> Option<Arc<Semaphore>> {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner.semaphore_list.get(sem_id)?.clone()
+}
+
+fn get_condvar(condvar_id: usize) -> Option<Arc<Condvar>> {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner.condvar_list.get(condvar_id)?.clone()
+}
+
+pub fn sys_mutex_create(blocking: bool) -> isize {
+    let mutex: Arc<dyn Mutex> = if blocking {
+        Arc::new(MutexBlocking::new())
+    } else {
+        Arc::new(MutexSpin::new())
+    };
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    insert_into_free_slot(&mut process_inner.mutex_list, mutex) as isize
+}
+
+/// Take the mutex, or give up with `ETIMEDOUT` once `deadline_us` (0 for
+/// none) passes.
+pub fn sys_mutex_lock(mutex_id: usize, deadline_us: usize) -> isize {
+    match get_mutex(mutex_id) {
+        Some(mutex) => {
+            if mutex.lock_until(Deadline::from_raw(deadline_us)) {
+                0
+            } else {
+                ETIMEDOUT
+            }
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
+    match get_mutex(mutex_id) {
+        Some(mutex) => {
+            mutex.unlock();
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_semaphore_create(res_count: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let sem = Arc::new(Semaphore::new(res_count));
+    insert_into_free_slot(&mut process_inner.semaphore_list, sem) as isize
+}
+
+pub fn sys_semaphore_up(sem_id: usize) -> isize {
+    match get_semaphore(sem_id) {
+        Some(sem) => {
+            sem.up();
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_semaphore_down(sem_id: usize) -> isize {
+    match get_semaphore(sem_id) {
+        Some(sem) => {
+            sem.down();
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_condvar_create(_arg: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let condvar = Arc::new(Condvar::new());
+    insert_into_free_slot(&mut process_inner.condvar_list, condvar) as isize
+}
+
+pub fn sys_condvar_signal(condvar_id: usize) -> isize {
+    match get_condvar(condvar_id) {
+        Some(condvar) => {
+            condvar.signal();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Wait on the condvar with the mutex released. The mutex is held again
+/// on return, also with `ETIMEDOUT` once `deadline_us` (0 for none) passes.
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize, deadline_us: usize) -> isize {
+    match (get_condvar(condvar_id), get_mutex(mutex_id)) {
+        (Some(condvar), Some(mutex)) => {
+            if condvar.wait_until(mutex, Deadline::from_raw(deadline_us)) {
+                0
+            } else {
+                ETIMEDOUT
+            }
+        }
+        _ => -1,
+    }
+}
+
+/// `FUTEX_WAIT`: block if the word at `uaddr` still holds `val`, returning
+/// 0 once woken up, -1 if it did not and `ETIMEDOUT` once `deadline_us` (0
+/// for none) passes. `FUTEX_WAKE`: wake up at most `val` threads blocked on
+/// the word, returning how many. -1 for a misaligned or unmapped `uaddr`.
+pub fn sys_futex(uaddr: usize, op: usize, val: usize, deadline_us: usize) -> isize {
+    let token = current_user_token();
+    match op {
+        FUTEX_WAIT => futex_wait(token, uaddr, val as u32, Deadline::from_raw(deadline_us)),
+        FUTEX_WAKE => match futex_key(token, uaddr) {
+            Some(key) => futex_wake(key, val) as isize,
+            None => -1,
+        },
+        _ => -1,
+    }
+}
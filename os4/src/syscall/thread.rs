@@ -0,0 +1,44 @@
+//! Thread management syscalls
+
+use crate::task::{current_process, current_task};
+
+/// Create a thread of the current process running `entry(arg)`, returning its tid
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    match current_process().create_thread(entry, arg) {
+        Some(task) => task.gettid() as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_gettid() -> isize {
+    current_task().unwrap().gettid() as isize
+}
+
+/// thread does not exist, return -1
+/// thread has not exited yet, return -2
+/// otherwise, return thread's exit code
+pub fn sys_waittid(tid: usize) -> i32 {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let task_inner = task.inner_exclusive_access();
+    let mut process_inner = process.inner_exclusive_access();
+    // a thread cannot wait for itself
+    if task_inner.res.as_ref().unwrap().tid == tid {
+        return -1;
+    }
+    let waited_task = match process_inner.tasks.get(tid) {
+        Some(Some(waited_task)) => waited_task.clone(),
+        // waited thread does not exist
+        _ => return -1,
+    };
+    let exit_code = waited_task.inner_exclusive_access().exit_code;
+    match exit_code {
+        Some(exit_code) => {
+            // dealloc the exited thread
+            process_inner.tasks[tid] = None;
+            exit_code
+        }
+        // waited thread has not exited
+        None => -2,
+    }
+}
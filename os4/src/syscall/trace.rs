@@ -0,0 +1,138 @@
+//! Tracing syscalls of a process, like strace
+//!
+//! [`syscall()`](super::syscall) logs every syscall of a traced process with
+//! its name, arguments and return value. How the arguments are shown comes
+//! from [`SYSCALLS`]; a syscall missing there is still traced, with its
+//! number and all six arguments in hex.
+
+use super::*;
+use crate::mm::translated_str;
+use crate::task::current_user_token;
+use alloc::string::String;
+use core::fmt::Write;
+
+/// how an argument is shown
+#[derive(Clone, Copy)]
+enum Arg {
+    /// a signed number
+    Int,
+    /// an address or flags
+    Hex,
+    /// a pointer to a nul-terminated string in user space
+    Str,
+}
+
+use Arg::*;
+
+/// (id, name, arguments) of the syscalls the tracer knows
+const SYSCALLS: &[(usize, &str, &[Arg])] = &[
+    (SYSCALL_EVENTFD, "eventfd", &[Int, Hex]),
+    (SYSCALL_DUP, "dup", &[Int]),
+    (SYSCALL_DUP2, "dup2", &[Int, Int]),
+    (SYSCALL_OPEN, "open", &[Int, Str, Hex]),
+    (SYSCALL_CLOSE, "close", &[Int]),
+    (SYSCALL_PIPE, "pipe", &[Hex]),
+    (SYSCALL_READ, "read", &[Int, Hex, Int, Int]),
+    (SYSCALL_WRITE, "write", &[Int, Hex, Int]),
+    (SYSCALL_EXIT, "exit", &[Int]),
+    (SYSCALL_FUTEX, "futex", &[Hex, Int, Int, Int]),
+    (SYSCALL_SLEEP, "sleep", &[Int]),
+    (SYSCALL_CLOCK_SETTIME, "clock_settime", &[Int, Hex]),
+    (SYSCALL_CLOCK_GETTIME, "clock_gettime", &[Int, Hex]),
+    (SYSCALL_YIELD, "yield", &[]),
+    (SYSCALL_KILL, "kill", &[Int, Int]),
+    (SYSCALL_SIGACTION, "sigaction", &[Int, Hex, Hex]),
+    (SYSCALL_SIGPROCMASK, "sigprocmask", &[Hex]),
+    (SYSCALL_SIGRETURN, "sigreturn", &[]),
+    (SYSCALL_GET_TIME, "get_time", &[Hex, Int]),
+    (SYSCALL_GETRUSAGE, "getrusage", &[Int, Hex]),
+    (SYSCALL_GETPID, "getpid", &[]),
+    (SYSCALL_GETTID, "gettid", &[]),
+    (SYSCALL_SYSINFO, "sysinfo", &[Hex]),
+    (SYSCALL_SBRK, "sbrk", &[Int]),
+    (SYSCALL_MUNMAP, "munmap", &[Hex, Hex]),
+    (SYSCALL_MREMAP, "mremap", &[Hex, Hex, Hex, Hex]),
+    (SYSCALL_FORK, "fork", &[]),
+    (SYSCALL_EXEC, "exec", &[Str]),
+    (SYSCALL_MMAP, "mmap", &[Hex, Hex, Hex, Int, Hex]),
+    (SYSCALL_MPROTECT, "mprotect", &[Hex, Hex, Hex]),
+    (SYSCALL_WAITPID, "waitpid", &[Int, Hex, Hex]),
+    (SYSCALL_SET_PRIORITY, "set_priority", &[Int]),
+    (SYSCALL_SPAWN, "spawn", &[Str]),
+    (SYSCALL_TASK_INFO, "task_info", &[Hex]),
+    (SYSCALL_TRACE, "trace", &[Int, Int]),
+    (SYSCALL_TASK_INFO2, "task_info2", &[Hex]),
+    (SYSCALL_MEMPINFO, "mempinfo", &[Hex]),
+    (SYSCALL_TASK_LIST, "task_list", &[Hex, Int]),
+    (SYSCALL_TASK_MAPS, "task_maps", &[Int, Hex, Int]),
+    (SYSCALL_SHUTDOWN, "shutdown", &[Int]),
+    (SYSCALL_REBOOT, "reboot", &[]),
+    (SYSCALL_SETRLIMIT, "setrlimit", &[Int, Hex]),
+    (SYSCALL_SHM_CREATE, "shm_create", &[Int]),
+    (SYSCALL_SHM_ATTACH, "shm_attach", &[Int, Hex]),
+    (SYSCALL_SHM_DETACH, "shm_detach", &[Hex]),
+    (SYSCALL_THREAD_CREATE, "thread_create", &[Hex, Hex]),
+    (SYSCALL_WAITTID, "waittid", &[Int]),
+    (SYSCALL_MUTEX_CREATE, "mutex_create", &[Int]),
+    (SYSCALL_MUTEX_LOCK, "mutex_lock", &[Int, Int]),
+    (SYSCALL_MUTEX_UNLOCK, "mutex_unlock", &[Int]),
+    (SYSCALL_SEMAPHORE_CREATE, "semaphore_create", &[Int]),
+    (SYSCALL_SEMAPHORE_UP, "semaphore_up", &[Int]),
+    (SYSCALL_SEMAPHORE_DOWN, "semaphore_down", &[Int]),
+    (SYSCALL_CONDVAR_CREATE, "condvar_create", &[Int]),
+    (SYSCALL_CONDVAR_SIGNAL, "condvar_signal", &[Int]),
+    (SYSCALL_CONDVAR_WAIT, "condvar_wait", &[Int, Int, Int]),
+];
+
+/// The name of syscall `syscall_id`, if the tracer knows it
+pub fn name(syscall_id: usize) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .find(|(id, _, _)| *id == syscall_id)
+        .map(|(_, name, _)| *name)
+}
+
+/// longer strings are cut off
+const MAX_STR_LEN: usize = 32;
+
+const UNKNOWN_ARGS: &[Arg] = &[Hex, Hex, Hex, Hex, Hex, Hex];
+
+/// The syscall as it is called, e.g. `open(-100, "hello", 0x200)`. Called
+/// before the syscall runs, while string arguments still mean what they
+/// meant to the caller.
+pub fn describe(syscall_id: usize, args: &[usize; 6]) -> String {
+    let mut call = String::new();
+    let arg_kinds = match SYSCALLS.iter().find(|(id, _, _)| *id == syscall_id) {
+        Some((_, name, arg_kinds)) => {
+            call.push_str(name);
+            *arg_kinds
+        }
+        None => {
+            write!(call, "syscall_{}", syscall_id).unwrap();
+            UNKNOWN_ARGS
+        }
+    };
+    call.push('(');
+    for (i, (kind, &arg)) in arg_kinds.iter().zip(args.iter()).enumerate() {
+        if i > 0 {
+            call.push_str(", ");
+        }
+        match kind {
+            Int => write!(call, "{}", arg as isize).unwrap(),
+            Hex => write!(call, "{:#x}", arg).unwrap(),
+            Str => match translated_str(current_user_token(), arg as *const u8) {
+                Ok(s) if s.len() > MAX_STR_LEN => {
+                    let mut end = MAX_STR_LEN;
+                    while !s.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    write!(call, "{:?}...", &s[..end]).unwrap()
+                }
+                Ok(s) => write!(call, "{:?}", s).unwrap(),
+                Err(_) => write!(call, "{:#x}", arg).unwrap(),
+            },
+        }
+    }
+    call.push(')');
+    call
+}
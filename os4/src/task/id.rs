@@ -0,0 +1,209 @@
+//! Allocation of process and thread identifiers and their resources.
+//!
+//! Assign PID to the process here. At the same time, the position of the
+//! application kernel stack is determined according to a kernel stack slot.
+//! Threads of a process get a TID together with their trap context and user
+//! stack. All of them are given back when their RAII handles are dropped.
+
+use super::ProcessControlBlock;
+use crate::config::{kernel_stack_position, PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::mm::{MapPermission, OutOfMemory, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::SpinLock;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Allocator of small integer ids, reusing the freed ones first
+pub struct RecycleAllocator {
+    /// A new id to be assigned
+    current: usize,
+    /// Ids freed and ready to be assigned again
+    recycled: Vec<usize>,
+}
+
+impl RecycleAllocator {
+    pub fn new() -> Self {
+        RecycleAllocator {
+            current: 0,
+            recycled: Vec::new(),
+        }
+    }
+    pub fn alloc(&mut self) -> usize {
+        if let Some(id) = self.recycled.pop() {
+            id
+        } else {
+            self.current += 1;
+            self.current - 1
+        }
+    }
+    pub fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current);
+        assert!(
+            !self.recycled.iter().any(|i| *i == id),
+            "id {} has been deallocated!",
+            id
+        );
+        self.recycled.push(id);
+    }
+}
+
+lazy_static! {
+    /// Pid allocator instance through lazy_static!
+    static ref PID_ALLOCATOR: SpinLock<RecycleAllocator> = SpinLock::new(RecycleAllocator::new());
+    /// Kernel stack slot allocator instance through lazy_static!
+    static ref KSTACK_ALLOCATOR: SpinLock<RecycleAllocator> = SpinLock::new(RecycleAllocator::new());
+}
+
+/// Bind pid lifetime to `PidHandle`
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        PID_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+/// Allocate a pid from PID_ALLOCATOR
+pub fn pid_alloc() -> PidHandle {
+    PidHandle(PID_ALLOCATOR.lock().alloc())
+}
+
+/// Kernel stack of a task, unmapped from kernel space on drop
+pub struct KernelStack(pub usize);
+
+/// Allocate a kernel stack slot and map the stack in kernel space.
+/// The guard page below it stays unmapped.
+pub fn kstack_alloc() -> KernelStack {
+    let kstack_id = KSTACK_ALLOCATOR.lock().alloc();
+    let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(kstack_id);
+    KERNEL_SPACE
+        .lock()
+        .insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        )
+        .expect("out of memory while mapping a kernel stack");
+    KernelStack(kstack_id)
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.0);
+        let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
+        KERNEL_SPACE
+            .lock()
+            .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        KSTACK_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+impl KernelStack {
+    /// Get the top of the kernel stack
+    pub fn get_top(&self) -> usize {
+        let (_, kernel_stack_top) = kernel_stack_position(self.0);
+        kernel_stack_top
+    }
+}
+
+/// Trap context of thread `tid`, the main thread owning the page at `TRAP_CONTEXT`
+fn trap_cx_bottom_from_tid(tid: usize) -> usize {
+    TRAP_CONTEXT - tid * PAGE_SIZE
+}
+
+/// User space resources of a thread: its tid, trap context and user stack
+pub struct TaskUserRes {
+    pub tid: usize,
+    /// Bottom of the user stack of an additional thread. The main thread
+    /// uses the stack set up by `MemorySet::from_elf` and has `None` here.
+    pub ustack_bottom: Option<usize>,
+    pub process: Weak<ProcessControlBlock>,
+}
+
+impl TaskUserRes {
+    /// Allocate a tid in `process`. Unless it is the main thread, also map a
+    /// trap context and a user stack for it in the shared address space.
+    pub fn new(process: &Arc<ProcessControlBlock>) -> Result<Self, OutOfMemory> {
+        let mut process_inner = process.inner_exclusive_access();
+        let tid = process_inner.alloc_tid();
+        let mut task_user_res = Self {
+            tid,
+            ustack_bottom: None,
+            process: Arc::downgrade(process),
+        };
+        if tid == 0 {
+            return Ok(task_user_res);
+        }
+        // 分配失败时drop会回收tid和已经映射的部分
+        drop(process_inner);
+        task_user_res.alloc_user_res()?;
+        Ok(task_user_res)
+    }
+
+    fn alloc_user_res(&mut self) -> Result<(), OutOfMemory> {
+        let process = self.process.upgrade().unwrap();
+        let mut process_inner = process.inner_exclusive_access();
+        // alloc trap_cx
+        let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
+        process_inner.memory_set.insert_framed_area(
+            trap_cx_bottom.into(),
+            (trap_cx_bottom + PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W,
+        )?;
+        // alloc user stack
+        let (ustack_bottom, _) = process_inner.memory_set.insert_thread_stack()?;
+        self.ustack_bottom = Some(ustack_bottom);
+        Ok(())
+    }
+
+    fn dealloc_user_res(&self, process: &ProcessControlBlock) {
+        if self.tid == 0 {
+            // part of the layout built from the elf, recycled with it
+            return;
+        }
+        let mut process_inner = process.inner_exclusive_access();
+        // dealloc ustack manually
+        if let Some(ustack_bottom) = self.ustack_bottom {
+            let ustack_bottom_va: VirtAddr = ustack_bottom.into();
+            process_inner
+                .memory_set
+                .remove_area_with_start_vpn(ustack_bottom_va.into());
+        }
+        // dealloc trap_cx manually
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
+        process_inner
+            .memory_set
+            .remove_area_with_start_vpn(trap_cx_bottom_va.into());
+    }
+
+    pub fn trap_cx_ppn(&self) -> PhysPageNum {
+        let process = self.process.upgrade().unwrap();
+        let process_inner = process.inner_exclusive_access();
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
+        process_inner
+            .memory_set
+            .translate(trap_cx_bottom_va.into())
+            .unwrap()
+            .ppn()
+    }
+
+    /// Where the trap context of this thread is mapped in user space
+    pub fn trap_cx_user_va(&self) -> usize {
+        trap_cx_bottom_from_tid(self.tid)
+    }
+
+    /// Top of the user stack of an additional thread
+    pub fn ustack_top(&self) -> Option<usize> {
+        self.ustack_bottom.map(|bottom| bottom + USER_STACK_SIZE)
+    }
+}
+
+impl Drop for TaskUserRes {
+    fn drop(&mut self) {
+        // the whole address space is gone with the process otherwise
+        if let Some(process) = self.process.upgrade() {
+            self.dealloc_user_res(&process);
+            process.inner_exclusive_access().dealloc_tid(self.tid);
+        }
+    }
+}
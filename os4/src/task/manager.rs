@@ -0,0 +1,138 @@
+//! Implementation of [`TaskManager`]
+//!
+//! It is only used to manage processes and schedule process based on ready queue.
+//! Other CPU process monitoring functions are in Processor.
+
+use super::replay;
+use super::{ProcessControlBlock, TaskControlBlock};
+use crate::boot_params::{boot_params, Scheduler};
+use crate::sync::{SpinLock, SpinNoIrq};
+use crate::timer::get_time_us;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::Ordering;
+use lazy_static::*;
+
+/// the pass of a thread of priority 1 grows by this much per dispatch
+const BIG_STRIDE: usize = 1 << 20;
+
+pub struct TaskManager {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// pass of the thread picked last, which new threads start at
+    pass: usize,
+}
+
+/// A simple FIFO scheduler, or a stride scheduler if the kernel command
+/// line asks for it.
+impl TaskManager {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+            pass: 0,
+        }
+    }
+    /// Add process back to ready queue
+    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    /// Take a process out of the ready queue
+    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        match boot_params().scheduler {
+            Scheduler::Fifo => self.ready_queue.pop_front(),
+            Scheduler::Stride => self.fetch_stride(),
+        }
+    }
+    /// Take the thread with the smallest pass, the one that became ready
+    /// first among equals, and advance its pass by its stride.
+    fn fetch_stride(&mut self) -> Option<Arc<TaskControlBlock>> {
+        // 比较与上次选中的pass之差，pass回绕也不会出错
+        let last = self.pass;
+        let (index, _) = self
+            .ready_queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, task)| task.pass.load(Ordering::Relaxed).wrapping_sub(last) as isize)?;
+        let task = self.ready_queue.remove(index).unwrap();
+        self.advance_pass(&task);
+        Some(task)
+    }
+    /// Advance the pass of `task`, picked to run, by its stride.
+    fn advance_pass(&mut self, task: &TaskControlBlock) {
+        let pass = task.pass.load(Ordering::Relaxed);
+        let stride = BIG_STRIDE / task.priority.load(Ordering::Relaxed);
+        task.pass.store(pass.wrapping_add(stride), Ordering::Relaxed);
+        self.pass = pass;
+    }
+    /// Take thread `tid` of `process` out of the ready queue, if it is
+    /// there, as a replayed decision. Locks the TCBs in the queue.
+    fn fetch_thread(&mut self, process: &Arc<ProcessControlBlock>, tid: usize) -> Option<Arc<TaskControlBlock>> {
+        let index = self.ready_queue.iter().position(|task| {
+            Weak::as_ptr(&task.process) == Arc::as_ptr(process)
+                && task.inner_exclusive_access().res.as_ref().map(|res| res.tid) == Some(tid)
+        })?;
+        let task = self.ready_queue.remove(index).unwrap();
+        if boot_params().scheduler == Scheduler::Stride {
+            self.advance_pass(&task);
+        }
+        Some(task)
+    }
+    /// Drop `task` from the ready queue, if it is there
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        self.ready_queue.retain(|t| !Arc::ptr_eq(t, task));
+    }
+}
+
+lazy_static! {
+    /// TASK_MANAGER instance through lazy_static!
+    pub static ref TASK_MANAGER: SpinNoIrq<TaskManager> = SpinNoIrq::new(TaskManager::new());
+    /// Every process not exited yet, by pid
+    pub static ref PID2PCB: SpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+pub fn add_task(task: Arc<TaskControlBlock>) {
+    // 调度延迟从进入就绪队列开始算
+    task.ready_since.store(get_time_us(), Ordering::Relaxed);
+    TASK_MANAGER.lock().add(task);
+}
+
+/// The next thread to run, the one in the log when replaying, see
+/// [`replay`]. Must be called with no lock held.
+pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
+    if let Some((pid, tid)) = replay::next_dispatch() {
+        let task = pid2process(pid).and_then(|process| TASK_MANAGER.lock().fetch_thread(&process, tid));
+        if replay::dispatched(task.is_some()) {
+            return task;
+        }
+    }
+    TASK_MANAGER.lock().fetch()
+}
+
+/// The pass a new thread starts at, so that it does not run until it has
+/// caught up with the others under the stride scheduler
+pub fn initial_pass() -> usize {
+    TASK_MANAGER.lock().pass
+}
+
+pub fn remove_task(task: &Arc<TaskControlBlock>) {
+    TASK_MANAGER.lock().remove(task);
+}
+
+pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    PID2PCB.lock().get(&pid).cloned()
+}
+
+/// Whether every process has exited
+pub fn no_process_left() -> bool {
+    PID2PCB.lock().is_empty()
+}
+
+pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
+    PID2PCB.lock().insert(pid, process);
+}
+
+pub fn remove_from_pid2process(pid: usize) {
+    if PID2PCB.lock().remove(&pid).is_none() {
+        panic!("cannot find pid {} in pid2process!", pid);
+    }
+}
@@ -6,247 +6,1040 @@
 //! A single global instance of [`TaskManager`] called `TASK_MANAGER` controls
 //! all the tasks in the operating system.
 //!
+//! A global array of [`Processor`] called `PROCESSORS` monitors the running
+//! task of each hart.
+//!
+//! Global instances of [`id::RecycleAllocator`] hand out pids and kernel
+//! stack slots, which are given back when the task is dropped.
+//!
+//! A process ([`ProcessControlBlock`]) owns an address space and one or more
+//! threads ([`TaskControlBlock`]), which are what actually gets scheduled.
+//!
+//! Signals are kept per thread and delivered in [`handle_signals`], right
+//! before the thread returns to user mode.
+//!
 //! Be careful when you see [`__switch`]. Control flow around this function
 //! might not be what you expect.
 
 mod context;
+mod id;
+mod manager;
+mod process;
+mod processor;
+mod profile;
+mod replay;
+mod rlimit;
+mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
+mod watchdog;
 
-use crate::config::MAX_SYSCALL_NUM;
-use crate::syscall::process::TaskInfo;
-use crate::loader::{get_app_data, get_num_app};
-use crate::sync::UPSafeCell;
-use crate::timer::get_time_us;
-use crate::trap::TrapContext;
+use crate::config::{ACCESS_SCAN_INTERVAL_US, DEFAULT_PRIORITY, MAX_SYSCALL_NUM, PAGE_CACHE_SHRINK, PAGE_SIZE, SWAP_BATCH};
+use crate::fs::{flush_stdout, list_files, read_file};
+use crate::mm::{swap_free_slots, FileBacking, MapRegion, PageFaultError, VirtPageNum, MAP_FILE, MAP_SHARED};
+use crate::mm::{frame_free_num, notify_mm_observer, page_cache, shm_attached, shm_frames, MapPermission};
+use crate::mm::{translated_byte_buffer, translated_phys_addr};
+use crate::sync::{is_futex_frame, SpinLock};
+use crate::syscall::process::{TaskInfo, TaskInfo2, TaskListEntry};
+use crate::timer::{get_time_us, remove_wakeup};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle, TaskUserRes};
 use lazy_static::*;
-pub use switch::__switch;
+use manager::{fetch_task, remove_from_pid2process, remove_task, PID2PCB};
+use switch::__switch;
 pub use task::{TaskControlBlock, TaskStatus};
+use task::TaskControlBlockInner;
 
 pub use context::TaskContext;
+pub use manager::{add_task, pid2process};
+pub use process::ProcessControlBlock;
+use rlimit::ResourceLimits;
+pub use signal::{SignalAction, SignalFlags, MAX_SIG, SIG_DFL, SIG_IGN};
+use signal::SignalActions;
+pub use processor::{
+    current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
+    count_tick, idle_time_us, run_tasks, schedule, take_current_task,
+};
+pub use watchdog::init as init_watchdog;
+pub use replay::{init as init_replay, record_wakeup, should_preempt};
+pub use profile::{report_kernel as report_kernel_profile, sample as profile_sample};
+use processor::try_current_task;
+#[cfg(feature = "board_test")]
+pub use processor::set_current_task;
+
+/// Suspend the current 'Running' task and run the next task in task list,
+/// counted as a voluntary yield.
+pub fn suspend_current_and_run_next() {
+    switch_out(false);
+}
+
+/// Preempt the current task from a timer interrupt.
+///
+/// Nothing happens if the task is on its way to block or exit, as it must
+/// not be put back into the ready queue then. The caller makes sure the
+/// hart holds no spinlock.
+pub fn preempt_current_and_run_next() {
+    let running = match current_task() {
+        Some(task) => task.inner_exclusive_access().task_status == TaskStatus::Running,
+        None => false,
+    };
+    if running {
+        switch_out(true);
+    }
+}
+
+/// Put the current task back into the ready queue and run the next one.
+fn switch_out(preempted: bool) {
+    // There must be an application running.
+    let task = take_current_task().unwrap();
+    task.charge_time(false);
+
+    // ---- access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // Change status to Ready
+    task_inner.task_status = TaskStatus::Ready;
+    if preempted {
+        task_inner.sched.preemptions += 1;
+        task_inner.sched.slices_in_a_row += 1;
+    } else {
+        task_inner.sched.voluntary_yields += 1;
+        task_inner.sched.gave_up_cpu();
+    }
+    drop(task_inner);
+    // ---- release current PCB
+    sched_log(&task, format_args!("{}", if preempted { "preempted" } else { "yields" }));
+    if preempted {
+        replay::record_preemption();
+    }
 
-/// The task manager, where all the tasks are managed.
+    // push back to ready queue.
+    add_task(task);
+    // jump to scheduling cycle
+    schedule(task_cx_ptr);
+}
+
+/// With feature `sched-log`, print a scheduling event of `task`, which must
+/// not be locked.
+fn sched_log(task: &TaskControlBlock, event: core::fmt::Arguments) {
+    if cfg!(feature = "sched-log") {
+        let pid = task.process.upgrade().map_or(0, |process| process.getpid());
+        let tid = task.gettid();
+        println!("[sched {}] pid {} tid {} {}", crate::hart::hart_id(), pid, tid, event);
+    }
+}
+
+/// Mark the current task as blocked while it keeps running until
+/// [`block_current_and_run_next`].
 ///
-/// Functions implemented on `TaskManager` deals with all task state transitions
-/// and task context switching. For convenience, you can find wrappers around it
-/// in the module level.
+/// Call this before putting the task where it will be woken up from, so a
+/// wake-up from another hart in between is not lost.
+pub fn mark_current_blocked() {
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().task_status = TaskStatus::Blocked;
+}
+
+/// Block the current 'Running' task and run the next task in task list.
 ///
-/// Most of `TaskManager` are hidden behind the field `inner`, to defer
-/// borrowing checks to runtime. You can see examples on how to use `inner` in
-/// existing functions on `TaskManager`.
-pub struct TaskManager {
-    /// total number of tasks
-    num_app: usize,
-    /// use inner value to get mutable access
-    inner: UPSafeCell<TaskManagerInner>,
+/// The caller must have put the task somewhere it will be woken up from,
+/// see [`wakeup_task`].
+pub fn block_current_and_run_next() {
+    let task = take_current_task().unwrap();
+    task.charge_time(false);
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // if it has been woken up already, it is in the ready queue and we only
+    // have to switch away from it
+    if task_inner.task_status == TaskStatus::Running {
+        task_inner.task_status = TaskStatus::Blocked;
+    }
+    task_inner.sched.gave_up_cpu();
+    drop(task_inner);
+    schedule(task_cx_ptr);
 }
 
-/// The task manager inner in 'UPSafeCell'
-struct TaskManagerInner {
-    /// task list
-    tasks: Vec<TaskControlBlock>,
-    /// id of current `Running` task
-    current_task: usize,
+/// Make a blocked task ready and put it back into the ready queue, returning
+/// whether it was blocked.
+///
+/// A task that is not blocked is left alone, so a stale wake-up from a timer
+/// or from a queue it has given up on cannot put it in the ready queue twice.
+pub fn wakeup_task(task: Arc<TaskControlBlock>) -> bool {
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.task_status != TaskStatus::Blocked {
+        return false;
+    }
+    task_inner.task_status = TaskStatus::Ready;
+    drop(task_inner);
+    add_task(task);
+    true
 }
 
-lazy_static! {
-    /// a `TaskManager` instance through lazy_static!
-    /// rust_main invoke run_first_task
-    pub static ref TASK_MANAGER: TaskManager = {
-        info!("init TASK_MANAGER");
-        // get the app number
-        let num_app = get_num_app();
-        info!("num_app = {}", num_app);
-        // get a new task control block empty vector
-        let mut tasks: Vec<TaskControlBlock> = Vec::new();
-        // get each apps ELF data
-        for i in 0..num_app {
-            // new tasks for each apps, get each tasks control block
-            tasks.push(TaskControlBlock::new(get_app_data(i), i));
-        }
-        TaskManager {
-            num_app,
-            inner: unsafe {
-                UPSafeCell::new(TaskManagerInner {
-                    tasks,
-                    current_task: 0,
-                })
-            },
+/// Stop another thread of the current process for good and take its user
+/// res, if it still has them.
+///
+/// A thread running on another hart cannot be stopped from here, so it is
+/// sent `SIGKILL` and exits on its own before it returns to user mode.
+fn stop_task(task: &Arc<TaskControlBlock>) -> Option<TaskUserRes> {
+    task.inner_exclusive_access().signals |= SignalFlags::SIGKILL;
+    loop {
+        remove_task(task);
+        remove_wakeup(task);
+        let mut task_inner = task.inner_exclusive_access();
+        match task_inner.task_status {
+            TaskStatus::Zombie => return task_inner.res.take(),
+            // the processor checks the status after setting `on_cpu`
+            _ if !task.on_cpu.load(Ordering::Acquire) => {
+                task_inner.task_status = TaskStatus::Zombie;
+                return task_inner.res.take();
+            }
+            _ => {}
         }
-    };
+        drop(task_inner);
+        core::hint::spin_loop();
+    }
 }
 
-impl TaskManager {
-    /// Run the first task in task list.
-    ///
-    /// Generally, the first task in task list is an idle task (we call it zero process later).
-    /// But in ch4, we load apps statically, so the first task is a real app.
-    fn run_first_task(&self) -> ! {
-        let mut inner = self.inner.exclusive_access();
-        let next_task = &mut inner.tasks[0];
-        next_task.task_status = TaskStatus::Running;
-        let next_task_cx_ptr = &next_task.task_cx as *const TaskContext;
-        drop(inner);
-        let mut _unused = TaskContext::zero_init();
-        // before this, we should drop local variables that must be dropped manually
-        unsafe {
-            __switch(&mut _unused as *mut _, next_task_cx_ptr);
+#[cfg(feature = "board_test")]
+/// Tear down `process`, made by a board test and never run, the way an
+/// exit would: its threads leave the ready queue, its pid is freed and its
+/// memory and files go away.
+pub fn discard_process(process: Arc<ProcessControlBlock>) {
+    remove_from_pid2process(process.getpid());
+    let tasks: Vec<_> = process.inner_exclusive_access().tasks.iter().flatten().cloned().collect();
+    // giving back the user res locks the PCB
+    let recycle_res: Vec<_> = tasks.iter().filter_map(stop_task).collect();
+    drop(recycle_res);
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.memory_set.recycle_data_pages();
+    process_inner.tasks.clear();
+    let fd_table = core::mem::take(&mut process_inner.fd_table);
+    drop(process_inner);
+    drop(fd_table);
+}
+
+/// Exit the current 'Running' thread and run the next task in task list.
+/// When the main thread exits the whole process goes with it.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    // take from Processor
+    let task = take_current_task().unwrap();
+    task.charge_time(false);
+    task.in_syscall.store(false, Ordering::Relaxed);
+    flush_stdout(&task);
+    // **** access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let process = task.process.upgrade().unwrap();
+    let tid = task_inner.res.as_ref().unwrap().tid;
+    // Record exit code
+    task_inner.exit_code = Some(exit_code);
+    task_inner.task_status = TaskStatus::Zombie;
+    let res = task_inner.res.take();
+    // here we do not remove the thread since we are still using the kstack
+    // it will be deallocated when sys_waittid is called
+    drop(task_inner);
+    // **** release current TCB
+    // giving back the user res locks the PCB
+    drop(res);
+    profile::report_task(&task.profile, process.getpid(), tid);
+
+    if tid == 0 {
+        let pid = process.getpid();
+        remove_from_pid2process(pid);
+        let mut process_inner = process.inner_exclusive_access();
+        // mark this process as a zombie process
+        process_inner.is_zombie = true;
+        // record exit code of main process
+        process_inner.exit_code = exit_code;
+        let parent = process_inner.parent.as_ref().and_then(|parent| parent.upgrade());
+        let children = core::mem::take(&mut process_inner.children);
+        let tasks: Vec<_> = process_inner.tasks.iter().flatten().cloned().collect();
+        // a parent locks itself before its children, so let go of ourselves
+        // before locking initproc
+        drop(process_inner);
+
+        // do not move to its parent but under initproc
+        // ++++++ access initproc PCB exclusively
+        if let Some(initproc) = INITPROC.as_ref() {
+            let mut initproc_inner = initproc.inner_exclusive_access();
+            for child in children.iter() {
+                child.inner_exclusive_access().parent = Some(Arc::downgrade(initproc));
+                initproc_inner.children.push(child.clone());
+            }
+            // some of them may be zombies already
+            if !children.is_empty() {
+                initproc.child_exited.wake_all();
+            }
+            drop(initproc_inner);
+        } else {
+            // no one is left to reap them
+            for child in children.iter() {
+                child.inner_exclusive_access().parent = None;
+            }
+        }
+        // ++++++ release initproc PCB
+        drop(children);
+
+        // stop the other threads wherever they are and take their user res
+        // (tid/trap_cx/ustack), which has to be done before we dealloc the
+        // whole memory_set, otherwise they will be deallocated twice
+        let mut recycle_res = Vec::new();
+        for task in tasks.iter() {
+            if let Some(res) = stop_task(task) {
+                recycle_res.push(res);
+            }
+            flush_stdout(task);
+        }
+        recycle_res.clear();
+
+        let mut process_inner = process.inner_exclusive_access();
+        // deallocate user space, the process never runs again
+        #[cfg(not(feature = "frame-debug"))]
+        process_inner.memory_set.recycle_data_pages();
+        #[cfg(feature = "frame-debug")]
+        {
+            process_inner.memory_set.recycle_data_pages_audited(pid);
+            crate::mm::dump_task_frames(pid);
+        }
+        // close the files, outside of the PCB
+        let fd_table = core::mem::take(&mut process_inner.fd_table);
+        drop(process_inner);
+        drop(fd_table);
+
+        // a parent blocked in waitpid gets to reap us, and it checks for
+        // zombies with its PCB held, so wake it with the PCB held too
+        if let Some(parent) = parent {
+            let _parent_inner = parent.inner_exclusive_access();
+            parent.child_exited.wake_all();
         }
-        panic!("unreachable in run_first_task!");
     }
+    // a process without parent is freed right here
+    drop(process);
+    // and so is this thread once the processor has switched away from it
+    drop(task);
+    // we do not have to save task context
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
+}
+
+/// Send `signal` caused by a fault of the current thread. Returning to the
+/// faulting instruction would only fault again, so if it cannot be caught
+/// right now the thread is terminated instead of keeping it pending.
+pub fn current_fault_signal(signal: SignalFlags) {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    let signum = signal.bits().trailing_zeros() as usize;
+    match signal_disposition(&task_inner, &process_inner.signal_actions, signum) {
+        Some(SignalDisposition::Ignore) | None => {
+            drop(task_inner);
+            drop(process_inner);
+            drop(process);
+            drop(task);
+            terminate_current(signum);
+        }
+        Some(_) => task_inner.signals |= signal,
+    }
+}
+
+/// What delivering a pending signal comes down to
+enum SignalDisposition {
+    Ignore,
+    Terminate,
+    Handler(SignalAction),
+}
 
-    /// Change the status of current `Running` task into `Ready`.
-    fn mark_current_suspended(&self) {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Ready;
+/// What to do about signal `signum` sent to a thread, `None` if it has to
+/// stay pending for now.
+///
+/// While a user handler runs, signals in the mask of its action stay
+/// pending, and so does any other signal to be caught, as there is only
+/// one trap context backup.
+fn signal_disposition(
+    task_inner: &TaskControlBlockInner,
+    actions: &SignalActions,
+    signum: usize,
+) -> Option<SignalDisposition> {
+    let signal = SignalFlags::from_signum(signum).unwrap();
+    if SignalFlags::uncatchable().contains(signal) {
+        return if SignalFlags::ignored_by_default().contains(signal) {
+            Some(SignalDisposition::Ignore)
+        } else {
+            Some(SignalDisposition::Terminate)
+        };
+    }
+    let mut blocked = task_inner.signal_mask;
+    if let Some(sig) = task_inner.handling_sig {
+        blocked |= actions.table[sig].mask;
+    }
+    if blocked.contains(signal) {
+        return None;
+    }
+    let action = actions.table[signum];
+    match action.handler {
+        SIG_DFL if SignalFlags::ignored_by_default().contains(signal) => {
+            Some(SignalDisposition::Ignore)
+        }
+        SIG_DFL => Some(SignalDisposition::Terminate),
+        SIG_IGN => Some(SignalDisposition::Ignore),
+        _ if task_inner.handling_sig.is_some() => None,
+        _ => Some(SignalDisposition::Handler(action)),
     }
+}
 
-    /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Exited;
+/// Take the first pending signal of the current thread that can be
+/// delivered now, and what has to be done about it.
+fn take_pending_signal() -> Option<(usize, SignalDisposition)> {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    for signum in 1..=MAX_SIG {
+        let signal = SignalFlags::from_signum(signum).unwrap();
+        if !task_inner.signals.contains(signal) {
+            continue;
+        }
+        if let Some(disposition) =
+            signal_disposition(&task_inner, &process_inner.signal_actions, signum)
+        {
+            task_inner.signals.remove(signal);
+            return Some((signum, disposition));
+        }
     }
+    None
+}
 
-    /// Find next task to run and return task id.
+/// Terminate the current thread because of signal `signum`, together with
+/// the rest of its process.
+fn terminate_current(signum: usize) {
+    let process = current_process();
+    if current_task().unwrap().gettid() != 0 {
+        // the main thread takes the rest of the process with it
+        // the next time it is about to return to user mode
+        let main_task = process.inner_exclusive_access().get_task(0);
+        main_task.inner_exclusive_access().signals |= SignalFlags::SIGKILL;
+    }
+    drop(process);
+    error!("[kernel] Application killed by signal {}.", signum);
+    exit_current_and_run_next(-(signum as i32));
+}
+
+/// Deliver the pending signals of the current thread. A signal caught by a
+/// user handler makes the thread enter the handler when it returns to user
+/// mode, while a signal whose action is to terminate ends the whole process.
+pub fn handle_signals() {
+    while let Some((signum, disposition)) = take_pending_signal() {
+        match disposition {
+            SignalDisposition::Ignore => {}
+            SignalDisposition::Terminate => terminate_current(signum),
+            SignalDisposition::Handler(action) => {
+                let task = current_task().unwrap();
+                let mut task_inner = task.inner_exclusive_access();
+                task_inner.handling_sig = Some(signum);
+                let trap_cx = task_inner.get_trap_cx();
+                let backup = *trap_cx;
+                // go to the handler with the signal number as the argument
+                trap_cx.sepc = action.handler;
+                trap_cx.x[10] = signum;
+                task_inner.trap_ctx_backup = Some(backup);
+                // no other handler can be entered before it returns
+                return;
+            }
+        }
+    }
+}
+
+/// Go back to where the current thread was when a user handler was entered,
+/// returning the a0 to restore, or `None` if no handler is running.
+pub fn sigreturn_current() -> Option<usize> {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let backup = task_inner.trap_ctx_backup.take()?;
+    task_inner.handling_sig = None;
+    *task_inner.get_trap_cx() = backup;
+    Some(backup.x[10])
+}
+
+lazy_static! {
+    /// Creation of initial process, `None` when there is no `ch5b_initproc`
+    /// on the disk
     ///
-    /// In this case, we only return the first `Ready` task in task list.
-    fn find_next_task(&self) -> Option<usize> {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)
-    }
-
-    /// Get the current 'Running' taTaskInfosk's token.
-    fn get_current_token(&self) -> usize {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_user_token()
-    }
-
-    #[allow(clippy::mut_from_ref)]
-    /// Get the current 'Running' task's trap contexts.
-    fn get_current_trap_cx(&self) -> &mut TrapContext {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_trap_cx()
-    }
-
-    /// Switch current `RuTaskInfonning` task to the task we have found,
-    /// or there is no `Ready` task and we can exit with all applications completed
-    fn run_next_task(&self) {
-        if let Some(next) = self.find_next_task() {
-            let mut inner = self.inner.exclusive_access();
-            let current = inner.current_task;
-            inner.tasks[next].task_status = TaskStatus::Running;
-            
-            if inner.tasks[next].task_first_running_time == None {
-                inner.tasks[next].task_first_running_time = Some(get_time_us() / 1000);
+    /// the name "initproc" may be changed to any other app name like "usertests",
+    /// but we have user_shell, so we don't need to change it.
+    pub static ref INITPROC: Option<Arc<ProcessControlBlock>> =
+        read_file("ch5b_initproc").map(|elf| {
+            ProcessControlBlock::new(&elf, None).expect("ch5b_initproc is not a valid elf")
+        });
+}
+
+/// Put the initial process into the ready queue. Without an initproc every
+/// app on the disk is started as a process of its own.
+pub fn add_initproc() {
+    // INITPROC must be referenced at least once so that it can be initialized
+    // through lazy_static
+    if INITPROC.is_none() {
+        for name in list_files() {
+            let elf = read_file(&name).unwrap();
+            if let Err(err) = ProcessControlBlock::new(&elf, None) {
+                warn!("[kernel] skipping {}: {:?}", name, err);
             }
-            inner.current_task = next;
-            let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
-            let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
-            drop(inner);
-            // before this, we should drop local variables that must be dropped manually
-            unsafe {
-                __switch(current_task_cx_ptr, next_task_cx_ptr);
+        }
+    }
+}
+
+// add the sys call count
+pub fn update_syscall_times(syscall_id: usize) {
+    let task = current_task().unwrap();
+    task.last_syscall.store(syscall_id, Ordering::Relaxed);
+    let process = task.process.upgrade().unwrap();
+    process.inner_exclusive_access().task_syscall_times[syscall_id] += 1;
+}
+
+// get the curr app task info
+pub fn get_task_info() -> TaskInfo {
+    let status = current_task().unwrap().inner_exclusive_access().task_status;
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let time = get_time_us() / 1000 - inner.task_first_running_time.unwrap();
+    TaskInfo {
+        status,
+        syscall_times: inner.task_syscall_times,
+        time,
+    }
+}
+
+/// Every process as `ps` shows it, in pid order
+pub fn task_list() -> Vec<TaskListEntry> {
+    let processes: Vec<Arc<ProcessControlBlock>> = PID2PCB.lock().values().cloned().collect();
+    processes
+        .iter()
+        .map(|process| {
+            let inner = process.inner_exclusive_access();
+            let (status, priority) = match inner.tasks.first() {
+                Some(Some(main_thread)) if !inner.is_zombie => (
+                    main_thread.inner_exclusive_access().task_status,
+                    main_thread.priority.load(Ordering::Relaxed),
+                ),
+                _ => (TaskStatus::Zombie, DEFAULT_PRIORITY),
+            };
+            TaskListEntry {
+                pid: process.getpid(),
+                status: status as usize,
+                priority,
+                threads: inner.thread_count(),
+                user_time_us: inner.user_time,
+                kernel_time_us: inner.kernel_time,
             }
-            // go back to user mode
-        } else {
-            panic!("All applications completed!");
+        })
+        .collect()
+}
+
+/// The memory map of process `pid`, or `None` if there is no such process
+pub fn process_regions(pid: usize) -> Option<Vec<MapRegion>> {
+    let process = pid2process(pid)?;
+    let regions = process.inner_exclusive_access().memory_set.regions();
+    Some(regions)
+}
+
+/// (pid, resident user pages) of every process, in pid order
+pub fn process_resident_pages() -> Vec<(usize, usize)> {
+    // PCBs are locked one by one after PID2PCB is released
+    let processes: Vec<Arc<ProcessControlBlock>> = PID2PCB.lock().values().cloned().collect();
+    processes
+        .iter()
+        .map(|process| {
+            let resident = process.inner_exclusive_access().memory_set.resident_pages();
+            (process.getpid(), resident)
+        })
+        .collect()
+}
+
+/// [`get_task_info`] along with memory statistics of the current process
+pub fn get_task_info2() -> TaskInfo2 {
+    let info = get_task_info();
+    let sched = current_task().unwrap().inner_exclusive_access().sched;
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let stats = inner.memory_set.stats();
+    TaskInfo2 {
+        status: info.status,
+        syscall_times: info.syscall_times,
+        time: info.time,
+        resident_pages: inner.memory_set.resident_pages(),
+        peak_resident_pages: stats.peak_resident,
+        minor_faults: stats.minor_faults,
+        major_faults: stats.major_faults,
+        mmap_regions: inner.memory_set.mmap_regions(),
+        preemptions: sched.preemptions,
+        voluntary_yields: sched.voluntary_yields,
+        dispatches: sched.dispatches,
+        sched_latency_us: sched.latency_us,
+        max_sched_latency_us: sched.max_latency_us,
+    }
+}
+
+/// Charge the time until a trap from user mode to the current process's user time.
+pub fn charge_user_time() {
+    current_task().unwrap().charge_time(true);
+}
+
+/// Charge the time until returning to user mode to the current process's kernel time.
+pub fn charge_kernel_time() {
+    current_task().unwrap().charge_time(false);
+}
+
+/// (user time, kernel time) of the current process, in us
+pub fn current_cpu_times() -> (usize, usize) {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    (inner.user_time, inner.kernel_time)
+}
+
+/// With [`MAP_FILE`] in `port`, the pages come from the regular file `fd`
+/// starting at the page aligned `offset`.
+pub fn mmap_in_current_memory_set(
+    start: usize,
+    len: usize,
+    port: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    let process = current_process();
+    let pages = len.saturating_add(PAGE_SIZE - 1) / PAGE_SIZE;
+    // only an anonymous mapping takes its frames right away
+    let result = retry_after_reclaim(
+        || mmap_in(&process, start, len, port, fd, offset),
+        |&result| result < 0 && port & MAP_FILE == 0 && frame_free_num() < pages,
+    );
+    if result >= 0 {
+        // 0 when mapped right at `start`
+        let addr = if result == 0 { start } else { result as usize };
+        notify_mm_observer(|observer| observer.on_map(process.getpid(), addr, len));
+    }
+    result
+}
+
+/// [`mmap_in_current_memory_set`] in `process`, without reclaiming frames
+fn mmap_in(
+    process: &Arc<ProcessControlBlock>,
+    start: usize,
+    len: usize,
+    port: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    let mut inner = process.inner_exclusive_access();
+    let limits = inner.rlimits;
+    let mmap_bytes = inner.memory_set.mmap_pages() * PAGE_SIZE;
+    if len > limits.max_mmap_bytes.saturating_sub(mmap_bytes) {
+        return -1;
+    }
+    // 匿名映射立即分配页帧，文件映射的页帧在缺页时再计入
+    let pages = len.saturating_add(PAGE_SIZE - 1) / PAGE_SIZE;
+    if port & MAP_FILE == 0
+        && pages > limits.max_frames.saturating_sub(inner.memory_set.resident_pages())
+    {
+        return -1;
+    }
+    let backing = if port & MAP_FILE != 0 {
+        let file = match inner.fd_table.get(fd) {
+            Some(Some(file)) => file.clone(),
+            _ => return -1,
+        };
+        let shared = port & MAP_SHARED != 0;
+        // a shared writable mapping writes to the file
+        let writes = shared && port & 0b010 != 0;
+        match file.inode() {
+            Some(inode)
+                if offset % PAGE_SIZE == 0 && file.readable() && (!writes || file.writable()) =>
+            {
+                Some(FileBacking {
+                    inode,
+                    offset,
+                    shared,
+                })
+            }
+            _ => return -1,
         }
+    } else {
+        None
+    };
+    inner.memory_set.mmap(start, len, port, backing)
+}
+
+pub fn munmap_in_current_memory_set(start: usize, len: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let result = inner.memory_set.munmap(start, len);
+    drop(inner);
+    if result == 0 {
+        notify_mm_observer(|observer| observer.on_unmap(process.getpid(), start, len));
     }
+    result
+}
 
-    // add the sys call count
-    fn update_syscall_times(&self, syscall_id: usize) {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        inner.tasks[current].task_syscall_times[syscall_id] += 1;
+/// Resize a mapping of the current process within its limits, see
+/// [`MemorySet::mremap`](crate::mm::MemorySet::mremap).
+pub fn mremap_in_current_memory_set(
+    old_addr: usize,
+    old_len: usize,
+    new_len: usize,
+    flags: usize,
+) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let limits = inner.rlimits;
+    let grow = new_len.saturating_sub(old_len);
+    let mmap_bytes = inner.memory_set.mmap_pages() * PAGE_SIZE;
+    if grow > limits.max_mmap_bytes.saturating_sub(mmap_bytes) {
+        return -1;
+    }
+    let pages = grow.saturating_add(PAGE_SIZE - 1) / PAGE_SIZE;
+    if pages > limits.max_frames.saturating_sub(inner.memory_set.resident_pages()) {
+        return -1;
+    }
+    let result = inner.memory_set.mremap(old_addr, old_len, new_len, flags);
+    drop(inner);
+    if result < 0 {
+        return result;
     }
+    let pid = process.getpid();
+    notify_mm_observer(|observer| {
+        if result as usize != old_addr {
+            observer.on_unmap(pid, old_addr, old_len);
+            observer.on_map(pid, result as usize, new_len);
+        } else if new_len > old_len {
+            observer.on_map(pid, old_addr + old_len, new_len - old_len);
+        } else if new_len < old_len {
+            observer.on_unmap(pid, old_addr + new_len, old_len - new_len);
+        }
+    });
+    result
+}
 
-    // get the curr app task info
-    fn get_task_info(&self) -> TaskInfo {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        let time = get_time_us() / 1000 - inner.tasks[current].task_first_running_time.unwrap();
-        TaskInfo {
-            status: inner.tasks[current].task_status,
-            syscall_times: inner.tasks[current].task_syscall_times,
-            time,
+/// Move the program break of the current process, returning the old one.
+pub fn change_program_brk(increment: isize) -> Option<usize> {
+    let process = current_process();
+    let pages = (increment.max(0) as usize).saturating_add(PAGE_SIZE - 1) / PAGE_SIZE;
+    // the heap grows with its frames allocated right away
+    let old_brk = retry_after_reclaim(
+        || {
+            let mut inner = process.inner_exclusive_access();
+            if pages > inner.rlimits.max_frames.saturating_sub(inner.memory_set.resident_pages()) {
+                return None;
+            }
+            inner.memory_set.sbrk(increment)
+        },
+        |old_brk| old_brk.is_none() && frame_free_num() < pages,
+    )?;
+    let pid = process.getpid();
+    notify_mm_observer(|observer| {
+        if increment > 0 {
+            observer.on_map(pid, old_brk, increment as usize);
+        } else if increment < 0 {
+            observer.on_unmap(pid, old_brk - increment.unsigned_abs(), increment.unsigned_abs());
         }
+    });
+    Some(old_brk)
+}
+
+/// Set the limit of `resource` for the current process, `false` if there is
+/// no such resource.
+pub fn set_current_rlimit(resource: usize, limit: usize) -> bool {
+    current_process().inner_exclusive_access().rlimits.set(resource, limit)
+}
+
+/// Map shared memory object `id` into the current process with the
+/// permission of `port`, returning the address or -1.
+pub fn attach_shm_in_current_memory_set(id: usize, port: usize) -> isize {
+    let map_perm = match MapPermission::from_port(port) {
+        Some(map_perm) => map_perm,
+        None => return -1,
+    };
+    // 先取页帧再锁PCB，不在PCB锁内拿共享内存表的锁
+    let frames = match shm_frames(id) {
+        Some(frames) => frames,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if frames.len() > inner.rlimits.max_frames.saturating_sub(inner.memory_set.resident_pages()) {
+        return -1;
     }
+    match inner.memory_set.attach_shm(id, &frames, map_perm) {
+        Some(addr) => {
+            drop(inner);
+            shm_attached(id);
+            addr as isize
+        }
+        None => -1,
+    }
+}
 
-    fn mmap_in_current_memory_set(&self, start: usize, len: usize, port: usize) -> isize {
-        let mut inner = self.inner.exclusive_access();
-        let current_task = inner.current_task;
-        inner.tasks[current_task].memory_set.mmap(start, len, port)
+/// Unmap the shared memory object the current process attached at `addr`.
+pub fn detach_shm_in_current_memory_set(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.memory_set.detach_shm(addr) {
+        0
+    } else {
+        -1
     }
+}
 
-    fn munmap_in_current_memory_set(&self, start: usize, len: usize) -> isize {
-        let mut inner = self.inner.exclusive_access();
-        let current_task = inner.current_task;
-        inner.tasks[current_task].memory_set.munmap(start, len)
+pub fn mprotect_in_current_memory_set(start: usize, len: usize, port: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    inner.memory_set.mprotect(start, len, port)
+}
+
+/// Mark the current thread as inside a syscall or out of it again, see
+/// [`TaskControlBlock::in_syscall`].
+pub fn set_current_in_syscall(in_syscall: bool) {
+    let task = current_task().unwrap();
+    if !in_syscall {
+        task.in_syscall.store(false, Ordering::Relaxed);
+        return;
     }
+    // the swapper checks the flag with the PCB held, so once we have held
+    // it as well, it either saw the flag or is done with our pages
+    let process = task.process.upgrade().unwrap();
+    let _inner = process.inner_exclusive_access();
+    task.in_syscall.store(true, Ordering::Relaxed);
 }
 
-/// Run the first task in task list.
-pub fn run_first_task() {
-    TASK_MANAGER.run_first_task();
+/// Try to resolve a page fault of the current process at `va`.
+pub fn handle_page_fault(va: usize) -> Result<(), PageFaultError> {
+    let process = current_process();
+    let result = resolve_page_fault(&process, va);
+    notify_mm_observer(|observer| observer.on_fault(process.getpid(), va, result));
+    result
 }
 
-/// Switch current `Running` task to the task we have found,
-/// or there is no `Ready` task and we can exit with all applications completed
-fn run_next_task() {
-    TASK_MANAGER.run_next_task();
+/// Bring in the pages of `[start, start + len)` of the current process that
+/// are not present yet, swapped out pages and untouched lazy mappings, so
+/// that the translation of a user buffer afterwards finds them.
+///
+/// Syscalls call this before translating and without holding any lock, as
+/// it takes the PCB and may swap. The swapper leaves a process alone while
+/// one of its threads is in a syscall, so the pages stay. Pages that cannot
+/// be brought in are left for the translation to report.
+pub fn fault_in_user_range(start: usize, len: usize) {
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return,
+    };
+    let token = current_user_token();
+    let mut va = start;
+    while va < end {
+        if translated_phys_addr(token, va).is_none() && handle_page_fault(va).is_err() {
+            return;
+        }
+        va = match (va & !(PAGE_SIZE - 1)).checked_add(PAGE_SIZE) {
+            Some(next) => next,
+            None => return,
+        };
+    }
 }
 
-/// Change the status of current `Running` task into `Ready`.
-fn mark_current_suspended() {
-    TASK_MANAGER.mark_current_suspended();
+/// [`fault_in_user_range`] for a `T` at `ptr`
+pub fn fault_in_user<T>(ptr: *const T) {
+    fault_in_user_range(ptr as usize, core::mem::size_of::<T>());
 }
 
-/// Change the status of current `Running` task into `Exited`.
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
+/// [`fault_in_user_range`] for the nul-terminated string at `ptr`, page by
+/// page up to the page holding the nul.
+pub fn fault_in_user_str(ptr: *const u8) {
+    let token = current_user_token();
+    let mut va = ptr as usize;
+    loop {
+        let page_end = match (va & !(PAGE_SIZE - 1)).checked_add(PAGE_SIZE) {
+            Some(page_end) => page_end,
+            None => return,
+        };
+        fault_in_user_range(va, page_end - va);
+        match translated_byte_buffer(token, va as *const u8, page_end - va) {
+            Ok(buffers) if !buffers.iter().any(|b| b.contains(&0)) => va = page_end,
+            _ => return,
+        }
+    }
 }
 
-/// Suspend the current 'Running' task and run the next task in task list.
-pub fn suspend_current_and_run_next() {
-    mark_current_suspended();
-    run_next_task();
+/// [`handle_page_fault`] in `process`, reclaiming frames when out of them
+fn resolve_page_fault(process: &Arc<ProcessControlBlock>, va: usize) -> Result<(), PageFaultError> {
+    retry_after_reclaim(
+        || {
+            let mut inner = process.inner_exclusive_access();
+            // a fault resolved maps one more page
+            if inner.memory_set.resident_pages() >= inner.rlimits.max_frames {
+                return Err(PageFaultError::OverLimit);
+            }
+            inner.memory_set.handle_page_fault(va.into())
+        },
+        |result| *result == Err(PageFaultError::OutOfMemory),
+    )
 }
 
-/// Exit the current 'Running' task and run the next task in task list.
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
-    run_next_task();
+/// Free frames after an allocation failed: evict cached file pages no
+/// mapping holds or, if there are none, swap out anonymous pages. Returns
+/// how many frames were freed.
+///
+/// Both write to the disk and take the page cache and the PCBs on the way,
+/// so the caller must not hold any lock. That is why `frame_alloc` does not
+/// reclaim by itself, and callers that can afford it go through
+/// [`retry_after_reclaim`].
+pub fn reclaim_frames() -> usize {
+    match page_cache::shrink(PAGE_CACHE_SHRINK) {
+        0 => swap_out(SWAP_BATCH),
+        freed => freed,
+    }
 }
 
-/// Get the current 'Running' task's token.
-pub fn current_user_token() -> usize {
-    TASK_MANAGER.get_current_token()
+/// Make `attempt` again after reclaiming frames for as long as its result
+/// is `out_of_frames` and reclaiming frees some, returning the last result.
+pub fn retry_after_reclaim<T>(
+    mut attempt: impl FnMut() -> T,
+    out_of_frames: impl Fn(&T) -> bool,
+) -> T {
+    loop {
+        let result = attempt();
+        if !out_of_frames(&result) || reclaim_frames() == 0 {
+            return result;
+        }
+    }
 }
 
-/// Get the current 'Running' task's trap contexts.
-pub fn current_trap_cx() -> &'static mut TrapContext {
-    TASK_MANAGER.get_current_trap_cx()
+lazy_static! {
+    /// Where swapping out goes on next time: a pid and a page of it
+    static ref SWAP_HAND: SpinLock<(usize, VirtPageNum)> = SpinLock::new((0, VirtPageNum(0)));
 }
 
-pub fn update_syscall_times(syscall_id: usize) {
-    TASK_MANAGER.update_syscall_times(syscall_id);
+/// Swap out up to `count` anonymous user pages, moving the clock hand round
+/// the processes in pid order, and return how many went out.
+///
+/// Processes whose PCB someone holds, this hart included, are passed over,
+/// and so are those with a thread inside a syscall, which may be working on
+/// their pages through translated buffers.
+pub fn swap_out(count: usize) -> usize {
+    let mut hand = SWAP_HAND.lock();
+    // holding the map keeps us from dropping the last reference to a PCB
+    let processes = PID2PCB.lock();
+    let mut swapped = 0;
+    // the first round may do nothing but clear A bits
+    for _ in 0..2 {
+        let (hand_pid, hand_vpn) = *hand;
+        for (&pid, process) in processes.range(hand_pid..).chain(processes.range(..hand_pid)) {
+            if swapped == count || swap_free_slots() == 0 {
+                return swapped;
+            }
+            let mut inner = match process.inner_try_access() {
+                Some(inner) => inner,
+                None => continue,
+            };
+            let busy = inner
+                .tasks
+                .iter()
+                .flatten()
+                .any(|task| task.in_syscall.load(Ordering::Relaxed));
+            if busy {
+                continue;
+            }
+            let from = if pid == hand_pid { hand_vpn } else { VirtPageNum(0) };
+            let (n, stop) = inner.memory_set.swap_out(from, count - swapped, &is_futex_frame);
+            swapped += n;
+            *hand = match stop {
+                Some(vpn) => (pid, vpn),
+                None => (pid + 1, VirtPageNum(0)),
+            };
+        }
+    }
+    swapped
 }
 
-pub fn get_task_info() -> TaskInfo {
-    TASK_MANAGER.get_task_info()
+/// Called as `hook(pid, vpn, accessed, dirty)` for every present user page
+/// on each access scan, e.g. to measure working sets
+pub type AccessScanHook = fn(usize, VirtPageNum, bool, bool);
+
+lazy_static! {
+    static ref ACCESS_SCAN_HOOK: SpinLock<Option<AccessScanHook>> = SpinLock::new(None);
 }
 
-pub fn mmap_in_current_memory_set(start: usize, len: usize, port: usize) -> isize {
-    TASK_MANAGER.mmap_in_current_memory_set(start, len, port)
+/// when the next access scan is due, in us
+static NEXT_ACCESS_SCAN_US: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(unused)]
+/// Scan the A and D bits of all processes every `ACCESS_SCAN_INTERVAL_US`
+/// and report them to `hook`, or stop scanning with `None`.
+///
+/// The swapper relies on the same A bits, a page counts as unused to it
+/// if it was not touched since the last scan.
+pub fn set_access_scan_hook(hook: Option<AccessScanHook>) {
+    *ACCESS_SCAN_HOOK.lock() = hook;
 }
 
-pub fn munmap_in_current_memory_set(start: usize, len: usize) -> isize {
-    TASK_MANAGER.munmap_in_current_memory_set(start, len)
-}
\ No newline at end of file
+/// Run the access scan if a hook is set and it is due, on one hart only.
+/// Processes whose PCB is held are left out this time.
+pub fn scan_access_periodically() {
+    let hook = match *ACCESS_SCAN_HOOK.lock() {
+        Some(hook) => hook,
+        None => return,
+    };
+    let now = get_time_us();
+    let due = NEXT_ACCESS_SCAN_US.load(Ordering::Relaxed);
+    if now < due
+        || NEXT_ACCESS_SCAN_US
+            .compare_exchange(due, now + ACCESS_SCAN_INTERVAL_US, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+    {
+        return;
+    }
+    for (&pid, process) in PID2PCB.lock().iter() {
+        if let Some(mut inner) = process.inner_try_access() {
+            inner
+                .memory_set
+                .scan_access(|vpn, accessed, dirty| hook(pid, vpn, accessed, dirty));
+        }
+    }
+}
+
+/// Print what is known about the task running on this hart, for the panic
+/// handler. Locks someone else holds are skipped rather than waited for.
+pub fn dump_current_task() {
+    let task = match try_current_task() {
+        Some(task) => task,
+        None => {
+            println!("[kernel] no task running on hart {}", crate::hart::hart_id());
+            return;
+        }
+    };
+    let pid = task.process.upgrade().map(|process| process.getpid());
+    let inner = match task.inner_try_access() {
+        Some(inner) => inner,
+        None => {
+            println!("[kernel] current task of pid {:?} is locked", pid);
+            return;
+        }
+    };
+    let tid = inner.res.as_ref().map(|res| res.tid);
+    println!(
+        "[kernel] current task: pid {:?} tid {:?} status {:?}",
+        pid, tid, inner.task_status
+    );
+    if inner.res.is_none() {
+        return;
+    }
+    let cx = inner.get_trap_cx();
+    println!("[kernel] trap context: sepc = {:#x}", cx.sepc);
+    for (i, regs) in cx.x.chunks(4).enumerate() {
+        println!(
+            "  x{:<2} {:#018x} {:#018x} {:#018x} {:#018x}",
+            i * 4,
+            regs[0],
+            regs[1],
+            regs[2],
+            regs[3]
+        );
+    }
+}
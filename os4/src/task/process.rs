@@ -0,0 +1,309 @@
+//! Implementation of [`ProcessControlBlock`]
+//!
+//! A process owns the address space and the process tree links, while each
+//! of its threads is a [`TaskControlBlock`] scheduled on its own.
+
+use super::id::RecycleAllocator;
+use super::manager::insert_into_pid2process;
+use super::{add_task, pid_alloc, PidHandle, ResourceLimits, SignalActions, TaskControlBlock};
+use super::MAX_SYSCALL_NUM;
+use crate::fs::{File, Stdin, Stdout};
+use crate::mm::{notify_mm_observer, ElfError, MemorySet, KERNEL_SPACE};
+use crate::sync::{Condvar, Mutex, Semaphore, SpinLock, SpinLockGuard, WaitQueue};
+use crate::trap::{trap_handler, TrapContext};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Process control block structure
+///
+/// Directly save the contents that will not change during running
+pub struct ProcessControlBlock {
+    // immutable
+    /// Process identifier
+    pub pid: PidHandle,
+    /// Woken up whenever a child exits
+    pub child_exited: WaitQueue,
+    /// Log every syscall of this process, inherited by its children
+    pub traced: AtomicBool,
+    // mutable
+    inner: SpinLock<ProcessControlBlockInner>,
+}
+
+/// Structure containing more process content
+///
+/// Store the contents that will change during operation
+/// and are wrapped by SpinLock to provide mutual exclusion between harts
+pub struct ProcessControlBlockInner {
+    /// Set once the main thread has exited
+    pub is_zombie: bool,
+    /// Application address space, shared by all threads
+    pub memory_set: MemorySet,
+    /// Parent process of the current process.
+    /// Weak will not affect the reference count of the parent
+    pub parent: Option<Weak<ProcessControlBlock>>,
+    /// A vector containing PCBs of all child processes of the current process
+    pub children: Vec<Arc<ProcessControlBlock>>,
+    /// Exit code of the main thread
+    pub exit_code: i32,
+    /// Threads indexed by tid, `None` once reaped
+    pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
+    pub task_res_allocator: RecycleAllocator,
+    /// Synchronization primitives indexed by id, `None` for a free slot
+    pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
+    pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
+    pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// What each signal does to the threads of this process
+    pub signal_actions: SignalActions,
+    /// Open files indexed by fd, `None` for a closed one
+    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// Inherited by children
+    pub rlimits: ResourceLimits,
+
+    pub task_syscall_times: [u32; MAX_SYSCALL_NUM], // syscall times
+    pub task_first_running_time: Option<usize>, // first time when the process was scheduled
+    /// Time spent in user mode by all threads, in us
+    pub user_time: usize,
+    /// Time spent in the kernel on behalf of all threads, in us
+    pub kernel_time: usize,
+}
+
+impl ProcessControlBlockInner {
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.token()
+    }
+    /// The lowest closed fd, growing the table if there is none, or `None`
+    /// if that fd would be over the limit of open fds
+    pub fn alloc_fd(&mut self) -> Option<usize> {
+        let fd = match (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            Some(fd) => fd,
+            None => self.fd_table.len(),
+        };
+        if fd >= self.rlimits.max_fds {
+            return None;
+        }
+        if fd == self.fd_table.len() {
+            self.fd_table.push(None);
+        }
+        Some(fd)
+    }
+    pub fn alloc_tid(&mut self) -> usize {
+        self.task_res_allocator.alloc()
+    }
+    pub fn dealloc_tid(&mut self, tid: usize) {
+        self.task_res_allocator.dealloc(tid)
+    }
+    /// number of threads not reaped yet
+    pub fn thread_count(&self) -> usize {
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+    pub fn get_task(&self, tid: usize) -> Arc<TaskControlBlock> {
+        self.tasks[tid].as_ref().unwrap().clone()
+    }
+}
+
+impl ProcessControlBlock {
+    /// Lock the inner part to get the SpinLockGuard ProcessControlBlockInner
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, ProcessControlBlockInner> {
+        self.inner.lock()
+    }
+    /// The inner part, unless someone holds it already
+    pub fn inner_try_access(&self) -> Option<SpinLockGuard<'_, ProcessControlBlockInner>> {
+        self.inner.try_lock()
+    }
+
+    fn new_with(
+        memory_set: MemorySet,
+        parent: Option<Weak<ProcessControlBlock>>,
+        fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            pid: pid_alloc(),
+            child_exited: WaitQueue::new(),
+            traced: AtomicBool::new(false),
+            inner: SpinLock::new(ProcessControlBlockInner {
+                is_zombie: false,
+                memory_set,
+                parent,
+                children: Vec::new(),
+                exit_code: 0,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+                signal_actions: SignalActions::default(),
+                fd_table,
+                rlimits: ResourceLimits::default(),
+                task_syscall_times: [0; MAX_SYSCALL_NUM],
+                task_first_running_time: None,
+                user_time: 0,
+                kernel_time: 0,
+            }),
+        })
+    }
+
+    /// Create a process from an ELF with a main thread, and put the thread
+    /// into the ready queue.
+    pub fn new(
+        elf_data: &[u8],
+        parent: Option<Weak<ProcessControlBlock>>,
+    ) -> Result<Arc<Self>, ElfError> {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
+        let fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = vec![
+            // 0 -> stdin
+            Some(Arc::new(Stdin)),
+            // 1 -> stdout
+            Some(Arc::new(Stdout)),
+            // 2 -> stderr
+            Some(Arc::new(Stdout)),
+        ];
+        // a child starts with the limits of its parent
+        let rlimits = parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map_or_else(ResourceLimits::default, |parent| parent.inner_exclusive_access().rlimits);
+        let process = Self::new_with(memory_set, parent, fd_table);
+        process.inner_exclusive_access().rlimits = rlimits;
+        // create a main thread, its ustack and trap_cx come from the elf layout
+        let task = Arc::new(TaskControlBlock::new(&process)?);
+        // prepare trap_cx of main thread
+        let trap_cx = task.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp, // 用户栈初始指针
+            KERNEL_SPACE.lock().token(), // 内核空间页表token
+            task.kernel_stack.get_top(), // 内核栈顶
+            trap_handler as usize, // trap处理函数
+        );
+        // add main thread to the process
+        process.inner_exclusive_access().tasks.push(Some(task.clone()));
+        insert_into_pid2process(process.getpid(), process.clone());
+        // add main thread to scheduler
+        add_task(task);
+        Ok(process)
+    }
+
+    /// Load a new elf to replace the original application address space and start execution
+    /// Only support processes with a single thread. Nothing changes if the
+    /// elf cannot be loaded.
+    pub fn exec(self: &Arc<Self>, elf_data: &[u8]) -> Result<(), ElfError> {
+        assert_eq!(self.inner_exclusive_access().thread_count(), 1);
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
+        let task = self.inner_exclusive_access().get_task(0);
+        // **** access inner exclusively
+        let mut inner = self.inner_exclusive_access();
+        // substitute memory_set, shared file mappings are written back first
+        inner.memory_set.recycle_data_pages();
+        inner.memory_set = memory_set;
+        // the handlers are gone with the old image
+        inner.signal_actions.reset_handlers();
+        drop(inner);
+        // update trap_cx ppn
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.handling_sig = None;
+        task_inner.trap_ctx_backup = None;
+        task_inner.trap_cx_ppn = task_inner.res.as_ref().unwrap().trap_cx_ppn();
+        // initialize trap_cx
+        *task_inner.get_trap_cx() = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.lock().token(),
+            task.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        // **** release inner automatically
+        Ok(())
+    }
+
+    /// Fork from parent to child
+    /// Only support processes with a single thread. `None` when out of
+    /// frames, with nothing of the child left behind.
+    pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
+        // ---- access parent PCB exclusively
+        let parent = self.inner_exclusive_access();
+        assert_eq!(parent.thread_count(), 1);
+        // copy user space(include trap context)
+        let memory_set = MemorySet::from_existed_user(&parent.memory_set).ok()?;
+        let copied_pages = memory_set.resident_pages();
+        // the child shares the open files of the parent
+        let child = Self::new_with(
+            memory_set,
+            Some(Arc::downgrade(self)),
+            parent.fd_table.clone(),
+        );
+        let mut child_inner = child.inner_exclusive_access();
+        child_inner.signal_actions = parent.signal_actions.clone();
+        child_inner.rlimits = parent.rlimits;
+        drop(child_inner);
+        child.traced.store(self.traced.load(Ordering::Relaxed), Ordering::Relaxed);
+        let signal_mask = parent.get_task(0).inner_exclusive_access().signal_mask;
+        drop(parent);
+        // ---- release parent PCB
+        // create main thread of child process, with a new kernel stack
+        let task = Arc::new(TaskControlBlock::new(&child).ok()?);
+        child.inner_exclusive_access().tasks.push(Some(task.clone()));
+        // add child, only once nothing can fail any more
+        self.inner_exclusive_access().children.push(child.clone());
+        // modify kernel_sp in trap_cx
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.signal_mask = signal_mask;
+        let trap_cx = task_inner.get_trap_cx();
+        trap_cx.kernel_sp = task.kernel_stack.get_top();
+        drop(task_inner);
+        insert_into_pid2process(child.getpid(), child.clone());
+        notify_mm_observer(|observer| observer.on_copy(self.getpid(), child.getpid(), copied_pages));
+        // add this thread to scheduler
+        add_task(task);
+        Some(child)
+    }
+
+    /// Create a child process running `elf_data` directly, without copying
+    /// the address space of the parent first
+    pub fn spawn(self: &Arc<Self>, elf_data: &[u8]) -> Result<Arc<Self>, ElfError> {
+        let child = Self::new(elf_data, Some(Arc::downgrade(self)))?;
+        child.traced.store(self.traced.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.inner_exclusive_access().children.push(child.clone());
+        Ok(child)
+    }
+
+    /// Create an additional thread starting at `entry` with `arg` in a0 and
+    /// put it into the ready queue.
+    pub fn create_thread(
+        self: &Arc<Self>,
+        entry: usize,
+        arg: usize,
+    ) -> Option<Arc<TaskControlBlock>> {
+        let task = Arc::new(TaskControlBlock::new(self).ok()?);
+        let task_inner = task.inner_exclusive_access();
+        let res = task_inner.res.as_ref().unwrap();
+        let tid = res.tid;
+        let trap_cx = task_inner.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry,
+            res.ustack_top().unwrap(),
+            KERNEL_SPACE.lock().token(),
+            task.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        trap_cx.x[10] = arg;
+        drop(task_inner);
+        // add new thread to current process
+        let mut process_inner = self.inner_exclusive_access();
+        let tasks = &mut process_inner.tasks;
+        while tasks.len() < tid + 1 {
+            tasks.push(None);
+        }
+        tasks[tid] = Some(task.clone());
+        drop(process_inner);
+        add_task(task.clone());
+        Some(task)
+    }
+
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+}
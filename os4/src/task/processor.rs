@@ -0,0 +1,245 @@
+//! Implementation of [`Processor`] and Intersection of control flow
+//!
+//! Here, the continuous operation of user apps in CPU is maintained,
+//! the current running state of CPU is recorded,
+//! and the replacement and transfer of control flow of different applications are executed.
+//!
+//! Every hart has a [`Processor`] of its own, picked by [`hart_id`].
+
+use super::__switch;
+use super::manager::no_process_left;
+use super::{fetch_task, sched_log, TaskStatus};
+use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use crate::sync::SpinLock;
+use crate::timer::{get_time_us, has_timers};
+use crate::trap::TrapContext;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::sstatus;
+
+/// Processor management structure
+pub struct Processor {
+    /// The task currently executing on the current processor
+    current: Option<Arc<TaskControlBlock>>,
+    /// The basic control flow of each core, helping to select and switch process
+    idle_task_cx: TaskContext,
+}
+
+impl Processor {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            idle_task_cx: TaskContext::zero_init(),
+        }
+    }
+    fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
+        &mut self.idle_task_cx as *mut _
+    }
+    pub fn take_current(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.current.take()
+    }
+    pub fn current(&self) -> Option<Arc<TaskControlBlock>> {
+        self.current.as_ref().map(Arc::clone)
+    }
+}
+
+lazy_static! {
+    /// PROCESSORS instance through lazy_static!, one for each hart
+    pub static ref PROCESSORS: [SpinLock<Processor>; MAX_HARTS] =
+        [(); MAX_HARTS].map(|_| SpinLock::new(Processor::new()));
+}
+
+/// Ticks a hart has taken since it was last in the scheduler, for the
+/// watchdog
+pub struct HartWatch {
+    pub ticks: AtomicUsize,
+    /// the watchdog has reported the current run of ticks
+    pub reported: AtomicBool,
+}
+
+const HART_WATCH_INIT: HartWatch = HartWatch {
+    ticks: AtomicUsize::new(0),
+    reported: AtomicBool::new(false),
+};
+
+pub static HART_WATCH: [HartWatch; MAX_HARTS] = [HART_WATCH_INIT; MAX_HARTS];
+
+/// Count a scheduling tick on this hart, without taking any lock.
+pub fn count_tick() {
+    HART_WATCH[hart_id()].ticks.fetch_add(1, Ordering::Relaxed);
+}
+
+const IDLE_ZERO: AtomicUsize = AtomicUsize::new(0);
+/// microseconds each hart has spent waiting for interrupts with nothing to run
+static IDLE_TIME_US: [AtomicUsize; MAX_HARTS] = [IDLE_ZERO; MAX_HARTS];
+
+/// Time `hartid` has spent idle since boot, in microseconds
+pub fn idle_time_us(hartid: usize) -> usize {
+    IDLE_TIME_US[hartid].load(Ordering::Relaxed)
+}
+
+/// Sleep in `wfi` until an interrupt comes, which the kernel trap handler
+/// takes right here: a tick or a timer event may make a task ready. A task
+/// readied by another hart in the meantime waits for the next tick.
+fn idle() {
+    let start = get_time_us();
+    unsafe {
+        sstatus::set_sie();
+        riscv::asm::wfi();
+        sstatus::clear_sie();
+    }
+    IDLE_TIME_US[hart_id()].fetch_add(get_time_us() - start, Ordering::Relaxed);
+}
+
+/// The task running on `hartid`, unless its processor is locked
+pub fn hart_task(hartid: usize) -> Option<Arc<TaskControlBlock>> {
+    PROCESSORS[hartid].try_lock().and_then(|processor| processor.current())
+}
+
+/// The processor of the current hart
+fn processor() -> &'static SpinLock<Processor> {
+    &PROCESSORS[hart_id()]
+}
+
+/// The main part of process execution and scheduling
+///
+/// Loop fetch_task to get the process that needs to run,
+/// and switch the process through __switch
+pub fn run_tasks() {
+    loop {
+        let watch = &HART_WATCH[hart_id()];
+        watch.ticks.store(0, Ordering::Relaxed);
+        watch.reported.store(false, Ordering::Relaxed);
+        if let Some(task) = fetch_task() {
+            // a task put back into the ready queue by another hart may not
+            // have switched away from it yet
+            while task.on_cpu.load(Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+            task.on_cpu.store(true, Ordering::Relaxed);
+            // access coming task TCB exclusively
+            let mut task_inner = task.inner_exclusive_access();
+            if task_inner.task_status == TaskStatus::Zombie {
+                // its process has exited while it was on the way here
+                drop(task_inner);
+                task.on_cpu.store(false, Ordering::Release);
+                continue;
+            }
+            let mut processor = processor().lock();
+            let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+            let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
+            task_inner.task_status = TaskStatus::Running;
+            // time from here on is charged to the coming task
+            task_inner.time_stamp = get_time_us();
+            let ready_since = task.ready_since.load(Ordering::Relaxed);
+            let latency = task_inner.time_stamp.saturating_sub(ready_since);
+            task_inner.sched.dispatched(latency);
+            drop(task_inner);
+            let process = task.process.upgrade().unwrap();
+            let mut process_inner = process.inner_exclusive_access();
+            if process_inner.task_first_running_time.is_none() {
+                process_inner.task_first_running_time = Some(get_time_us() / 1000);
+            }
+            drop(process_inner);
+            #[cfg(feature = "frame-debug")]
+            crate::mm::set_frame_owner_task(Some(process.getpid()));
+            drop(process);
+            // release coming task TCB manually
+            processor.current = Some(task.clone());
+            // release processor manually
+            drop(processor);
+            sched_log(&task, format_args!("runs after {} us ready", latency));
+            super::replay::record_dispatch(&task);
+            unsafe {
+                __switch(idle_task_cx_ptr, next_task_cx_ptr);
+            }
+            // back on the idle stack, the task has its context saved now and
+            // may run elsewhere; if it has exited, its kernel stack goes here
+            task.on_cpu.store(false, Ordering::Release);
+            drop(task);
+        } else if !has_timers() && no_process_left() {
+            println!("[kernel] All applications completed!");
+            super::report_kernel_profile();
+            super::replay::save();
+            crate::sbi::shutdown();
+        } else {
+            // every task is asleep, blocked or running on other harts
+            idle();
+        }
+    }
+}
+
+/// Get current task through take, leaving a None in its place
+pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
+    processor().lock().take_current()
+}
+
+#[cfg(feature = "board_test")]
+/// Make `task` the current task of this hart without running it, so that
+/// board tests can make syscalls on behalf of its process.
+pub fn set_current_task(task: Option<Arc<TaskControlBlock>>) {
+    processor().lock().current = task;
+}
+
+/// Get a copy of the current task
+pub fn current_task() -> Option<Arc<TaskControlBlock>> {
+    processor().lock().current()
+}
+
+/// A copy of the current task, or `None` if the processor is locked, e.g.
+/// when panicking with it held
+pub fn try_current_task() -> Option<Arc<TaskControlBlock>> {
+    processor().try_lock().and_then(|processor| processor.current())
+}
+
+/// Get the process the current task belongs to
+pub fn current_process() -> Arc<ProcessControlBlock> {
+    current_task().unwrap().process.upgrade().unwrap()
+}
+
+/// Get token of the address space of current task
+pub fn current_user_token() -> usize {
+    let task = current_task().unwrap();
+    task.get_user_token()
+}
+
+/// Where the trap context of the current thread is mapped in user space
+pub fn current_trap_cx_user_va() -> usize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .trap_cx_user_va()
+}
+
+/// Get the mutable reference to trap context of current task
+pub fn current_trap_cx() -> &'static mut TrapContext {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .get_trap_cx()
+}
+
+/// Return to idle control flow for new scheduling
+pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
+    // the idle control flow runs with interrupts off, and the task may come
+    // back on another hart, so give it its own setting back afterwards
+    let sie = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    let mut processor = processor().lock();
+    let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+    drop(processor);
+    unsafe {
+        __switch(switched_task_cx_ptr, idle_task_cx_ptr);
+        if sie {
+            sstatus::set_sie();
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Sampling profiler, built with the `profiling` feature
+//!
+//! Every timer tick records the pc it interrupted into the ring of the
+//! thread running, or of the idle loop, and kernel pcs into a ring of their
+//! own as well. A thread prints where it spent its last ticks when it
+//! exits, the kernel prints where its own time went when it shuts down.
+//! The addresses are left to `addr2line` or `objdump -d` of the kernel or
+//! the app. Without the feature the rings hold nothing and cost nothing.
+
+use super::processor::try_current_task;
+use crate::config::{PROFILE_KERNEL_SAMPLES, PROFILE_SAMPLES, PROFILE_TOP};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const ENABLED: bool = cfg!(feature = "profiling");
+/// samples kept per thread
+const TASK_RING: usize = if ENABLED { PROFILE_SAMPLES } else { 0 };
+const KERNEL_RING: usize = if ENABLED { PROFILE_KERNEL_SAMPLES } else { 0 };
+/// set in a sample taken in user mode, pcs are always even
+const USER: usize = 1;
+
+/// The last `N` pcs interrupted by a tick, written from interrupt handlers
+/// without taking a lock
+pub struct Profile<const N: usize> {
+    samples: [AtomicUsize; N],
+    /// ticks recorded so far, the next one goes to `taken % N`
+    taken: AtomicUsize,
+    /// how many of them were in user mode
+    user: AtomicUsize,
+}
+
+/// the profile of a thread
+pub type TaskProfile = Profile<TASK_RING>;
+
+/// a slot of the ring nothing has been written to
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+
+impl<const N: usize> Profile<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [EMPTY; N],
+            taken: AtomicUsize::new(0),
+            user: AtomicUsize::new(0),
+        }
+    }
+
+    fn record(&self, sample: usize) {
+        if N == 0 {
+            return;
+        }
+        let index = self.taken.fetch_add(1, Ordering::Relaxed) % N;
+        self.samples[index].store(sample, Ordering::Relaxed);
+        if sample & USER != 0 {
+            self.user.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Print the pcs seen most often among the samples kept.
+    fn report(&self, who: fmt::Arguments) {
+        let taken = self.taken.load(Ordering::Relaxed);
+        if taken == 0 {
+            return;
+        }
+        let kept = taken.min(N);
+        let mut histogram = BTreeMap::new();
+        for sample in &self.samples[..kept] {
+            *histogram.entry(sample.load(Ordering::Relaxed)).or_insert(0usize) += 1;
+        }
+        let mut top: Vec<(usize, usize)> = histogram.into_iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+        println!(
+            "[profile] {}: {} ticks, {} in user mode, top pcs of the last {}:",
+            who,
+            taken,
+            self.user.load(Ordering::Relaxed),
+            kept
+        );
+        for &(sample, count) in top.iter().take(PROFILE_TOP) {
+            println!(
+                "[profile]   {:#x} {:6} {:3}%",
+                sample & !USER,
+                if sample & USER != 0 { "user" } else { "kernel" },
+                count * 100 / kept
+            );
+        }
+    }
+}
+
+/// ticks that came while no thread was running
+static IDLE: Profile<TASK_RING> = Profile::new();
+/// every tick that interrupted the kernel
+static KERNEL: Profile<KERNEL_RING> = Profile::new();
+
+/// Record a tick that interrupted `pc`, in user mode if `user`.
+pub fn sample(pc: usize, user: bool) {
+    if !ENABLED {
+        return;
+    }
+    let sample = if user { pc | USER } else { pc };
+    // the processor holds the task as well, it is not dropped here
+    match try_current_task() {
+        Some(task) => task.profile.record(sample),
+        None => IDLE.record(sample),
+    }
+    if !user {
+        KERNEL.record(pc);
+    }
+}
+
+/// Print the profile of a thread that is exiting.
+pub fn report_task(profile: &TaskProfile, pid: usize, tid: usize) {
+    if ENABLED {
+        profile.report(format_args!("pid {} tid {}", pid, tid));
+    }
+}
+
+/// Print where the kernel spent its ticks, when shutting down.
+pub fn report_kernel() {
+    if ENABLED {
+        IDLE.report(format_args!("idle"));
+        KERNEL.report(format_args!("kernel"));
+    }
+}
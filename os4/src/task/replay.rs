@@ -0,0 +1,278 @@
+//! Record and replay of scheduling decisions
+//!
+//! With `schedreplay=record` on the kernel command line every dispatch of a
+//! thread, whether it was preempted at the end of that run, and every
+//! wake-up by a timer are logged with the tick they happened at. The log
+//! goes to [`REPLAY_FILE`] on the easy-fs image once all applications have
+//! completed. Booting the same image again with `schedreplay=replay` reads
+//! it back and makes the same decisions: threads are dispatched in the
+//! logged order, a thread is preempted on the tick it was preempted at
+//! before, and time jumps to the tick of each logged event, so that timers
+//! and deadlines come due at the same point of the run.
+//!
+//! Both modes run tasks on the boot hart only and count time in ticks
+//! rather than reading `mtime`, see [`timer`](crate::timer), so what the
+//! threads see of time is the same in both runs. Threads are known by pid
+//! and tid, which come out the same as long as the decisions do. Where a
+//! thread goes another way between two ticks, e.g. with other input, the
+//! logged thread may never become ready: replay then gives up after a
+//! second of ticks and schedules as usual from there on.
+
+use super::TaskControlBlock;
+use crate::boot_params::{boot_params, ReplayMode};
+use crate::fs::{read_file, write_file};
+use crate::sync::SpinNoIrq;
+use crate::timer::{advance_ticks, check_timer, ticks};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+/// the log in the root directory of the easy-fs image
+const REPLAY_FILE: &str = "sched.log";
+/// events recorded at most, 32 bytes each
+const REPLAY_MAX_EVENTS: usize = 1 << 14;
+/// an event in the file is four little-endian `u64`
+const EVENT_BYTES: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum EventKind {
+    /// the thread was picked to run
+    Dispatch,
+    /// the thread was woken up by a timer
+    Wakeup,
+}
+
+/// A scheduling decision about thread `tid` of process `pid`
+#[derive(Copy, Clone, Debug)]
+struct SchedEvent {
+    kind: EventKind,
+    tick: usize,
+    pid: usize,
+    tid: usize,
+    /// the run starting with this dispatch ended in a preemption
+    preempted: bool,
+}
+
+impl SchedEvent {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let kind = match self.kind {
+            EventKind::Dispatch => 0,
+            EventKind::Wakeup => 1,
+        };
+        let head = kind | (self.preempted as usize) << 8;
+        for word in [head, self.tick, self.pid, self.tid] {
+            bytes.extend_from_slice(&(word as u64).to_le_bytes());
+        }
+    }
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let word = |i: usize| u64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap()) as usize;
+        let kind = match word(0) & 0xff {
+            0 => EventKind::Dispatch,
+            1 => EventKind::Wakeup,
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            tick: word(1),
+            pid: word(2),
+            tid: word(3),
+            preempted: word(0) >> 8 & 1 != 0,
+        })
+    }
+}
+
+struct Replay {
+    /// recorded so far, or read back to be replayed
+    events: Vec<SchedEvent>,
+    /// the next event to replay
+    cursor: usize,
+    /// following the log, until it runs out or the run goes another way
+    replaying: bool,
+    /// the tick the running thread is to be preempted at, `None` if it
+    /// gave up the CPU on its own in the recorded run
+    preempt_at: Option<usize>,
+    /// dispatches in a row the logged thread was not ready for
+    misses: usize,
+}
+
+static REPLAY: SpinNoIrq<Replay> = SpinNoIrq::new(Replay {
+    events: Vec::new(),
+    cursor: 0,
+    replaying: false,
+    preempt_at: None,
+    misses: 0,
+});
+
+/// pid and tid of `task`, `None` once it has exited. Locks the TCB.
+fn task_key(task: &TaskControlBlock) -> Option<(usize, usize)> {
+    let pid = task.process.upgrade()?.getpid();
+    let tid = task.inner_exclusive_access().res.as_ref()?.tid;
+    Some((pid, tid))
+}
+
+fn record(kind: EventKind, task: &TaskControlBlock) {
+    if boot_params().replay != ReplayMode::Record {
+        return;
+    }
+    let (pid, tid) = match task_key(task) {
+        Some(key) => key,
+        None => return,
+    };
+    let mut replay = REPLAY.lock();
+    if replay.events.len() < REPLAY_MAX_EVENTS {
+        replay.events.push(SchedEvent {
+            kind,
+            tick: ticks(),
+            pid,
+            tid,
+            preempted: false,
+        });
+        if replay.events.len() == REPLAY_MAX_EVENTS {
+            warn!("[kernel] schedreplay: log full after {} events", REPLAY_MAX_EVENTS);
+        }
+    }
+}
+
+/// Log that `task` is about to run. It must not be locked.
+pub fn record_dispatch(task: &TaskControlBlock) {
+    record(EventKind::Dispatch, task);
+}
+
+/// Log that a timer has woken `task` up. It must not be locked.
+pub fn record_wakeup(task: &TaskControlBlock) {
+    record(EventKind::Wakeup, task);
+}
+
+/// Log that the running thread has been preempted.
+pub fn record_preemption() {
+    if boot_params().replay != ReplayMode::Record {
+        return;
+    }
+    let mut replay = REPLAY.lock();
+    if let Some(event) = replay.events.iter_mut().rev().find(|event| event.kind == EventKind::Dispatch) {
+        event.preempted = true;
+    }
+}
+
+/// The thread to dispatch next as `(pid, tid)`, `None` unless replaying.
+///
+/// Time is moved on to the tick of the dispatch first, firing the timers
+/// due by then, so the logged wake-ups happen before it. Must be called
+/// with no lock held.
+pub fn next_dispatch() -> Option<(usize, usize)> {
+    loop {
+        let mut replay = REPLAY.lock();
+        if !replay.replaying {
+            return None;
+        }
+        let event = match replay.events.get(replay.cursor) {
+            Some(event) => *event,
+            None => {
+                info!("[kernel] schedreplay: all {} events replayed", replay.events.len());
+                replay.replaying = false;
+                return None;
+            }
+        };
+        if event.kind == EventKind::Wakeup {
+            replay.cursor += 1;
+        }
+        drop(replay);
+        advance_ticks(event.tick);
+        check_timer();
+        if event.kind == EventKind::Dispatch {
+            return Some((event.pid, event.tid));
+        }
+    }
+}
+
+/// Account for the dispatch [`next_dispatch`] asked for, `found` if the
+/// thread was ready. Returns whether to keep to the log, which is given up
+/// once the thread has not been ready for a second of ticks.
+pub fn dispatched(found: bool) -> bool {
+    let mut replay = REPLAY.lock();
+    if found {
+        let event = replay.events[replay.cursor];
+        replay.cursor += 1;
+        replay.misses = 0;
+        // 被抢占的时刻即下一次调度的时刻
+        replay.preempt_at = if event.preempted {
+            replay.events[replay.cursor..]
+                .iter()
+                .find(|next| next.kind == EventKind::Dispatch)
+                .map(|next| next.tick)
+        } else {
+            None
+        };
+        return true;
+    }
+    replay.misses += 1;
+    if replay.misses > boot_params().ticks_per_sec {
+        let event = replay.events[replay.cursor];
+        warn!(
+            "[kernel] schedreplay: pid {} tid {} never ready after event {}, scheduling as usual",
+            event.pid, event.tid, replay.cursor
+        );
+        replay.replaying = false;
+        return false;
+    }
+    true
+}
+
+/// Whether a tick may preempt the running thread. While replaying only
+/// the tick it was preempted at in the recorded run does.
+pub fn should_preempt() -> bool {
+    let replay = REPLAY.lock();
+    if !replay.replaying {
+        return true;
+    }
+    matches!(replay.preempt_at, Some(tick) if ticks() >= tick)
+}
+
+/// Read the log back with `schedreplay=replay`, once the file system is
+/// up and before any task runs.
+pub fn init() {
+    if boot_params().replay != ReplayMode::Replay {
+        return;
+    }
+    let bytes = match read_file(REPLAY_FILE) {
+        Some(bytes) => bytes,
+        None => {
+            warn!("[kernel] schedreplay: no {} to replay, scheduling as usual", REPLAY_FILE);
+            return;
+        }
+    };
+    let events = match bytes.len() % EVENT_BYTES {
+        0 => bytes.chunks(EVENT_BYTES).map(SchedEvent::decode).collect::<Option<Vec<_>>>(),
+        _ => None,
+    };
+    let events = match events {
+        Some(events) => events,
+        None => {
+            warn!("[kernel] schedreplay: {} is corrupt, scheduling as usual", REPLAY_FILE);
+            return;
+        }
+    };
+    info!("[kernel] schedreplay: replaying {} events from {}", events.len(), REPLAY_FILE);
+    let mut replay = REPLAY.lock();
+    replay.events = events;
+    replay.replaying = true;
+}
+
+/// Write the log out with `schedreplay=record`, once all applications have
+/// completed.
+pub fn save() {
+    if boot_params().replay != ReplayMode::Record {
+        return;
+    }
+    let replay = REPLAY.lock();
+    let mut bytes = Vec::with_capacity(replay.events.len() * EVENT_BYTES);
+    for event in replay.events.iter() {
+        event.encode(&mut bytes);
+    }
+    let count = replay.events.len();
+    drop(replay);
+    if write_file(REPLAY_FILE, &bytes) {
+        println!("[kernel] schedreplay: {} events recorded to {}", count, REPLAY_FILE);
+    } else {
+        warn!("[kernel] schedreplay: could not write {}", REPLAY_FILE);
+    }
+}
@@ -0,0 +1,47 @@
+//! Resource limits of a process, set by `sys_setrlimit`
+//!
+//! They are kept per process, as the address space and the fd table they
+//! bound are shared by all its threads, and children inherit them. Going
+//! over a limit fails the request: `mmap`, `sbrk` and opening files return
+//! -1, and a page fault that would need one more frame raises SIGSEGV.
+
+use crate::config::MAX_FD;
+
+/// frames backing user pages at once
+pub const RLIMIT_FRAMES: usize = 0;
+/// bytes mapped by `mmap` at once
+pub const RLIMIT_MMAP: usize = 1;
+/// open fds, bounded by `MAX_FD` anyway
+pub const RLIMIT_NOFILE: usize = 2;
+/// no limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ResourceLimits {
+    pub max_frames: usize,
+    pub max_mmap_bytes: usize,
+    pub max_fds: usize,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_frames: RLIM_INFINITY,
+            max_mmap_bytes: RLIM_INFINITY,
+            max_fds: MAX_FD,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Set the limit of `resource`, `false` if there is no such resource.
+    pub fn set(&mut self, resource: usize, limit: usize) -> bool {
+        match resource {
+            RLIMIT_FRAMES => self.max_frames = limit,
+            RLIMIT_MMAP => self.max_mmap_bytes = limit,
+            RLIMIT_NOFILE => self.max_fds = limit.min(MAX_FD),
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,119 @@
+//! Signal numbers, actions and masks
+
+/// Largest signal number
+pub const MAX_SIG: usize = 31;
+
+/// `SignalAction::handler` for the default action of a signal
+pub const SIG_DFL: usize = 0;
+/// `SignalAction::handler` for ignoring a signal
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    /// a set of signals, bit `n` standing for signal number `n`
+    pub struct SignalFlags: u32 {
+        const SIGDEF = 1; // Default signal handling
+        const SIGHUP = 1 << 1;
+        const SIGINT = 1 << 2;
+        const SIGQUIT = 1 << 3;
+        const SIGILL = 1 << 4;
+        const SIGTRAP = 1 << 5;
+        const SIGABRT = 1 << 6;
+        const SIGBUS = 1 << 7;
+        const SIGFPE = 1 << 8;
+        const SIGKILL = 1 << 9;
+        const SIGUSR1 = 1 << 10;
+        const SIGSEGV = 1 << 11;
+        const SIGUSR2 = 1 << 12;
+        const SIGPIPE = 1 << 13;
+        const SIGALRM = 1 << 14;
+        const SIGTERM = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD = 1 << 17;
+        const SIGCONT = 1 << 18;
+        const SIGSTOP = 1 << 19;
+        const SIGTSTP = 1 << 20;
+        const SIGTTIN = 1 << 21;
+        const SIGTTOU = 1 << 22;
+        const SIGURG = 1 << 23;
+        const SIGXCPU = 1 << 24;
+        const SIGXFSZ = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF = 1 << 27;
+        const SIGWINCH = 1 << 28;
+        const SIGIO = 1 << 29;
+        const SIGPWR = 1 << 30;
+        const SIGSYS = 1 << 31;
+    }
+}
+
+impl SignalFlags {
+    /// The set holding just signal `signum`, `None` if there is no such signal
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if signum == 0 || signum > MAX_SIG {
+            return None;
+        }
+        Self::from_bits(1 << signum)
+    }
+    /// Signals that can be neither caught, ignored nor blocked
+    pub fn uncatchable() -> Self {
+        Self::SIGKILL | Self::SIGSTOP
+    }
+    /// Signals whose default action is to do nothing. Stopping is not
+    /// supported, so the job control signals are ignored by default as well.
+    pub fn ignored_by_default() -> Self {
+        Self::SIGCHLD
+            | Self::SIGCONT
+            | Self::SIGSTOP
+            | Self::SIGTSTP
+            | Self::SIGTTIN
+            | Self::SIGTTOU
+            | Self::SIGURG
+            | Self::SIGWINCH
+    }
+}
+
+/// What to do when a signal is delivered, as set by `sys_sigaction`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SignalAction {
+    /// `SIG_DFL`, `SIG_IGN` or the user address of a handler taking the
+    /// signal number, which has to finish with `sys_sigreturn`
+    pub handler: usize,
+    /// signals additionally blocked while the handler runs
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
+/// Actions of every signal, indexed by signal number
+#[derive(Clone)]
+pub struct SignalActions {
+    pub table: [SignalAction; MAX_SIG + 1],
+}
+
+impl Default for SignalActions {
+    fn default() -> Self {
+        Self {
+            table: [SignalAction::default(); MAX_SIG + 1],
+        }
+    }
+}
+
+impl SignalActions {
+    /// Actions after exec: handlers are gone with the old image, but
+    /// ignored signals stay ignored
+    pub fn reset_handlers(&mut self) {
+        for action in self.table.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+    }
+}
@@ -1,71 +1,192 @@
-//! Types related to task management
-use super::TaskContext;
-use crate::config::{kernel_stack_position, TRAP_CONTEXT};
-use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
-use crate::trap::{trap_handler, TrapContext};
-use super::MAX_SYSCALL_NUM;
+//! Types related to task management & Functions for completely changing TCB
+use super::id::TaskUserRes;
+use super::profile::TaskProfile;
+use super::manager::initial_pass;
+use super::{kstack_alloc, KernelStack, ProcessControlBlock, SignalFlags, TaskContext};
+use crate::config::DEFAULT_PRIORITY;
+use crate::mm::{OutOfMemory, PhysPageNum};
+use crate::sync::{SpinLock, SpinLockGuard};
+use crate::timer::get_time_us;
+use crate::trap::TrapContext;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize};
 
-/// task control block structure
+/// Task control block structure, one for each thread
+///
+/// Directly save the contents that will not change during running
 pub struct TaskControlBlock {
-    pub task_status: TaskStatus,
-    pub task_cx: TaskContext,
-    pub memory_set: MemorySet,
+    // immutable
+    /// The process this thread belongs to
+    pub process: Weak<ProcessControlBlock>,
+    /// Kernel stack, unmapped when the TCB is dropped
+    pub kernel_stack: KernelStack,
+    /// Set while some hart runs on the kernel stack of this thread
+    pub on_cpu: AtomicBool,
+    /// Set while the thread is inside a syscall, which may hold translated
+    /// user buffers, so its pages are not swapped out
+    pub in_syscall: AtomicBool,
+    /// When the thread last went into the ready queue, in us
+    pub ready_since: AtomicUsize,
+    /// The syscall the thread made last, `usize::MAX` before the first
+    pub last_syscall: AtomicUsize,
+    /// Where the ticks found the thread, empty without `profiling`
+    pub profile: TaskProfile,
+    /// Set by `sys_set_priority`, at least 2
+    pub priority: AtomicUsize,
+    /// How far the stride scheduler has let the thread run
+    pub pass: AtomicUsize,
+    /// Output to the console after the last newline, not written out yet
+    pub stdout_pending: SpinLock<Vec<u8>>,
+    // mutable
+    inner: SpinLock<TaskControlBlockInner>,
+}
+
+/// Structure containing more thread content
+///
+/// Store the contents that will change during operation
+/// and are wrapped by SpinLock to provide mutual exclusion between harts
+pub struct TaskControlBlockInner {
+    /// Tid, trap context and user stack, given back when this goes None
+    pub res: Option<TaskUserRes>,
+    /// The physical page number of the frame where the trap context is placed
     pub trap_cx_ppn: PhysPageNum,
-    pub base_size: usize,
+    /// Save task context
+    pub task_cx: TaskContext,
+    /// Maintain the execution status of the current thread
+    pub task_status: TaskStatus,
+    /// It is set when active exit or execution error occurs
+    pub exit_code: Option<i32>,
+    /// When the time since then was last charged to the process, in us
+    pub time_stamp: usize,
+    /// Signals sent to this thread and not delivered yet
+    pub signals: SignalFlags,
+    /// Signals this thread has blocked
+    pub signal_mask: SignalFlags,
+    /// The signal whose user handler is running, if any
+    pub handling_sig: Option<usize>,
+    /// Trap context to go back to by `sys_sigreturn`
+    pub trap_ctx_backup: Option<TrapContext>,
+    /// How the scheduler has treated this thread
+    pub sched: SchedStats,
+}
 
-    pub task_syscall_times: [u32; MAX_SYSCALL_NUM], // syscall times
-    pub task_first_running_time: Option<usize>, // first time when the task was scheduled
+/// Scheduling counters of a thread
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SchedStats {
+    /// switched out by a timer interrupt
+    pub preemptions: usize,
+    /// switched out by `sys_yield` or while spinning on a mutex
+    pub voluntary_yields: usize,
+    /// picked from the ready queue to run
+    pub dispatches: usize,
+    /// time from entering the ready queue to running, summed up, in us
+    pub latency_us: usize,
+    /// the longest of those waits, in us
+    pub max_latency_us: usize,
+    /// preempted since the last time it yielded or blocked
+    pub slices_in_a_row: usize,
+    /// the watchdog has reported the current run of slices
+    pub runaway_reported: bool,
 }
 
-impl TaskControlBlock {
+impl SchedStats {
+    /// Count a dispatch after waiting `latency` us in the ready queue.
+    pub fn dispatched(&mut self, latency: usize) {
+        self.dispatches += 1;
+        self.latency_us += latency;
+        self.max_latency_us = self.max_latency_us.max(latency);
+    }
+    /// The thread gave up the CPU on its own, ending a run of slices.
+    pub fn gave_up_cpu(&mut self) {
+        self.slices_in_a_row = 0;
+        self.runaway_reported = false;
+    }
+}
+
+/// Simple access to its internal fields
+impl TaskControlBlockInner {
     pub fn get_trap_cx(&self) -> &'static mut TrapContext {
         self.trap_cx_ppn.get_mut()
     }
+}
+
+impl TaskControlBlock {
+    /// Create a thread in `process`, the caller fills in its trap context
+    pub fn new(process: &Arc<ProcessControlBlock>) -> Result<Self, OutOfMemory> {
+        let res = TaskUserRes::new(process)?;
+        let trap_cx_ppn = res.trap_cx_ppn();
+        // alloc a kernel stack in kernel space
+        let kernel_stack = kstack_alloc();
+        let kstack_top = kernel_stack.get_top();
+        Ok(Self {
+            process: Arc::downgrade(process),
+            kernel_stack,
+            on_cpu: AtomicBool::new(false),
+            in_syscall: AtomicBool::new(false),
+            ready_since: AtomicUsize::new(0),
+            last_syscall: AtomicUsize::new(usize::MAX),
+            profile: TaskProfile::new(),
+            priority: AtomicUsize::new(DEFAULT_PRIORITY),
+            pass: AtomicUsize::new(initial_pass()),
+            stdout_pending: SpinLock::new(Vec::new()),
+            inner: SpinLock::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                // push a task context which goes to trap_return to the top of kernel stack
+                task_cx: TaskContext::goto_trap_return(kstack_top),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                time_stamp: 0,
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                handling_sig: None,
+                trap_ctx_backup: None,
+                sched: SchedStats::default(),
+            }),
+        })
+    }
+    /// Lock the inner part to get the SpinLockGuard TaskControlBlockInner
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, TaskControlBlockInner> {
+        self.inner.lock()
+    }
+    /// The inner part, unless someone holds it already
+    pub fn inner_try_access(&self) -> Option<SpinLockGuard<'_, TaskControlBlockInner>> {
+        self.inner.try_lock()
+    }
     pub fn get_user_token(&self) -> usize {
-        self.memory_set.token()
+        let process = self.process.upgrade().unwrap();
+        let inner = process.inner_exclusive_access();
+        inner.memory_set.token()
     }
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-        let task_status = TaskStatus::Ready;
-        // map a kernel-stack in kernel space
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
-        KERNEL_SPACE.lock().insert_framed_area(
-            kernel_stack_bottom.into(),
-            kernel_stack_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
-        let task_control_block = Self {
-            task_status,
-            task_cx: TaskContext::goto_trap_return(kernel_stack_top),
-            memory_set,
-            trap_cx_ppn,
-            base_size: user_sp,
-            task_syscall_times: [0; MAX_SYSCALL_NUM],
-            task_first_running_time: None,
-        };
-        // prepare TrapContext in user space
-        let trap_cx = task_control_block.get_trap_cx();
-        *trap_cx = TrapContext::app_init_context(
-            entry_point,
-            user_sp, // 用户栈初始指针
-            KERNEL_SPACE.lock().token(), // 内核空间页表token
-            kernel_stack_top, // 内核栈顶
-            trap_handler as usize, // trap处理函数
-        );
-        task_control_block
+    /// Charge the time since the last stamp to user mode or to the kernel.
+    pub fn charge_time(&self, user: bool) {
+        let now = get_time_us();
+        let mut inner = self.inner_exclusive_access();
+        let elapsed = now - inner.time_stamp;
+        inner.time_stamp = now;
+        drop(inner);
+        if let Some(process) = self.process.upgrade() {
+            let mut process_inner = process.inner_exclusive_access();
+            if user {
+                process_inner.user_time += elapsed;
+            } else {
+                process_inner.kernel_time += elapsed;
+            }
+        }
+    }
+    pub fn gettid(&self) -> usize {
+        self.inner_exclusive_access().res.as_ref().unwrap().tid
     }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// task status: UnInit, Ready, Running, Exited
+/// task status: UnInit, Ready, Running, Zombie, Blocked
 pub enum TaskStatus {
     UnInit,
     Ready,
     Running,
-    Exited,
-}
\ No newline at end of file
+    Zombie,
+    /// waiting for an event, not in the ready queue
+    Blocked,
+}
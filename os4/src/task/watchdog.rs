@@ -0,0 +1,86 @@
+//! Watchdog reporting tasks that look stuck
+//!
+//! A thread preempted at the end of [`TASK_WATCHDOG_SLICES`] time slices in
+//! a row, without yielding or blocking in between, is reported once as
+//! runaway, with where it was in user mode and its last syscall. A hart
+//! that has not been back to the scheduler for as many ticks is reported
+//! too: its task is stuck in the kernel, where it is not preempted while it
+//! holds a lock.
+
+use super::manager::PID2PCB;
+use super::processor::{hart_task, HART_WATCH};
+use crate::config::{MAX_HARTS, TASK_WATCHDOG_SLICES, WATCHDOG_INTERVAL_US};
+use crate::syscall::syscall_name;
+use crate::timer::{add_periodic_timer, register_timer_callback};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+/// Start the watchdog, unless `TASK_WATCHDOG_SLICES` turns it off.
+pub fn init() {
+    if TASK_WATCHDOG_SLICES.is_some() {
+        add_periodic_timer(WATCHDOG_INTERVAL_US, register_timer_callback(check_tasks));
+    }
+}
+
+fn check_tasks() {
+    let limit = match TASK_WATCHDOG_SLICES {
+        Some(limit) => limit,
+        None => return,
+    };
+    // PID2PCB is let go before the PCBs are locked
+    let processes: Vec<_> = PID2PCB.lock().values().cloned().collect();
+    for process in processes {
+        let pid = process.getpid();
+        let tasks: Vec<_> = process
+            .inner_exclusive_access()
+            .tasks
+            .iter()
+            .flatten()
+            .map(Arc::clone)
+            .collect();
+        for task in tasks {
+            let mut inner = match task.inner_try_access() {
+                Some(inner) => inner,
+                None => continue,
+            };
+            let tid = match inner.res.as_ref() {
+                Some(res) => res.tid,
+                None => continue,
+            };
+            if inner.sched.slices_in_a_row < limit || inner.sched.runaway_reported {
+                continue;
+            }
+            inner.sched.runaway_reported = true;
+            let slices = inner.sched.slices_in_a_row;
+            let sepc = inner.get_trap_cx().sepc;
+            drop(inner);
+            let last_syscall = task.last_syscall.load(Ordering::Relaxed);
+            warn!(
+                "[kernel] watchdog: pid {} tid {} ran {} slices without yielding, at {:#x}, last syscall {}",
+                pid,
+                tid,
+                slices,
+                sepc,
+                syscall_name(last_syscall).unwrap_or("none")
+            );
+        }
+    }
+    for hartid in 0..MAX_HARTS {
+        let watch = &HART_WATCH[hartid];
+        let ticks = watch.ticks.load(Ordering::Relaxed);
+        if ticks < limit || watch.reported.swap(true, Ordering::Relaxed) {
+            continue;
+        }
+        match hart_task(hartid).and_then(|task| Some((task.process.upgrade()?, task))) {
+            Some((process, task)) => warn!(
+                "[kernel] watchdog: hart {} stuck in the kernel for {} ticks, running pid {}, last syscall {}",
+                hartid,
+                ticks,
+                process.getpid(),
+                syscall_name(task.last_syscall.load(Ordering::Relaxed)).unwrap_or("none")
+            ),
+            None => warn!("[kernel] watchdog: hart {} stuck in the kernel for {} ticks", hartid, ticks),
+        }
+    }
+}
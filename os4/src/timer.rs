@@ -1,9 +1,33 @@
-use crate::config::CLOCK_FREQ;
+//! Time, the timer interrupt and the timers behind it
+//!
+//! Every hart has its scheduling tick, `ticks_per_sec` times a second as
+//! the kernel command line sets it, see [`boot_params`](crate::boot_params).
+//! Other timer events, tasks to wake up and callbacks to run once or
+//! periodically, sit in a min-heap shared by all harts, see [`add_timer`].
+//! A hart programs its timer for its next tick or the earliest event,
+//! whichever comes first, so a sleep ends when it is due rather than on the
+//! next tick.
+//!
+//! With `schedreplay` time is counted in ticks instead of read from `mtime`
+//! and timer events only fire on ticks, so that a replayed run sees the
+//! same time at the same point as the recorded one.
+
+use crate::boot_params::{boot_params, ReplayMode};
+use crate::config::{CLOCK_FREQ, MAX_HARTS, WATCHDOG_INTERVAL_US};
+use crate::hart::hart_id;
 use crate::sbi::set_timer;
+use crate::sync::SpinNoIrq;
+use crate::task::{record_wakeup, wakeup_task, TaskControlBlock};
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::sync::atomic::{self, AtomicIsize, AtomicUsize};
+use lazy_static::*;
 use riscv::register::time;
 
-const TICKS_PER_SEC: usize = 100;
 const MICRO_PER_SEC: usize = 1_000_000;
+pub const NANO_PER_SEC: usize = 1_000_000_000;
 
 // read the `mtime` register
 pub fn get_time() -> usize {
@@ -12,10 +36,317 @@ pub fn get_time() -> usize {
 
 // get current time in microseconds
 pub fn get_time_us() -> usize {
+    if tick_clock() {
+        return ticks() * (MICRO_PER_SEC / boot_params().ticks_per_sec);
+    }
     time::read() / (CLOCK_FREQ / MICRO_PER_SEC)
 }
 
-// set the next timer interrupt
+// get current time in nanoseconds
+pub fn get_time_ns() -> usize {
+    if tick_clock() {
+        return ticks() * (NANO_PER_SEC / boot_params().ticks_per_sec);
+    }
+    (time::read() as u128 * NANO_PER_SEC as u128 / CLOCK_FREQ as u128) as usize
+}
+
+/// tick periods started since boot, the clock with `schedreplay`
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether time is counted in ticks, to be replayed
+fn tick_clock() -> bool {
+    boot_params().replay != ReplayMode::Off
+}
+
+/// Ticks since boot, 0 unless time is counted in ticks
+pub fn ticks() -> usize {
+    TICKS.load(atomic::Ordering::Relaxed)
+}
+
+/// Move the tick clock on to `tick`, if it is not there yet, when a replay
+/// skips time the recorded run spent elsewhere.
+pub fn advance_ticks(tick: usize) {
+    TICKS.fetch_max(tick, atomic::Ordering::Relaxed);
+}
+
+/// wall clock time, settable by `sys_clock_settime`
+pub const CLOCK_REALTIME: usize = 0;
+/// time since boot, never goes back
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// `CLOCK_REALTIME` minus `CLOCK_MONOTONIC` in nanoseconds. There is no
+/// RTC, so the wall clock starts at the epoch at boot until it is set.
+static REALTIME_OFFSET_NS: AtomicIsize = AtomicIsize::new(0);
+
+/// Read `clock` in nanoseconds, `None` if there is no such clock.
+pub fn clock_gettime_ns(clock: usize) -> Option<usize> {
+    let now = get_time_ns();
+    match clock {
+        CLOCK_REALTIME => {
+            let offset = REALTIME_OFFSET_NS.load(atomic::Ordering::Relaxed);
+            Some(now.wrapping_add(offset as usize))
+        }
+        CLOCK_MONOTONIC => Some(now),
+        _ => None,
+    }
+}
+
+/// Set `clock` to `ns` nanoseconds, `false` if it cannot be set. Only the
+/// wall clock can, timers and deadlines stay on the monotonic clock.
+pub fn clock_settime_ns(clock: usize, ns: usize) -> bool {
+    if clock != CLOCK_REALTIME {
+        return false;
+    }
+    let offset = ns.wrapping_sub(get_time_ns()) as isize;
+    REALTIME_OFFSET_NS.store(offset, atomic::Ordering::Relaxed);
+    true
+}
+
+const TIME_ZERO: AtomicUsize = AtomicUsize::new(0);
+/// `mtime` of the next scheduling tick of each hart
+static NEXT_TICK: [AtomicUsize; MAX_HARTS] = [TIME_ZERO; MAX_HARTS];
+/// `mtime` of the last scheduling tick of each hart, 0 before the first
+static LAST_TICK: [AtomicUsize; MAX_HARTS] = [TIME_ZERO; MAX_HARTS];
+
+/// Start the next tick period of this hart and set the timer for it.
 pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+    // 持有锁时中断关闭，不会换到别的hart上
+    let timers = TIMERS.lock();
+    let now = get_time();
+    if tick_clock() {
+        TICKS.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+    LAST_TICK[hart_id()].store(now, atomic::Ordering::Relaxed);
+    NEXT_TICK[hart_id()].store(now + CLOCK_FREQ / boot_params().ticks_per_sec, atomic::Ordering::Relaxed);
+    program_timer(&timers);
+}
+
+/// Whether the scheduling tick of this hart is due, rather than only some
+/// timer event. Interrupts have to be off.
+pub fn tick_expired() -> bool {
+    get_time() >= NEXT_TICK[hart_id()].load(atomic::Ordering::Relaxed)
+}
+
+/// Set the timer of this hart for its next tick or the earliest timer
+/// event, leaving events that are already due to the next tick. Called with
+/// the timers locked, so that we stay on this hart.
+fn program_timer(timers: &BinaryHeap<TimerEvent>) {
+    let tick = NEXT_TICK[hart_id()].load(atomic::Ordering::Relaxed);
+    let next = match timers.peek() {
+        Some(event) if !tick_clock() && us_to_time(event.expire_us) > get_time() => {
+            tick.min(us_to_time(event.expire_us))
+        }
+        _ => tick,
+    };
+    set_timer(next);
+}
+
+/// Set the timer of this hart again after a timer interrupt whose events
+/// could not be handled, see [`check_timer`].
+pub fn rearm_timer() {
+    program_timer(&TIMERS.lock());
+}
+
+fn us_to_time(us: usize) -> usize {
+    us.saturating_mul(CLOCK_FREQ / MICRO_PER_SEC)
+}
+
+/// error code of a blocking syscall whose deadline has passed
+pub const ETIMEDOUT: isize = -110;
+
+/// Absolute deadline of a blocking syscall, in microseconds since boot.
+///
+/// Blocking syscalls take the deadline as a raw argument where 0 means
+/// waiting forever, and check it with [`Deadline::expired`] every time they
+/// are woken up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Deadline(Option<usize>);
+
+#[allow(unused)]
+impl Deadline {
+    pub const NEVER: Deadline = Deadline(None);
+
+    pub fn from_raw(deadline_us: usize) -> Self {
+        if deadline_us == 0 {
+            Self::NEVER
+        } else {
+            Self(Some(deadline_us))
+        }
+    }
+    pub fn expired(&self) -> bool {
+        matches!(self.0, Some(deadline) if get_time_us() >= deadline)
+    }
+    /// the deadline in microseconds, if there is one
+    pub fn as_us(&self) -> Option<usize> {
+        self.0
+    }
+}
+
+/// What a timer event does when it fires
+enum TimerAction {
+    /// make a sleeping task ready again
+    Wakeup(Arc<TaskControlBlock>),
+    /// run a callback registered with [`register_timer_callback`]
+    Callback(usize),
+}
+
+/// An event in the timer heap, due at `expire_us`
+struct TimerEvent {
+    expire_us: usize,
+    /// 0 for a one-shot event, otherwise it comes back this much later
+    period_us: usize,
+    action: TimerAction,
+}
+
+impl PartialEq for TimerEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.expire_us == other.expire_us
+    }
+}
+impl Eq for TimerEvent {}
+impl PartialOrd for TimerEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+// BinaryHeap是大顶堆，反过来比较让最早到期的排在堆顶
+impl Ord for TimerEvent {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.expire_us.cmp(&self.expire_us)
+    }
+}
+
+lazy_static! {
+    /// timer events ordered by when they are due
+    static ref TIMERS: SpinNoIrq<BinaryHeap<TimerEvent>> = SpinNoIrq::new(BinaryHeap::new());
+    /// callbacks timer events can run, indexed by id
+    static ref CALLBACKS: SpinNoIrq<Vec<fn()>> = SpinNoIrq::new(Vec::new());
+}
+
+/// Put `event` into the heap, setting the timer of this hart again in case
+/// it comes first.
+fn push_event(event: TimerEvent) {
+    let mut timers = TIMERS.lock();
+    timers.push(event);
+    program_timer(&timers);
+}
+
+/// Make `callback` available to timer events, returning its id. It runs
+/// from the timer interrupt with no lock held.
+pub fn register_timer_callback(callback: fn()) -> usize {
+    let mut callbacks = CALLBACKS.lock();
+    callbacks.push(callback);
+    callbacks.len() - 1
+}
+
+/// Run callback `callback_id` once `deadline_us` has passed.
+#[allow(unused)]
+pub fn add_timer(deadline_us: usize, callback_id: usize) {
+    push_event(TimerEvent {
+        expire_us: deadline_us,
+        period_us: 0,
+        action: TimerAction::Callback(callback_id),
+    });
+}
+
+/// Run callback `callback_id` every `period_us` from now on.
+pub fn add_periodic_timer(period_us: usize, callback_id: usize) {
+    assert!(period_us > 0);
+    push_event(TimerEvent {
+        expire_us: get_time_us() + period_us,
+        period_us,
+        action: TimerAction::Callback(callback_id),
+    });
+}
+
+/// Wake `task` up once `expire_us` has passed.
+pub fn add_wakeup(expire_us: usize, task: Arc<TaskControlBlock>) {
+    push_event(TimerEvent {
+        expire_us,
+        period_us: 0,
+        action: TimerAction::Wakeup(task),
+    });
+}
+
+/// Fire every timer event that is due, then set the timer of this hart for
+/// what comes next. Must be called with no lock held.
+pub fn check_timer() {
+    let current_us = get_time_us();
+    loop {
+        let mut timers = TIMERS.lock();
+        let event = match timers.peek() {
+            Some(event) if event.expire_us <= current_us => timers.pop().unwrap(),
+            _ => {
+                program_timer(&timers);
+                return;
+            }
+        };
+        match event.action {
+            TimerAction::Wakeup(task) => {
+                drop(timers);
+                if wakeup_task(task.clone()) {
+                    record_wakeup(&task);
+                }
+            }
+            TimerAction::Callback(id) => {
+                if event.period_us != 0 {
+                    // periods missed while the timer was held up are skipped
+                    let mut expire_us = event.expire_us + event.period_us;
+                    if expire_us <= current_us {
+                        expire_us = current_us + event.period_us;
+                    }
+                    timers.push(TimerEvent {
+                        expire_us,
+                        period_us: event.period_us,
+                        action: TimerAction::Callback(id),
+                    });
+                }
+                drop(timers);
+                let callback = CALLBACKS.lock()[id];
+                callback();
+            }
+        }
+    }
+}
+
+/// Drop the pending wake-up of `task`, if any.
+pub fn remove_wakeup(task: &Arc<TaskControlBlock>) {
+    let mut timers = TIMERS.lock();
+    let remaining = core::mem::take(&mut *timers);
+    *timers = remaining
+        .into_iter()
+        .filter(|event| match &event.action {
+            TimerAction::Wakeup(waiter) => !Arc::ptr_eq(waiter, task),
+            TimerAction::Callback(_) => true,
+        })
+        .collect();
+}
+
+/// Whether any task is still sleeping.
+pub fn has_timers() -> bool {
+    TIMERS
+        .lock()
+        .iter()
+        .any(|event| matches!(event.action, TimerAction::Wakeup(_)))
+}
+
+/// Warn about harts that have not had a tick for a whole watchdog interval,
+/// which are stuck with interrupts off.
+fn watchdog() {
+    let now = get_time();
+    for hartid in 0..MAX_HARTS {
+        let last = LAST_TICK[hartid].load(atomic::Ordering::Relaxed);
+        if last != 0 && now.saturating_sub(last) > us_to_time(WATCHDOG_INTERVAL_US) {
+            warn!(
+                "[kernel] watchdog: no tick on hart {} for {} ms",
+                hartid,
+                (now - last) / (CLOCK_FREQ / 1000)
+            );
+        }
+    }
+}
+
+/// Start the kernel timers, once on the boot hart.
+pub fn init() {
+    add_periodic_timer(WATCHDOG_INTERVAL_US, register_timer_callback(watchdog));
 }
@@ -1,8 +1,13 @@
 //! Implementation of [`TrapContext`]
+//!
+//! The FP registers are saved lazily: `__alltraps` only stores them when
+//! `sstatus.FS` says user code has written them since they were loaded, and
+//! `__restore` loads them back unless the FP unit was never turned on.
 
-use riscv::register::sstatus::{self, Sstatus, SPP};
+use riscv::register::sstatus::{self, Sstatus, FS, SPP};
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 /// trap context structure containing sstatus, sepc and registers
 pub struct TrapContext {
     pub x: [usize; 32],
@@ -11,6 +16,20 @@ pub struct TrapContext {
     pub kernel_satp: usize,
     pub kernel_sp: usize,
     pub trap_handler: usize,
+    /// Hart id to put into tp on a trap, refreshed on every return to user
+    pub kernel_tp: usize,
+    /// f0~f31, as of the last trap taken with `sstatus.FS` dirty
+    pub f: [usize; 32],
+    pub fcsr: usize,
+}
+
+#[repr(C)]
+/// registers of kernel code stopped by an `ebreak`, saved by `__kernelbreak`
+pub struct KernelBreakFrame {
+    /// x2 is the sp at the `ebreak` and x0 is not saved
+    pub x: [usize; 32],
+    pub sstatus: usize,
+    pub sepc: usize,
 }
 
 impl TrapContext {
@@ -24,6 +43,10 @@ impl TrapContext {
         kernel_sp: usize,
         trap_handler: usize,
     ) -> Self {
+        // 用户程序一开始就能用浮点，寄存器全为0
+        unsafe {
+            sstatus::set_fs(FS::Initial);
+        }
         let mut sstatus = sstatus::read();
         sstatus.set_spp(SPP::User);
         let mut cx = Self {
@@ -33,6 +56,9 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            kernel_tp: 0,
+            f: [0; 32],
+            fcsr: 0,
         };
         cx.set_sp(sp);
         cx
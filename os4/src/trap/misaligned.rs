@@ -0,0 +1,161 @@
+//! Emulation of misaligned user loads and stores
+//!
+//! Harts may trap on a load or store that is not naturally aligned instead
+//! of doing it, and the firmware may leave that to us. The instruction at
+//! `sepc` is decoded just far enough to know the width, the register and
+//! the direction, the access is done byte by byte at `stval` through the
+//! page table of the task, and the task goes on after the instruction.
+
+use super::TrapContext;
+use crate::mm::{translated_byte_buffer, user_page_writable};
+use crate::task::{current_user_token, fault_in_user_range, SignalFlags};
+use alloc::vec::Vec;
+
+/// `scause` of a misaligned load, which the `riscv` crate calls `Unknown`
+const LOAD_MISALIGNED: usize = 4;
+const STORE_MISALIGNED: usize = 6;
+
+/// Whether `scause` is a misaligned load or store.
+pub fn is_misaligned(scause: usize) -> bool {
+    scause == LOAD_MISALIGNED || scause == STORE_MISALIGNED
+}
+
+/// A decoded load or store
+struct Access {
+    /// bytes accessed, 2, 4 or 8
+    width: usize,
+    /// a load sign-extends its value
+    signed: bool,
+    store: bool,
+    /// x or f register loaded into or stored from
+    reg: usize,
+    fp: bool,
+    /// length of the instruction, 2 or 4
+    len: usize,
+}
+
+impl Access {
+    fn new(width: usize, signed: bool, store: bool, reg: usize, fp: bool, len: usize) -> Self {
+        Self { width, signed, store, reg, fp, len }
+    }
+}
+
+/// Decode the load or store `inst`, `None` for any other instruction.
+fn decode(inst: u32) -> Option<Access> {
+    let inst = inst as usize;
+    if inst & 0b11 != 0b11 {
+        // x8~x15 in the 3-bit register fields of the compressed forms
+        let low_reg = 8 + ((inst >> 2) & 0b111);
+        let rd = (inst >> 7) & 0x1f;
+        let rs2 = (inst >> 2) & 0x1f;
+        return Some(match (inst & 0b11, (inst >> 13) & 0b111) {
+            // c.fld, c.lw, c.ld
+            (0b00, 0b001) => Access::new(8, false, false, low_reg, true, 2),
+            (0b00, 0b010) => Access::new(4, true, false, low_reg, false, 2),
+            (0b00, 0b011) => Access::new(8, false, false, low_reg, false, 2),
+            // c.fsd, c.sw, c.sd
+            (0b00, 0b101) => Access::new(8, false, true, low_reg, true, 2),
+            (0b00, 0b110) => Access::new(4, false, true, low_reg, false, 2),
+            (0b00, 0b111) => Access::new(8, false, true, low_reg, false, 2),
+            // c.fldsp, c.lwsp, c.ldsp
+            (0b10, 0b001) => Access::new(8, false, false, rd, true, 2),
+            (0b10, 0b010) => Access::new(4, true, false, rd, false, 2),
+            (0b10, 0b011) => Access::new(8, false, false, rd, false, 2),
+            // c.fsdsp, c.swsp, c.sdsp
+            (0b10, 0b101) => Access::new(8, false, true, rs2, true, 2),
+            (0b10, 0b110) => Access::new(4, false, true, rs2, false, 2),
+            (0b10, 0b111) => Access::new(8, false, true, rs2, false, 2),
+            _ => return None,
+        });
+    }
+    let rd = (inst >> 7) & 0x1f;
+    let rs2 = (inst >> 20) & 0x1f;
+    let funct3 = (inst >> 12) & 0b111;
+    Some(match (inst & 0x7f, funct3) {
+        // lh, lw, ld, lhu, lwu
+        (0x03, 0b001) => Access::new(2, true, false, rd, false, 4),
+        (0x03, 0b010) => Access::new(4, true, false, rd, false, 4),
+        (0x03, 0b011) => Access::new(8, false, false, rd, false, 4),
+        (0x03, 0b101) => Access::new(2, false, false, rd, false, 4),
+        (0x03, 0b110) => Access::new(4, false, false, rd, false, 4),
+        // sh, sw, sd
+        (0x23, 0b001) => Access::new(2, false, true, rs2, false, 4),
+        (0x23, 0b010) => Access::new(4, false, true, rs2, false, 4),
+        (0x23, 0b011) => Access::new(8, false, true, rs2, false, 4),
+        // flw, fld, fsw, fsd
+        (0x07, 0b010) => Access::new(4, false, false, rd, true, 4),
+        (0x07, 0b011) => Access::new(8, false, false, rd, true, 4),
+        (0x27, 0b010) => Access::new(4, false, true, rs2, true, 4),
+        (0x27, 0b011) => Access::new(8, false, true, rs2, true, 4),
+        _ => return None,
+    })
+}
+
+/// The user bytes at `[addr, addr + len)`, faulting in pages that are not
+/// there yet, like the access itself would have.
+fn user_bytes(
+    token: usize,
+    addr: usize,
+    len: usize,
+) -> Result<Vec<&'static mut [u8]>, SignalFlags> {
+    fault_in_user_range(addr, len);
+    translated_byte_buffer(token, addr as *const u8, len).map_err(|_| SignalFlags::SIGSEGV)
+}
+
+/// The instruction at `pc`, 2 or 4 bytes long.
+fn fetch(token: usize, pc: usize) -> Result<u32, SignalFlags> {
+    let read_half = |va: usize| -> Result<u32, SignalFlags> {
+        let buffers = user_bytes(token, va, 2)?;
+        let mut half = 0;
+        for (i, &byte) in buffers.iter().flat_map(|buffer| buffer.iter()).enumerate() {
+            half |= (byte as u32) << (i * 8);
+        }
+        Ok(half)
+    };
+    let low = read_half(pc)?;
+    if low & 0b11 != 0b11 {
+        return Ok(low);
+    }
+    Ok(low | read_half(pc + 2)? << 16)
+}
+
+/// Do the misaligned access at `addr` of the instruction at `cx.sepc` and
+/// step over it, or return the signal to send to the task. The task has to
+/// be marked as in a syscall, see [`crate::task::set_current_in_syscall`].
+pub fn emulate(cx: &mut TrapContext, addr: usize) -> Result<(), SignalFlags> {
+    let token = current_user_token();
+    let access = decode(fetch(token, cx.sepc)?).ok_or(SignalFlags::SIGBUS)?;
+    let mut buffers = user_bytes(token, addr, access.width)?;
+    let bytes = buffers.iter_mut().flat_map(|buffer| buffer.iter_mut());
+    if access.store {
+        if !user_page_writable(token, addr) || !user_page_writable(token, addr + access.width - 1) {
+            return Err(SignalFlags::SIGSEGV);
+        }
+        let value = match (access.fp, access.reg) {
+            (true, reg) => cx.f[reg],
+            (false, 0) => 0,
+            (false, reg) => cx.x[reg],
+        };
+        for (i, byte) in bytes.enumerate() {
+            *byte = (value >> (i * 8)) as u8;
+        }
+    } else {
+        let mut value = 0usize;
+        for (i, byte) in bytes.enumerate() {
+            value |= (*byte as usize) << (i * 8);
+        }
+        let shift = 64 - access.width * 8;
+        if access.signed && shift > 0 {
+            value = (((value << shift) as isize) >> shift) as usize;
+        }
+        match (access.fp, access.reg) {
+            // a single is NaN-boxed in the 64-bit register
+            (true, reg) if access.width == 4 => cx.f[reg] = value | 0xffff_ffff_0000_0000,
+            (true, reg) => cx.f[reg] = value,
+            (false, 0) => {}
+            (false, reg) => cx.x[reg] = value,
+        }
+    }
+    cx.sepc += access.len;
+    Ok(())
+}
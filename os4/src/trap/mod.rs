@@ -11,18 +11,31 @@
 //! It then calls different functionality based on what exactly the exception
 //! was. For example, timer interrupts trigger task preemption, and syscalls go
 //! to [`syscall()`].
+//!
+//! Interrupts stay enabled while the kernel works on behalf of a task. Those
+//! arriving then go through `__kerneltrap` to [`kernel_interrupt_handler()`]
+//! and return to where the kernel was, any other trap from the kernel is
+//! fatal and ends up in [`trap_from_kernel()`].
 mod context;
+mod misaligned;
 
-use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::config::{kernel_stack_guard_owner, TRAMPOLINE};
+use crate::dtb::board;
+use crate::drivers::{handle_irq, uart::wake_pending_readers};
 use crate::syscall::syscall;
+use crate::mm::PageFaultError;
+use crate::sync::locks_held;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,
+    charge_kernel_time, charge_user_time, count_tick, current_fault_signal, current_trap_cx,
+    current_trap_cx_user_va, current_user_token, handle_page_fault, handle_signals,
+    preempt_current_and_run_next, profile_sample, scan_access_periodically, set_current_in_syscall,
+    should_preempt, SignalFlags,
 };
-use crate::timer::set_next_trigger;
+use crate::timer::{check_timer, rearm_timer, set_next_trigger, tick_expired};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    sepc, sie, sstatus, stval, stvec,
 };
 
 core::arch::global_asm!(include_str!("trap.S"));
@@ -32,8 +45,11 @@ pub fn init() {
 }
 
 fn set_kernel_trap_entry() {
+    extern "C" {
+        fn __kerneltrap();
+    }
     unsafe {
-        stvec::write(trap_from_kernel as usize, TrapMode::Direct);
+        stvec::write(__kerneltrap as usize, TrapMode::Direct);
     }
 }
 
@@ -49,30 +65,107 @@ pub fn enable_timer_interrupt() {
     }
 }
 
+/// Let device interrupts routed by the PLIC in.
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// Let interrupts in while the kernel is running.
+fn enable_kernel_interrupt() {
+    unsafe {
+        sstatus::set_sie();
+    }
+}
+
+fn disable_kernel_interrupt() {
+    unsafe {
+        sstatus::clear_sie();
+    }
+}
+
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    charge_user_time();
     let cx = current_trap_cx();
     let scause = scause::read();
     let stval = stval::read();
+    // the trap CSRs are read, an interrupt in the kernel may overwrite them
+    enable_kernel_interrupt();
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
+            // jump to next instruction anyway
             cx.sepc += 4;
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            // get system call return value
+            set_current_in_syscall(true);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
+            set_current_in_syscall(false);
+            // cx is changed during sys_exec, so we have to call it again
+            let cx = current_trap_cx();
+            cx.x[10] = result as usize;
+        }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            match handle_page_fault(stval) {
+                Ok(()) => {}
+                Err(PageFaultError::StackOverflow) => {
+                    debug!("[kernel] StackOverflow in application, bad addr = {:#x}, bad instruction = {:#x}", stval, cx.sepc);
+                    current_fault_signal(SignalFlags::SIGSEGV);
+                }
+                Err(PageFaultError::OverLimit) => {
+                    debug!("[kernel] frame limit reached in application, bad addr = {:#x}, bad instruction = {:#x}", stval, cx.sepc);
+                    current_fault_signal(SignalFlags::SIGSEGV);
+                }
+                Err(_) => {
+                    debug!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}", stval, cx.sepc);
+                    current_fault_signal(SignalFlags::SIGSEGV);
+                }
+            }
+        }
+        Trap::Exception(Exception::StoreFault) => {
+            debug!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}", stval, cx.sepc);
+            current_fault_signal(SignalFlags::SIGSEGV);
         }
-        Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::LoadPageFault) => {
-            error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
-            exit_current_and_run_next();
+        Trap::Exception(Exception::StoreMisaligned) | Trap::Exception(Exception::Unknown)
+            if misaligned::is_misaligned(scause.bits()) =>
+        {
+            // like a syscall, so the swapper leaves the pages it brings in
+            // alone until the access is done
+            set_current_in_syscall(true);
+            let result = misaligned::emulate(cx, stval);
+            set_current_in_syscall(false);
+            if let Err(signal) = result {
+                debug!("[kernel] misaligned access in application, bad addr = {:#x}, bad instruction = {:#x}", stval, cx.sepc);
+                current_fault_signal(signal);
+            }
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            error!("[kernel] IllegalInstruction in application, core dumped.");
-            exit_current_and_run_next();
+            debug!("[kernel] IllegalInstruction in application, bad instruction = {:#x}", cx.sepc);
+            current_fault_signal(SignalFlags::SIGILL);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_next_trigger();
-            suspend_current_and_run_next();
+            // the interrupt may be for a timer event before the tick
+            let tick = tick_expired();
+            if tick {
+                set_next_trigger();
+                count_tick();
+                profile_sample(cx.sepc, true);
+            }
+            check_timer();
+            wake_pending_readers();
+            scan_access_periodically();
+            if tick && should_preempt() {
+                preempt_current_and_run_next();
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            handle_irq();
         }
         _ => {
             panic!(
@@ -82,15 +175,20 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    // faults above turn into signals, which may terminate the application
+    handle_signals();
     trap_return();
 }
 
 #[no_mangle]
 pub fn trap_return() -> ! {
+    // no interrupt may come in through the trampoline before we are in U
+    disable_kernel_interrupt();
     // from S to U, set `stvec` register `trap` process addr as springboard adr
     set_user_trap_entry();
+    charge_kernel_time();
     // prepare two params that __restore needs:
-    let trap_cx_ptr = TRAP_CONTEXT;
+    let trap_cx_ptr = current_trap_cx_user_va();
     let user_satp = current_user_token();
     extern "C" {
         fn __alltraps();
@@ -111,9 +209,82 @@ pub fn trap_return() -> ! {
     }
 }
 
+/// Entered through `__kerneltrap` for an interrupt, with the registers of
+/// the interrupted kernel code saved on its own stack.
+#[no_mangle]
+pub fn kernel_interrupt_handler() {
+    match scause::read().cause() {
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            let tick = tick_expired();
+            if tick {
+                set_next_trigger();
+                count_tick();
+                profile_sample(sepc::read(), false);
+            }
+            // the interrupted code may hold a lock the timers or another
+            // task on this hart need, try again on the next tick then
+            if locks_held() == 0 {
+                check_timer();
+                wake_pending_readers();
+                scan_access_periodically();
+                if tick && should_preempt() {
+                    preempt_current_and_run_next();
+                }
+            } else {
+                rearm_timer();
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            handle_irq();
+        }
+        cause => {
+            panic!("Unsupported interrupt {:?} in kernel!", cause);
+        }
+    }
+}
+
+/// Entered through `__kerneltrap` on a dedicated stack for any other trap.
 #[no_mangle]
 pub fn trap_from_kernel() -> ! {
-    panic!("a trap from kernel!");
+    let scause = scause::read();
+    let stval = stval::read();
+    if let Trap::Exception(
+        Exception::StorePageFault | Exception::LoadPageFault | Exception::StoreFault,
+    ) = scause.cause()
+    {
+        // kernel stacks live above physical memory, right below the trampoline
+        if let Some(kstack_id) = kernel_stack_guard_owner(stval).filter(|_| stval >= board().memory_end) {
+            panic!(
+                "kernel stack overflow in kernel stack {}, bad addr = {:#x}, bad instruction = {:#x}",
+                kstack_id,
+                stval,
+                sepc::read()
+            );
+        }
+    }
+    panic!(
+        "a trap {:?} from kernel, stval = {:#x}, sepc = {:#x}!",
+        scause.cause(),
+        stval,
+        sepc::read()
+    );
+}
+
+/// Entered through `__kernelbreak` for an `ebreak` in the kernel, on the
+/// trap stack of the hart. Returns `cx`, the saved registers to go on with.
+#[cfg(feature = "gdbstub")]
+#[no_mangle]
+pub fn kernel_breakpoint(cx: &mut KernelBreakFrame) -> &mut KernelBreakFrame {
+    crate::gdbstub::handle_break(cx);
+    cx
+}
+
+/// Without the gdb stub an `ebreak` in the kernel is as fatal as any other
+/// exception.
+#[cfg(not(feature = "gdbstub"))]
+#[no_mangle]
+pub fn kernel_breakpoint(cx: &mut KernelBreakFrame) -> &mut KernelBreakFrame {
+    panic!("a trap Breakpoint from kernel, sepc = {:#x}!", cx.sepc);
 }
 
-pub use context::TrapContext;
+pub use context::{KernelBreakFrame, TrapContext};
@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, eventfd, fork, read, wait, write, EFD_SEMAPHORE};
+
+fn read_count(fd: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, &mut buf), 8);
+    u64::from_ne_bytes(buf)
+}
+
+fn add(fd: usize, value: u64) -> isize {
+    write(fd, &value.to_ne_bytes())
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // writes add up until a read takes all of it
+    let fd = eventfd(0, 0) as usize;
+    assert_eq!(add(fd, 3), 8);
+    assert_eq!(add(fd, 4), 8);
+    assert_eq!(read_count(fd), 7);
+    // short buffers and overflowing the counter are refused
+    let mut short = [0u8; 4];
+    assert!(read(fd, &mut short) < 0);
+    assert_eq!(add(fd, u64::MAX - 1), 8);
+    assert_eq!(add(fd, 1), -11);
+    assert_eq!(read_count(fd), u64::MAX - 1);
+    // a reader blocks until the child signals it
+    if fork() == 0 {
+        add(fd, 42);
+        return 0;
+    }
+    assert_eq!(read_count(fd), 42);
+    let mut exit_code = 0;
+    wait(&mut exit_code);
+    assert_eq!(exit_code, 0);
+    close(fd);
+    // in semaphore mode a read takes one at a time
+    let fd = eventfd(2, EFD_SEMAPHORE) as usize;
+    assert_eq!(read_count(fd), 1);
+    assert_eq!(read_count(fd), 1);
+    close(fd);
+    assert_eq!(eventfd(0, 2), -1);
+    println!("eventfd test passed!");
+    0
+}
@@ -27,6 +27,7 @@ const TESTS: &[&str] = &[
     "ch8b_test_condvar\0",
     "ch8b_threads\0",
     "ch8b_threads_arg\0",
+    "ch8b_timeout\0",
 ];
 
 const TEST_NUM: usize = TESTS.len();
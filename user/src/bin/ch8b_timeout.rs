@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::AtomicU32;
+use user_lib::{
+    close, condvar_create, condvar_wait_until, deadline_after_ms, futex_wait_until, get_time,
+    mutex_blocking_create, mutex_create, mutex_lock, mutex_lock_until, mutex_unlock, pipe,
+    read_until, ETIMEDOUT,
+};
+
+const WAIT_MS: usize = 50;
+
+/// Run `f` with a deadline `WAIT_MS` from now, checking that it times out
+/// no earlier than that.
+fn times_out(what: &str, f: impl FnOnce(usize) -> isize) {
+    let start = get_time();
+    assert_eq!(f(deadline_after_ms(WAIT_MS)), ETIMEDOUT, "{}", what);
+    assert!(get_time() - start >= WAIT_MS as isize, "{}", what);
+    println!("{} timed out", what);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // an empty pipe whose write end is still open
+    let mut fds = [0usize; 2];
+    assert_eq!(pipe(&mut fds), 0);
+    let mut buf = [0u8; 8];
+    times_out("pipe read", |deadline| read_until(fds[0], &mut buf, deadline));
+    close(fds[0]);
+    close(fds[1]);
+    // mutexes we already hold
+    for mutex_id in [mutex_create() as usize, mutex_blocking_create() as usize] {
+        mutex_lock(mutex_id);
+        times_out("mutex lock", |deadline| mutex_lock_until(mutex_id, deadline));
+        mutex_unlock(mutex_id);
+        assert_eq!(mutex_lock_until(mutex_id, deadline_after_ms(WAIT_MS)), 0);
+        mutex_unlock(mutex_id);
+    }
+    // a condvar nobody signals, the mutex is held again afterwards
+    let mutex_id = mutex_blocking_create() as usize;
+    let condvar_id = condvar_create() as usize;
+    mutex_lock(mutex_id);
+    times_out("condvar wait", |deadline| {
+        condvar_wait_until(condvar_id, mutex_id, deadline)
+    });
+    assert_eq!(mutex_lock_until(mutex_id, 1), ETIMEDOUT);
+    mutex_unlock(mutex_id);
+    // a futex nobody wakes
+    let word = AtomicU32::new(0);
+    times_out("futex wait", |deadline| futex_wait_until(&word, 0, deadline));
+    assert_eq!(futex_wait_until(&word, 1, deadline_after_ms(WAIT_MS)), -1);
+    println!("timeout test passed!");
+    0
+}
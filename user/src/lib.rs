@@ -14,6 +14,7 @@ extern crate core;
 extern crate bitflags;
 
 use alloc::vec::Vec;
+use core::sync::atomic::AtomicU32;
 use buddy_system_allocator::LockedHeap;
 pub use console::{flush, STDIN, STDOUT};
 pub use syscall::*;
@@ -98,6 +99,36 @@ impl TimeVal {
     }
 }
 
+/// Time of a clock, see [`clock_gettime`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+/// wall clock time, settable by [`clock_settime`]
+pub const CLOCK_REALTIME: usize = 0;
+/// time since boot, never goes back
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// what the `_until` calls return once their deadline has passed
+pub const ETIMEDOUT: isize = -110;
+
+/// CPU time spent by a process in user mode and in the kernel
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+}
+
+impl RUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TaskStatus {
     UnInit,
@@ -131,6 +162,178 @@ impl TaskInfo {
     }
 }
 
+/// [`TaskListEntry::status`] of a process still starting up
+pub const PROC_UNINIT: usize = 0;
+/// [`TaskListEntry::status`] of a process waiting to run
+pub const PROC_READY: usize = 1;
+/// [`TaskListEntry::status`] of a process running on some hart
+pub const PROC_RUNNING: usize = 2;
+/// [`TaskListEntry::status`] of a process that exited, not waited for yet
+pub const PROC_ZOMBIE: usize = 3;
+/// [`TaskListEntry::status`] of a process waiting for an event
+pub const PROC_BLOCKED: usize = 4;
+
+/// One process as listed by [`task_list`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskListEntry {
+    pub pid: usize,
+    /// one of the `PROC_*` constants
+    pub status: usize,
+    pub priority: usize,
+    pub threads: usize,
+    pub user_time_us: usize,
+    pub kernel_time_us: usize,
+}
+
+/// [`MapRegion::backing`] of anonymous memory
+pub const REGION_ANONYMOUS: usize = 0;
+/// [`MapRegion::backing`] of a private file mapping
+pub const REGION_FILE_PRIVATE: usize = 1;
+/// [`MapRegion::backing`] of a shared file mapping
+pub const REGION_FILE_SHARED: usize = 2;
+/// [`MapRegion::backing`] of kernel pages such as the trampoline
+pub const REGION_UNTRACKED: usize = 3;
+/// [`MapRegion::backing`] of a shared memory object
+pub const REGION_SHM: usize = 4;
+
+/// resources for [`setrlimit`]: frames backing user pages at once
+pub const RLIMIT_FRAMES: usize = 0;
+/// bytes mapped by `mmap` at once
+pub const RLIMIT_MMAP: usize = 1;
+/// open fds
+pub const RLIMIT_NOFILE: usize = 2;
+/// no limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// `op` of [`sys_futex`]: block while the word holds the value
+pub const FUTEX_WAIT: usize = 0;
+/// `op` of [`sys_futex`]: wake up sleepers on the word
+pub const FUTEX_WAKE: usize = 1;
+
+/// A range of an address space as listed by [`task_maps`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MapRegion {
+    pub start: usize,
+    /// 0 for a region reaching the top of the address space
+    pub end: usize,
+    /// R = 1 << 1, W = 1 << 2, X = 1 << 3, U = 1 << 4
+    pub perm: usize,
+    /// one of the `REGION_*` constants
+    pub backing: usize,
+    /// pages backed by memory now
+    pub resident_pages: usize,
+}
+
+/// processes [`mempinfo`] reports at most, matching the kernel
+pub const MEMINFO_MAX_TASKS: usize = 32;
+
+/// Resident pages of one process
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskMemInfo {
+    pub pid: usize,
+    pub resident_pages: usize,
+}
+
+/// System-wide memory usage, in frames
+#[repr(C)]
+#[derive(Debug)]
+pub struct MemInfo {
+    /// all of physical memory
+    pub total_frames: usize,
+    /// frames no one holds
+    pub free_frames: usize,
+    /// taken by the SBI firmware and the kernel image for good
+    pub reserved_frames: usize,
+    /// holding pages of files
+    pub page_cache_frames: usize,
+    /// free pages in the swap area
+    pub swap_free_pages: usize,
+    /// processes alive, `tasks` holds the first of them in pid order
+    pub task_count: usize,
+    pub tasks: [TaskMemInfo; MEMINFO_MAX_TASKS],
+}
+
+impl MemInfo {
+    pub fn new() -> Self {
+        MemInfo {
+            total_frames: 0,
+            free_frames: 0,
+            reserved_frames: 0,
+            page_cache_frames: 0,
+            swap_free_pages: 0,
+            task_count: 0,
+            tasks: [TaskMemInfo::default(); MEMINFO_MAX_TASKS],
+        }
+    }
+}
+
+/// harts [`sysinfo`] reports at most, matching the kernel
+pub const SYSINFO_MAX_HARTS: usize = 4;
+
+/// Uptime and how much of it each hart spent idle
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SysInfo {
+    /// microseconds since boot
+    pub uptime_us: usize,
+    /// harts running the kernel, `idle_us` holds as many
+    pub harts: usize,
+    /// microseconds each hart has waited with nothing to run
+    pub idle_us: [usize; SYSINFO_MAX_HARTS],
+}
+
+/// [`TaskInfo`] followed by memory statistics of the process and scheduling
+/// statistics of the calling thread
+#[derive(Debug)]
+pub struct TaskInfo2 {
+    pub status: TaskStatus,
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    pub time: usize,
+    /// pages backed by memory now
+    pub resident_pages: usize,
+    /// most pages ever backed by memory at once
+    pub peak_resident_pages: usize,
+    /// page faults resolved without I/O
+    pub minor_faults: usize,
+    /// page faults reading from the swap area or a file
+    pub major_faults: usize,
+    /// areas mapped by `mmap`
+    pub mmap_regions: usize,
+    /// times the thread was switched out by the timer
+    pub preemptions: usize,
+    /// times the thread gave up the CPU by `yield`
+    pub voluntary_yields: usize,
+    /// times the thread was picked to run
+    pub dispatches: usize,
+    /// time spent ready in the queue before running, in us
+    pub sched_latency_us: usize,
+    /// longest such wait, in us
+    pub max_sched_latency_us: usize,
+}
+
+impl TaskInfo2 {
+    pub fn new() -> Self {
+        TaskInfo2 {
+            status: TaskStatus::UnInit,
+            syscall_times: [0; MAX_SYSCALL_NUM],
+            time: 0,
+            resident_pages: 0,
+            peak_resident_pages: 0,
+            minor_faults: 0,
+            major_faults: 0,
+            mmap_regions: 0,
+            preemptions: 0,
+            voluntary_yields: 0,
+            dispatches: 0,
+            sched_latency_us: 0,
+            max_sched_latency_us: 0,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct Stat {
@@ -174,6 +377,65 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// a set of signals, bit `n` standing for signal number `n`
+    pub struct SignalFlags: u32 {
+        const SIGHUP = 1 << 1;
+        const SIGINT = 1 << 2;
+        const SIGQUIT = 1 << 3;
+        const SIGILL = 1 << 4;
+        const SIGTRAP = 1 << 5;
+        const SIGABRT = 1 << 6;
+        const SIGBUS = 1 << 7;
+        const SIGFPE = 1 << 8;
+        const SIGKILL = 1 << 9;
+        const SIGUSR1 = 1 << 10;
+        const SIGSEGV = 1 << 11;
+        const SIGUSR2 = 1 << 12;
+        const SIGPIPE = 1 << 13;
+        const SIGALRM = 1 << 14;
+        const SIGTERM = 1 << 15;
+        const SIGCHLD = 1 << 17;
+        const SIGCONT = 1 << 18;
+        const SIGSTOP = 1 << 19;
+    }
+}
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGABRT: i32 = 6;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+
+/// `SignalAction::handler` for the default action of a signal
+pub const SIG_DFL: usize = 0;
+/// `SignalAction::handler` for ignoring a signal
+pub const SIG_IGN: usize = 1;
+
+/// What to do when a signal arrives. A handler gets the signal number and
+/// has to finish by calling `sigreturn`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
 const AT_FDCWD: isize = -100;
 
 pub fn open(path: &str, flags: OpenFlags) -> isize {
@@ -188,7 +450,13 @@ pub fn close(fd: usize) -> isize {
 }
 
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
-    sys_read(fd, buf)
+    sys_read(fd, buf, 0)
+}
+
+/// [`read`] giving up with [`ETIMEDOUT`] once `deadline_us` passes, see
+/// [`deadline_after_ms`].
+pub fn read_until(fd: usize, buf: &mut [u8], deadline_us: usize) -> isize {
+    sys_read(fd, buf, deadline_us)
 }
 
 pub fn write(fd: usize, buf: &[u8]) -> isize {
@@ -232,10 +500,56 @@ pub fn get_time() -> isize {
     }
 }
 
+/// Read `clock_id`, [`CLOCK_REALTIME`] or [`CLOCK_MONOTONIC`].
+pub fn clock_gettime(clock_id: usize, time: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock_id, time)
+}
+
+/// The deadline `ms` milliseconds from now for the `_until` calls, in
+/// microseconds of [`CLOCK_MONOTONIC`].
+pub fn deadline_after_ms(ms: usize) -> usize {
+    let mut now = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut now);
+    now.sec * 1_000_000 + now.nsec / 1000 + ms * 1000
+}
+
+/// Set the wall clock, the only one that can be set.
+pub fn clock_settime(clock_id: usize, time: &TimeSpec) -> isize {
+    sys_clock_settime(clock_id, time)
+}
+
+pub fn getrusage(usage: &RUsage) -> isize {
+    sys_getrusage(0, usage)
+}
+
 pub fn getpid() -> isize {
     sys_getpid()
 }
 
+pub fn kill(pid: usize, signum: i32) -> isize {
+    sys_kill(pid, signum)
+}
+
+pub fn sigaction(
+    signum: i32,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    sys_sigaction(
+        signum,
+        action.map_or(core::ptr::null(), |a| a as *const _),
+        old_action.map_or(core::ptr::null_mut(), |a| a as *mut _),
+    )
+}
+
+pub fn sigprocmask(mask: u32) -> isize {
+    sys_sigprocmask(mask)
+}
+
+pub fn sigreturn() -> isize {
+    sys_sigreturn()
+}
+
 pub fn fork() -> isize {
     sys_fork()
 }
@@ -288,10 +602,45 @@ pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
     sys_mmap(start, len, prot)
 }
 
+/// mmap flag: back the pages by the file `fd`, read in on first access
+pub const MAP_FILE: usize = 1 << 10;
+/// mmap flag: with `MAP_FILE`, stores reach the file and other mappings of it
+pub const MAP_SHARED: usize = 1 << 11;
+
+/// Map `len` bytes of the file `fd` from `offset` on at `start`,
+/// `flags` being `MAP_FILE` plus optionally `MAP_SHARED`
+pub fn mmap_file(
+    start: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap_file(start, len, prot | flags, fd, offset)
+}
+
 pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
 
+/// mremap flag: move the mapping if it cannot grow where it is
+pub const MREMAP_MAYMOVE: usize = 1;
+
+/// Resize the mapping at `old_addr` to `new_len` bytes, returning its
+/// address, which only changes with [`MREMAP_MAYMOVE`], or -1.
+pub fn mremap(old_addr: usize, old_len: usize, new_len: usize, flags: usize) -> isize {
+    sys_mremap(old_addr, old_len, new_len, flags)
+}
+
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
+
 pub fn spawn(path: &str) -> isize {
     sys_spawn(path)
 }
@@ -299,13 +648,80 @@ pub fn spawn(path: &str) -> isize {
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+/// Make `new_fd` refer to the file of `old_fd`, closing what it referred to
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    sys_dup2(old_fd, new_fd)
+}
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
+/// eventfd flag: a read takes 1 from the counter instead of all of it
+pub const EFD_SEMAPHORE: usize = 1;
+/// Make an eventfd with its counter at `initval`, returning its fd or -1.
+/// Write a `u64` to it to add to the counter, read one to take the count,
+/// blocking while it is 0.
+pub fn eventfd(initval: u64, flags: usize) -> isize {
+    sys_eventfd(initval as usize, flags)
+}
 
 pub fn task_info(info: &TaskInfo) -> isize {
     sys_task_info(info)
 }
+pub fn task_info2(info: &TaskInfo2) -> isize {
+    sys_task_info2(info)
+}
+/// Fill `info` with the memory usage of the whole system.
+pub fn mempinfo(info: &mut MemInfo) -> isize {
+    sys_mempinfo(info)
+}
+/// Fill `info` with the uptime and the idle time of every hart.
+pub fn sysinfo(info: &mut SysInfo) -> isize {
+    sys_sysinfo(info)
+}
+/// Fill `list` with processes in pid order, returning how many there are,
+/// which may be more than fit.
+pub fn task_list(list: &mut [TaskListEntry]) -> isize {
+    sys_task_list(list)
+}
+/// Fill `regions` with the memory map of process `pid`, returning how many
+/// regions there are, or -1 without such a process.
+pub fn task_maps(pid: usize, regions: &mut [MapRegion]) -> isize {
+    sys_task_maps(pid, regions)
+}
+/// Limit `resource` of this process and of children created from now on,
+/// -1 if there is no such resource.
+pub fn setrlimit(resource: usize, limit: usize) -> isize {
+    sys_setrlimit(resource, limit)
+}
+/// Create a shared memory object of `pages` zeroed pages, returning its id
+/// for [`shm_attach`], or -1.
+pub fn shm_create(pages: usize) -> isize {
+    sys_shm_create(pages)
+}
+/// Map shared memory object `id` with `prot` like [`mmap`], returning the
+/// address, or -1. Children created by `fork` map it as well.
+pub fn shm_attach(id: usize, prot: usize) -> isize {
+    sys_shm_attach(id, prot)
+}
+/// Unmap the shared memory object attached at `addr`.
+pub fn shm_detach(addr: usize) -> isize {
+    sys_shm_detach(addr)
+}
+/// Power the machine off, QEMU exiting with a failure code if `failure`.
+pub fn shutdown(failure: bool) -> ! {
+    console::flush();
+    sys_shutdown(failure);
+}
+/// Restart the machine, or power it off where that is not possible.
+pub fn reboot() -> ! {
+    console::flush();
+    sys_reboot();
+}
+/// Log the syscalls of process `pid`, -1 for the caller, and of its
+/// children from then on.
+pub fn trace(pid: isize, enable: bool) -> isize {
+    sys_trace(pid, enable)
+}
 
 pub fn thread_create(entry: usize, arg: usize) -> isize {
     sys_thread_create(entry, arg)
@@ -331,7 +747,11 @@ pub fn mutex_blocking_create() -> isize {
     sys_mutex_create(true)
 }
 pub fn mutex_lock(mutex_id: usize) -> isize {
-    sys_mutex_lock(mutex_id)
+    sys_mutex_lock(mutex_id, 0)
+}
+/// [`mutex_lock`] giving up with [`ETIMEDOUT`] once `deadline_us` passes.
+pub fn mutex_lock_until(mutex_id: usize, deadline_us: usize) -> isize {
+    sys_mutex_lock(mutex_id, deadline_us)
 }
 pub fn mutex_unlock(mutex_id: usize) {
     sys_mutex_unlock(mutex_id);
@@ -355,5 +775,24 @@ pub fn condvar_signal(condvar_id: usize) {
     sys_condvar_signal(condvar_id);
 }
 pub fn condvar_wait(condvar_id: usize, mutex_id: usize) {
-    sys_condvar_wait(condvar_id, mutex_id);
+    sys_condvar_wait(condvar_id, mutex_id, 0);
+}
+/// [`condvar_wait`] giving up with [`ETIMEDOUT`] once `deadline_us` passes,
+/// holding the mutex again either way.
+pub fn condvar_wait_until(condvar_id: usize, mutex_id: usize, deadline_us: usize) -> isize {
+    sys_condvar_wait(condvar_id, mutex_id, deadline_us)
+}
+/// Block while `word` holds `val`, returning 0 once woken up by
+/// [`futex_wake`] and -1 if it did not hold `val`.
+pub fn futex_wait(word: &AtomicU32, val: u32) -> isize {
+    sys_futex(word as *const AtomicU32 as usize, FUTEX_WAIT, val as usize, 0)
+}
+/// [`futex_wait`] giving up with [`ETIMEDOUT`] once `deadline_us` passes.
+pub fn futex_wait_until(word: &AtomicU32, val: u32, deadline_us: usize) -> isize {
+    sys_futex(word as *const AtomicU32 as usize, FUTEX_WAIT, val as usize, deadline_us)
+}
+/// Wake up at most `count` threads blocked on `word` by [`futex_wait`], in
+/// this process or any other mapping the same page, returning how many.
+pub fn futex_wake(word: &AtomicU32, count: usize) -> isize {
+    sys_futex(word as *const AtomicU32 as usize, FUTEX_WAKE, count, 0)
 }
@@ -1,6 +1,6 @@
-use crate::TaskInfo;
+use crate::{MapRegion, MemInfo, SysInfo, TaskInfo, TaskInfo2, TaskListEntry};
 
-use super::{Stat, TimeVal};
+use super::{RUsage, SignalAction, Stat, TimeSpec, TimeVal};
 
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
@@ -10,23 +10,48 @@ pub const SYSCALL_UNLINKAT: usize = 35;
 pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_EXIT: usize = 93;
+pub const SYSCALL_FUTEX: usize = 98;
 pub const SYSCALL_SLEEP: usize = 101;
+pub const SYSCALL_CLOCK_SETTIME: usize = 112;
+pub const SYSCALL_CLOCK_GETTIME: usize = 113;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_KILL: usize = 129;
+pub const SYSCALL_SIGACTION: usize = 134;
+pub const SYSCALL_SIGPROCMASK: usize = 135;
+pub const SYSCALL_SIGRETURN: usize = 139;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
+pub const SYSCALL_GETRUSAGE: usize = 165;
 pub const SYSCALL_GETPID: usize = 172;
 pub const SYSCALL_GETTID: usize = 178;
+pub const SYSCALL_SYSINFO: usize = 179;
 pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_SBRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
+pub const SYSCALL_MREMAP: usize = 216;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_MPROTECT: usize = 226;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
+pub const SYSCALL_EVENTFD: usize = 19;
 pub const SYSCALL_DUP: usize = 24;
+pub const SYSCALL_DUP2: usize = 25;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_TRACE: usize = 411;
+pub const SYSCALL_TASK_INFO2: usize = 412;
+pub const SYSCALL_MEMPINFO: usize = 413;
+pub const SYSCALL_TASK_LIST: usize = 414;
+pub const SYSCALL_TASK_MAPS: usize = 415;
+pub const SYSCALL_SHUTDOWN: usize = 416;
+pub const SYSCALL_REBOOT: usize = 417;
+pub const SYSCALL_SETRLIMIT: usize = 418;
+pub const SYSCALL_SHM_CREATE: usize = 419;
+pub const SYSCALL_SHM_ATTACH: usize = 420;
+pub const SYSCALL_SHM_DETACH: usize = 421;
 pub const SYSCALL_THREAD_CREATE: usize = 460;
 pub const SYSCALL_WAITTID: usize = 462;
 pub const SYSCALL_MUTEX_CREATE: usize = 463;
@@ -88,10 +113,10 @@ pub fn sys_close(fd: usize) -> isize {
     syscall(SYSCALL_CLOSE, [fd, 0, 0])
 }
 
-pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
-    syscall(
+pub fn sys_read(fd: usize, buffer: &mut [u8], deadline_us: usize) -> isize {
+    syscall6(
         SYSCALL_READ,
-        [fd, buffer.as_mut_ptr() as usize, buffer.len()],
+        [fd, buffer.as_mut_ptr() as usize, buffer.len(), deadline_us, 0, 0],
     )
 }
 
@@ -146,10 +171,22 @@ pub fn sys_exit(exit_code: i32) -> ! {
     panic!("sys_exit never returns!");
 }
 
+pub fn sys_futex(uaddr: usize, op: usize, val: usize, deadline_us: usize) -> isize {
+    syscall6(SYSCALL_FUTEX, [uaddr, op, val, deadline_us, 0, 0])
+}
+
 pub fn sys_sleep(sleep_ms: usize) -> isize {
     syscall(SYSCALL_SLEEP, [sleep_ms, 0, 0])
 }
 
+pub fn sys_clock_settime(clock_id: usize, time: &TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_SETTIME, [clock_id, time as *const _ as usize, 0])
+}
+
+pub fn sys_clock_gettime(clock_id: usize, time: &mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_GETTIME, [clock_id, time as *mut _ as usize, 0])
+}
+
 pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0])
 }
@@ -158,10 +195,37 @@ pub fn sys_get_time(time: &TimeVal, tz: usize) -> isize {
     syscall(SYSCALL_GETTIMEOFDAY, [time as *const _ as usize, tz, 0])
 }
 
+pub fn sys_getrusage(who: isize, usage: &RUsage) -> isize {
+    syscall(SYSCALL_GETRUSAGE, [who as usize, usage as *const _ as usize, 0])
+}
+
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
 
+pub fn sys_kill(pid: usize, signum: i32) -> isize {
+    syscall(SYSCALL_KILL, [pid, signum as usize, 0])
+}
+
+pub fn sys_sigaction(
+    signum: i32,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    syscall(
+        SYSCALL_SIGACTION,
+        [signum as usize, action as usize, old_action as usize],
+    )
+}
+
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    syscall(SYSCALL_SIGPROCMASK, [mask as usize, 0, 0])
+}
+
+pub fn sys_sigreturn() -> isize {
+    syscall(SYSCALL_SIGRETURN, [0, 0, 0])
+}
+
 pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
@@ -185,10 +249,26 @@ pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
     syscall(SYSCALL_MMAP, [start, len, prot])
 }
 
+pub fn sys_mmap_file(start: usize, len: usize, prot: usize, fd: usize, offset: usize) -> isize {
+    syscall6(SYSCALL_MMAP, [start, len, prot, fd, offset, 0])
+}
+
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
+pub fn sys_mremap(old_addr: usize, old_len: usize, new_len: usize, flags: usize) -> isize {
+    syscall6(SYSCALL_MREMAP, [old_addr, old_len, new_len, flags, 0, 0])
+}
+
+pub fn sys_sbrk(increment: isize) -> isize {
+    syscall(SYSCALL_SBRK, [increment as usize, 0, 0])
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot])
+}
+
 pub fn sys_spawn(path: &str) -> isize {
     syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
 }
@@ -197,14 +277,72 @@ pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    syscall(SYSCALL_DUP2, [old_fd, new_fd, 0])
+}
+
 pub fn sys_pipe(pipe: &mut [usize]) -> isize {
     syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
 }
 
+pub fn sys_eventfd(initval: usize, flags: usize) -> isize {
+    syscall(SYSCALL_EVENTFD, [initval, flags, 0])
+}
+
 pub fn sys_task_info(info: &TaskInfo) -> isize {
     syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, 0, 0])
 }
 
+pub fn sys_task_info2(info: &TaskInfo2) -> isize {
+    syscall(SYSCALL_TASK_INFO2, [info as *const _ as usize, 0, 0])
+}
+
+pub fn sys_mempinfo(info: &mut MemInfo) -> isize {
+    syscall(SYSCALL_MEMPINFO, [info as *mut _ as usize, 0, 0])
+}
+
+pub fn sys_sysinfo(info: &mut SysInfo) -> isize {
+    syscall(SYSCALL_SYSINFO, [info as *mut _ as usize, 0, 0])
+}
+
+pub fn sys_task_list(list: &mut [TaskListEntry]) -> isize {
+    syscall(SYSCALL_TASK_LIST, [list.as_mut_ptr() as usize, list.len(), 0])
+}
+
+pub fn sys_task_maps(pid: usize, regions: &mut [MapRegion]) -> isize {
+    syscall(SYSCALL_TASK_MAPS, [pid, regions.as_mut_ptr() as usize, regions.len()])
+}
+
+pub fn sys_shutdown(failure: bool) -> ! {
+    syscall(SYSCALL_SHUTDOWN, [failure as usize, 0, 0]);
+    panic!("sys_shutdown never returns!");
+}
+
+pub fn sys_setrlimit(resource: usize, limit: usize) -> isize {
+    syscall(SYSCALL_SETRLIMIT, [resource, limit, 0])
+}
+
+pub fn sys_shm_create(pages: usize) -> isize {
+    syscall(SYSCALL_SHM_CREATE, [pages, 0, 0])
+}
+
+pub fn sys_shm_attach(id: usize, prot: usize) -> isize {
+    syscall(SYSCALL_SHM_ATTACH, [id, prot, 0])
+}
+
+pub fn sys_shm_detach(addr: usize) -> isize {
+    syscall(SYSCALL_SHM_DETACH, [addr, 0, 0])
+}
+
+pub fn sys_reboot() -> ! {
+    syscall(SYSCALL_REBOOT, [0; 3]);
+    panic!("sys_reboot never returns!");
+}
+
+pub fn sys_trace(pid: isize, enable: bool) -> isize {
+    syscall(SYSCALL_TRACE, [pid as usize, enable as usize, 0])
+}
+
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
 }
@@ -221,8 +359,8 @@ pub fn sys_mutex_create(blocking: bool) -> isize {
     syscall(SYSCALL_MUTEX_CREATE, [blocking as usize, 0, 0])
 }
 
-pub fn sys_mutex_lock(id: usize) -> isize {
-    syscall(SYSCALL_MUTEX_LOCK, [id, 0, 0])
+pub fn sys_mutex_lock(id: usize, deadline_us: usize) -> isize {
+    syscall(SYSCALL_MUTEX_LOCK, [id, deadline_us, 0])
 }
 
 pub fn sys_mutex_unlock(id: usize) -> isize {
@@ -253,6 +391,6 @@ pub fn sys_condvar_signal(condvar_id: usize) -> isize {
     syscall(SYSCALL_CONDVAR_SIGNAL, [condvar_id, 0, 0])
 }
 
-pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
-    syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, 0])
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize, deadline_us: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, deadline_us])
 }